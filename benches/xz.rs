@@ -0,0 +1,127 @@
+//! Decompression of a multi-block xz tarball, like the ones of the LLVM and GCC releases, with the
+//! single-threaded decoder used by espup and the multi-threaded one.
+//!
+//! Run with `cargo bench --bench xz` on a multi-core host. The multi-threaded decoder only pays
+//! off when it beats the single-threaded one there, it is overhead on single core hosts.
+
+use espup::toolchain::archive::extract;
+use liblzma::{read::XzDecoder, stream::MtStreamBuilder, write::XzEncoder};
+use std::{
+    io::{copy, sink, Read, Write},
+    thread,
+    time::{Duration, Instant},
+};
+use tar::{Builder, Header};
+use tempfile::TempDir;
+
+/// Number of files of the tarball.
+const FILES: usize = 32;
+/// Size of each file of the tarball.
+const FILE_SIZE: usize = 4 * 1024 * 1024;
+/// Size of the blocks of the xz stream.
+const BLOCK_SIZE: u64 = 8 * 1024 * 1024;
+/// Runs of each measure, the fastest one is reported.
+const RUNS: usize = 3;
+
+/// Memory the multi-threaded decoder may use, fewer threads are used beyond it.
+const MEMLIMIT_THREADING: u64 = 1024 * 1024 * 1024;
+
+/// Returns compressible pseudo-random content, similar to binaries.
+fn content(seed: u64) -> Vec<u8> {
+    let mut state = seed;
+    (0..FILE_SIZE)
+        .map(|_| {
+            state = state
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            b"\0\0\0\0 abcdefghijklmnop"[(state >> 59) as usize % 21]
+        })
+        .collect()
+}
+
+/// Returns a tarball compressed in blocks, like `xz -T0` does.
+fn tarball() -> Vec<u8> {
+    let mut builder = Builder::new(Vec::new());
+    for file in 0..FILES {
+        let data = content(file as u64);
+        let mut header = Header::new_gnu();
+        header.set_size(data.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        builder
+            .append_data(
+                &mut header,
+                format!("esp-clang/lib/lib{file}.so"),
+                data.as_slice(),
+            )
+            .unwrap();
+    }
+    let threads = thread::available_parallelism().map_or(1, |threads| threads.get());
+    let stream = MtStreamBuilder::new()
+        .threads(threads as u32)
+        .block_size(BLOCK_SIZE)
+        .preset(6)
+        .encoder()
+        .unwrap();
+    let mut encoder = XzEncoder::new_stream(Vec::new(), stream);
+    encoder.write_all(&builder.into_inner().unwrap()).unwrap();
+    encoder.finish().unwrap()
+}
+
+/// Returns the fastest duration of the runs.
+fn measure(mut run: impl FnMut()) -> Duration {
+    (0..RUNS)
+        .map(|_| {
+            let started = Instant::now();
+            run();
+            started.elapsed()
+        })
+        .min()
+        .unwrap()
+}
+
+/// Returns a decoder of the xz stream decompressing its blocks on every core.
+fn parallel_decoder(bytes: &[u8]) -> XzDecoder<&[u8]> {
+    let threads = thread::available_parallelism().map_or(1, |threads| threads.get());
+    let stream = MtStreamBuilder::new()
+        .threads(threads as u32)
+        .memlimit_threading(MEMLIMIT_THREADING)
+        .memlimit_stop(u64::MAX)
+        .decoder()
+        .unwrap();
+    XzDecoder::new_stream(bytes, stream)
+}
+
+/// Decompresses the whole stream.
+fn decompress(mut decoder: impl Read) {
+    copy(&mut decoder, &mut sink()).unwrap();
+}
+
+fn main() {
+    let bytes = tarball();
+    println!(
+        "{} MiB tarball, {} MiB compressed, {} threads",
+        FILES * FILE_SIZE / (1024 * 1024),
+        bytes.len() / (1024 * 1024),
+        thread::available_parallelism().map_or(1, |threads| threads.get())
+    );
+
+    let single = measure(|| decompress(XzDecoder::new(bytes.as_slice())));
+    let parallel = measure(|| decompress(parallel_decoder(&bytes)));
+    let extraction = measure(|| {
+        let temp_dir = TempDir::new().unwrap();
+        extract(&bytes, "llvm.tar.xz", temp_dir.path(), None).unwrap();
+    });
+    println!(
+        "single-threaded decompression: {:>8.3}s",
+        single.as_secs_f64()
+    );
+    println!(
+        "multi-threaded decompression:  {:>8.3}s",
+        parallel.as_secs_f64()
+    );
+    println!(
+        "extraction:                    {:>8.3}s",
+        extraction.as_secs_f64()
+    );
+}
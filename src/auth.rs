@@ -0,0 +1,265 @@
+//! Credentials of the mirrors and of the GitHub API, kept in the credential store of the OS.
+//!
+//! Secrets passed as flags or environment variables end up in the shell history and CI logs, so
+//! they can be stored once with `espup auth` instead: in the Keychain on macOS, the Credential
+//! Manager on Windows and the kernel keyring on Linux. `GITHUB_TOKEN` still takes precedence
+//! over the stored token.
+//!
+//! The kernel keyring is kept in memory, so the secrets stored on Linux are lost on reboot. The
+//! credential store requires the `credential-store` feature, without it only `GITHUB_TOKEN` is
+//! used.
+
+use crate::error::Error;
+#[cfg(feature = "credential-store")]
+use log::debug;
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, env, sync::Mutex};
+
+/// Service the credentials are stored under.
+#[cfg(feature = "credential-store")]
+const SERVICE: &str = "espup";
+/// Entry of the GitHub token.
+const GITHUB_ENTRY: &str = "github";
+/// Reason of the credential store errors of the builds without it.
+#[cfg(not(feature = "credential-store"))]
+const UNAVAILABLE: &str = "espup was built without the `credential-store` feature";
+
+/// Credentials read from the store, by mirror origin, so the store is queried once per origin.
+static MIRROR_CREDENTIALS: Mutex<BTreeMap<String, Option<MirrorCredentials>>> =
+    Mutex::new(BTreeMap::new());
+
+/// Basic authentication credentials of a mirror.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MirrorCredentials {
+    pub username: String,
+    pub password: String,
+}
+
+/// Storage of the secrets.
+trait SecretStore {
+    /// Returns the secret of an entry, `None` if it's missing or the store is unavailable.
+    fn read(&self, name: &str) -> Option<String>;
+    /// Stores the secret of an entry.
+    fn write(&self, name: &str, secret: &str) -> Result<(), Error>;
+    /// Removes an entry, returning whether it existed.
+    fn delete(&self, name: &str) -> Result<bool, Error>;
+}
+
+/// Credential store of the OS.
+struct OsStore;
+
+#[cfg(feature = "credential-store")]
+impl OsStore {
+    fn entry(name: &str) -> Result<keyring::Entry, Error> {
+        keyring::Entry::new(SERVICE, name).map_err(|e| Error::CredentialStore(e.to_string()))
+    }
+}
+
+#[cfg(feature = "credential-store")]
+impl SecretStore for OsStore {
+    fn read(&self, name: &str) -> Option<String> {
+        match Self::entry(name).map(|entry| entry.get_password()) {
+            Ok(Ok(secret)) => Some(secret),
+            Ok(Err(keyring::Error::NoEntry)) => None,
+            Ok(Err(e)) => {
+                debug!("Failed to read the '{}' credentials: {}", name, e);
+                None
+            }
+            Err(e) => {
+                debug!("{}", e);
+                None
+            }
+        }
+    }
+
+    fn write(&self, name: &str, secret: &str) -> Result<(), Error> {
+        Self::entry(name)?
+            .set_password(secret)
+            .map_err(|e| Error::CredentialStore(e.to_string()))
+    }
+
+    fn delete(&self, name: &str) -> Result<bool, Error> {
+        match Self::entry(name)?.delete_credential() {
+            Ok(()) => Ok(true),
+            Err(keyring::Error::NoEntry) => Ok(false),
+            Err(e) => Err(Error::CredentialStore(e.to_string())),
+        }
+    }
+}
+
+#[cfg(not(feature = "credential-store"))]
+impl SecretStore for OsStore {
+    fn read(&self, _name: &str) -> Option<String> {
+        None
+    }
+
+    fn write(&self, _name: &str, _secret: &str) -> Result<(), Error> {
+        Err(Error::CredentialStore(UNAVAILABLE.to_string()))
+    }
+
+    fn delete(&self, _name: &str) -> Result<bool, Error> {
+        Err(Error::CredentialStore(UNAVAILABLE.to_string()))
+    }
+}
+
+/// Returns the entry of the credentials of the mirrors of a scheme and host, with any port.
+///
+/// The scheme is part of the entry, so credentials stored for an `https` mirror are never sent
+/// in clear text to the `http` URLs of its host.
+fn mirror_entry_name(mirror: &Url) -> Option<String> {
+    mirror
+        .host_str()
+        .map(|host| format!("mirror:{}://{host}", mirror.scheme()))
+}
+
+/// Stores the GitHub token.
+pub fn set_github_token(token: &str) -> Result<(), Error> {
+    OsStore.write(GITHUB_ENTRY, token)
+}
+
+/// Returns the GitHub token, from `GITHUB_TOKEN` or the credential store.
+pub fn github_token() -> Option<String> {
+    env::var("GITHUB_TOKEN")
+        .ok()
+        .filter(|token| !token.is_empty())
+        .or_else(|| OsStore.read(GITHUB_ENTRY))
+}
+
+/// Removes the stored GitHub token, returning whether one was stored.
+pub fn clear_github_token() -> Result<bool, Error> {
+    OsStore.delete(GITHUB_ENTRY)
+}
+
+fn store_mirror_credentials(
+    store: &impl SecretStore,
+    mirror: &Url,
+    credentials: &MirrorCredentials,
+) -> Result<(), Error> {
+    let name = mirror_entry_name(mirror).ok_or_else(|| Error::InvalidMirror(mirror.to_string()))?;
+    MIRROR_CREDENTIALS.lock().unwrap().remove(&name);
+    store.write(&name, &serde_json::to_string(credentials).unwrap())
+}
+
+fn lookup_mirror_credentials(store: &impl SecretStore, url: &Url) -> Option<MirrorCredentials> {
+    let secret = store.read(&mirror_entry_name(url)?)?;
+    serde_json::from_str(&secret).ok()
+}
+
+fn delete_mirror_credentials(store: &impl SecretStore, mirror: &Url) -> Result<bool, Error> {
+    let name = mirror_entry_name(mirror).ok_or_else(|| Error::InvalidMirror(mirror.to_string()))?;
+    MIRROR_CREDENTIALS.lock().unwrap().remove(&name);
+    store.delete(&name)
+}
+
+/// Stores the credentials of the mirrors of a scheme and host.
+pub fn set_mirror_credentials(mirror: &Url, credentials: &MirrorCredentials) -> Result<(), Error> {
+    store_mirror_credentials(&OsStore, mirror, credentials)
+}
+
+/// Returns the stored credentials of the scheme and host of a URL.
+pub fn mirror_credentials(url: &Url) -> Option<MirrorCredentials> {
+    let name = mirror_entry_name(url)?;
+    MIRROR_CREDENTIALS
+        .lock()
+        .unwrap()
+        .entry(name)
+        .or_insert_with(|| lookup_mirror_credentials(&OsStore, url))
+        .clone()
+}
+
+/// Removes the stored credentials of the mirrors of a scheme and host, returning whether they
+/// were stored.
+pub fn clear_mirror_credentials(mirror: &Url) -> Result<bool, Error> {
+    delete_mirror_credentials(&OsStore, mirror)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        auth::{
+            delete_mirror_credentials, lookup_mirror_credentials, mirror_entry_name,
+            store_mirror_credentials, MirrorCredentials, SecretStore,
+        },
+        error::Error,
+    };
+    use reqwest::Url;
+    use std::{cell::RefCell, collections::BTreeMap};
+
+    /// Store keeping the secrets in memory.
+    #[derive(Default)]
+    struct MockStore(RefCell<BTreeMap<String, String>>);
+
+    impl SecretStore for MockStore {
+        fn read(&self, name: &str) -> Option<String> {
+            self.0.borrow().get(name).cloned()
+        }
+
+        fn write(&self, name: &str, secret: &str) -> Result<(), Error> {
+            self.0
+                .borrow_mut()
+                .insert(name.to_string(), secret.to_string());
+            Ok(())
+        }
+
+        fn delete(&self, name: &str) -> Result<bool, Error> {
+            Ok(self.0.borrow_mut().remove(name).is_some())
+        }
+    }
+
+    #[test]
+    fn test_mirror_entry_name() {
+        let entry = |url| mirror_entry_name(&Url::parse(url).unwrap());
+        assert_eq!(
+            entry("https://mirror.example.com:8443/github/"),
+            Some("mirror:https://mirror.example.com".to_string())
+        );
+        assert_eq!(
+            entry("https://mirror.example.com/espressif/llvm-project/releases/download/x.tar.xz"),
+            Some("mirror:https://mirror.example.com".to_string())
+        );
+        assert_eq!(
+            entry("http://mirror.example.com/github/"),
+            Some("mirror:http://mirror.example.com".to_string())
+        );
+        assert_eq!(entry("file:///srv/mirror"), None);
+    }
+
+    #[test]
+    fn test_mirror_credentials() {
+        let store = MockStore::default();
+        let url = |url| Url::parse(url).unwrap();
+        let credentials = MirrorCredentials {
+            username: "ci".to_string(),
+            password: "p:ss\nword".to_string(),
+        };
+        store_mirror_credentials(
+            &store,
+            &url("https://mirror.example.com:8443/github/"),
+            &credentials,
+        )
+        .unwrap();
+        assert!(
+            store_mirror_credentials(&store, &url("file:///srv/mirror"), &credentials).is_err()
+        );
+
+        // Every URL of the host is matched, but only over the same scheme
+        assert_eq!(
+            lookup_mirror_credentials(&store, &url("https://mirror.example.com/x.tar.xz")),
+            Some(credentials.clone())
+        );
+        assert_eq!(
+            lookup_mirror_credentials(&store, &url("http://mirror.example.com/x.tar.xz")),
+            None
+        );
+        assert_eq!(
+            lookup_mirror_credentials(&store, &url("https://github.com/x.tar.xz")),
+            None
+        );
+
+        let mirror = url("https://mirror.example.com");
+        assert!(delete_mirror_credentials(&store, &mirror).unwrap());
+        assert!(!delete_mirror_credentials(&store, &mirror).unwrap());
+        assert_eq!(lookup_mirror_credentials(&store, &mirror), None);
+    }
+}
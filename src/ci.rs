@@ -0,0 +1,293 @@
+//! Continuous Integration output modes.
+
+#[cfg(all(feature = "cli", windows))]
+use crate::env::EnvScope;
+#[cfg(feature = "cli")]
+use crate::{
+    cli::{CiInstallOpts, InstallOpts},
+    report::OutputFormat,
+    target_dir::TargetDirStyle,
+    toolchain::{rust::Homes, rustup::ConcurrentRustup},
+};
+use crate::{
+    env::{Export, ExportKind},
+    error::Error,
+};
+#[cfg(feature = "cli")]
+use directories::BaseDirs;
+use std::{
+    env,
+    fs::OpenOptions,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+/// Continuous Integration systems with a dedicated output mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum CiMode {
+    /// GitHub Actions: grouped logs, error annotations, step outputs and environment files.
+    Github,
+}
+
+/// Default export file of `espup ci-install`, in the home directory.
+#[cfg(all(feature = "cli", windows))]
+const CI_EXPORT_FILE: &str = "export-esp.ps1";
+#[cfg(all(feature = "cli", not(windows)))]
+const CI_EXPORT_FILE: &str = "export-esp.sh";
+
+/// Returns the installation options of `espup ci-install`.
+///
+/// Every option is set here, so the frozen flag set doesn't follow the changes of the defaults of
+/// `espup install`.
+#[cfg(feature = "cli")]
+pub fn ci_install_opts(args: &CiInstallOpts) -> InstallOpts {
+    let export_file = args
+        .export
+        .clone()
+        .unwrap_or_else(|| BaseDirs::new().unwrap().home_dir().join(CI_EXPORT_FILE));
+    InstallOpts {
+        allow_foreign_host: false,
+        artifact_arch: None,
+        artifacts_dir: None,
+        asset_host_override: None,
+        ci: env::var("GITHUB_ACTIONS")
+            .is_ok_and(|value| value == "true")
+            .then_some(CiMode::Github),
+        compat: None,
+        concurrent_rustup: ConcurrentRustup::Wait,
+        default_host: None,
+        detect_device: false,
+        diff_only: false,
+        #[cfg(windows)]
+        env_scope: EnvScope::User,
+        #[cfg(windows)]
+        cmd_autorun: false,
+        esp_riscv_gcc: false,
+        export_file: Some(export_file),
+        export_shells: Vec::new(),
+        experimental_targets: false,
+        extended_llvm: false,
+        force: false,
+        from_espidf: false,
+        homes: Homes::default(),
+        io_parallelism: None,
+        jobs: None,
+        ldproxy: args.ldproxy,
+        llvm_only_libs_path: None,
+        log_level: args.log_level.clone(),
+        mirrors: Vec::new(),
+        name: "esp".to_string(),
+        nightly_version: "nightly".to_string(),
+        no_codesign: false,
+        no_fallback: false,
+        no_modify_env: false,
+        no_progress: false,
+        #[cfg(windows)]
+        no_ps1: false,
+        no_rustup: false,
+        override_artifact: Vec::new(),
+        permissive: false,
+        prefix: None,
+        print_av_exclusion: false,
+        progress_events: false,
+        proxy_for: Vec::new(),
+        quiet: false,
+        record_hashes: false,
+        regenerate_exports: false,
+        retries: 5,
+        reuse_exports: false,
+        riscv_components: Vec::new(),
+        riscv_profile: "minimal".to_string(),
+        sandbox: false,
+        skip_version_parse: false,
+        std: false,
+        summary_only: false,
+        summary_format: OutputFormat::Text,
+        system_proxy: false,
+        target_dir_style: TargetDirStyle::Default,
+        targets: args.buildtargets.clone(),
+        timeout: 30,
+        tmp_dir: None,
+        toolchain_version: (args.version != "latest").then(|| args.version.clone()),
+        toolchain_version_file: None,
+        verify_build: false,
+        xtensa_components: None,
+    }
+}
+
+/// GitHub Actions workflow commands support.
+///
+/// See https://docs.github.com/en/actions/writing-workflows/choosing-what-your-workflow-does/workflow-commands-for-github-actions
+pub struct GithubActions;
+
+impl GithubActions {
+    /// Starts a collapsible group of log lines.
+    pub fn group(title: &str) {
+        println!("::group::{title}");
+    }
+
+    /// Ends the current group of log lines.
+    pub fn endgroup() {
+        println!("::endgroup::");
+    }
+
+    /// Emits an error annotation.
+    pub fn error(title: &str, message: &str) {
+        // Annotations are single line, newlines must be escaped
+        let message = message
+            .replace('%', "%25")
+            .replace('\r', "%0D")
+            .replace('\n', "%0A");
+        println!("::error title={title}::{message}");
+    }
+
+    /// Sets a step output.
+    pub fn set_output(name: &str, value: &str) -> Result<(), Error> {
+        append_to_env_file("GITHUB_OUTPUT", &format!("{name}={value}"))
+    }
+
+    /// Makes the exports available to the following steps of the job by writing them into the
+    /// `GITHUB_ENV` and `GITHUB_PATH` files.
+    pub fn export(exports: &[Export]) -> Result<(), Error> {
+        let (variables, paths) = split_exports(exports);
+        for (name, value) in variables {
+            append_to_env_file("GITHUB_ENV", &format!("{name}={value}"))?;
+        }
+        for path in paths {
+            append_to_env_file("GITHUB_PATH", &path.display().to_string())?;
+        }
+        Ok(())
+    }
+}
+
+/// Appends a line to the file pointed by the given GitHub Actions environment variable.
+///
+/// Does nothing when the variable is not set (e.g. when not running in GitHub Actions).
+fn append_to_env_file(variable: &str, line: &str) -> Result<(), Error> {
+    let Some(path) = env::var_os(variable) else {
+        return Ok(());
+    };
+    append_line(Path::new(&path), line)
+}
+
+fn append_line(path: &Path, line: &str) -> Result<(), Error> {
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    writeln!(file, "{line}")?;
+    Ok(())
+}
+
+/// Splits the exports into variables and PATH additions, sourced scripts are not supported.
+fn split_exports(exports: &[Export]) -> (Vec<(String, String)>, Vec<PathBuf>) {
+    let mut variables = Vec::new();
+    let mut paths = Vec::new();
+    for export in exports {
+        match export.kind {
+            ExportKind::PrependPath => paths.push(PathBuf::from(&export.value)),
+            ExportKind::Set => variables.push((export.variable.clone(), export.value.clone())),
+            ExportKind::Source => {}
+        }
+    }
+    (variables, paths)
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(feature = "cli")]
+    use crate::{
+        ci::ci_install_opts,
+        cli::CiInstallOpts,
+        targets::{parse_targets, Target},
+    };
+    use crate::{
+        ci::{append_line, split_exports},
+        env::Export,
+    };
+    #[cfg(feature = "cli")]
+    use clap::Parser;
+    #[cfg(feature = "cli")]
+    use directories::BaseDirs;
+    use std::{fs::read_to_string, path::PathBuf};
+    use tempfile::TempDir;
+
+    #[cfg(feature = "cli")]
+    #[test]
+    fn test_ci_install_opts() {
+        let args = CiInstallOpts::parse_from([
+            "ci-install",
+            "--buildtargets",
+            "esp32,esp32c3",
+            "--version",
+            "1.82.0.3",
+            "--ldproxy",
+            "--default",
+            "false",
+            "--export",
+            "/home/runner/exports",
+        ]);
+        assert!(args.ldproxy && !args.default && !args.override_toolchain);
+        let opts = ci_install_opts(&args);
+        assert_eq!(opts.toolchain_version, Some("1.82.0.3".to_string()));
+        assert_eq!(
+            opts.targets,
+            [Target::ESP32, Target::ESP32C3].into_iter().collect()
+        );
+        assert_eq!(
+            opts.export_file,
+            Some(PathBuf::from("/home/runner/exports"))
+        );
+        assert!(opts.ldproxy);
+        assert_eq!(opts.name, "esp");
+
+        let opts = ci_install_opts(&CiInstallOpts::parse_from(["ci-install"]));
+        assert_eq!(opts.toolchain_version, None);
+        assert_eq!(opts.targets, parse_targets("all").unwrap());
+        let home_dir = BaseDirs::new().unwrap().home_dir().to_path_buf();
+        assert_eq!(
+            opts.export_file,
+            Some(home_dir.join(if cfg!(windows) {
+                "export-esp.ps1"
+            } else {
+                "export-esp.sh"
+            }))
+        );
+    }
+
+    #[test]
+    fn test_split_exports() {
+        let exports = vec![
+            Export::prepend_path(
+                "xtensa-gcc",
+                "/home/user/.rustup/toolchains/esp/xtensa-esp-elf/bin",
+            ),
+            Export::set("llvm", "LIBCLANG_PATH", "/home/user/.espup/esp-clang"),
+            Export::source("llvm", "/home/user/.espup/env.sh"),
+        ];
+        let (variables, paths) = split_exports(&exports);
+        assert_eq!(
+            variables,
+            vec![(
+                "LIBCLANG_PATH".to_string(),
+                "/home/user/.espup/esp-clang".to_string()
+            )]
+        );
+        assert_eq!(
+            paths,
+            vec![PathBuf::from(
+                "/home/user/.rustup/toolchains/esp/xtensa-esp-elf/bin"
+            )]
+        );
+    }
+
+    #[test]
+    fn test_append_line() {
+        let temp_dir = TempDir::new().unwrap();
+        let output = temp_dir.path().join("output");
+        append_line(&output, "toolchain-version=1.82.0.3").unwrap();
+        append_line(&output, "toolchain-path=/home/user/.rustup/toolchains/esp").unwrap();
+        assert_eq!(
+            read_to_string(output).unwrap(),
+            "toolchain-version=1.82.0.3\ntoolchain-path=/home/user/.rustup/toolchains/esp\n"
+        );
+    }
+}
@@ -1,70 +1,829 @@
 //! Command line interface.
+//!
+//! The options of the installer functions of the library, e.g. [`InstallOpts`], are always
+//! available, the others and the argument parsing require the `cli` feature.
 
-use crate::targets::{parse_targets, Target};
-use clap::Parser;
+#[cfg(windows)]
+use crate::env::EnvScope;
+use crate::{
+    ci::CiMode,
+    env::ExportShell,
+    report::OutputFormat,
+    target_dir::TargetDirStyle,
+    targets::Target,
+    toolchain::{
+        fetch::ProxyRoute,
+        overrides::ArtifactOverride,
+        rust::{Homes, XtensaComponent},
+        rustup::ConcurrentRustup,
+        ToolchainComponent,
+    },
+};
+#[cfg(feature = "cli")]
+use crate::{
+    targets::{parse_target, parse_targets},
+    toolchain::{
+        fetch::parse_proxy_route, overrides::parse_artifact_override, rust::parse_component,
+    },
+};
+#[cfg(feature = "cli")]
+use clap::{Parser, Subcommand};
+#[cfg(feature = "cli")]
 use clap_complete::Shell;
+use reqwest::Url;
 use std::{collections::HashSet, path::PathBuf};
 
+#[cfg(feature = "cli")]
+#[derive(Debug, Parser)]
+pub struct AuthOpts {
+    #[command(subcommand)]
+    pub subcommand: AuthSubcommand,
+}
+
+#[cfg(feature = "cli")]
+#[derive(Debug, Subcommand)]
+pub enum AuthSubcommand {
+    /// Removes the stored GitHub token, or the credentials of a mirror.
+    Clear(AuthClearOpts),
+    /// Stores the GitHub token used to query the GitHub API, read from stdin.
+    SetGithub(AuthSetGithubOpts),
+    /// Stores the credentials of a mirror, whose password is read from stdin.
+    SetMirror(AuthSetMirrorOpts),
+}
+
+#[cfg(feature = "cli")]
+#[derive(Debug, Parser)]
+pub struct AuthClearOpts {
+    /// Verbosity level of the logs.
+    #[arg(short = 'l', long, default_value = "info", value_parser = ["debug", "info", "warn", "error"])]
+    pub log_level: String,
+    /// Mirror whose credentials are removed, instead of the GitHub token.
+    #[arg(long, value_name = "URL")]
+    pub mirror: Option<Url>,
+}
+
+#[cfg(feature = "cli")]
+#[derive(Debug, Parser)]
+pub struct AuthSetGithubOpts {
+    /// Verbosity level of the logs.
+    #[arg(short = 'l', long, default_value = "info", value_parser = ["debug", "info", "warn", "error"])]
+    pub log_level: String,
+}
+
+#[cfg(feature = "cli")]
+#[derive(Debug, Parser)]
+pub struct AuthSetMirrorOpts {
+    /// Verbosity level of the logs.
+    #[arg(short = 'l', long, default_value = "info", value_parser = ["debug", "info", "warn", "error"])]
+    pub log_level: String,
+    /// Mirror, e.g. `https://mirror.example.com`. The credentials are sent to every URL of its scheme and host.
+    pub mirror: Url,
+    /// User name of the mirror.
+    #[arg(short = 'u', long)]
+    pub username: String,
+}
+
+/// Options of `espup ci-install`, the entry point of the `esp-rs/xtensa-toolchain` GitHub Action.
+///
+/// Unlike the other subcommands, these flags are frozen: the flags of an `--api-version` are never
+/// changed or removed, new flags are only added with a new API version.
+#[cfg(feature = "cli")]
+#[derive(Debug, Parser)]
+pub struct CiInstallOpts {
+    /// Version of the flag set, only `1` is available.
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..=1))]
+    pub api_version: u32,
+    /// Comma or space separated list of targets [esp32,esp32c2,esp32c3,esp32c6,esp32h2,esp32s2,esp32s3,esp32p4,esp32c61,all].
+    #[arg(long, default_value = "all", value_parser = parse_targets)]
+    pub buildtargets: HashSet<Target>,
+    /// Makes the installed toolchain the rustup default one.
+    #[arg(long, num_args = 0..=1, default_missing_value = "true", action = clap::ArgAction::Set, default_value_t = false)]
+    pub default: bool,
+    /// Path of the export file, `~/export-esp.sh` by default (`~/export-esp.ps1` on Windows).
+    #[arg(long)]
+    pub export: Option<PathBuf>,
+    /// Installs `ldproxy`, the linker of STD applications.
+    #[arg(long, num_args = 0..=1, default_missing_value = "true", action = clap::ArgAction::Set, default_value_t = false)]
+    pub ldproxy: bool,
+    /// Verbosity level of the logs.
+    #[arg(short = 'l', long, default_value = "info", value_parser = ["debug", "info", "warn", "error"])]
+    pub log_level: String,
+    /// Sets the installed toolchain as the rustup override of the current directory.
+    #[arg(long = "override", num_args = 0..=1, default_missing_value = "true", action = clap::ArgAction::Set, default_value_t = false)]
+    pub override_toolchain: bool,
+    /// Xtensa Rust toolchain version, or `latest`.
+    #[arg(long, default_value = "latest")]
+    pub version: String,
+}
+
+#[cfg(feature = "cli")]
 #[derive(Debug, Parser)]
 pub struct CompletionsOpts {
+    /// Installs the completions of every detected shell into its completion directory, instead of printing them.
+    #[arg(long, conflicts_with = "shell")]
+    pub all: bool,
     /// Verbosity level of the logs.
     #[arg(short = 'l', long, default_value = "info", value_parser = ["debug", "info", "warn", "error"])]
     pub log_level: String,
     /// Shell to generate completions for.
-    pub shell: Shell,
+    #[arg(required_unless_present = "all")]
+    pub shell: Option<Shell>,
+    /// Removes the completions installed with `--all`.
+    #[arg(long, requires = "all")]
+    pub uninstall: bool,
+}
+
+#[cfg(feature = "cli")]
+#[derive(Debug, Parser)]
+pub struct DaemonOpts {
+    /// Speaks JSON-RPC 2.0 over stdin and stdout, one message per line.
+    #[arg(long, required = true)]
+    pub stdio: bool,
+    /// Verbosity level of the logs, written to stderr.
+    #[arg(short = 'l', long, default_value = "info", value_parser = ["debug", "info", "warn", "error"])]
+    pub log_level: String,
+}
+
+#[derive(Debug)]
+#[cfg_attr(feature = "cli", derive(Parser))]
+pub struct DockerOpts {
+    /// Generates a `.devcontainer/devcontainer.json` file building the Dockerfile as well.
+    #[cfg_attr(feature = "cli", arg(long))]
+    pub devcontainer: bool,
+    /// Directory where the files are generated.
+    #[cfg_attr(feature = "cli", arg(short = 'D', long, default_value = "."))]
+    pub dir: PathBuf,
+    /// Overwrites the existing files.
+    #[cfg_attr(feature = "cli", arg(long))]
+    pub force: bool,
+    /// Verbosity level of the logs.
+    #[cfg_attr(feature = "cli", arg(short = 'l', long, default_value = "info", value_parser = ["debug", "info", "warn", "error"]))]
+    pub log_level: String,
+    /// Xtensa Rust toolchain name of the reproduced installation.
+    #[cfg_attr(feature = "cli", arg(short = 'a', long, default_value = "esp"))]
+    pub name: String,
+    /// Only install toolchains required for STD applications, even if the reproduced installation didn't.
+    #[cfg_attr(feature = "cli", arg(short = 's', long))]
+    pub std: bool,
+    /// Comma or space separated list of targets to install instead of the installed ones [esp32,esp32c2,esp32c3,esp32c6,esp32h2,esp32s2,esp32s3,esp32p4,esp32c61,all].
+    #[cfg_attr(feature = "cli", arg(short = 't', long, value_parser = parse_targets))]
+    pub targets: Option<HashSet<Target>>,
+}
+
+#[cfg(feature = "cli")]
+#[derive(Debug, Parser)]
+pub struct EnvOpts {
+    /// Output format.
+    #[arg(short = 'f', long, value_enum, default_value_t = OutputFormat::Text)]
+    pub format: OutputFormat,
+    /// Verbosity level of the logs.
+    #[arg(short = 'l', long, default_value = "info", value_parser = ["debug", "info", "warn", "error"])]
+    pub log_level: String,
+    /// Xtensa Rust toolchain name.
+    #[arg(short = 'a', long, default_value = "esp")]
+    pub name: String,
+    /// Prints a self-contained environment, e.g. for `env -i` or Nix derivations: `PATH` only contains the directories of the installation, including the Xtensa Rust binaries, instead of extending the current one.
+    #[arg(long)]
+    pub pure: bool,
+}
+
+#[derive(Debug)]
+#[cfg_attr(feature = "cli", derive(Parser))]
+pub struct FetchOpts {
+    /// Fetch Espressif RISC-V toolchain built with croostool-ng.
+    #[cfg_attr(feature = "cli", arg(short = 'r', long))]
+    pub esp_riscv_gcc: bool,
+    /// Fetch the whole LLVM instead of only the libs.
+    #[cfg_attr(feature = "cli", arg(short = 'e', long))]
+    pub extended_llvm: bool,
+    /// Target triple of a host whose artifacts are fetched. Can be repeated.
+    #[cfg_attr(feature = "cli", arg(long = "host", required = true, value_parser = ["x86_64-unknown-linux-gnu", "aarch64-unknown-linux-gnu", "x86_64-pc-windows-msvc", "x86_64-pc-windows-gnu" , "x86_64-apple-darwin" , "aarch64-apple-darwin"]))]
+    pub hosts: Vec<String>,
+    /// Maximum number of artifacts downloaded at once.
+    #[cfg_attr(feature = "cli", arg(short = 'j', long, default_value_t = 4, value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..)))]
+    pub jobs: usize,
+    /// Verbosity level of the logs.
+    #[cfg_attr(feature = "cli", arg(short = 'l', long, default_value = "info", value_parser = ["debug", "info", "warn", "error"]))]
+    pub log_level: String,
+    /// Logs plain text progress lines instead of drawing progress bars, which is the default when the output is not a terminal.
+    #[cfg_attr(feature = "cli", arg(long))]
+    pub no_progress: bool,
+    /// Directory where the artifacts are stored, one subdirectory per host.
+    #[cfg_attr(
+        feature = "cli",
+        arg(short = 'o', long, default_value = "espup-artifacts")
+    )]
+    pub output_dir: PathBuf,
+    /// Only fetch toolchains required for STD applications.
+    #[cfg_attr(feature = "cli", arg(short = 's', long))]
+    pub std: bool,
+    /// Comma or space separated list of targets [esp32,esp32c2,esp32c3,esp32c6,esp32h2,esp32s2,esp32s3,esp32p4,esp32c61,all].
+    ///
+    /// Chips can also be given by their product, shorthand or devkit names, e.g. `ESP32-S3`, `s3` or `esp32-s3-devkitc-1`.
+    #[cfg_attr(feature = "cli", arg(short = 't', long, default_value = "all", value_parser = parse_targets))]
+    pub targets: HashSet<Target>,
+    /// Xtensa Rust toolchain version, defaults to the latest one.
+    #[cfg_attr(feature = "cli", arg(short = 'v', long))]
+    pub toolchain_version: Option<String>,
+}
+
+#[cfg(feature = "cli")]
+#[derive(Debug, Parser)]
+pub struct GcOpts {
+    /// Only list the artifacts that would be removed.
+    #[arg(long)]
+    pub dry_run: bool,
+    /// Verbosity level of the logs.
+    #[arg(short = 'l', long, default_value = "info", value_parser = ["debug", "info", "warn", "error"])]
+    pub log_level: String,
+    /// Removes the artifacts without asking for confirmation.
+    #[arg(short = 'y', long)]
+    pub yes: bool,
 }
 
+#[cfg(feature = "cli")]
 #[derive(Debug, Parser)]
+pub struct GenerateOpts {
+    #[command(subcommand)]
+    pub subcommand: GenerateSubcommand,
+}
+
+#[cfg(feature = "cli")]
+#[derive(Debug, Subcommand)]
+pub enum GenerateSubcommand {
+    /// Generates a Dockerfile reproducing an installation, with the versions pinned.
+    Docker(DockerOpts),
+}
+
+#[derive(Debug)]
+#[cfg_attr(feature = "cli", derive(Parser))]
 pub struct InstallOpts {
+    /// Installs the artifacts of a `--default-host` that doesn't match the running system.
+    #[cfg_attr(feature = "cli", arg(long, requires = "default_host"))]
+    pub allow_foreign_host: bool,
+    /// Installs the artifacts prefetched with `espup fetch` into this directory instead of downloading them.
+    ///
+    /// Use it with `--toolchain-version` and `--skip-version-parse` to install without network access.
+    #[cfg_attr(feature = "cli", arg(long, env = "ESPUP_ARTIFACTS_DIR"))]
+    pub artifacts_dir: Option<PathBuf>,
+    /// Target triple whose GCC and LLVM artifacts are installed, instead of the host ones.
+    ///
+    /// Useful on hosts able to run binaries of another architecture (e.g. aarch64 Linux with x86_64 emulation). This combination is not supported.
+    #[cfg_attr(feature = "cli", arg(long, value_parser = ["x86_64-unknown-linux-gnu", "aarch64-unknown-linux-gnu", "x86_64-pc-windows-msvc", "x86_64-pc-windows-gnu" , "x86_64-apple-darwin" , "aarch64-apple-darwin"]))]
+    pub artifact_arch: Option<String>,
+    /// Downloads the assets redirected to other hosts, e.g. the GitHub release assets host, from this mirror instead.
+    ///
+    /// The path and query of the redirects are kept, e.g. `https://mirror.example.com` downloads `https://objects.githubusercontent.com/<path>` from `https://mirror.example.com/<path>`.
+    #[cfg_attr(
+        feature = "cli",
+        arg(long, value_name = "URL", env = "ESPUP_ASSET_HOST_OVERRIDE")
+    )]
+    pub asset_host_override: Option<Url>,
+    /// Continuous Integration output mode.
+    ///
+    /// `github` groups the logs per component, annotates failures, sets the step outputs and exports the environment variables to the following steps.
+    #[cfg_attr(feature = "cli", arg(long, value_enum))]
+    pub ci: Option<CiMode>,
+    /// Generates an `export-esp.bat` file next to the export file and runs it in every new `cmd.exe` session, through the `AutoRun` registry value of the current user.
+    ///
+    /// The `AutoRun` command is removed on uninstallation.
+    #[cfg(windows)]
+    #[cfg_attr(feature = "cli", arg(long, conflicts_with_all = ["no_modify_env", "sandbox"]))]
+    pub cmd_autorun: bool,
+    /// Selects the newest versions known to work with an ESP-IDF release line, e.g. `5.2`.
+    ///
+    /// The resolved Xtensa Rust, LLVM and GCC versions are printed before installing them. The Xtensa Rust version is never replaced by an older one lacking the host artifacts, as with `--no-fallback`.
+    #[cfg_attr(
+        feature = "cli",
+        arg(
+            long,
+            value_name = "ESP_IDF_VERSION",
+            conflicts_with = "toolchain_version"
+        )
+    )]
+    pub compat: Option<String>,
+    /// Behavior when other rustup processes are running while installing the RISC-V targets, as concurrent rustup invocations may corrupt its download cache.
+    #[cfg_attr(feature = "cli", arg(long, value_enum, default_value_t = ConcurrentRustup::Wait))]
+    pub concurrent_rustup: ConcurrentRustup,
     /// Target triple of the host.
-    #[arg(short = 'd', long, value_parser = ["x86_64-unknown-linux-gnu", "aarch64-unknown-linux-gnu", "x86_64-pc-windows-msvc", "x86_64-pc-windows-gnu" , "x86_64-apple-darwin" , "aarch64-apple-darwin"])]
+    #[cfg_attr(feature = "cli", arg(short = 'd', long, value_parser = ["x86_64-unknown-linux-gnu", "aarch64-unknown-linux-gnu", "x86_64-pc-windows-msvc", "x86_64-pc-windows-gnu" , "x86_64-apple-darwin" , "aarch64-apple-darwin", "riscv64gc-unknown-linux-gnu", "powerpc64le-unknown-linux-gnu"]))]
     pub default_host: Option<String>,
     /// Install Espressif RISC-V toolchain built with croostool-ng
     ///
     /// Only install this if you don't want to use the systems RISC-V toolchain
-    #[arg(short = 'r', long)]
+    #[cfg_attr(feature = "cli", arg(short = 'r', long))]
     pub esp_riscv_gcc: bool,
+    /// Scope of the environment variables changes.
+    ///
+    /// `user` persists the variables in the registry, `process` only sets them for the current process and the export file, `none` skips environment modification entirely.
+    #[cfg(windows)]
+    #[cfg_attr(feature = "cli", arg(long, value_enum, default_value_t = EnvScope::User))]
+    pub env_scope: EnvScope,
+    /// Detects the chip of the connected device with `espflash board-info` and only installs its toolchains, after confirmation.
+    ///
+    /// Requires `espflash` (`cargo install espflash --locked`). The serial port can be selected with `ESPFLASH_PORT`.
+    #[cfg_attr(feature = "cli", arg(long, conflicts_with = "targets"))]
+    pub detect_device: bool,
+    /// Prints the installed and target versions and the download size of every component, without installing anything.
+    #[cfg_attr(feature = "cli", arg(long))]
+    pub diff_only: bool,
     /// Relative or full path for the export file that will be generated. If no path is provided, the file will be generated under home directory (https://docs.rs/dirs/latest/dirs/fn.home_dir.html).
-    #[arg(short = 'f', long, env = "ESPUP_EXPORT_FILE")]
+    #[cfg_attr(feature = "cli", arg(short = 'f', long, env = "ESPUP_EXPORT_FILE"))]
     pub export_file: Option<PathBuf>,
+    /// Comma separated list of additional shells to generate export files for, next to the export file and named after it, e.g. `export-esp.fish`.
+    #[cfg_attr(feature = "cli", arg(long, value_delimiter = ','))]
+    pub export_shells: Vec<ExportShell>,
+    /// Allows using a toolchain name that collides with rustup channels or existing toolchains.
+    #[cfg_attr(feature = "cli", arg(long))]
+    pub force: bool,
+    /// Reuses the GCC toolchains installed by ESP-IDF (in `$IDF_TOOLS_PATH`, `~/.espressif` by default) instead of downloading them.
+    #[cfg_attr(feature = "cli", arg(long, env = "ESPUP_FROM_ESPIDF"))]
+    pub from_espidf: bool,
+    /// Rustup and Cargo homes, set from the global `--rustup-home` and `--cargo-home` options.
+    #[cfg_attr(feature = "cli", arg(skip))]
+    pub homes: Homes,
+    /// Maximum number of archives extracted at once, downloads always run in parallel.
+    ///
+    /// Defaults to 1 on rotational disks and hosts with two CPUs or less, where concurrent extractions are slower.
+    #[cfg_attr(feature = "cli", arg(long, value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..)))]
+    pub io_parallelism: Option<usize>,
+    /// Maximum number of components installed at once, all of them by default.
+    ///
+    /// With `--ci github`, components are installed one at a time so each has its own log group.
+    #[cfg_attr(feature = "cli", arg(short = 'j', long, value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..)))]
+    pub jobs: Option<usize>,
+    /// Allows selecting experimental targets (e.g. `esp32c61`), whose toolchain support may be incomplete.
+    #[cfg_attr(feature = "cli", arg(long))]
+    pub experimental_targets: bool,
     /// Extends the LLVM installation.
     ///
     /// This will install the whole LLVM instead of only installing the libs.
-    #[arg(short = 'e', long)]
+    #[cfg_attr(feature = "cli", arg(short = 'e', long))]
     pub extended_llvm: bool,
+    /// Installs `ldproxy`, the linker of STD applications, and prints the `.cargo/config.toml` linker configuration of the installed targets.
+    #[cfg_attr(
+        feature = "cli",
+        arg(long, requires = "std", conflicts_with = "sandbox")
+    )]
+    pub ldproxy: bool,
+    /// Only installs the LLVM libraries into the given directory, for projects bringing their own clang that only need Espressif's libclang to run bindgen on Xtensa targets.
+    ///
+    /// The libraries are extracted into a `xtensa-esp32-elf-clang` subdirectory, leaving the other files of the directory untouched. Nothing else is installed and only `LIBCLANG_PATH` is exported, merged into the file given by `--export-file` or printed otherwise.
+    #[cfg_attr(
+        feature = "cli",
+        arg(
+            long,
+            value_name = "DIR",
+            conflicts_with_all = ["extended_llvm", "ldproxy", "verify_build", "no_rustup", "diff_only"]
+        )
+    )]
+    pub llvm_only_libs_path: Option<PathBuf>,
     /// Verbosity level of the logs.
-    #[arg(short = 'l', long, default_value = "info", value_parser = ["debug", "info", "warn", "error"])]
+    #[cfg_attr(feature = "cli", arg(short = 'l', long, default_value = "info", value_parser = ["debug", "info", "warn", "error"]))]
     pub log_level: String,
+    /// Mirror serving the artifacts at the same path as their original host, e.g. `https://mirror.example.com` serves `https://github.com/<path>` at `https://mirror.example.com/<path>`.
+    ///
+    /// Each artifact is downloaded from the fastest of its original host and the mirrors, falling back to the others when it fails or stalls. Can be repeated, or given as a comma separated list in `ESPUP_MIRRORS`.
+    #[cfg_attr(
+        feature = "cli",
+        arg(
+            long = "mirror",
+            value_name = "URL",
+            env = "ESPUP_MIRRORS",
+            value_delimiter = ','
+        )
+    )]
+    pub mirrors: Vec<Url>,
     /// Xtensa Rust toolchain name.
-    #[arg(short = 'a', long, default_value = "esp")]
+    #[cfg_attr(feature = "cli", arg(short = 'a', long, default_value = "esp"))]
     pub name: String,
     /// Nightly Rust toolchain version.
     ///
     /// Note that only RISC-V targets use nightly Rust channel.
-    #[arg(short = 'n', long, default_value = "nightly")]
+    #[cfg_attr(feature = "cli", arg(short = 'n', long, default_value = "nightly"))]
     pub nightly_version: String,
+    /// Skips the ad-hoc signing (`codesign -s -`) of the unsigned binaries extracted on macOS, which the kernel kills on Apple Silicon.
+    #[cfg_attr(feature = "cli", arg(long, env = "ESPUP_NO_CODESIGN"))]
+    pub no_codesign: bool,
+    /// Fails when the Xtensa Rust version is not published for the host, instead of installing the nearest older version that is. Implied by `--compat`.
+    #[cfg_attr(feature = "cli", arg(long))]
+    pub no_fallback: bool,
+    /// Logs plain text progress lines instead of drawing progress bars, which is the default when the output is not a terminal.
+    #[cfg_attr(feature = "cli", arg(long))]
+    pub no_progress: bool,
+    /// Skips the PowerShell export script, writing an `export-esp.bat` file for `cmd.exe` instead, for hosts where running `.ps1` scripts is blocked.
+    ///
+    /// The user environment variables are still written to the registry, unless `--env-scope` says otherwise.
+    #[cfg(windows)]
+    #[cfg_attr(feature = "cli", arg(long))]
+    pub no_ps1: bool,
+    /// Installs without rustup, into the directory given by `--prefix`.
+    ///
+    /// Rust is not required to be installed and RISC-V targets, which are installed through rustup, are skipped. The exports point `PATH` and `RUSTC` to the binaries of the Xtensa Rust toolchain.
+    #[cfg_attr(
+        feature = "cli",
+        arg(long, requires = "prefix", conflicts_with = "verify_build")
+    )]
+    pub no_rustup: bool,
+    /// Do not modify the environment.
+    ///
+    /// The export file won't be generated (and, on Windows, the user environment variables won't be updated); the required environment variables are printed instead.
+    #[cfg_attr(feature = "cli", arg(long))]
+    pub no_modify_env: bool,
+    /// Installs a local archive instead of downloading an artifact, e.g. `llvm=/path/to/clang.tar.xz` for a locally built LLVM. Can be repeated.
+    ///
+    /// The artifacts are `xtensa-rust`, `rust-src`, `llvm`, `llvm-full` (extended LLVM), `xtensa-gcc` and `riscv-gcc`. Their components are reinstalled even if already installed.
+    #[cfg_attr(feature = "cli", arg(long, value_name = "ARTIFACT=PATH", value_parser = parse_artifact_override))]
+    pub override_artifact: Vec<ArtifactOverride>,
+    /// Leaves the permissions of the extracted toolchains and export files as created.
+    ///
+    /// By default, directories and executables get `755` and other files `644`, whatever the umask and the archive contents.
+    #[cfg_attr(feature = "cli", arg(long, env = "ESPUP_PERMISSIVE"))]
+    pub permissive: bool,
+    /// Prints the directory to exclude from antivirus real-time scanning and exits without installing anything.
+    ///
+    /// Antiviruses may quarantine the extracted toolchains or slow down their extraction.
+    #[cfg_attr(feature = "cli", arg(long))]
+    pub print_av_exclusion: bool,
+    /// Directory where the toolchains are installed with `--no-rustup`.
+    ///
+    /// It must be new or empty, or a prefix of a previous installation: espup only replaces and removes directories it created.
+    #[cfg_attr(feature = "cli", arg(long, requires = "no_rustup"))]
+    pub prefix: Option<PathBuf>,
+    /// Writes the download progress as JSON lines on stderr, for the processes driving espup, e.g. the daemon.
+    #[cfg_attr(feature = "cli", arg(long, hide = true, env = "ESPUP_PROGRESS_EVENTS"))]
+    pub progress_events: bool,
+    /// Proxy of the requests to a host and its subdomains, overriding the global proxy, e.g. `github.com=http://proxy:3128` or `dl.espressif.com=direct` to connect directly.
+    ///
+    /// Can be repeated, or given as a comma separated list in `ESPUP_PROXY_FOR`.
+    #[cfg_attr(feature = "cli", arg(long, value_name = "HOST=URL", env = "ESPUP_PROXY_FOR", value_delimiter = ',', value_parser = parse_proxy_route))]
+    pub proxy_for: Vec<ProxyRoute>,
+    /// Suppresses all logs, progress bars and instructions.
+    #[cfg_attr(feature = "cli", arg(short = 'q', long))]
+    pub quiet: bool,
+    /// Records the SHA-256 hashes of the installed files, so `espup verify` can detect modified toolchains.
+    ///
+    /// Once recorded, the hashes are updated by the following installations of the toolchain.
+    #[cfg_attr(feature = "cli", arg(long))]
+    pub record_hashes: bool,
+    /// Fails before doing any work if the installation would write outside the prefix, the export files and the directories set with `ESPUP_DATA_DIR` and `ESPUP_CACHE_DIR`, e.g. for Nix or sandboxed CI.
+    #[cfg_attr(feature = "cli", arg(long, requires = "no_rustup"))]
+    pub sandbox: bool,
+    /// Additional rustup components installed in the RISC-V toolchain (e.g. `clippy,rustfmt`).
+    ///
+    /// `rust-src` is always installed.
+    #[cfg_attr(feature = "cli", arg(long, value_delimiter = ',', value_parser = parse_component))]
+    pub riscv_components: Vec<String>,
+    /// Rustup profile of the RISC-V toolchain.
+    #[cfg_attr(feature = "cli", arg(long, default_value = "minimal", value_parser = ["minimal", "default", "complete"]))]
+    pub riscv_profile: String,
+    /// Overwrites the whole export file instead of only replacing the block managed by espup.
+    #[cfg_attr(feature = "cli", arg(long, conflicts_with = "reuse_exports"))]
+    pub regenerate_exports: bool,
+    /// Number of retries of the GitHub API queries.
+    ///
+    /// Rate limited queries are retried once the limit resets, if that happens within a minute.
+    #[cfg_attr(feature = "cli", arg(long, default_value_t = 5))]
+    pub retries: usize,
+    /// Leaves an existing export file untouched.
+    #[cfg_attr(feature = "cli", arg(long))]
+    pub reuse_exports: bool,
     /// Skips parsing Xtensa Rust version.
-    #[arg(short = 'k', long, requires = "toolchain_version")]
+    #[cfg_attr(
+        feature = "cli",
+        arg(short = 'k', long, requires = "toolchain_version")
+    )]
     pub skip_version_parse: bool,
     /// Only install toolchains required for STD applications.
     ///
     /// With this option, espup will skip GCC installation (it will be handled by esp-idf-sys), hence you won't be able to build no_std applications.
-    #[arg(short = 's', long)]
+    #[cfg_attr(feature = "cli", arg(short = 's', long))]
     pub std: bool,
-    /// Comma or space separated list of targets [esp32,esp32c2,esp32c3,esp32c6,esp32h2,esp32s2,esp32s3,esp32p4,all].
-    #[arg(short = 't', long, default_value = "all", value_parser = parse_targets)]
+    /// Only prints one line per installed component with its status, version and path, for wrapper scripts.
+    ///
+    /// Implies `--quiet`. Lines look like `component=xtensa-rust status=installed version=1.85.0.0 path=...`.
+    #[cfg_attr(feature = "cli", arg(long))]
+    pub summary_only: bool,
+    /// Format of the summary printed with `--summary-only`.
+    ///
+    /// The JSON summary also includes the timings of each installation phase (resolve, download, verify, extract, configure), the downloaded bytes, the prefetched artifacts used and the retries of each component, e.g. for CI dashboards.
+    #[cfg_attr(feature = "cli", arg(long, value_enum, default_value_t = OutputFormat::Text, requires = "summary_only"))]
+    pub summary_format: OutputFormat,
+    /// Uses the proxy configured in the system settings when no proxy environment variable is set.
+    ///
+    /// Reads the Internet Settings on Windows and `scutil --proxy` on macOS.
+    #[cfg_attr(feature = "cli", arg(long, env = "ESPUP_SYSTEM_PROXY"))]
+    pub system_proxy: bool,
+    /// Location of the Cargo target directory.
+    ///
+    /// `short` sets `CARGO_TARGET_DIR` to `C:\t` in the environment to avoid the long path build failures of `esp-idf-sys` on Windows.
+    #[cfg_attr(feature = "cli", arg(long, value_enum, default_value_t = TargetDirStyle::Default))]
+    pub target_dir_style: TargetDirStyle,
+    /// Comma or space separated list of targets [esp32,esp32c2,esp32c3,esp32c6,esp32h2,esp32s2,esp32s3,esp32p4,esp32c61,all].
+    #[cfg_attr(feature = "cli", arg(short = 't', long, default_value = "all", value_parser = parse_targets))]
     pub targets: HashSet<Target>,
+    /// Timeout of the GitHub API queries, in seconds.
+    #[cfg_attr(feature = "cli", arg(long, default_value_t = 30))]
+    pub timeout: u64,
+    /// Directory where the Xtensa Rust archives are downloaded and installed from, `TMPDIR` by default, or the rustup `tmp` directory when it isn't set.
+    ///
+    /// With `--sandbox`, `TMPDIR` is ignored and the temporary directories are created in the prefix unless this directory is given, which is then writable. The temporary directory is removed once the installation completes or fails, and the ones left by crashed runs are removed after a day.
+    #[cfg_attr(feature = "cli", arg(long, value_name = "DIR"))]
+    pub tmp_dir: Option<PathBuf>,
     /// Xtensa Rust toolchain version.
-    #[arg(short = 'v', long)]
+    #[cfg_attr(feature = "cli", arg(short = 'v', long))]
     pub toolchain_version: Option<String>,
+    /// Reads the Xtensa Rust toolchain version from a file.
+    ///
+    /// Either a `.xtensa-rust-version` file, whose first line is the version, or a `rust-toolchain.toml` file with a `toolchain-version` key in its `[espup]` section. Without this option, `espup install` uses the closest of these files found in the current directory or its parents, when no version is given.
+    #[cfg_attr(feature = "cli", arg(long, value_name = "PATH", conflicts_with_all = ["toolchain_version", "compat"]))]
+    pub toolchain_version_file: Option<PathBuf>,
+    /// Verifies the installation by building a small test crate for one of the installed targets.
+    ///
+    /// This exercises the Rust toolchain, LLVM and GCC, and reports which stage fails, if any.
+    #[cfg_attr(feature = "cli", arg(long))]
+    pub verify_build: bool,
+    /// Comma separated list of the optional components of the Xtensa Rust toolchain to install, e.g. `clippy,rustfmt`.
+    ///
+    /// Without this option, all the optional components shipped with the Xtensa Rust version are installed. With an empty value, none of them are.
+    #[cfg_attr(
+        feature = "cli",
+        arg(long, value_enum, value_delimiter = ',', num_args = 0..)
+    )]
+    pub xtensa_components: Option<Vec<XtensaComponent>>,
+}
+
+#[cfg(feature = "cli")]
+#[derive(Debug, Parser)]
+pub struct LlvmOpts {
+    #[command(subcommand)]
+    pub subcommand: LlvmSubcommand,
+}
+
+#[cfg(feature = "cli")]
+#[derive(Debug, Subcommand)]
+pub enum LlvmSubcommand {
+    /// Points `LIBCLANG_PATH` and the `esp-clang` symlink to another installed LLVM version, e.g.
+    /// one of another toolchain.
+    Use(LlvmUseOpts),
 }
 
+#[cfg(feature = "cli")]
 #[derive(Debug, Parser)]
+pub struct LlvmUseOpts {
+    /// Verbosity level of the logs.
+    #[arg(short = 'l', long, default_value = "info", value_parser = ["debug", "info", "warn", "error"])]
+    pub log_level: String,
+    /// Xtensa Rust toolchain name.
+    #[arg(short = 'a', long, default_value = "esp")]
+    pub name: String,
+    /// LLVM version, e.g. `18` or `esp-18.1.2_20240912`.
+    pub version: String,
+}
+
+#[cfg(feature = "cli")]
+#[derive(Debug, Parser)]
+pub struct LogsOpts {
+    /// Prints the log of the last run instead of listing the log files.
+    #[arg(long)]
+    pub last: bool,
+    /// Verbosity level of the logs.
+    #[arg(short = 'l', long, default_value = "info", value_parser = ["debug", "info", "warn", "error"])]
+    pub log_level: String,
+}
+
+#[cfg(feature = "cli")]
+#[derive(Debug, Parser)]
+pub struct MatrixOpts {
+    /// Output format.
+    #[arg(short = 'f', long, value_enum, default_value_t = OutputFormat::Text)]
+    pub format: OutputFormat,
+    /// Verbosity level of the logs.
+    #[arg(short = 'l', long, default_value = "info", value_parser = ["debug", "info", "warn", "error"])]
+    pub log_level: String,
+}
+
+#[cfg(feature = "cli")]
+#[derive(Debug, Parser)]
+pub struct PinOpts {
+    /// Project directory where the `rust-toolchain.toml` file is written.
+    #[arg(short = 'D', long, default_value = ".")]
+    pub dir: PathBuf,
+    /// Sets `ldproxy` as the linker of the STD targets in the `.cargo/config.toml` file of the project, installing it if needed.
+    #[arg(long)]
+    pub ldproxy: bool,
+    /// Verbosity level of the logs.
+    #[arg(short = 'l', long, default_value = "info", value_parser = ["debug", "info", "warn", "error"])]
+    pub log_level: String,
+    /// Xtensa Rust toolchain name.
+    #[arg(short = 'a', long, default_value = "esp")]
+    pub name: String,
+    /// Location of the Cargo target directory of the project.
+    ///
+    /// `short` sets `build.target-dir` to `C:\t` in `.cargo/config.toml` to avoid the long path build failures of `esp-idf-sys` on Windows.
+    #[arg(long, value_enum, default_value_t = TargetDirStyle::Default)]
+    pub target_dir_style: TargetDirStyle,
+    /// Comma or space separated list of targets the project builds for, defaults to the installed targets [esp32,esp32c2,esp32c3,esp32c6,esp32h2,esp32s2,esp32s3,esp32p4,esp32c61,all].
+    #[arg(short = 't', long, value_parser = parse_targets)]
+    pub targets: Option<HashSet<Target>>,
+}
+
+#[cfg(feature = "cli")]
+#[derive(Debug, Parser)]
+pub struct PreflightOpts {
+    /// Output format.
+    #[arg(short = 'f', long, value_enum, default_value_t = OutputFormat::Text)]
+    pub format: OutputFormat,
+    /// Target triple of a host whose artifacts are checked, every host by default. Can be repeated.
+    #[arg(long = "host", value_parser = ["x86_64-unknown-linux-gnu", "aarch64-unknown-linux-gnu", "x86_64-pc-windows-msvc", "x86_64-pc-windows-gnu" , "x86_64-apple-darwin" , "aarch64-apple-darwin"])]
+    pub hosts: Vec<String>,
+    /// Verbosity level of the logs.
+    #[arg(short = 'l', long, default_value = "info", value_parser = ["debug", "info", "warn", "error"])]
+    pub log_level: String,
+    /// Xtensa Rust toolchain version of the release.
+    #[arg(short = 'v', long)]
+    pub toolchain_version: String,
+}
+
+#[cfg(feature = "cli")]
+#[derive(Debug, Parser)]
+pub struct ReinstallOpts {
+    #[command(subcommand)]
+    pub subcommand: ReinstallSubcommand,
+}
+
+#[cfg(feature = "cli")]
+#[derive(Debug, Subcommand)]
+pub enum ReinstallSubcommand {
+    /// Reinstalls the `rust-src` component of the Xtensa Rust toolchain, e.g. to repair it.
+    RustSrc(ReinstallComponentOpts),
+}
+
+#[cfg(feature = "cli")]
+#[derive(Debug, Parser)]
+pub struct ReinstallComponentOpts {
+    /// Verbosity level of the logs.
+    #[arg(short = 'l', long, default_value = "info", value_parser = ["debug", "info", "warn", "error"])]
+    pub log_level: String,
+    /// Xtensa Rust toolchain name.
+    #[arg(short = 'a', long, default_value = "esp")]
+    pub name: String,
+}
+
+#[cfg(feature = "cli")]
+#[derive(Debug, Parser)]
+pub struct SchemaOpts {
+    /// Verbosity level of the logs.
+    #[arg(short = 'l', long, default_value = "info", value_parser = ["debug", "info", "warn", "error"])]
+    pub log_level: String,
+}
+
+#[cfg(feature = "serve")]
+#[derive(Debug, Parser)]
+pub struct ServeOpts {
+    /// Verbosity level of the logs.
+    #[arg(short = 'l', long, default_value = "info", value_parser = ["debug", "info", "warn", "error"])]
+    pub log_level: String,
+    /// Port of the page on `127.0.0.1`, `0` selecting a free one.
+    #[arg(short = 'p', long, default_value_t = 8177)]
+    pub port: u16,
+}
+
+#[cfg(feature = "cli")]
+#[derive(Debug, Parser)]
+pub struct VerifyOpts {
+    /// Output format.
+    #[arg(short = 'f', long, value_enum, default_value_t = OutputFormat::Text)]
+    pub format: OutputFormat,
+    /// Verbosity level of the logs.
+    #[arg(short = 'l', long, default_value = "info", value_parser = ["debug", "info", "warn", "error"])]
+    pub log_level: String,
+    /// Xtensa Rust toolchain name.
+    #[arg(short = 'a', long, default_value = "esp")]
+    pub name: String,
+}
+
+#[cfg(feature = "cli")]
+#[derive(Debug, Parser)]
+pub struct VersionOpts {
+    /// Also queries the latest available versions of espup and of the toolchains.
+    #[arg(long)]
+    pub check: bool,
+    /// Output format.
+    #[arg(short = 'f', long, value_enum, default_value_t = OutputFormat::Text)]
+    pub format: OutputFormat,
+    /// Verbosity level of the logs.
+    #[arg(short = 'l', long, default_value = "info", value_parser = ["debug", "info", "warn", "error"])]
+    pub log_level: String,
+}
+
+#[cfg(feature = "cli")]
+#[derive(Debug, Parser)]
+pub struct ToolchainOpts {
+    #[command(subcommand)]
+    pub subcommand: ToolchainSubcommand,
+}
+
+#[cfg(feature = "cli")]
+#[derive(Debug, Subcommand)]
+pub enum ToolchainSubcommand {
+    /// Copies an installed toolchain under a new name, with its own export file.
+    Clone(CloneOpts),
+}
+
+#[cfg(feature = "cli")]
+#[derive(Debug, Parser)]
+pub struct CloneOpts {
+    /// Export file of the copy, `export-<NAME>` next to the export file of the original by default.
+    #[arg(short = 'f', long)]
+    pub export_file: Option<PathBuf>,
+    /// Allows names colliding with rustup channels.
+    #[arg(long)]
+    pub force: bool,
+    /// Hard links the files instead of copying them, when possible.
+    ///
+    /// Saves disk space, but modifying the files in place modifies both toolchains.
+    #[arg(long)]
+    pub hardlink: bool,
+    /// Verbosity level of the logs.
+    #[arg(short = 'l', long, default_value = "info", value_parser = ["debug", "info", "warn", "error"])]
+    pub log_level: String,
+    /// Name of the installed toolchain to copy.
+    pub source: String,
+    /// Name of the copy.
+    pub name: String,
+}
+
+#[derive(Debug)]
+#[cfg_attr(feature = "cli", derive(Parser))]
 pub struct UninstallOpts {
+    /// Comma or space separated list of components to remove, keeping the rest of the installation.
+    #[cfg_attr(feature = "cli", arg(short = 'c', long, value_delimiter = ',', num_args = 1.., conflicts_with = "targets"))]
+    pub components: Option<Vec<ToolchainComponent>>,
+    /// Keeps the export files, whose block managed by espup is removed by default.
+    #[cfg_attr(feature = "cli", arg(long, alias = "keep-export-file"))]
+    pub except_exports: bool,
+    /// Export file to clean up, in addition to the ones recorded in the installation manifest. Defaults to the default export file for installations without manifest.
+    #[cfg_attr(feature = "cli", arg(short = 'f', long, env = "ESPUP_EXPORT_FILE"))]
+    pub export_file: Option<PathBuf>,
+    /// Allows uninstalling a toolchain whose name collides with rustup channels or toolchains not installed by espup.
+    #[cfg_attr(feature = "cli", arg(long))]
+    pub force: bool,
+    /// Verbosity level of the logs.
+    #[cfg_attr(feature = "cli", arg(short = 'l', long, default_value = "info", value_parser = ["debug", "info", "warn", "error"]))]
+    pub log_level: String,
+    /// Xtensa Rust toolchain name.
+    #[cfg_attr(feature = "cli", arg(short = 'a', long, default_value = "esp"))]
+    pub name: String,
+    /// Schedules the removal of files that are in use on the next reboot. Requires administrator privileges.
+    #[cfg(windows)]
+    #[cfg_attr(feature = "cli", arg(long))]
+    pub remove_on_reboot: bool,
+    /// Comma or space separated list of targets to remove, keeping the rest of the installation [esp32,esp32c2,esp32c3,esp32c6,esp32h2,esp32s2,esp32s3,esp32p4,esp32c61,all].
+    #[cfg_attr(feature = "cli", arg(short = 't', long, value_parser = parse_targets))]
+    pub targets: Option<HashSet<Target>>,
+}
+
+#[cfg(feature = "cli")]
+#[derive(Debug, Parser)]
+pub struct TargetsOpts {
+    #[command(subcommand)]
+    pub subcommand: TargetsSubcommand,
+}
+
+#[cfg(feature = "cli")]
+#[derive(Debug, Subcommand)]
+pub enum TargetsSubcommand {
+    /// Adds targets to an existing installation.
+    Add(ModifyTargetsOpts),
+    /// Removes targets from an existing installation.
+    Remove(ModifyTargetsOpts),
+}
+
+#[cfg(feature = "cli")]
+#[derive(Debug, Parser)]
+pub struct ModifyTargetsOpts {
+    /// Verbosity level of the logs.
+    #[arg(short = 'l', long, default_value = "info", value_parser = ["debug", "info", "warn", "error"])]
+    pub log_level: String,
+    /// Xtensa Rust toolchain name.
+    #[arg(short = 'a', long, default_value = "esp")]
+    pub name: String,
+    /// Comma or space separated list of targets [esp32,esp32c2,esp32c3,esp32c6,esp32h2,esp32s2,esp32s3,esp32p4,esp32c61,all].
+    #[arg(value_parser = parse_targets)]
+    pub targets: HashSet<Target>,
+}
+
+#[cfg(feature = "cli")]
+#[derive(Debug, Parser)]
+pub struct WhichToolchainOpts {
     /// Verbosity level of the logs.
     #[arg(short = 'l', long, default_value = "info", value_parser = ["debug", "info", "warn", "error"])]
     pub log_level: String,
     /// Xtensa Rust toolchain name.
     #[arg(short = 'a', long, default_value = "esp")]
     pub name: String,
+    /// Target chip [esp32,esp32c2,esp32c3,esp32c6,esp32h2,esp32s2,esp32s3,esp32p4,esp32c61].
+    #[arg(value_parser = parse_target)]
+    pub target: Target,
 }
@@ -0,0 +1,253 @@
+//! Installation of the shell completions.
+//!
+//! Completion scripts are written into the directories each shell loads them from on demand,
+//! except for PowerShell, which has no such directory and sources the script from its profile.
+
+use crate::{env::get_espup_dir, error::Error, sandbox::write_checked};
+use clap::Command;
+use clap_complete::Shell;
+use directories::BaseDirs;
+use log::{debug, info, warn};
+use std::{
+    env,
+    fs::{read_to_string, remove_file},
+    path::{Path, PathBuf},
+};
+
+/// Name of the binary the completions are generated for.
+const BIN_NAME: &str = "espup";
+
+/// Environment of the user the completion locations are derived from.
+#[derive(Debug, Clone)]
+pub struct CompletionEnv {
+    /// Shell of the current session, if known.
+    pub current_shell: Option<Shell>,
+    /// Home directory of the user.
+    pub home: PathBuf,
+    /// Base directory of the user data files (`XDG_DATA_HOME`).
+    pub data_home: PathBuf,
+    /// Base directory of the user configuration files (`XDG_CONFIG_HOME`).
+    pub config_home: PathBuf,
+    /// Directory where the scripts sourced from a profile are written.
+    pub espup_dir: PathBuf,
+    /// Directory of the zsh configuration files (`ZDOTDIR`).
+    pub zdotdir: PathBuf,
+}
+
+impl CompletionEnv {
+    /// Returns the environment of the current user.
+    pub fn from_env() -> Self {
+        let home = BaseDirs::new().unwrap().home_dir().to_path_buf();
+        // Shells follow the XDG conventions on every Unix, including macOS
+        let xdg_dir = |var: &str, default: &str| {
+            env::var_os(var)
+                .map(PathBuf::from)
+                .filter(|dir| dir.is_absolute())
+                .unwrap_or_else(|| home.join(default))
+        };
+        Self {
+            current_shell: Shell::from_env(),
+            data_home: xdg_dir("XDG_DATA_HOME", ".local/share"),
+            config_home: xdg_dir("XDG_CONFIG_HOME", ".config"),
+            espup_dir: get_espup_dir(),
+            zdotdir: env::var_os("ZDOTDIR")
+                .map(PathBuf::from)
+                .unwrap_or_else(|| home.clone()),
+            home,
+        }
+    }
+
+    /// Returns the path of the completion script of the shell, if supported.
+    pub fn script_path(&self, shell: Shell) -> Option<PathBuf> {
+        match shell {
+            Shell::Bash => Some(
+                self.data_home
+                    .join("bash-completion/completions")
+                    .join(BIN_NAME),
+            ),
+            Shell::Fish => Some(
+                self.config_home
+                    .join("fish/completions")
+                    .join(format!("{BIN_NAME}.fish")),
+            ),
+            Shell::Zsh => Some(self.zdotdir.join(".zfunc").join(format!("_{BIN_NAME}"))),
+            Shell::PowerShell => Some(
+                self.espup_dir
+                    .join("completions")
+                    .join(format!("{BIN_NAME}.ps1")),
+            ),
+            _ => None,
+        }
+    }
+
+    /// Returns the profile of PowerShell.
+    fn powershell_profile(&self) -> PathBuf {
+        if cfg!(windows) {
+            self.home.join("Documents/PowerShell")
+        } else {
+            self.config_home.join("powershell")
+        }
+        .join("Microsoft.PowerShell_profile.ps1")
+    }
+
+    /// Returns the shells used by the user: the current one and the configured ones.
+    pub fn detect_shells(&self) -> Vec<Shell> {
+        let mut shells: Vec<Shell> = self.current_shell.into_iter().collect();
+        let configured = [
+            (Shell::Bash, self.home.join(".bashrc")),
+            (Shell::Zsh, self.zdotdir.join(".zshrc")),
+            (Shell::Fish, self.config_home.join("fish")),
+            (
+                Shell::PowerShell,
+                self.powershell_profile().parent().unwrap().to_path_buf(),
+            ),
+        ];
+        for (shell, path) in configured {
+            if path.exists() && !shells.contains(&shell) {
+                shells.push(shell);
+            }
+        }
+        if cfg!(windows) && !shells.contains(&Shell::PowerShell) {
+            shells.push(Shell::PowerShell);
+        }
+        shells
+    }
+}
+
+/// Line of the PowerShell profile that sources the completion script.
+fn profile_line(script: &Path) -> String {
+    format!(". \"{}\"", script.display())
+}
+
+/// Writes the completions of the detected shells, returning the installed scripts.
+pub fn install_completions(
+    command: &mut Command,
+    shell_env: &CompletionEnv,
+) -> Result<Vec<PathBuf>, Error> {
+    let mut installed = Vec::new();
+    for shell in shell_env.detect_shells() {
+        let Some(script) = shell_env.script_path(shell) else {
+            debug!("Completions can't be installed for {}, skipping it", shell);
+            continue;
+        };
+        let mut content = Vec::new();
+        clap_complete::generate(shell, command, BIN_NAME, &mut content);
+        write_checked(&script, content)?;
+
+        match shell {
+            Shell::PowerShell => {
+                let profile = shell_env.powershell_profile();
+                let line = profile_line(&script);
+                let existing = read_to_string(&profile).unwrap_or_default();
+                if !existing.lines().any(|l| l.trim() == line) {
+                    let separator = if existing.is_empty() || existing.ends_with('\n') {
+                        ""
+                    } else {
+                        "\n"
+                    };
+                    write_checked(&profile, format!("{existing}{separator}{line}\n"))?;
+                }
+            }
+            Shell::Zsh => {
+                let zshrc = shell_env.zdotdir.join(".zshrc");
+                let zsh_dir = script.parent().unwrap().display().to_string();
+                if !read_to_string(zshrc).unwrap_or_default().contains(".zfunc") {
+                    warn!(
+                        "Add 'fpath+={}' before 'compinit' in your '.zshrc' to load the completions",
+                        zsh_dir
+                    );
+                }
+            }
+            _ => {}
+        }
+        info!("Installed {} completions in '{}'", shell, script.display());
+        installed.push(script);
+    }
+    Ok(installed)
+}
+
+/// Removes the completions of every supported shell, returning the removed scripts.
+pub fn uninstall_completions(shell_env: &CompletionEnv) -> Result<Vec<PathBuf>, Error> {
+    let mut removed = Vec::new();
+    for shell in [Shell::Bash, Shell::Fish, Shell::PowerShell, Shell::Zsh] {
+        let Some(script) = shell_env.script_path(shell) else {
+            continue;
+        };
+        if shell == Shell::PowerShell {
+            let profile = shell_env.powershell_profile();
+            if let Ok(existing) = read_to_string(&profile) {
+                let line = profile_line(&script);
+                if existing.lines().any(|l| l.trim() == line) {
+                    let kept: String = existing
+                        .lines()
+                        .filter(|l| l.trim() != line)
+                        .map(|l| format!("{l}\n"))
+                        .collect();
+                    write_checked(&profile, kept)?;
+                }
+            }
+        }
+        if script.exists() {
+            remove_file(&script)?;
+            info!("Removed {} completions from '{}'", shell, script.display());
+            removed.push(script);
+        }
+    }
+    Ok(removed)
+}
+
+// The PowerShell profile lives in the documents folder on Windows
+#[cfg(all(test, unix))]
+mod tests {
+    use crate::completions::{install_completions, uninstall_completions, CompletionEnv};
+    use clap::Command;
+    use clap_complete::Shell;
+    use std::fs::{create_dir_all, read_to_string, write};
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_install_completions() {
+        let temp_dir = TempDir::new().unwrap();
+        let home = temp_dir.path().to_path_buf();
+        let shell_env = CompletionEnv {
+            current_shell: Some(Shell::Bash),
+            data_home: home.join(".local/share"),
+            config_home: home.join(".config"),
+            espup_dir: home.join(".espup"),
+            zdotdir: home.clone(),
+            home: home.clone(),
+        };
+        create_dir_all(home.join(".config/fish")).unwrap();
+        create_dir_all(home.join(".config/powershell")).unwrap();
+        let profile = home.join(".config/powershell/Microsoft.PowerShell_profile.ps1");
+        write(&profile, "Set-PSReadLineOption -EditMode Emacs").unwrap();
+
+        let mut command = Command::new("espup");
+        let installed = install_completions(&mut command, &shell_env).unwrap();
+        for shell in [Shell::Bash, Shell::Fish, Shell::PowerShell] {
+            let script = shell_env.script_path(shell).unwrap();
+            assert!(installed.contains(&script));
+            assert!(!read_to_string(script).unwrap().is_empty());
+        }
+        let line = format!(
+            ". \"{}\"",
+            shell_env.script_path(Shell::PowerShell).unwrap().display()
+        );
+        assert_eq!(
+            read_to_string(&profile).unwrap(),
+            format!("Set-PSReadLineOption -EditMode Emacs\n{line}\n")
+        );
+
+        // Installing again doesn't duplicate the profile line
+        install_completions(&mut command, &shell_env).unwrap();
+        assert_eq!(read_to_string(&profile).unwrap().matches(&line).count(), 1);
+
+        let removed = uninstall_completions(&shell_env).unwrap();
+        assert!(removed.len() >= 3);
+        assert!(installed.iter().all(|script| !script.exists()));
+        assert_eq!(
+            read_to_string(&profile).unwrap(),
+            "Set-PSReadLineOption -EditMode Emacs\n"
+        );
+    }
+}
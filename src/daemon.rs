@@ -0,0 +1,450 @@
+//! `espup daemon --stdio`, driving espup through JSON-RPC 2.0 messages over stdin and stdout.
+//!
+//! Every message is a single line of JSON. The supported methods are:
+//! - `version`: returns the espup version and the protocol version.
+//! - `list`: returns the manifests of the installed toolchains.
+//! - `install`, `update` and `uninstall`: run the subcommand with the `args` parameter, a list of
+//!   command line arguments, and return whether it succeeded once it exits. In the meantime, its
+//!   download progress is sent as `progress` notifications, whose parameters are a
+//!   [`ProgressEvent`], its logs as `log` notifications and its other output as `output`
+//!   notifications.
+//! - `cancel`: stops the running operation, if any.
+//! - `shutdown`: stops the running operation, if any, and exits.
+//!
+//! Requests without `id` are notifications: they are handled, but never answered, even when they
+//! fail.
+//!
+//! Operations run in a separate espup process whose standard input is null, so they never wait
+//! for an answer: confirmations are declined, secrets are missing and detected devices are
+//! accepted. Only one operation runs at a time.
+
+use crate::{error::Error, manifest::Manifest, toolchain::progress::ProgressEvent};
+use log::debug;
+use serde_json::{json, Value};
+use std::{
+    future::pending,
+    io,
+    path::Path,
+    process::{ExitStatus, Stdio},
+};
+use tokio::{
+    io::{AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader},
+    process::{Child, Command},
+    sync::mpsc::{unbounded_channel, UnboundedSender},
+    task::JoinHandle,
+};
+
+/// Version of the protocol, increased on breaking changes.
+pub const PROTOCOL_VERSION: u32 = 1;
+/// Subcommands that can be run through the daemon.
+const OPERATIONS: [&str; 3] = ["install", "update", "uninstall"];
+
+/// JSON-RPC error codes.
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const INTERNAL_ERROR: i64 = -32603;
+/// Another operation is already running.
+const BUSY: i64 = -32000;
+
+/// Operation running in a child process.
+struct Operation {
+    /// Identifier of the request that started it, `None` for notifications.
+    id: Option<Value>,
+    child: Child,
+    /// Tasks forwarding the output of the child.
+    forwarders: Vec<JoinHandle<()>>,
+}
+
+impl Operation {
+    /// Waits for the child to exit and for its output to be forwarded.
+    async fn wait(&mut self) -> io::Result<ExitStatus> {
+        let status = self.child.wait().await;
+        // Only forgotten once completed, as this future may be cancelled
+        while let Some(forwarder) = self.forwarders.last_mut() {
+            let _ = forwarder.await;
+            self.forwarders.pop();
+        }
+        status
+    }
+}
+
+/// Returns a successful response.
+fn response(id: &Value, result: Value) -> Value {
+    json!({ "jsonrpc": "2.0", "id": id, "result": result })
+}
+
+/// Returns an error response.
+fn error_response(id: &Value, code: i64, message: &str) -> Value {
+    json!({ "jsonrpc": "2.0", "id": id, "error": { "code": code, "message": message } })
+}
+
+/// Returns a notification.
+fn notification(method: &str, params: Value) -> Value {
+    json!({ "jsonrpc": "2.0", "method": method, "params": params })
+}
+
+/// Returns the notification of a line written by an operation, progress events being JSON lines
+/// and logs being formatted as `[level]: message`.
+fn output_notification(line: &str, stream: &str) -> Value {
+    if let Some(event) = (stream == "stderr")
+        .then(|| serde_json::from_str::<ProgressEvent>(line).ok())
+        .flatten()
+    {
+        return notification("progress", json!(event));
+    }
+    let log = (stream == "stderr")
+        .then(|| line.strip_prefix('['))
+        .flatten()
+        .and_then(|line| line.split_once("]: "))
+        .filter(|(level, _)| ["error", "warn", "info", "debug", "trace"].contains(level));
+    match log {
+        Some((level, message)) => {
+            notification("log", json!({ "level": level, "message": message }))
+        }
+        None => notification("output", json!({ "stream": stream, "line": line })),
+    }
+}
+
+/// Forwards the lines of an output of the child as notifications.
+fn forward<R: AsyncRead + Unpin + Send + 'static>(
+    reader: R,
+    stream: &'static str,
+    sender: UnboundedSender<Value>,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut lines = BufReader::new(reader).lines();
+        while let Ok(Some(line)) = lines.next_line().await {
+            if sender.send(output_notification(&line, stream)).is_err() {
+                return;
+            }
+        }
+    })
+}
+
+/// Returns the command line arguments of the `args` parameter.
+fn operation_args(params: Option<&Value>) -> Option<Vec<String>> {
+    match params.and_then(|params| params.get("args")) {
+        None => Some(Vec::new()),
+        Some(Value::Array(args)) => args
+            .iter()
+            .map(|arg| arg.as_str().map(String::from))
+            .collect(),
+        Some(_) => None,
+    }
+}
+
+/// Handles a request line, returning whether it is a `shutdown` request.
+///
+/// The responses are sent to `sender`, except for the operations, answered once they complete,
+/// and for the notifications, never answered.
+fn handle_request(
+    line: &str,
+    espup: &Path,
+    running: &mut Option<Operation>,
+    sender: &UnboundedSender<Value>,
+) -> bool {
+    let request: Value = match serde_json::from_str(line) {
+        Ok(request) => request,
+        Err(e) => {
+            let _ = sender.send(error_response(&Value::Null, PARSE_ERROR, &e.to_string()));
+            return false;
+        }
+    };
+    let Some(id) = request.get("id").cloned() else {
+        return handle_notification(&request, espup, running, sender);
+    };
+    let Some(method) = request.get("method").and_then(Value::as_str) else {
+        let _ = sender.send(error_response(&id, INVALID_REQUEST, "Missing method"));
+        return false;
+    };
+    debug!("Received '{}' request", method);
+    let message = match method {
+        "version" => response(
+            &id,
+            json!({ "espup": env!("CARGO_PKG_VERSION"), "protocol": PROTOCOL_VERSION }),
+        ),
+        "list" => match Manifest::list() {
+            Ok(manifests) => response(&id, json!(manifests)),
+            Err(e) => error_response(&id, INTERNAL_ERROR, &e.to_string()),
+        },
+        "cancel" => {
+            let cancelled = match running.as_mut() {
+                Some(operation) => operation.child.start_kill().is_ok(),
+                None => false,
+            };
+            response(&id, json!(cancelled))
+        }
+        "shutdown" => {
+            let _ = sender.send(response(&id, Value::Null));
+            return true;
+        }
+        method if OPERATIONS.contains(&method) => {
+            if running.is_some() {
+                error_response(&id, BUSY, "Another operation is already running")
+            } else if let Some(args) = operation_args(request.get("params")) {
+                match start_operation(espup, method, &args, Some(id.clone()), sender) {
+                    Ok(operation) => {
+                        *running = Some(operation);
+                        return false;
+                    }
+                    Err(e) => error_response(&id, INTERNAL_ERROR, &e.to_string()),
+                }
+            } else {
+                error_response(&id, INVALID_PARAMS, "'args' must be a list of strings")
+            }
+        }
+        method => error_response(&id, METHOD_NOT_FOUND, &format!("Unknown method '{method}'")),
+    };
+    let _ = sender.send(message);
+    false
+}
+
+/// Handles a request without `id`, returning whether it is a `shutdown` notification.
+///
+/// Notifications are never answered: the operations they start send their notifications but not
+/// their completion, and failures are only logged.
+fn handle_notification(
+    request: &Value,
+    espup: &Path,
+    running: &mut Option<Operation>,
+    sender: &UnboundedSender<Value>,
+) -> bool {
+    let Some(method) = request.get("method").and_then(Value::as_str) else {
+        debug!("Ignoring notification without method");
+        return false;
+    };
+    debug!("Received '{}' notification", method);
+    match method {
+        "shutdown" => return true,
+        "cancel" => {
+            if let Some(operation) = running.as_mut() {
+                let _ = operation.child.start_kill();
+            }
+        }
+        method if OPERATIONS.contains(&method) && running.is_none() => {
+            match operation_args(request.get("params"))
+                .ok_or_else(|| io::Error::other("'args' must be a list of strings"))
+                .and_then(|args| start_operation(espup, method, &args, None, sender))
+            {
+                Ok(operation) => *running = Some(operation),
+                Err(e) => debug!("Failed to start the '{}' operation: {}", method, e),
+            }
+        }
+        method => debug!("Ignoring '{}' notification", method),
+    }
+    false
+}
+
+/// Starts an operation in a child espup process, forwarding its output to `sender`.
+///
+/// The standard input of the child is null, so its prompts never block: see the module
+/// documentation.
+fn start_operation(
+    espup: &Path,
+    method: &str,
+    args: &[String],
+    id: Option<Value>,
+    sender: &UnboundedSender<Value>,
+) -> io::Result<Operation> {
+    let mut child = Command::new(espup)
+        .arg(method)
+        .args(args)
+        .env("ESPUP_PROGRESS_EVENTS", "true")
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()?;
+    let forwarders = vec![
+        forward(child.stdout.take().unwrap(), "stdout", sender.clone()),
+        forward(child.stderr.take().unwrap(), "stderr", sender.clone()),
+    ];
+    Ok(Operation {
+        id,
+        child,
+        forwarders,
+    })
+}
+
+/// Serves the requests read from `input`, writing the responses and notifications to `output`,
+/// until a `shutdown` request or the end of the input.
+///
+/// Operations run the `espup` executable.
+pub async fn serve<R, W>(input: R, mut output: W, espup: &Path) -> Result<(), Error>
+where
+    R: AsyncBufRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    // Responses go through the same channel as the notifications, so they are written after the
+    // output of the operations
+    let (sender, mut receiver) = unbounded_channel::<Value>();
+    let mut lines = input.lines();
+    let mut running: Option<Operation> = None;
+    loop {
+        tokio::select! {
+            Some(message) = receiver.recv() => {
+                output.write_all(format!("{message}\n").as_bytes()).await?;
+                output.flush().await?;
+            }
+            status = async {
+                match running.as_mut() {
+                    Some(operation) => operation.wait().await,
+                    None => pending().await,
+                }
+            } => {
+                // Operations started by notifications are not answered
+                if let Some(id) = running.take().unwrap().id {
+                    let _ = sender.send(match status {
+                        Ok(status) => response(
+                            &id,
+                            json!({ "success": status.success(), "code": status.code() }),
+                        ),
+                        Err(e) => error_response(&id, INTERNAL_ERROR, &e.to_string()),
+                    });
+                }
+            }
+            line = lines.next_line() => match line? {
+                Some(line) if line.trim().is_empty() => {}
+                Some(line) => {
+                    if handle_request(&line, espup, &mut running, &sender) {
+                        break;
+                    }
+                }
+                None => break,
+            }
+        }
+    }
+    if let Some(mut operation) = running.take() {
+        let _ = operation.child.kill().await;
+    }
+    while let Ok(message) = receiver.try_recv() {
+        output.write_all(format!("{message}\n").as_bytes()).await?;
+    }
+    output.flush().await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::daemon::{output_notification, serve};
+    use serde_json::{json, Value};
+    use std::path::Path;
+
+    #[test]
+    fn test_output_notification() {
+        assert_eq!(
+            output_notification("[info]: Installing the Espressif Rust ecosystem", "stderr"),
+            json!({
+                "jsonrpc": "2.0",
+                "method": "log",
+                "params": { "level": "info", "message": "Installing the Espressif Rust ecosystem" }
+            })
+        );
+        assert_eq!(
+            output_notification("[info]: export", "stdout")["method"],
+            "output"
+        );
+        assert_eq!(
+            output_notification("  × Failed to query GitHub API", "stderr")["params"],
+            json!({ "stream": "stderr", "line": "  × Failed to query GitHub API" })
+        );
+        let event = r#"{"event":"advanced","name":"rust.tar.xz","downloaded":250,"len":1000}"#;
+        assert_eq!(
+            output_notification(event, "stderr"),
+            json!({
+                "jsonrpc": "2.0",
+                "method": "progress",
+                "params": {
+                    "event": "advanced",
+                    "name": "rust.tar.xz",
+                    "downloaded": 250,
+                    "len": 1000
+                }
+            })
+        );
+        assert_eq!(output_notification(event, "stdout")["method"], "output");
+    }
+
+    #[tokio::test]
+    async fn test_serve() {
+        let input = [
+            r#"{"jsonrpc": "2.0", "id": 1, "method": "version"}"#,
+            "not json",
+            // Notifications are never answered
+            r#"{"jsonrpc": "2.0", "method": "version"}"#,
+            r#"{"jsonrpc": "2.0", "method": "remove"}"#,
+            r#"{"jsonrpc": "2.0", "method": "install", "params": {"args": [1]}}"#,
+            r#"{"jsonrpc": "2.0", "id": 2, "method": "remove"}"#,
+            r#"{"jsonrpc": "2.0", "id": 3, "method": "install", "params": {"args": [1]}}"#,
+            r#"{"jsonrpc": "2.0", "id": 4, "method": "cancel"}"#,
+            r#"{"jsonrpc": "2.0", "id": 5, "method": "shutdown"}"#,
+            r#"{"jsonrpc": "2.0", "id": 6, "method": "version"}"#,
+        ]
+        .join("\n");
+        let mut output = Vec::new();
+        serve(input.as_bytes(), &mut output, Path::new("espup"))
+            .await
+            .unwrap();
+        let messages: Vec<Value> = String::from_utf8(output)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(messages.len(), 6);
+        assert_eq!(messages[0]["result"]["espup"], env!("CARGO_PKG_VERSION"));
+        assert_eq!(messages[1]["error"]["code"], -32700);
+        assert_eq!(messages[2]["error"]["code"], -32601);
+        assert_eq!(messages[3]["error"]["code"], -32602);
+        assert_eq!(
+            messages[4],
+            json!({ "jsonrpc": "2.0", "id": 4, "result": false })
+        );
+        assert_eq!(
+            messages[5],
+            json!({ "jsonrpc": "2.0", "id": 5, "result": null })
+        );
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_serve_operation() {
+        let input = r#"{"jsonrpc": "2.0", "id": 1, "method": "install", "params": {"args": ["--targets", "esp32"]}}"#;
+        let mut output = Vec::new();
+        // The end of the input stops the running operation, so wait for it to complete first
+        let (reader, mut writer) = tokio::io::duplex(1024);
+        let server = serve(
+            tokio::io::BufReader::new(reader),
+            &mut output,
+            Path::new("echo"),
+        );
+        let client = async {
+            use tokio::io::AsyncWriteExt;
+            writer
+                .write_all(format!("{input}\n").as_bytes())
+                .await
+                .unwrap();
+            tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+            drop(writer);
+        };
+        let (result, _) = tokio::join!(server, client);
+        result.unwrap();
+        let messages: Vec<Value> = String::from_utf8(output)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(
+            messages,
+            vec![
+                json!({
+                    "jsonrpc": "2.0",
+                    "method": "output",
+                    "params": { "stream": "stdout", "line": "install --targets esp32" }
+                }),
+                json!({ "jsonrpc": "2.0", "id": 1, "result": { "success": true, "code": 0 } }),
+            ]
+        );
+    }
+}
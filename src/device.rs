@@ -0,0 +1,76 @@
+//! Detection of the chip of a connected device, for `espup install --detect-device`.
+//!
+//! The serial ports are probed by `espflash board-info`, which prompts for the port when several
+//! devices are connected and honors `ESPFLASH_PORT`. Its output includes a line such as
+//! `Chip type:         esp32c3 (revision v0.4)`.
+
+use crate::{
+    error::Error,
+    targets::{parse_target, Target},
+};
+use log::{debug, info};
+use std::{
+    io::ErrorKind,
+    process::{Command, Stdio},
+};
+
+/// Name of the flashing tool used to probe the devices.
+pub const ESPFLASH: &str = "espflash";
+
+/// Returns the chip reported in the `espflash board-info` output, if it is a known target.
+///
+/// The chip name is normalized, so `ESP32-C3` and `esp32c3` are both accepted.
+pub fn parse_chip(output: &str) -> Option<Target> {
+    output.lines().find_map(|line| {
+        let chip = line
+            .trim()
+            .strip_prefix("Chip type:")?
+            .split_whitespace()
+            .next()?;
+        parse_target(chip).ok()
+    })
+}
+
+/// Probes the connected device with `espflash board-info` and returns its chip.
+pub fn detect_target() -> Result<Target, Error> {
+    info!("Probing the connected device with '{}'", ESPFLASH);
+    let output = Command::new(ESPFLASH)
+        .arg("board-info")
+        .stdin(Stdio::inherit())
+        .stderr(Stdio::inherit())
+        .output()
+        .map_err(|e| match e.kind() {
+            ErrorKind::NotFound => Error::MissingEspflash,
+            _ => Error::DeviceDetection(e.to_string()),
+        })?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    debug!("'{} board-info' output:\n{}", ESPFLASH, stdout.trim_end());
+    if !output.status.success() {
+        return Err(Error::DeviceDetection(format!(
+            "'{ESPFLASH} board-info' exited with {}",
+            output.status
+        )));
+    }
+    parse_chip(&stdout).ok_or_else(|| {
+        Error::DeviceDetection("the chip is not reported or not supported".to_string())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{device::parse_chip, targets::Target};
+
+    #[test]
+    fn test_parse_chip() {
+        let output = "Chip type:         esp32c3 (revision v0.4)\n\
+                      Crystal frequency: 40 MHz\n\
+                      Flash size:        4MB\n";
+        assert_eq!(parse_chip(output), Some(Target::ESP32C3));
+        assert_eq!(
+            parse_chip("Chip type:         ESP32-S3 (revision v0.2)"),
+            Some(Target::ESP32S3)
+        );
+        assert_eq!(parse_chip("Chip type:         esp8266"), None);
+        assert_eq!(parse_chip("Flash size:        4MB"), None);
+    }
+}
@@ -1,13 +1,20 @@
 //! Environment variables set up and export file support.
 
-use crate::error::Error;
-use directories::BaseDirs;
-use log::debug;
+use crate::{
+    error::Error,
+    permissions::{replace_file, write_file},
+    report::OutputFormat,
+    sandbox::{check_write, sandboxed},
+};
+use directories::{BaseDirs, ProjectDirs};
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
 use std::{
+    collections::BTreeMap,
     env,
-    fs::File,
-    io::Write,
+    fs::{create_dir_all, read_to_string, remove_file, rename},
     path::{Path, PathBuf},
+    sync::OnceLock,
 };
 #[cfg(windows)]
 use winreg::{
@@ -19,6 +26,316 @@ use winreg::{
 const DEFAULT_EXPORT_FILE: &str = "export-esp.ps1";
 #[cfg(not(windows))]
 const DEFAULT_EXPORT_FILE: &str = "export-esp.sh";
+/// Version of the templates of the export files, increased when their generated content changes
+/// so `espup update` regenerates the export files of older installations.
+pub const EXPORT_TEMPLATE_VERSION: u32 = 1;
+/// Environment variable overriding the directory where espup keeps its state.
+pub const DATA_DIR_ENV: &str = "ESPUP_DATA_DIR";
+/// Environment variable overriding the directory where espup keeps files that can be recreated.
+pub const CACHE_DIR_ENV: &str = "ESPUP_CACHE_DIR";
+/// First line of the export file block managed by espup, the other lines are left untouched.
+const MANAGED_BLOCK_START: &str = "# >>> espup managed block >>>";
+/// Last line of the export file block managed by espup.
+const MANAGED_BLOCK_END: &str = "# <<< espup managed block <<<";
+/// Byte order mark of the PowerShell export files with non-ASCII characters.
+const UTF8_BOM: char = '\u{feff}';
+/// Lines of the managed block of batch export files switching `cmd.exe` to UTF-8, which reads
+/// them in the legacy code page otherwise, and restoring the previous code page.
+const CMD_UTF8_START: [&str; 2] = [
+    "@for /f \"tokens=2 delims=:.\" %%p in ('chcp') do @set \"ESPUP_CODE_PAGE=%%p\"",
+    "@chcp 65001 >nul",
+];
+const CMD_UTF8_END: [&str; 2] = ["@chcp %ESPUP_CODE_PAGE% >nul", "@set \"ESPUP_CODE_PAGE=\""];
+/// First line of the export files whose lines were commented out on uninstallation.
+const TOMBSTONE: &str =
+    "# The toolchain set up by this file was uninstalled by espup, the lines below are disabled";
+
+/// Shell syntax of an export file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[serde(rename_all = "lowercase")]
+pub enum ExportShell {
+    /// POSIX shells, e.g. bash or zsh (`.sh`).
+    #[cfg_attr(feature = "cli", value(alias = "sh", alias = "zsh"))]
+    Bash,
+    /// fish (`.fish`).
+    Fish,
+    /// PowerShell (`.ps1`).
+    #[cfg_attr(feature = "cli", value(name = "powershell"))]
+    PowerShell,
+    /// Windows Command Prompt (`.bat`).
+    Cmd,
+}
+
+impl ExportShell {
+    /// Returns the shell of the export files of the host: PowerShell on Windows and POSIX shell
+    /// elsewhere.
+    pub fn host() -> Self {
+        if cfg!(windows) {
+            ExportShell::PowerShell
+        } else {
+            ExportShell::Bash
+        }
+    }
+
+    /// Returns the shell of an export file from its extension, the host one for other extensions.
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("sh") => ExportShell::Bash,
+            Some("fish") => ExportShell::Fish,
+            Some("ps1") => ExportShell::PowerShell,
+            Some("bat") | Some("cmd") => ExportShell::Cmd,
+            _ => Self::host(),
+        }
+    }
+
+    /// Returns the extension of the export files of the shell.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ExportShell::Bash => "sh",
+            ExportShell::Fish => "fish",
+            ExportShell::PowerShell => "ps1",
+            ExportShell::Cmd => "bat",
+        }
+    }
+
+    /// Renders a comment line, e.g. the delimiters of the managed block.
+    ///
+    /// The lines of batch files are prefixed with `@`, so they are not echoed when the file is
+    /// run at the start of an interactive `cmd.exe` session.
+    fn comment(&self, line: &str) -> String {
+        match self {
+            ExportShell::Cmd => format!("@rem {line}"),
+            _ => line.to_string(),
+        }
+    }
+
+    /// Encodes the content of an export file, so non-ASCII paths, e.g. of profiles like
+    /// `C:\Users\José`, are not decoded in the legacy code page: Windows PowerShell only reads
+    /// scripts as UTF-8 when they start with a byte order mark.
+    fn encode(&self, content: &str) -> String {
+        match self {
+            ExportShell::PowerShell if !content.is_ascii() => format!("{UTF8_BOM}{content}"),
+            _ => content.to_string(),
+        }
+    }
+}
+
+/// Reads an export file, without the byte order mark of [`ExportShell::encode`].
+fn read_export_file(export_file: &Path) -> Result<String, Error> {
+    let content = read_to_string(export_file)?;
+    Ok(content
+        .strip_prefix(UTF8_BOM)
+        .map(String::from)
+        .unwrap_or(content))
+}
+
+/// Returns the export file and its siblings for the additional shells, e.g. `export-esp.fish`
+/// next to `export-esp.sh`.
+pub fn export_files(export_file: &Path, shells: &[ExportShell]) -> Vec<PathBuf> {
+    let mut files = vec![export_file.to_path_buf()];
+    for shell in shells {
+        let file = export_file.with_extension(shell.extension());
+        if !files.contains(&file) {
+            files.push(file);
+        }
+    }
+    files
+}
+
+/// Returns the export file and the additional shells without PowerShell scripts, for hosts that
+/// block their execution: a PowerShell export file is replaced by its batch sibling.
+pub fn without_powershell(
+    export_file: &Path,
+    shells: &[ExportShell],
+) -> (PathBuf, Vec<ExportShell>) {
+    let export_file = match ExportShell::from_path(export_file) {
+        ExportShell::PowerShell => export_file.with_extension(ExportShell::Cmd.extension()),
+        _ => export_file.to_path_buf(),
+    };
+    let shells = shells
+        .iter()
+        .filter(|shell| **shell != ExportShell::PowerShell)
+        .copied()
+        .collect();
+    (export_file, shells)
+}
+
+/// How an export modifies the environment.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExportKind {
+    /// Prepends a directory to `PATH`.
+    PrependPath,
+    /// Sets a variable.
+    Set,
+    /// Sources a script.
+    Source,
+}
+
+/// Environment change required by an installed component.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Export {
+    /// Identifier of the component that requires the export.
+    pub component: String,
+    /// How the environment is modified.
+    pub kind: ExportKind,
+    /// Name of the variable, `PATH` for directories prepended to it and empty for sourced scripts.
+    pub variable: String,
+    /// Value of the variable, directory prepended to `PATH` or path of the sourced script.
+    pub value: String,
+}
+
+impl Export {
+    /// Creates an export prepending a directory to `PATH`.
+    pub fn prepend_path(component: &str, dir: &str) -> Self {
+        Self {
+            component: component.to_string(),
+            kind: ExportKind::PrependPath,
+            variable: "PATH".to_string(),
+            value: dir.to_string(),
+        }
+    }
+
+    /// Creates an export setting a variable.
+    pub fn set(component: &str, variable: &str, value: &str) -> Self {
+        Self {
+            component: component.to_string(),
+            kind: ExportKind::Set,
+            variable: variable.to_string(),
+            value: value.to_string(),
+        }
+    }
+
+    /// Creates an export sourcing a script.
+    pub fn source(component: &str, script: &str) -> Self {
+        Self {
+            component: component.to_string(),
+            kind: ExportKind::Source,
+            variable: String::new(),
+            value: script.to_string(),
+        }
+    }
+
+    /// Renders the export in the syntax of the export file of the host: PowerShell on Windows
+    /// and POSIX shell elsewhere.
+    pub fn to_shell(&self) -> String {
+        self.render(ExportShell::host())
+    }
+
+    /// Renders the export in the syntax of the shell.
+    pub fn render(&self, shell: ExportShell) -> String {
+        match shell {
+            ExportShell::Bash => self.to_posix(),
+            ExportShell::Fish => self.to_fish(),
+            ExportShell::PowerShell => self.to_powershell(),
+            ExportShell::Cmd => self.to_cmd(),
+        }
+    }
+
+    fn to_posix(&self) -> String {
+        match self.kind {
+            ExportKind::PrependPath => format!("export PATH=\"{}:$PATH\"", self.value),
+            ExportKind::Set => format!("export {}=\"{}\"", self.variable, self.value),
+            ExportKind::Source => format!(". \"{}\"", self.value),
+        }
+    }
+
+    fn to_fish(&self) -> String {
+        match self.kind {
+            ExportKind::PrependPath => format!("set -gx PATH \"{}\" $PATH", self.value),
+            ExportKind::Set => format!("set -gx {} \"{}\"", self.variable, self.value),
+            ExportKind::Source => format!("source \"{}\"", self.value),
+        }
+    }
+
+    fn to_powershell(&self) -> String {
+        let value = self.value.replace('/', r"\");
+        match self.kind {
+            ExportKind::PrependPath => format!("$Env:PATH = \"{value};\" + $Env:PATH"),
+            ExportKind::Set => format!("$Env:{} = \"{value}\"", self.variable),
+            ExportKind::Source => format!(". \"{value}\""),
+        }
+    }
+
+    fn to_cmd(&self) -> String {
+        let value = self.value.replace('/', r"\");
+        match self.kind {
+            ExportKind::PrependPath => format!("@set \"PATH={value};%PATH%\""),
+            ExportKind::Set => format!("@set \"{}={value}\"", self.variable),
+            ExportKind::Source => format!("@call \"{value}\""),
+        }
+    }
+}
+
+/// Returns the environment variables set up by the exports, with `PATH` made of their
+/// directories followed by `path`.
+///
+/// Pure environments pass no `path`, so nothing is inherited from the current environment.
+/// Sourced scripts can't be represented and are skipped.
+pub fn environment(exports: &[Export], path: Option<&str>) -> BTreeMap<String, String> {
+    let mut variables = BTreeMap::new();
+    let mut dirs = Vec::new();
+    for export in exports {
+        match export.kind {
+            // Later exports are prepended to the earlier ones
+            ExportKind::PrependPath => dirs.insert(0, export.value.clone()),
+            ExportKind::Set => {
+                variables.insert(export.variable.clone(), export.value.clone());
+            }
+            ExportKind::Source => warn!("Skipping the sourced script '{}'", export.value),
+        }
+    }
+    dirs.extend(path.map(String::from));
+    if !dirs.is_empty() {
+        let separator = if cfg!(windows) { ";" } else { ":" };
+        variables.insert("PATH".to_string(), dirs.join(separator));
+    }
+    variables
+}
+
+/// Renders environment variables in the given format.
+pub fn render_environment(variables: &BTreeMap<String, String>, format: OutputFormat) -> String {
+    match format {
+        // `NAME=value` lines, e.g. for `env -i`
+        OutputFormat::Text => variables
+            .iter()
+            .map(|(variable, value)| format!("{variable}={value}\n"))
+            .collect(),
+        // JSON object, e.g. for Nix derivations
+        OutputFormat::Json => serde_json::to_string_pretty(variables).unwrap() + "\n",
+    }
+}
+
+/// Removes the exports required by the given component.
+pub fn remove_component_exports(exports: &mut Vec<Export>, component: &str) {
+    exports.retain(|export| export.component != component);
+}
+
+/// How an existing export file is updated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ExportFileMode {
+    /// Replace the block managed by espup, keeping the other lines.
+    #[default]
+    Merge,
+    /// Overwrite the whole file.
+    Regenerate,
+    /// Leave the file untouched if it exists.
+    Reuse,
+}
+
+/// Scope of the environment variables changes on Windows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum EnvScope {
+    /// Persist the environment variables for the current user (registry).
+    #[default]
+    User,
+    /// Only set the environment variables for the current process and the export file.
+    Process,
+    /// Do not modify the environment.
+    None,
+}
 
 #[cfg(windows)]
 /// Sets an environment variable for the current user.
@@ -69,7 +386,106 @@ pub fn delete_env_variable(key: &str) -> Result<(), Error> {
     Ok(())
 }
 
+/// Returns the directory set by an environment variable, if any.
+fn env_dir(var: &str) -> Option<PathBuf> {
+    env::var_os(var)
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+}
+
+/// Returns the espup directories set by environment variables.
+pub fn declared_dirs() -> Vec<PathBuf> {
+    [DATA_DIR_ENV, CACHE_DIR_ENV]
+        .into_iter()
+        .filter_map(env_dir)
+        .collect()
+}
+
+/// Returns the XDG base directories of espup, only used on Linux hosts.
+fn xdg_dirs() -> Option<ProjectDirs> {
+    if cfg!(target_os = "linux") {
+        ProjectDirs::from("", "", "espup")
+    } else {
+        None
+    }
+}
+
+/// Returns the directory where espup kept its state before following the XDG base directories.
+fn legacy_espup_dir() -> PathBuf {
+    BaseDirs::new().unwrap().home_dir().join(".espup")
+}
+
+/// Returns the path of the directory where espup keeps its state.
+///
+/// `ESPUP_DATA_DIR` overrides it. Otherwise, it is `$XDG_DATA_HOME/espup` on Linux, where the
+/// legacy `~/.espup` directory is migrated on first use, and `~/.espup` on other hosts.
+pub fn get_espup_dir() -> PathBuf {
+    static ESPUP_DIR: OnceLock<PathBuf> = OnceLock::new();
+    ESPUP_DIR
+        .get_or_init(|| {
+            if let Some(dir) = env_dir(DATA_DIR_ENV) {
+                return dir;
+            }
+            match xdg_dirs() {
+                Some(dirs) if sandboxed() => dirs.data_dir().to_path_buf(),
+                Some(dirs) => migrate_legacy_dir(&legacy_espup_dir(), dirs.data_dir()),
+                None => legacy_espup_dir(),
+            }
+        })
+        .clone()
+}
+
+/// Returns the path of the directory where espup keeps files that can be recreated.
+///
+/// `ESPUP_CACHE_DIR` overrides it. Otherwise, it is `$XDG_CACHE_HOME/espup` on Linux and
+/// `~/.espup/cache` on other hosts.
+pub fn get_cache_dir() -> PathBuf {
+    env_dir(CACHE_DIR_ENV)
+        .or_else(|| xdg_dirs().map(|dirs| dirs.cache_dir().to_path_buf()))
+        .unwrap_or_else(|| legacy_espup_dir().join("cache"))
+}
+
+/// Moves the legacy state directory to its new location, returning the directory to use.
+///
+/// A symlink is left in place of the legacy directory, so the paths written to existing export
+/// files keep working. Failed migrations keep using the legacy directory.
+fn migrate_legacy_dir(legacy: &Path, dir: &Path) -> PathBuf {
+    let is_legacy_dir = legacy
+        .symlink_metadata()
+        .is_ok_and(|metadata| metadata.is_dir());
+    if !is_legacy_dir || dir.exists() {
+        return dir.to_path_buf();
+    }
+    let migrated = dir
+        .parent()
+        .map_or(Ok(()), create_dir_all)
+        .and_then(|_| rename(legacy, dir));
+    if let Err(e) = migrated {
+        warn!(
+            "Failed to move '{}' to '{}', keeping it: {}",
+            legacy.display(),
+            dir.display(),
+            e
+        );
+        return legacy.to_path_buf();
+    }
+    info!("Moved '{}' to '{}'", legacy.display(), dir.display());
+    #[cfg(unix)]
+    if let Err(e) = std::os::unix::fs::symlink(dir, legacy) {
+        warn!(
+            "Failed to link '{}' to '{}': {}",
+            legacy.display(),
+            dir.display(),
+            e
+        );
+    }
+    dir.to_path_buf()
+}
+
 /// Returns the absolute path to the export file, uses the DEFAULT_EXPORT_FILE if no arg is provided.
+///
+/// The default export file stays in the home directory on every host, as the user profiles and
+/// CI scripts source it from there.
 pub fn get_export_file(export_file: Option<PathBuf>) -> Result<PathBuf, Error> {
     if let Some(export_file) = export_file {
         if export_file.is_dir() {
@@ -89,20 +505,159 @@ pub fn get_export_file(export_file: Option<PathBuf>) -> Result<PathBuf, Error> {
     }
 }
 
-/// Creates the export file with the necessary environment variables.
-pub fn create_export_file(export_file: &PathBuf, exports: &[String]) -> Result<(), Error> {
+/// Creates or updates the export file with the necessary environment variables.
+pub fn create_export_file(
+    export_file: &Path,
+    exports: &[Export],
+    mode: ExportFileMode,
+) -> Result<(), Error> {
     debug!("Creating export file");
-    let mut file = File::create(export_file)?;
-    for e in exports.iter() {
-        #[cfg(windows)]
-        let e = e.replace('/', r"\");
-        file.write_all(e.as_bytes())?;
-        file.write_all(b"\n")?;
+    check_write(export_file)?;
+    if mode == ExportFileMode::Reuse && export_file.exists() {
+        debug!("Reusing export file '{}'", export_file.display());
+        return Ok(());
     }
+    if export_file
+        .metadata()
+        .is_ok_and(|metadata| metadata.permissions().readonly())
+    {
+        return Err(Error::ReadOnlyFile(export_file.display().to_string()));
+    }
+    let existing = if mode == ExportFileMode::Merge && export_file.is_file() {
+        Some(read_export_file(export_file)?)
+    } else {
+        None
+    };
+    let existing = existing
+        .as_deref()
+        .map(|content| split_managed_block(content, export_file))
+        .transpose()?
+        .flatten();
+    let shell = ExportShell::from_path(export_file);
+    replace_file(
+        export_file,
+        shell.encode(&merge_exports(existing, exports, shell)),
+    )?;
 
     Ok(())
 }
 
+/// Returns the content of the export file with the managed block replaced by the exports,
+/// keeping the content before and after the existing block.
+///
+/// Export files without a managed block were entirely generated by older espup versions, so
+/// they are replaced.
+fn merge_exports(existing: Option<(&str, &str)>, exports: &[Export], shell: ExportShell) -> String {
+    let mut lines: Vec<String> = exports.iter().map(|export| export.render(shell)).collect();
+    if shell == ExportShell::Cmd && lines.iter().any(|line| !line.is_ascii()) {
+        lines.splice(0..0, CMD_UTF8_START.map(String::from));
+        lines.extend(CMD_UTF8_END.map(String::from));
+    }
+    let mut block = format!("{}\n", shell.comment(MANAGED_BLOCK_START));
+    for line in lines {
+        block.push_str(&line);
+        block.push('\n');
+    }
+    block.push_str(&shell.comment(MANAGED_BLOCK_END));
+    block.push('\n');
+
+    match existing {
+        Some((before, after)) => format!("{before}{block}{after}"),
+        None => block,
+    }
+}
+
+/// Splits the content around the managed block, returning the content before and after it.
+///
+/// The block starts at the beginning of the line of its first delimiter, which may be prefixed
+/// by the comment syntax of the shell. A block without its end delimiter is an error, as where
+/// the exports end is unknown.
+fn split_managed_block<'a>(
+    content: &'a str,
+    export_file: &Path,
+) -> Result<Option<(&'a str, &'a str)>, Error> {
+    let Some(start) = content.find(MANAGED_BLOCK_START) else {
+        return Ok(None);
+    };
+    let start = content[..start]
+        .rfind('\n')
+        .map_or(0, |newline| newline + 1);
+    let end = content[start..]
+        .find(MANAGED_BLOCK_END)
+        .ok_or_else(|| Error::UnterminatedManagedBlock(export_file.display().to_string()))?;
+    let end = start + end + MANAGED_BLOCK_END.len();
+    let after = &content[end..];
+    Ok(Some((
+        &content[..start],
+        after.strip_prefix('\n').unwrap_or(after),
+    )))
+}
+
+/// Returns the byte offsets of the mentions of the `dir` path in `content`.
+///
+/// Only whole path components match: `/opt/esp` is mentioned by `/opt/esp/bin` but not by
+/// `/opt/esp2`.
+pub(crate) fn dir_mentions(content: &str, dir: &str) -> Vec<usize> {
+    content
+        .match_indices(dir)
+        .map(|(index, _)| index)
+        .filter(|index| {
+            content[index + dir.len()..]
+                .chars()
+                .next()
+                .map_or(true, |next| {
+                    !(next.is_alphanumeric() || matches!(next, '-' | '_' | '.' | '+' | '~'))
+                })
+        })
+        .collect()
+}
+
+/// Comments out the lines of an export file, after a tombstone notice.
+fn tombstone(content: &str) -> String {
+    let mut tombstone = format!("{TOMBSTONE}\n");
+    for line in content.lines().filter(|line| !line.trim().is_empty()) {
+        tombstone.push_str(&format!("# {line}\n"));
+    }
+    tombstone
+}
+
+/// Removes the managed block from the export file, deleting it when nothing else remains.
+///
+/// Export files without a managed block, written by older espup versions, are commented out when
+/// they refer to the uninstalled toolchain directory, so the profiles sourcing them keep working.
+/// Other files are left untouched.
+pub fn remove_managed_exports(export_file: &Path, toolchain_dir: &Path) -> Result<(), Error> {
+    if !export_file.is_file() {
+        return Ok(());
+    }
+    let shell = ExportShell::from_path(export_file);
+    let content = read_export_file(export_file)?;
+    let Some((before, after)) = split_managed_block(&content, export_file)? else {
+        if content.starts_with(TOMBSTONE) {
+            return Ok(());
+        }
+        if !dir_mentions(&content, &toolchain_dir.display().to_string()).is_empty() {
+            debug!("Disabling export file '{}'", export_file.display());
+            write_file(export_file, shell.encode(&tombstone(&content)))?;
+        } else {
+            warn!(
+                "Export file '{}' has no espup managed block, leaving it untouched",
+                export_file.display()
+            );
+        }
+        return Ok(());
+    };
+    let remaining = format!("{before}{after}");
+    if remaining.trim().is_empty() {
+        debug!("Removing export file '{}'", export_file.display());
+        remove_file(export_file)?;
+    } else {
+        debug!("Removing managed block of '{}'", export_file.display());
+        write_file(export_file, shell.encode(&remaining))?;
+    }
+    Ok(())
+}
+
 #[cfg(windows)]
 // Get the windows PATH variable out of the registry as a String.
 pub fn get_windows_path_var() -> Result<String, Error> {
@@ -112,45 +667,223 @@ pub fn get_windows_path_var() -> Result<String, Error> {
     Ok(path)
 }
 
+/// Normalizes a Windows `PATH` entry for comparisons: backslash separators, no trailing separator
+/// and lowercase, as Windows paths are case insensitive.
+fn normalize_path_entry(entry: &str) -> String {
+    entry
+        .trim()
+        .replace('/', r"\")
+        .trim_end_matches('\\')
+        .to_lowercase()
+}
+
+/// Returns the Windows `PATH` without the entries in, or below, the given directories, dropping
+/// empty entries.
+pub fn remove_path_entries(path: &str, dirs: &[String]) -> String {
+    let dirs: Vec<String> = dirs.iter().map(|dir| normalize_path_entry(dir)).collect();
+    path.split(';')
+        .filter(|entry| {
+            let entry = normalize_path_entry(entry);
+            !entry.is_empty()
+                && !dirs
+                    .iter()
+                    .any(|dir| entry == *dir || entry.starts_with(&format!("{dir}\\")))
+        })
+        .collect::<Vec<_>>()
+        .join(";")
+}
+
+/// Returns the Windows `PATH` with the directory prepended, unless it is already an entry.
+pub fn prepend_path_entry(path: &str, dir: &str) -> String {
+    let normalized = normalize_path_entry(dir);
+    if path
+        .split(';')
+        .any(|entry| normalize_path_entry(entry) == normalized)
+    {
+        return path.to_string();
+    }
+    let dir = dir.replace('/', r"\");
+    if path.is_empty() {
+        dir
+    } else {
+        format!("{dir};{path}")
+    }
+}
+
 #[cfg(windows)]
-/// Instructions to export the environment variables.
-pub fn set_env() -> Result<(), Error> {
-    let mut path = get_windows_path_var()?;
+/// Removes the entries in, or below, the given directories from the user `PATH`.
+pub fn remove_windows_path_entries(dirs: &[String]) -> Result<(), Error> {
+    let path = get_windows_path_var()?;
+    let updated_path = remove_path_entries(&path, dirs);
+    if updated_path != path {
+        set_env_variable("PATH", &updated_path)?;
+    }
+    Ok(())
+}
 
-    if let Ok(xtensa_gcc) = env::var("XTENSA_GCC") {
-        let xtensa_gcc: &str = &xtensa_gcc;
-        if !path.contains(xtensa_gcc) {
-            path = format!("{};{}", xtensa_gcc, path);
-        }
+#[cfg(windows)]
+/// Reverts the exports persisted in the user environment variables by [`set_env`].
+pub fn unset_env(exports: &[Export]) -> Result<(), Error> {
+    let dirs: Vec<String> = exports
+        .iter()
+        .filter(|export| export.kind == ExportKind::PrependPath)
+        .map(|export| export.value.clone())
+        .collect();
+    remove_windows_path_entries(&dirs)?;
+    for export in exports.iter().filter(|e| e.kind == ExportKind::Set) {
+        delete_env_variable(&export.variable)?;
     }
+    Ok(())
+}
 
-    if let Ok(riscv_gcc) = env::var("RISCV_GCC") {
-        let riscv_gcc: &str = &riscv_gcc;
-        if !path.contains(riscv_gcc) {
-            path = format!("{};{}", riscv_gcc, path);
+#[cfg(windows)]
+/// Persists the exports in the user environment variables.
+pub fn set_env(exports: &[Export]) -> Result<(), Error> {
+    let mut path = get_windows_path_var()?;
+    for export in exports {
+        match export.kind {
+            ExportKind::PrependPath => path = prepend_path_entry(&path, &export.value),
+            ExportKind::Set => {
+                set_env_variable(&export.variable, &export.value.replace('/', r"\"))?
+            }
+            // Scripts can't be sourced into the user environment
+            ExportKind::Source => {}
         }
     }
+    set_env_variable("PATH", &path)?;
+    Ok(())
+}
+
+/// Separator of the commands of the `cmd.exe` `AutoRun` value.
+const AUTORUN_SEPARATOR: &str = " & ";
+
+/// Returns the command of the `cmd.exe` `AutoRun` value running a batch export file, skipped
+/// once the file is removed.
+pub fn autorun_command(export_file: &Path) -> String {
+    let export_file = export_file.display().to_string().replace('/', r"\");
+    format!("if exist \"{export_file}\" call \"{export_file}\"")
+}
 
-    if let Ok(libclang_path) = env::var("LIBCLANG_PATH") {
-        set_env_variable("LIBCLANG_PATH", &libclang_path)?;
+/// Returns the `AutoRun` value with the command appended, keeping the existing commands.
+pub fn add_autorun_command(autorun: &str, command: &str) -> String {
+    if autorun.trim().is_empty() {
+        command.to_string()
+    } else if autorun
+        .split(AUTORUN_SEPARATOR)
+        .any(|existing| existing.trim() == command)
+    {
+        autorun.to_string()
+    } else {
+        format!("{autorun}{AUTORUN_SEPARATOR}{command}")
     }
+}
 
-    if let Ok(libclang_bin_path) = env::var("LIBCLANG_BIN_PATH") {
-        let libclang_bin_path: &str = &libclang_bin_path;
-        if !path.contains(libclang_bin_path) {
-            path = format!("{};{}", libclang_bin_path, path);
-        }
+/// Returns the `AutoRun` value without the command, keeping the other commands.
+pub fn remove_autorun_command(autorun: &str, command: &str) -> String {
+    autorun
+        .split(AUTORUN_SEPARATOR)
+        .filter(|existing| existing.trim() != command)
+        .collect::<Vec<_>>()
+        .join(AUTORUN_SEPARATOR)
+}
+
+#[cfg(windows)]
+/// Registry key of the `cmd.exe` settings of the current user.
+const COMMAND_PROCESSOR_KEY: &str = r"Software\Microsoft\Command Processor";
+
+#[cfg(windows)]
+/// Runs the batch export file at the start of every `cmd.exe` session of the current user.
+pub fn register_cmd_autorun(export_file: &Path) -> Result<(), Error> {
+    let (key, _) = RegKey::predef(HKEY_CURRENT_USER).create_subkey(COMMAND_PROCESSOR_KEY)?;
+    let autorun: String = key.get_value("AutoRun").unwrap_or_default();
+    let updated = add_autorun_command(&autorun, &autorun_command(export_file));
+    if updated != autorun {
+        debug!("Setting the 'cmd.exe' AutoRun value to '{}'", updated);
+        key.set_value("AutoRun", &updated)?;
     }
+    Ok(())
+}
 
-    if let Ok(clang_path) = env::var("CLANG_PATH") {
-        let clang_path: &str = &clang_path;
-        if !path.contains(clang_path) {
-            path = format!("{};{}", clang_path, path);
+#[cfg(windows)]
+/// Stops running the batch export file at the start of the `cmd.exe` sessions, removing the
+/// `AutoRun` value when no other command remains.
+pub fn unregister_cmd_autorun(export_file: &Path) -> Result<(), Error> {
+    let Ok(key) = RegKey::predef(HKEY_CURRENT_USER)
+        .open_subkey_with_flags(COMMAND_PROCESSOR_KEY, KEY_READ | KEY_WRITE)
+    else {
+        return Ok(());
+    };
+    let Ok(autorun) = key.get_value::<String, _>("AutoRun") else {
+        return Ok(());
+    };
+    let updated = remove_autorun_command(&autorun, &autorun_command(export_file));
+    if updated.trim().is_empty() {
+        debug!("Removing the 'cmd.exe' AutoRun value");
+        key.delete_value("AutoRun")?;
+    } else if updated != autorun {
+        debug!("Setting the 'cmd.exe' AutoRun value to '{}'", updated);
+        key.set_value("AutoRun", &updated)?;
+    }
+    Ok(())
+}
+
+/// Writes the export file, falling back to printing the exports when the file can't be written.
+///
+/// Returns `true` if the export file was written.
+pub fn try_create_export_file(
+    export_file: &Path,
+    exports: &[Export],
+    mode: ExportFileMode,
+) -> Result<bool, Error> {
+    match create_export_file(export_file, exports, mode) {
+        Ok(()) => Ok(true),
+        Err(Error::ReadOnlyFile(_)) => {
+            warn!(
+                "Export file '{}' is read-only, skipping it. Use '--export-file' to choose a different location or '--no-modify-env' to skip this step",
+                export_file.display()
+            );
+            print_manual_env_instructions(exports, ExportShell::from_path(export_file));
+            Ok(false)
+        }
+        Err(Error::IoError(e)) if e.kind() == std::io::ErrorKind::PermissionDenied => {
+            warn!(
+                "Export file '{}' is not writable ({}), skipping it. Use '--export-file' to choose a different location or '--no-modify-env' to skip this step",
+                export_file.display(),
+                e
+            );
+            print_manual_env_instructions(exports, ExportShell::from_path(export_file));
+            Ok(false)
         }
+        Err(e) => Err(e),
     }
+}
 
-    set_env_variable("PATH", &path)?;
-    Ok(())
+/// Prints the exports so they can be set up manually, in the syntax of the given shell.
+pub fn print_manual_env_instructions(exports: &[Export], shell: ExportShell) {
+    println!("\n\tAdd the following environment variables to your environment manually:\n");
+    for export in exports.iter() {
+        println!("\t{}", export.render(shell));
+    }
+}
+
+/// Returns the name of the shell running an export file, for the instructions.
+#[cfg(windows)]
+fn shell_name(export_file: &Path) -> &'static str {
+    match ExportShell::from_path(export_file) {
+        ExportShell::Cmd => "cmd.exe",
+        _ => "PowerShell",
+    }
+}
+
+#[cfg(windows)]
+/// Instructions to set the environment variables when they are not persisted for the user.
+pub fn print_process_scope_msg(export_file: &Path) {
+    println!(
+        "\n\tYour user environment variables have not been modified. To set them up in the current {} session, run: '{}'",
+        shell_name(export_file),
+        export_file.display()
+    );
+    println!("\tThis step must be done every time you open a new terminal.");
 }
 
 /// Instructions to export the environment variables.
@@ -164,7 +897,7 @@ pub fn print_post_install_msg(export_file: &Path) -> Result<(), Error> {
             "\tA file was created at '{}' showing the injected environment variables",
             export_file.display()
         );
-        println!("\tIf you get still get errors, try manually adding the environment variables by running '{}'", export_file.display()
+        println!("\tIf you get still get errors, try manually adding the environment variables by running '{}' in {}", export_file.display(), shell_name(export_file)
         );
     }
     #[cfg(unix)]
@@ -182,22 +915,63 @@ pub fn print_post_install_msg(export_file: &Path) -> Result<(), Error> {
 
 #[cfg(test)]
 mod tests {
-    use crate::env::{create_export_file, get_export_file, DEFAULT_EXPORT_FILE};
+    use crate::{
+        env::{
+            add_autorun_command, autorun_command, create_export_file, environment, export_files,
+            get_export_file, migrate_legacy_dir, prepend_path_entry, remove_autorun_command,
+            remove_component_exports, remove_managed_exports, remove_path_entries,
+            render_environment, try_create_export_file, without_powershell, Export, ExportFileMode,
+            ExportShell, CMD_UTF8_END, CMD_UTF8_START, DEFAULT_EXPORT_FILE, MANAGED_BLOCK_END,
+            MANAGED_BLOCK_START, TOMBSTONE, UTF8_BOM,
+        },
+        error::Error,
+        report::OutputFormat,
+    };
     use directories::BaseDirs;
     use std::{
         env::current_dir,
-        fs::{create_dir_all, read_to_string},
-        path::PathBuf,
+        fs::{create_dir_all, read_to_string, set_permissions, write},
+        path::{Path, PathBuf},
     };
     use tempfile::TempDir;
 
+    #[test]
+    fn test_migrate_legacy_dir() {
+        let temp_dir = TempDir::new().unwrap();
+        let legacy = temp_dir.path().join(".espup");
+        let dir = temp_dir.path().join(".local/share/espup");
+
+        // Nothing to migrate
+        assert_eq!(migrate_legacy_dir(&legacy, &dir), dir);
+        assert!(!dir.exists());
+
+        create_dir_all(legacy.join("toolchains")).unwrap();
+        write(legacy.join("toolchains/esp.json"), "{}").unwrap();
+        assert_eq!(migrate_legacy_dir(&legacy, &dir), dir);
+        assert_eq!(
+            read_to_string(dir.join("toolchains/esp.json")).unwrap(),
+            "{}"
+        );
+        // The legacy paths keep working
+        #[cfg(unix)]
+        assert_eq!(
+            read_to_string(legacy.join("toolchains/esp.json")).unwrap(),
+            "{}"
+        );
+
+        // Already migrated
+        assert_eq!(migrate_legacy_dir(&legacy, &dir), dir);
+    }
+
     #[test]
     #[allow(unused_variables)]
     fn test_get_export_file() {
         // No arg provided
         let home_dir = BaseDirs::new().unwrap().home_dir().to_path_buf();
-        let export_file = home_dir.join(DEFAULT_EXPORT_FILE);
-        assert!(matches!(get_export_file(None), Ok(export_file)));
+        assert_eq!(
+            get_export_file(None).unwrap(),
+            home_dir.join(DEFAULT_EXPORT_FILE)
+        );
         // Relative path
         let current_dir = current_dir().unwrap();
         let export_file = current_dir.join("export.sh");
@@ -221,21 +995,388 @@ mod tests {
         let temp_dir = TempDir::new().unwrap();
         let export_file = temp_dir.path().join("export.sh");
         let exports = vec![
-            "export VAR1=value1".to_string(),
-            "export VAR2=value2".to_string(),
+            Export::set("llvm", "VAR1", "value1"),
+            Export::set("llvm", "VAR2", "value2"),
         ];
-        create_export_file(&export_file, &exports).unwrap();
-        let contents = read_to_string(export_file).unwrap();
-        assert_eq!(contents, "export VAR1=value1\nexport VAR2=value2\n");
+        create_export_file(&export_file, &exports, ExportFileMode::Merge).unwrap();
+        let block = format!(
+            "{MANAGED_BLOCK_START}\n{}\n{}\n{MANAGED_BLOCK_END}\n",
+            exports[0].to_shell(),
+            exports[1].to_shell()
+        );
+        assert_eq!(read_to_string(&export_file).unwrap(), block);
+
+        // Hand-added lines are kept when merging
+        write(
+            &export_file,
+            format!("alias ll='ls -l'\n{block}. ~/custom.sh\n"),
+        )
+        .unwrap();
+        let exports = vec![Export::set("llvm", "VAR3", "value3")];
+        create_export_file(&export_file, &exports, ExportFileMode::Merge).unwrap();
+        let block = format!(
+            "{MANAGED_BLOCK_START}\n{}\n{MANAGED_BLOCK_END}\n",
+            exports[0].to_shell()
+        );
+        assert_eq!(
+            read_to_string(&export_file).unwrap(),
+            format!("alias ll='ls -l'\n{block}. ~/custom.sh\n")
+        );
+
+        // Blocks without end marker are left untouched
+        let unterminated =
+            format!("{MANAGED_BLOCK_START}\nexport VAR3=\"value3\"\n. ~/custom.sh\n");
+        write(&export_file, &unterminated).unwrap();
+        assert!(matches!(
+            create_export_file(&export_file, &exports, ExportFileMode::Merge),
+            Err(Error::UnterminatedManagedBlock(_))
+        ));
+        assert!(matches!(
+            remove_managed_exports(&export_file, Path::new("/opt/esp")),
+            Err(Error::UnterminatedManagedBlock(_))
+        ));
+        assert_eq!(read_to_string(&export_file).unwrap(), unterminated);
+
+        // Reused export files are left untouched
+        create_export_file(&export_file, &[], ExportFileMode::Reuse).unwrap();
+        assert!(read_to_string(&export_file).unwrap().contains("VAR3"));
+
+        // Regenerated export files only contain the managed block
+        create_export_file(&export_file, &exports, ExportFileMode::Regenerate).unwrap();
+        assert_eq!(read_to_string(&export_file).unwrap(), block);
 
         // Returns the correct error when it fails to create the export file (it already exists)
         let temp_dir = TempDir::new().unwrap();
         let export_file = temp_dir.path().join("export.sh");
         create_dir_all(&export_file).unwrap();
+        assert!(create_export_file(&export_file, &exports, ExportFileMode::Merge).is_err());
+    }
+
+    #[test]
+    fn test_environment() {
+        let exports = vec![
+            Export::prepend_path("xtensa-rust", "/opt/esp/bin"),
+            Export::set("llvm", "LIBCLANG_PATH", "/opt/esp/esp-clang/lib"),
+            Export::prepend_path("xtensa-gcc", "/opt/esp/xtensa-esp-elf/bin"),
+            Export::source("llvm", "/home/user/.espup/env.sh"),
+        ];
+        let pure = environment(&exports, None);
+        assert_eq!(
+            render_environment(&pure, OutputFormat::Text),
+            if cfg!(windows) {
+                "LIBCLANG_PATH=/opt/esp/esp-clang/lib\nPATH=/opt/esp/xtensa-esp-elf/bin;/opt/esp/bin\n"
+            } else {
+                "LIBCLANG_PATH=/opt/esp/esp-clang/lib\nPATH=/opt/esp/xtensa-esp-elf/bin:/opt/esp/bin\n"
+            }
+        );
+        let json: serde_json::Value =
+            serde_json::from_str(&render_environment(&pure, OutputFormat::Json)).unwrap();
+        assert_eq!(json["LIBCLANG_PATH"], "/opt/esp/esp-clang/lib");
+
+        let inherited = environment(&exports, Some("/usr/bin"));
+        assert!(inherited["PATH"].ends_with("/opt/esp/bin:/usr/bin") || cfg!(windows));
+        assert!(environment(&[], None).is_empty());
+    }
+
+    #[test]
+    fn test_remove_managed_exports() {
+        let temp_dir = TempDir::new().unwrap();
+        let export_file = temp_dir.path().join("export.sh");
+        let exports = vec![Export::set("llvm", "VAR1", "value1")];
+
+        // Hand-added lines are kept
+        create_export_file(&export_file, &exports, ExportFileMode::Merge).unwrap();
+        let content = read_to_string(&export_file).unwrap();
+        write(&export_file, format!("alias ll='ls -l'\n{content}")).unwrap();
+        let toolchain_dir = temp_dir.path().join("toolchains/esp");
+        remove_managed_exports(&export_file, &toolchain_dir).unwrap();
+        assert_eq!(read_to_string(&export_file).unwrap(), "alias ll='ls -l'\n");
+
+        // Files without a managed block are left untouched
+        remove_managed_exports(&export_file, &toolchain_dir).unwrap();
+        assert_eq!(read_to_string(&export_file).unwrap(), "alias ll='ls -l'\n");
+
+        // Unless written by older versions for the uninstalled toolchain, not one next to it
+        let other = format!("export PATH=\"{}2/bin:$PATH\"\n", toolchain_dir.display());
+        write(&export_file, &other).unwrap();
+        remove_managed_exports(&export_file, &toolchain_dir).unwrap();
+        assert_eq!(read_to_string(&export_file).unwrap(), other);
+        let legacy = format!(
+            "export PATH=\"{}/bin:$PATH\"\n\nexport LIBCLANG_PATH=\"/lib\"\n",
+            toolchain_dir.display()
+        );
+        write(&export_file, &legacy).unwrap();
+        remove_managed_exports(&export_file, &toolchain_dir).unwrap();
+        remove_managed_exports(&export_file, &toolchain_dir).unwrap();
+        assert_eq!(
+            read_to_string(&export_file).unwrap(),
+            format!(
+                "{TOMBSTONE}\n# export PATH=\"{}/bin:$PATH\"\n# export LIBCLANG_PATH=\"/lib\"\n",
+                toolchain_dir.display()
+            )
+        );
+
+        // Files only containing the managed block are deleted
+        create_export_file(&export_file, &exports, ExportFileMode::Regenerate).unwrap();
+        remove_managed_exports(&export_file, &toolchain_dir).unwrap();
+        assert!(!export_file.exists());
+    }
+
+    #[test]
+    fn test_export() {
+        let mut exports = vec![
+            Export::prepend_path("xtensa-gcc", "/home/user/.rustup/toolchains/esp/bin"),
+            Export::set("llvm", "LIBCLANG_PATH", "C:/esp/libclang.dll"),
+            Export::source("llvm", "/home/user/.espup/env.sh"),
+        ];
+        assert_eq!(
+            exports[0].to_posix(),
+            "export PATH=\"/home/user/.rustup/toolchains/esp/bin:$PATH\""
+        );
+        assert_eq!(
+            exports[1].to_posix(),
+            "export LIBCLANG_PATH=\"C:/esp/libclang.dll\""
+        );
+        assert_eq!(exports[2].to_posix(), ". \"/home/user/.espup/env.sh\"");
+        assert_eq!(
+            exports[1].to_powershell(),
+            "$Env:LIBCLANG_PATH = \"C:\\esp\\libclang.dll\""
+        );
+
+        remove_component_exports(&mut exports, "llvm");
+        assert_eq!(exports.len(), 1);
+        assert_eq!(exports[0].component, "xtensa-gcc");
+    }
+
+    #[test]
+    fn test_try_create_export_file() {
+        let exports = vec![Export::set("llvm", "VAR1", "value1")];
+
+        // Writable export file
+        let temp_dir = TempDir::new().unwrap();
+        let export_file = temp_dir.path().join("export.sh");
+        assert!(try_create_export_file(&export_file, &exports, ExportFileMode::Merge).unwrap());
+        assert!(read_to_string(&export_file)
+            .unwrap()
+            .contains(&exports[0].to_shell()));
+
+        // Read-only export file is skipped and left untouched
+        write(&export_file, "managed elsewhere").unwrap();
+        let mut permissions = export_file.metadata().unwrap().permissions();
+        permissions.set_readonly(true);
+        set_permissions(&export_file, permissions).unwrap();
+        assert!(!try_create_export_file(&export_file, &exports, ExportFileMode::Merge).unwrap());
+        assert_eq!(read_to_string(&export_file).unwrap(), "managed elsewhere");
+    }
+
+    #[test]
+    fn test_remove_path_entries() {
+        let path = r"C:\Users\user\.rustup\toolchains\esp\xtensa-esp32-elf-clang\esp-18.1.2_20240912\esp-clang\bin;C:\Windows;;c:/users/user/.rustup/toolchains/esp/xtensa-esp-elf/esp-14.2.0_20240906/xtensa-esp-elf/bin\;C:\Users\user\.rustup\toolchains\esp-other\bin;";
+        let dirs = vec![
+            r"C:\Users\user\.rustup\toolchains\esp\xtensa-esp32-elf-clang".to_string(),
+            "C:/Users/user/.rustup/toolchains/esp/xtensa-esp-elf/".to_string(),
+        ];
+        assert_eq!(
+            remove_path_entries(path, &dirs),
+            r"C:\Windows;C:\Users\user\.rustup\toolchains\esp-other\bin"
+        );
+        // Prefixes of other directories are not removed
+        let dirs = vec![r"C:\Users\user\.rustup\toolchains\esp".to_string()];
+        assert_eq!(
+            remove_path_entries(path, &dirs),
+            r"C:\Windows;C:\Users\user\.rustup\toolchains\esp-other\bin"
+        );
+    }
+
+    #[test]
+    fn test_prepend_path_entry() {
+        let path = r"C:\Users\José\.cargo\bin;C:\Windows";
+        let dir = "C:/Users/José/.rustup/toolchains/esp/xtensa-esp-elf/bin";
+        let prepended = prepend_path_entry(path, dir);
+        assert_eq!(
+            prepended,
+            r"C:\Users\José\.rustup\toolchains\esp\xtensa-esp-elf\bin;C:\Users\José\.cargo\bin;C:\Windows"
+        );
+        // Entries are compared case insensitively, including the non-ASCII characters
+        assert_eq!(
+            prepend_path_entry(
+                &prepended,
+                r"C:\USERS\JOSÉ\.rustup\toolchains\esp\xtensa-esp-elf\bin\"
+            ),
+            prepended
+        );
+        // Prefixes of existing entries are still prepended
+        assert_eq!(
+            prepend_path_entry(path, r"C:\Users\José\.cargo"),
+            format!(r"C:\Users\José\.cargo;{path}")
+        );
+        assert_eq!(prepend_path_entry("", r"C:\bin"), r"C:\bin");
+
+        let dirs = vec![r"C:\Users\JOSÉ\.rustup\toolchains\esp".to_string()];
+        assert_eq!(remove_path_entries(&prepended, &dirs), path);
+    }
+
+    #[test]
+    fn test_non_ascii_export_files() {
+        let temp_dir = TempDir::new().unwrap();
+        let profile = temp_dir.path().join("José");
+        create_dir_all(&profile).unwrap();
+        let lib = profile.join(".espup/esp-clang/lib").display().to_string();
+        let exports = vec![Export::set("llvm", "LIBCLANG_PATH", &lib)];
+
+        // PowerShell scripts start with a byte order mark, kept once when merged
+        let powershell = profile.join("export-esp.ps1");
+        create_export_file(&powershell, &exports, ExportFileMode::Merge).unwrap();
+        create_export_file(&powershell, &exports, ExportFileMode::Merge).unwrap();
+        let content = read_to_string(&powershell).unwrap();
+        assert!(content.starts_with(&format!("{UTF8_BOM}{MANAGED_BLOCK_START}\n")));
+        assert_eq!(content.matches(UTF8_BOM).count(), 1);
+        assert!(content.contains(&format!(
+            "$Env:LIBCLANG_PATH = \"{}\"",
+            lib.replace('/', r"\")
+        )));
+        remove_managed_exports(&powershell, temp_dir.path()).unwrap();
+        assert!(!powershell.exists());
+
+        // Batch files switch to UTF-8 while setting the variables
+        let cmd = profile.join("export-esp.bat");
+        create_export_file(&cmd, &exports, ExportFileMode::Merge).unwrap();
+        let content = read_to_string(&cmd).unwrap();
+        let lines: Vec<&str> = content.lines().collect();
+        assert_eq!(lines[1..3], CMD_UTF8_START);
+        assert_eq!(lines[4..6], CMD_UTF8_END);
+        assert_eq!(
+            lines[3],
+            format!("@set \"LIBCLANG_PATH={}\"", lib.replace('/', r"\"))
+        );
+
+        // Other export files are plain UTF-8
+        let posix = profile.join("export-esp.sh");
+        create_export_file(&posix, &exports, ExportFileMode::Merge).unwrap();
+        assert!(read_to_string(&posix)
+            .unwrap()
+            .starts_with(MANAGED_BLOCK_START));
+
+        // ASCII export files are unchanged
+        let exports = vec![Export::set("llvm", "LIBCLANG_PATH", "C:/esp-clang/lib")];
+        create_export_file(&powershell, &exports, ExportFileMode::Regenerate).unwrap();
+        create_export_file(&cmd, &exports, ExportFileMode::Regenerate).unwrap();
+        assert!(read_to_string(&powershell)
+            .unwrap()
+            .starts_with(MANAGED_BLOCK_START));
+        assert_eq!(read_to_string(&cmd).unwrap().lines().count(), 3);
+    }
+
+    #[test]
+    fn test_export_files_for_shells() {
+        let temp_dir = TempDir::new().unwrap();
+        let export_file = temp_dir.path().join("export-esp.sh");
+        let files = export_files(
+            &export_file,
+            &[
+                ExportShell::Fish,
+                ExportShell::Bash,
+                ExportShell::PowerShell,
+            ],
+        );
+        assert_eq!(
+            files,
+            vec![
+                export_file.clone(),
+                temp_dir.path().join("export-esp.fish"),
+                temp_dir.path().join("export-esp.ps1"),
+            ]
+        );
+
+        let exports = vec![
+            Export::prepend_path("xtensa-gcc", "/home/user/.rustup/toolchains/esp/bin"),
+            Export::set("llvm", "LIBCLANG_PATH", "/home/user/.espup/esp-clang/lib"),
+        ];
+        for file in &files {
+            create_export_file(file, &exports, ExportFileMode::Merge).unwrap();
+        }
+        let fish = read_to_string(&files[1]).unwrap();
+        assert!(fish.contains("set -gx PATH \"/home/user/.rustup/toolchains/esp/bin\" $PATH\n"));
+        assert!(fish.contains("set -gx LIBCLANG_PATH \"/home/user/.espup/esp-clang/lib\"\n"));
+        let powershell = read_to_string(&files[2]).unwrap();
+        assert!(powershell.contains("$Env:LIBCLANG_PATH = "));
+        let posix = read_to_string(&files[0]).unwrap();
+        assert!(posix.contains("export LIBCLANG_PATH=\"/home/user/.espup/esp-clang/lib\"\n"));
+    }
+
+    #[test]
+    fn test_cmd_export_file() {
+        let temp_dir = TempDir::new().unwrap();
+        let export_file = temp_dir.path().join("export-esp.bat");
+        assert_eq!(ExportShell::from_path(&export_file), ExportShell::Cmd);
         let exports = vec![
-            "export VAR1=value1".to_string(),
-            "export VAR2=value2".to_string(),
+            Export::prepend_path("xtensa-gcc", "C:/Users/user/.rustup/toolchains/esp/bin"),
+            Export::set(
+                "llvm",
+                "LIBCLANG_PATH",
+                "C:/Users/user/.espup/esp-clang/lib",
+            ),
         ];
-        assert!(create_export_file(&export_file, &exports).is_err());
+        create_export_file(&export_file, &exports, ExportFileMode::Merge).unwrap();
+        let block = format!(
+            "@rem {MANAGED_BLOCK_START}\n\
+             @set \"PATH=C:\\Users\\user\\.rustup\\toolchains\\esp\\bin;%PATH%\"\n\
+             @set \"LIBCLANG_PATH=C:\\Users\\user\\.espup\\esp-clang\\lib\"\n\
+             @rem {MANAGED_BLOCK_END}\n"
+        );
+        assert_eq!(read_to_string(&export_file).unwrap(), block);
+
+        // The block is replaced as a whole, its delimiters being prefixed by the comment syntax
+        write(
+            &export_file,
+            format!("@set \"IDF_PATH=C:\\esp-idf\"\n{block}"),
+        )
+        .unwrap();
+        create_export_file(&export_file, &exports[1..], ExportFileMode::Merge).unwrap();
+        let content = read_to_string(&export_file).unwrap();
+        assert!(content.starts_with("@set \"IDF_PATH=C:\\esp-idf\"\n@rem # >>>"));
+        assert!(!content.contains("%PATH%"));
+        remove_managed_exports(&export_file, temp_dir.path()).unwrap();
+        assert_eq!(
+            read_to_string(&export_file).unwrap(),
+            "@set \"IDF_PATH=C:\\esp-idf\"\n"
+        );
+    }
+
+    #[test]
+    fn test_without_powershell() {
+        let (export_file, shells) = without_powershell(
+            &PathBuf::from("C:/Users/user/export-esp.ps1"),
+            &[ExportShell::PowerShell, ExportShell::Bash],
+        );
+        assert_eq!(export_file, PathBuf::from("C:/Users/user/export-esp.bat"));
+        assert_eq!(shells, vec![ExportShell::Bash]);
+
+        let (export_file, _) = without_powershell(&PathBuf::from("/home/user/export-esp.sh"), &[]);
+        assert_eq!(export_file, PathBuf::from("/home/user/export-esp.sh"));
+    }
+
+    #[test]
+    fn test_autorun_command() {
+        let command = autorun_command(&PathBuf::from("C:/Users/user/export-esp.bat"));
+        assert_eq!(
+            command,
+            r#"if exist "C:\Users\user\export-esp.bat" call "C:\Users\user\export-esp.bat""#
+        );
+        assert_eq!(add_autorun_command("", &command), command);
+        let autorun = add_autorun_command("doskey /macrofile=macros.txt", &command);
+        assert_eq!(autorun, format!("doskey /macrofile=macros.txt & {command}"));
+        // Registering twice is a no-op
+        assert_eq!(add_autorun_command(&autorun, &command), autorun);
+
+        assert_eq!(
+            remove_autorun_command(&autorun, &command),
+            "doskey /macrofile=macros.txt"
+        );
+        assert_eq!(remove_autorun_command(&command, &command), "");
+        assert_eq!(
+            remove_autorun_command("doskey /macrofile=macros.txt", &command),
+            "doskey /macrofile=macros.txt"
+        );
     }
 }
@@ -2,27 +2,56 @@
 
 #[derive(Debug, miette::Diagnostic, thiserror::Error)]
 pub enum Error {
+    #[diagnostic(
+        code(espup::toolchain::rustup::concurrent_rustup),
+        help("Wait for it to finish, or use '--concurrent-rustup wait'")
+    )]
+    #[error("Another rustup process is running: {0}")]
+    ConcurrentRustup(String),
+
     #[diagnostic(code(espup::toolchain::create_directory))]
     #[error("Creating directory '{0}' failed")]
     CreateDirectory(String),
 
-    #[diagnostic(code(espup::toolchain::rust::query_github))]
-    #[error("Failed to query GitHub API: Rate Limiting")]
-    GithubRateLimit,
+    #[diagnostic(
+        code(espup::auth::credential_store),
+        help("Pass the credentials through the environment instead, e.g. 'GITHUB_TOKEN'")
+    )]
+    #[error("Failed to access the credential store: {0}")]
+    CredentialStore(String),
+
+    #[diagnostic(
+        code(espup::toolchain::rust::query_github),
+        help("Set the GITHUB_TOKEN environment variable, or store a token with 'espup auth set-github', to get a higher rate limit")
+    )]
+    #[error("Failed to query GitHub API: Rate Limiting, the limit resets in {0} seconds")]
+    GithubRateLimit(u64),
 
     #[diagnostic(code(espup::toolchain::rust::query_github))]
     #[error("Failed to query GitHub API: Invalid Github token")]
     GithubTokenInvalid,
 
     #[diagnostic(code(espup::toolchain::rust::install_riscv_target))]
-    #[error("Failed to Install RISC-V targets for '{0}' toolchain")]
-    InstallRiscvTarget(String),
+    #[error("Failed to Install RISC-V targets for '{0}' toolchain: {1}")]
+    InstallRiscvTarget(String, String),
 
     #[diagnostic(code(espup::ivalid_destination))]
     #[error(
         "Invalid export file destination: '{0}'. Please, use an absolute or releative path (including the file and its extension)")]
     InvalidDestination(String),
 
+    #[diagnostic(code(espup::pin::invalid_toolchain_version_file))]
+    #[error("Failed to read the Xtensa Rust version from '{0}': {1}")]
+    InvalidToolchainVersionFile(String, String),
+
+    #[diagnostic(code(espup::auth::invalid_mirror))]
+    #[error("Invalid mirror '{0}', its URL has no host")]
+    InvalidMirror(String),
+
+    #[diagnostic(code(espup::toolchain::rust::invalid_toolchain_name))]
+    #[error("Invalid toolchain name '{0}': {1}")]
+    InvalidToolchainName(String, String),
+
     #[diagnostic(code(espup::toolchain::rust::invalid_version))]
     #[error(
         "Invalid toolchain version '{0}'. Verify that the format is correct: '<major>.<minor>.<patch>.<subpatch>' or '<major>.<minor>.<patch>', and that the release exists in https://github.com/esp-rs/rust-build/releases")]
@@ -31,14 +60,195 @@ pub enum Error {
     #[error(transparent)]
     IoError(#[from] std::io::Error),
 
+    #[diagnostic(code(espup::manifest::invalid_manifest))]
+    #[error("Failed to parse the installation manifest '{0}'")]
+    InvalidManifest(String),
+
+    #[diagnostic(
+        code(espup::manifest::unsupported_manifest_version),
+        help("The manifest was written by a newer espup version, please update espup")
+    )]
+    #[error("Installation manifest '{0}' uses the unsupported schema version {1}")]
+    UnsupportedManifestVersion(String, u32),
+
+    #[diagnostic(
+        code(espup::toolchain::hashes::missing_hashes),
+        help("Reinstall it with '--record-hashes' to record them")
+    )]
+    #[error("No file hashes were recorded for the '{0}' toolchain")]
+    MissingHashes(String),
+
+    #[diagnostic(code(espup::toolchain::hashes::modified_toolchain))]
+    #[error(
+        "{1} files of the '{0}' toolchain were modified, removed or added since its installation"
+    )]
+    ModifiedToolchain(String, usize),
+
+    #[diagnostic(
+        code(espup::auth::missing_secret),
+        help("Type it when prompted, or pipe it, e.g. 'echo \"$TOKEN\" | espup auth set-github'")
+    )]
+    #[error("No {0} was given")]
+    MissingSecret(String),
+
+    #[diagnostic(code(espup::manifest::missing_manifest))]
+    #[error(
+        "No installation manifest found for the '{0}' toolchain. Please, run 'espup install' first"
+    )]
+    MissingManifest(String),
+
+    #[diagnostic(
+        code(espup::toolchain::llvm::missing_llvm_version),
+        help("Installed versions: {1}")
+    )]
+    #[error("LLVM {0} is not installed")]
+    MissingLlvmVersion(String, String),
+
+    #[diagnostic(code(espup::toolchain::no_targets_left))]
+    #[error("Removing the targets would leave the '{0}' toolchain without targets. Please, use 'espup uninstall' instead")]
+    NoTargetsLeft(String),
+
+    #[diagnostic(code(espup::pin::targets_not_installed))]
+    #[error("Targets '{1}' are not installed in the '{0}' toolchain. Please, run 'espup targets add' first")]
+    TargetsNotInstalled(String, String),
+
+    #[diagnostic(
+        code(espup::toolchain::verify::broken_rust_src),
+        help("Reinstall it with `espup reinstall rust-src --name {0}`")
+    )]
+    #[error("The 'rust-src' component of the '{0}' toolchain is broken: {1}")]
+    BrokenRustSrc(String, String),
+
+    #[diagnostic(
+        code(espup::toolchain::preflight::preflight_failed),
+        help("Publish the missing artifacts and checksums before announcing the release")
+    )]
+    #[error("{1} artifacts of Xtensa Rust {0} are missing or without checksum")]
+    PreflightFailed(String, usize),
+
+    #[diagnostic(code(espup::toolchain::verify::verify_build))]
+    #[error("Build verification failed at the '{0}' stage:\n{1}")]
+    VerifyBuild(String, String),
+
+    #[diagnostic(code(espup::toolchain::verify::missing_dependency), help("{2}"))]
+    #[error("{0} can't be executed in this system:\n{1}")]
+    MissingDependency(String, String, String),
+
+    #[diagnostic(code(espup::toolchain::fetch::network_unreachable))]
+    #[error(
+        "Unable to reach '{0}': {1}. Please, check your network connection and proxy configuration"
+    )]
+    NetworkUnreachable(String, String),
+
+    #[diagnostic(
+        code(espup::toolchain::fetch::redirect_target_unreachable),
+        help("Allow the access to '{0}' in your network settings, or download the assets from a mirror with '--asset-host-override'")
+    )]
+    #[error("Unable to reach '{0}', where '{1}' redirected the download: {2}")]
+    RedirectTargetUnreachable(String, String, String),
+
+    #[diagnostic(
+        code(espup::toolchain::artifact_not_published),
+        help("Check the `--default-host`, `--artifact-arch` and `--toolchain-version` arguments")
+    )]
+    #[error("{0} is not published for this host and version: '{1}' was not found")]
+    ArtifactNotPublished(String, String),
+
+    #[diagnostic(
+        code(espup::toolchain::fetch::invalid_proxy_route),
+        help("Use 'host=url', e.g. 'github.com=http://proxy:3128', or 'host=direct' to bypass the proxies")
+    )]
+    #[error("Invalid proxy route: '{0}'")]
+    InvalidProxyRoute(String),
+
+    #[diagnostic(
+        code(espup::toolchain::fetch::download_stalled),
+        help("Add a faster source with '--mirror'")
+    )]
+    #[error("Download of '{0}' stalled, no data was received for {1} seconds")]
+    DownloadStalled(String, u64),
+
+    #[diagnostic(code(espup::toolchain::offline::download_task))]
+    #[error("Download task failed: {0}")]
+    DownloadTask(String),
+
+    #[diagnostic(
+        code(espup::device::detection),
+        help("Check that the device is connected, or select the targets with '--targets'")
+    )]
+    #[error("Failed to detect the chip of the connected device: {0}")]
+    DeviceDetection(String),
+
+    #[diagnostic(
+        code(espup::device::missing_espflash),
+        help("Install it with 'cargo install espflash --locked', or select the targets with '--targets'")
+    )]
+    #[error("'espflash' is required to detect the connected device")]
+    MissingEspflash,
+
+    #[diagnostic(
+        code(espup::toolchain::overrides::invalid_artifact_override),
+        help("Use 'artifact=path', e.g. 'llvm=/path/to/clang.tar.xz'")
+    )]
+    #[error("Invalid artifact override '{0}': {1}")]
+    InvalidArtifactOverride(String, String),
+
+    #[diagnostic(
+        code(espup::ldproxy::installation),
+        help("Install it manually with 'cargo install ldproxy --locked'")
+    )]
+    #[error("Failed to install ldproxy: {0}")]
+    LdproxyInstallation(String),
+
     #[diagnostic(code(espup::toolchain::rust::missing_rust))]
     #[error("Rust is not installed. Please, install Rust via rustup: https://rustup.rs/")]
     MissingRust,
 
+    #[diagnostic(
+        code(espup::generate::file_exists),
+        help("Use '--force' to overwrite it")
+    )]
+    #[error("File '{0}' already exists")]
+    FileExists(String),
+
     #[diagnostic(code(espup::remove_directory))]
     #[error("Failed to remove '{0}'")]
     RemoveDirectory(String),
 
+    #[diagnostic(
+        code(espup::directory_in_use),
+        help(
+            "Close the programs using it and try again, or run `espup uninstall --remove-on-reboot` to remove it on the next reboot"
+        )
+    )]
+    #[error("Failed to remove '{0}', it is in use by {1}")]
+    DirectoryInUse(String, String),
+
+    #[diagnostic(
+        code(espup::toolchain::foreign_prefix),
+        help("Use a new or empty directory, e.g. a subdirectory of '{0}'")
+    )]
+    #[error("'{0}' is not empty and was not created by espup")]
+    ForeignPrefix(String),
+
+    #[diagnostic(
+        code(espup::schedule_removal),
+        help("Scheduling removals on reboot requires administrator privileges")
+    )]
+    #[error("Failed to schedule the removal of '{0}' on reboot")]
+    ScheduleRemoval(String),
+
+    #[diagnostic(code(espup::env::read_only_file))]
+    #[error("File '{0}' is read-only")]
+    ReadOnlyFile(String),
+
+    #[diagnostic(
+        code(espup::env::unterminated_managed_block),
+        help("Restore the `# <<< espup managed block <<<` line after the espup exports, or remove the block")
+    )]
+    #[error("Export file '{0}' has an espup managed block without end marker")]
+    UnterminatedManagedBlock(String),
+
     #[error(transparent)]
     RewquestError(#[from] reqwest::Error),
 
@@ -51,26 +261,87 @@ pub enum Error {
     SerializeJson,
 
     #[diagnostic(code(espup::toolchain::rust::uninstall_riscv_target))]
-    #[error("Failed to uninstall RISC-V target")]
-    UninstallRiscvTarget,
+    #[error("Failed to uninstall RISC-V target: {0}")]
+    UninstallRiscvTarget(String),
 
     #[diagnostic(code(espup::toolchain::unsupported_file_extension))]
     #[error("Unsuported file extension: '{0}'")]
     UnsuportedFileExtension(String),
 
+    #[diagnostic(code(espup::toolchain::compatibility::unsupported_esp_idf_version))]
+    #[error("ESP-IDF version '{0}' is not supported, the supported release lines are: {1}")]
+    UnsupportedEspIdfVersion(String, String),
+
     #[diagnostic(code(espup::host_triple::unsupported_host_triple))]
     #[error("Host triple '{0}' is not supported")]
     UnsupportedHostTriple(String),
 
+    #[diagnostic(
+        code(espup::host_triple::foreign_host_triple),
+        help("Check the `--default-host` argument, or use `--allow-foreign-host` to install its artifacts anyway")
+    )]
+    #[error("Host triple '{0}' doesn't match the running system ('{1}'), the installed artifacts won't run")]
+    ForeignHostTriple(String, String),
+
+    #[diagnostic(
+        code(espup::host_triple::unsupported_artifact),
+        help("Only the RISC-V targets, without `--esp-riscv-gcc`, can be installed on this host")
+    )]
+    #[error("{0} is not available for '{1}' hosts")]
+    UnsupportedArtifact(String, String),
+
+    #[diagnostic(
+        code(espup::toolchain::rust::unsupported_component),
+        help("Check the components available with 'rustup component list'")
+    )]
+    #[error("Rustup component '{0}' is not supported")]
+    UnsupportedComponent(String),
+
+    #[diagnostic(
+        code(espup::targets::experimental_targets),
+        help("Use `--experimental-targets` to install them, their toolchain support may be incomplete")
+    )]
+    #[error("Targets {0} are experimental")]
+    ExperimentalTargets(String),
+
     #[diagnostic(code(espup::targets::unsupported_target))]
     #[error("Target '{0}' is not supported")]
     UnsupportedTarget(String),
 
+    #[diagnostic(code(espup::targets::unknown_target))]
+    #[error("Target '{0}' is not supported, did you mean '{1}'?")]
+    UnknownTarget(String, String),
+
+    #[diagnostic(
+        code(espup::toolchain::antivirus::quarantined),
+        help("Exclude '{0}' from the antivirus real-time scanning (e.g. run `Add-MpPreference -ExclusionPath '{0}'` in an elevated PowerShell) and run espup again")
+    )]
+    #[error("Extracted files were removed, likely quarantined by an antivirus: {1}")]
+    Quarantined(String, String),
+
+    #[diagnostic(code(espup::toolchain::rust::select_toolchain))]
+    #[error("Failed to select the '{0}' toolchain with rustup: {1}")]
+    SelectToolchain(String, String),
+
     #[diagnostic(code(espup::toolchain::rust::rust))]
-    #[error("Failed to install 'rust' component of Xtensa Rust")]
-    XtensaRust,
+    #[error("Failed to install 'rust' component of Xtensa Rust: {0}")]
+    XtensaRust(String),
 
     #[diagnostic(code(espup::toolchain::rust::rust_src))]
-    #[error("Failed to install 'rust-src' component of Xtensa Rust")]
-    XtensaRustSrc,
+    #[error("Failed to install 'rust-src' component of Xtensa Rust: {0}")]
+    XtensaRustSrc(String),
+
+    #[diagnostic(
+        code(espup::toolchain::rust::missing_xtensa_component),
+        help("Install the toolchain without it, or select another version with `--toolchain-version`")
+    )]
+    #[error("Component '{0}' is not shipped with Xtensa Rust {1}")]
+    MissingXtensaComponent(String, String),
+
+    #[diagnostic(
+        code(espup::sandbox::write_outside_sandbox),
+        help("Only the prefix, the export files and the directories set with 'ESPUP_DATA_DIR' and 'ESPUP_CACHE_DIR' are writable in sandbox mode")
+    )]
+    #[error("Writing '{0}' is not allowed in sandbox mode")]
+    WriteOutsideSandbox(String),
 }
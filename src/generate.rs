@@ -0,0 +1,232 @@
+//! Generation of container definitions reproducing an installation.
+//!
+//! The generated Dockerfile installs the same espup release and runs `espup install` with the
+//! versions pinned in the installation manifest, so containerized builds (e.g. CI) use the same
+//! toolchains as the local installation.
+
+use crate::{cli::DockerOpts, error::Error, manifest::Manifest};
+use log::info;
+use miette::Result;
+use serde_json::json;
+use std::{
+    collections::BTreeSet,
+    fs::{create_dir_all, write},
+    path::Path,
+};
+
+/// User the toolchains are installed for in the container.
+const CONTAINER_USER: &str = "esp";
+/// Base image of the generated Dockerfile.
+const BASE_IMAGE: &str = "debian:bookworm-slim";
+/// Packages required to build `no_std` applications.
+const PACKAGES: [&str; 7] = [
+    "build-essential",
+    "ca-certificates",
+    "curl",
+    "git",
+    "libudev-dev",
+    "pkg-config",
+    "xz-utils",
+];
+/// Additional packages required by the ESP-IDF build of `std` applications.
+const STD_PACKAGES: [&str; 6] = [
+    "cmake",
+    "libusb-1.0-0",
+    "ninja-build",
+    "python3",
+    "python3-pip",
+    "python3-venv",
+];
+
+/// Installation reproduced by the generated container.
+#[derive(Debug, Clone)]
+pub struct ContainerSpec {
+    /// espup version installed in the container.
+    pub espup_version: String,
+    /// Installation manifest.
+    pub manifest: Manifest,
+}
+
+impl ContainerSpec {
+    /// Returns the `espup install` arguments reproducing the installation.
+    fn install_args(&self) -> Vec<String> {
+        let manifest = &self.manifest;
+        let targets: BTreeSet<String> = manifest.targets.iter().map(|t| t.to_string()).collect();
+        let mut args = vec![
+            format!("--name {}", manifest.name),
+            format!("--targets {}", Vec::from_iter(targets).join(",")),
+            format!("--toolchain-version {}", manifest.toolchain_version),
+            "--skip-version-parse".to_string(),
+            format!("--nightly-version {}", manifest.nightly_version),
+            format!("--export-file /home/{CONTAINER_USER}/export-esp.sh"),
+        ];
+        if manifest.std {
+            args.push("--std".to_string());
+        }
+        if manifest.esp_riscv_gcc {
+            args.push("--esp-riscv-gcc".to_string());
+        }
+        if manifest.extended_llvm {
+            args.push("--extended-llvm".to_string());
+        }
+        if manifest.targets.iter().any(|t| t.is_experimental()) {
+            args.push("--experimental-targets".to_string());
+        }
+        if manifest.riscv_profile != "minimal" {
+            args.push(format!("--riscv-profile {}", manifest.riscv_profile));
+        }
+        if !manifest.riscv_components.is_empty() {
+            args.push(format!(
+                "--riscv-components {}",
+                manifest.riscv_components.join(",")
+            ));
+        }
+        if let Some(components) = &manifest.xtensa_components {
+            let components: Vec<String> = components.iter().map(ToString::to_string).collect();
+            // Without a value, none of the optional components are installed
+            args.push(match components.is_empty() {
+                true => "--xtensa-components".to_string(),
+                false => format!("--xtensa-components {}", components.join(",")),
+            });
+        }
+        args
+    }
+
+    /// Renders the Dockerfile.
+    pub fn dockerfile(&self) -> String {
+        let mut packages: BTreeSet<&str> = PACKAGES.into_iter().collect();
+        if self.manifest.std {
+            packages.extend(STD_PACKAGES);
+        }
+        let continuation = " \\\n    ";
+        let mut dockerfile = format!(
+            "# Generated by espup {version} from the '{name}' installation.\n\
+             FROM {BASE_IMAGE}\n\
+             ENV DEBIAN_FRONTEND=noninteractive\n\
+             RUN apt-get update && apt-get install -y --no-install-recommends{continuation}{packages}{continuation}&& rm -rf /var/lib/apt/lists/*\n\
+             RUN useradd --create-home --shell /bin/bash {CONTAINER_USER}\n\
+             USER {CONTAINER_USER}\n\
+             WORKDIR /home/{CONTAINER_USER}\n\
+             ENV PATH=/home/{CONTAINER_USER}/.cargo/bin:$PATH\n\
+             RUN curl --proto '=https' --tlsv1.2 -sSf https://sh.rustup.rs | sh -s -- -y --profile minimal --default-toolchain stable\n\
+             RUN curl -L --proto '=https' --tlsv1.2 -sSf -o .cargo/bin/espup{continuation}\"https://github.com/esp-rs/espup/releases/download/v{version}/espup-$(uname -m)-unknown-linux-gnu\"{continuation}&& chmod +x .cargo/bin/espup\n\
+             RUN espup install{continuation}{args}{continuation}&& rm -rf .rustup/downloads .rustup/tmp\n",
+            version = self.espup_version,
+            name = self.manifest.name,
+            packages = Vec::from_iter(packages).join(" "),
+            args = self.install_args().join(continuation),
+        );
+        if self.manifest.std {
+            dockerfile.push_str("RUN cargo install ldproxy --locked\n");
+        }
+        dockerfile.push_str(&format!(
+            "RUN echo '. /home/{CONTAINER_USER}/export-esp.sh' >> .bashrc\n"
+        ));
+        dockerfile
+    }
+
+    /// Renders the `devcontainer.json` file, which builds the Dockerfile of the parent directory.
+    pub fn devcontainer(&self) -> String {
+        let devcontainer = json!({
+            "name": format!("esp-rs ({})", self.manifest.name),
+            "build": {
+                "dockerfile": "../Dockerfile",
+                "context": "..",
+            },
+            "remoteUser": CONTAINER_USER,
+            "customizations": {
+                "vscode": {
+                    "extensions": ["rust-lang.rust-analyzer", "tamasfe.even-better-toml"],
+                },
+            },
+        });
+        format!("{}\n", serde_json::to_string_pretty(&devcontainer).unwrap())
+    }
+}
+
+/// Writes a file, refusing to overwrite existing ones unless forced.
+fn write_new(path: &Path, content: &str, force: bool) -> Result<(), Error> {
+    if path.exists() && !force {
+        return Err(Error::FileExists(path.display().to_string()));
+    }
+    if let Some(parent) = path.parent() {
+        create_dir_all(parent).map_err(|_| Error::CreateDirectory(parent.display().to_string()))?;
+    }
+    write(path, content)?;
+    info!("Generated '{}'", path.display());
+    Ok(())
+}
+
+/// Generates a Dockerfile, and optionally a devcontainer, reproducing an installation.
+///
+/// The `std` and `targets` arguments replace the installed ones.
+pub fn generate_docker(args: &DockerOpts) -> Result<(), Error> {
+    let mut manifest =
+        Manifest::load(&args.name)?.ok_or_else(|| Error::MissingManifest(args.name.clone()))?;
+    if args.std {
+        manifest.std = true;
+    }
+    if let Some(targets) = &args.targets {
+        manifest.targets = targets.clone();
+    }
+    let spec = ContainerSpec {
+        espup_version: env!("CARGO_PKG_VERSION").to_string(),
+        manifest,
+    };
+    write_new(&args.dir.join("Dockerfile"), &spec.dockerfile(), args.force)?;
+    if args.devcontainer {
+        write_new(
+            &args.dir.join(".devcontainer/devcontainer.json"),
+            &spec.devcontainer(),
+            args.force,
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        generate::ContainerSpec,
+        manifest::{manifest_fixture, Manifest},
+        targets::Target,
+    };
+
+    fn spec(std: bool) -> ContainerSpec {
+        ContainerSpec {
+            espup_version: "0.14.0".to_string(),
+            manifest: Manifest {
+                nightly_version: "nightly-2024-12-01".to_string(),
+                riscv_components: vec!["clippy".to_string()],
+                std,
+                targets: [Target::ESP32S3, Target::ESP32C3].into_iter().collect(),
+                ..manifest_fixture()
+            },
+        }
+    }
+
+    #[test]
+    fn test_dockerfile() {
+        let dockerfile = spec(false).dockerfile();
+        assert!(dockerfile.contains("/download/v0.14.0/espup-$(uname -m)-unknown-linux-gnu"));
+        assert!(dockerfile.contains("--targets esp32c3,esp32s3 \\\n"));
+        assert!(dockerfile.contains("--toolchain-version 1.82.0.3 \\\n    --skip-version-parse"));
+        assert!(dockerfile.contains("--nightly-version nightly-2024-12-01"));
+        assert!(dockerfile.contains("--riscv-components clippy"));
+        assert!(!dockerfile.contains("--std"));
+        assert!(!dockerfile.contains("ldproxy"));
+
+        let dockerfile = spec(true).dockerfile();
+        assert!(dockerfile.contains("--std"));
+        assert!(dockerfile.contains("ninja-build"));
+        assert!(dockerfile.contains("cargo install ldproxy"));
+    }
+
+    #[test]
+    fn test_devcontainer() {
+        let devcontainer: serde_json::Value =
+            serde_json::from_str(&spec(false).devcontainer()).unwrap();
+        assert_eq!(devcontainer["build"]["dockerfile"], "../Dockerfile");
+        assert_eq!(devcontainer["remoteUser"], "esp");
+    }
+}
@@ -2,11 +2,12 @@
 
 use crate::error::Error;
 use guess_host_triple::guess_host_triple;
+use log::warn;
 use miette::Result;
 use std::str::FromStr;
-use strum::{Display, EnumString};
+use strum::{Display, EnumIter, EnumString};
 
-#[derive(Display, Debug, Clone, EnumString, Default)]
+#[derive(Display, Debug, Clone, EnumIter, EnumString, Default)]
 pub enum HostTriple {
     /// 64-bit Linux
     #[strum(serialize = "x86_64-unknown-linux-gnu")]
@@ -15,6 +16,12 @@ pub enum HostTriple {
     /// ARM64 Linux
     #[strum(serialize = "aarch64-unknown-linux-gnu")]
     Aarch64UnknownLinuxGnu,
+    /// RISC-V 64-bit Linux
+    #[strum(serialize = "riscv64gc-unknown-linux-gnu")]
+    Riscv64gcUnknownLinuxGnu,
+    /// PowerPC 64-bit little-endian Linux
+    #[strum(serialize = "powerpc64le-unknown-linux-gnu")]
+    Powerpc64leUnknownLinuxGnu,
     /// 64-bit MSVC
     #[strum(serialize = "x86_64-pc-windows-msvc")]
     X86_64PcWindowsMsvc,
@@ -29,6 +36,18 @@ pub enum HostTriple {
     Aarch64AppleDarwin,
 }
 
+impl HostTriple {
+    /// Returns whether Espressif publishes Xtensa Rust, LLVM and GCC artifacts for this host.
+    ///
+    /// Other hosts can only install the RISC-V targets, which are provided by rustup.
+    pub fn has_espressif_artifacts(&self) -> bool {
+        !matches!(
+            self,
+            HostTriple::Riscv64gcUnknownLinuxGnu | HostTriple::Powerpc64leUnknownLinuxGnu
+        )
+    }
+}
+
 /// Parse the host triple if specified, otherwise guess it.
 pub fn get_host_triple(host_triple_arg: Option<String>) -> Result<HostTriple, Error> {
     let host_triple = if let Some(host_triple) = &host_triple_arg {
@@ -40,9 +59,64 @@ pub fn get_host_triple(host_triple_arg: Option<String>) -> Result<HostTriple, Er
     HostTriple::from_str(host_triple).map_err(|_| Error::UnsupportedHostTriple(host_triple.into()))
 }
 
+/// Checks that the artifacts of the host triple run on the actual system, unless `allow_foreign`
+/// is set.
+pub fn check_host_triple(host_triple: &HostTriple, allow_foreign: bool) -> Result<(), Error> {
+    let Some(actual) = guess_host_triple() else {
+        return Ok(());
+    };
+    let host_triple = host_triple.to_string();
+    if runs_on(&host_triple, actual) {
+        return Ok(());
+    }
+    if !allow_foreign {
+        return Err(Error::ForeignHostTriple(host_triple, actual.to_string()));
+    }
+    warn!(
+        "Host triple '{}' doesn't match the running system ('{}'), the installed artifacts may not run",
+        host_triple, actual
+    );
+    Ok(())
+}
+
+/// Returns whether binaries built for the `selected` triple run on an `actual` host.
+fn runs_on(selected: &str, actual: &str) -> bool {
+    selected == actual
+        // Rosetta 2 runs x86_64 binaries on Apple Silicon
+        || (selected == "x86_64-apple-darwin" && actual == "aarch64-apple-darwin")
+        // Both Windows environments use the same MinGW artifacts
+        || (selected.starts_with("x86_64-pc-windows-") && actual.starts_with("x86_64-pc-windows-"))
+}
+
+/// Returns the host triple whose GCC and LLVM artifacts are installed, which defaults to the
+/// host triple.
+pub fn get_artifact_triple(
+    host_triple: &HostTriple,
+    artifact_arch: Option<&str>,
+) -> Result<HostTriple, Error> {
+    let Some(artifact_arch) = artifact_arch else {
+        return Ok(host_triple.clone());
+    };
+    let artifact_triple = HostTriple::from_str(artifact_arch)
+        .map_err(|_| Error::UnsupportedHostTriple(artifact_arch.into()))?;
+    if artifact_triple.to_string() != host_triple.to_string() {
+        warn!(
+            "Installing '{}' GCC and LLVM artifacts on a '{}' host, this combination is not supported",
+            artifact_triple, host_triple
+        );
+    }
+    Ok(artifact_triple)
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::host_triple::{get_host_triple, HostTriple};
+    use crate::{
+        error::Error,
+        host_triple::{
+            check_host_triple, get_artifact_triple, get_host_triple, runs_on, HostTriple,
+        },
+    };
+    use guess_host_triple::guess_host_triple;
 
     #[test]
     fn test_get_host_triple() {
@@ -71,6 +145,17 @@ mod tests {
             Ok(HostTriple::Aarch64AppleDarwin)
         ));
 
+        assert!(matches!(
+            get_host_triple(Some("riscv64gc-unknown-linux-gnu".to_string())),
+            Ok(HostTriple::Riscv64gcUnknownLinuxGnu)
+        ));
+        assert!(matches!(
+            get_host_triple(Some("powerpc64le-unknown-linux-gnu".to_string())),
+            Ok(HostTriple::Powerpc64leUnknownLinuxGnu)
+        ));
+        assert!(!HostTriple::Riscv64gcUnknownLinuxGnu.has_espressif_artifacts());
+        assert!(HostTriple::Aarch64UnknownLinuxGnu.has_espressif_artifacts());
+
         assert!(get_host_triple(Some("some-fake-triple".to_string())).is_err());
 
         // Guessed Host Triples
@@ -105,4 +190,49 @@ mod tests {
             Ok(HostTriple::Aarch64AppleDarwin)
         ));
     }
+
+    #[test]
+    fn test_check_host_triple() {
+        let actual = get_host_triple(None).unwrap();
+        check_host_triple(&actual, false).unwrap();
+        let foreign = if guess_host_triple() == Some("powerpc64le-unknown-linux-gnu") {
+            HostTriple::Riscv64gcUnknownLinuxGnu
+        } else {
+            HostTriple::Powerpc64leUnknownLinuxGnu
+        };
+        assert!(matches!(
+            check_host_triple(&foreign, false),
+            Err(Error::ForeignHostTriple(..))
+        ));
+        check_host_triple(&foreign, true).unwrap();
+    }
+
+    #[test]
+    fn test_runs_on() {
+        assert!(runs_on(
+            "x86_64-unknown-linux-gnu",
+            "x86_64-unknown-linux-gnu"
+        ));
+        assert!(runs_on("x86_64-apple-darwin", "aarch64-apple-darwin"));
+        assert!(runs_on("x86_64-pc-windows-gnu", "x86_64-pc-windows-msvc"));
+        assert!(!runs_on("aarch64-apple-darwin", "x86_64-apple-darwin"));
+        assert!(!runs_on(
+            "x86_64-unknown-linux-gnu",
+            "aarch64-unknown-linux-gnu"
+        ));
+    }
+
+    #[test]
+    fn test_get_artifact_triple() {
+        let host_triple = HostTriple::Aarch64UnknownLinuxGnu;
+        assert!(matches!(
+            get_artifact_triple(&host_triple, None),
+            Ok(HostTriple::Aarch64UnknownLinuxGnu)
+        ));
+        assert!(matches!(
+            get_artifact_triple(&host_triple, Some("x86_64-unknown-linux-gnu")),
+            Ok(HostTriple::X86_64UnknownLinuxGnu)
+        ));
+        assert!(get_artifact_triple(&host_triple, Some("x86_64-unknown-linux-musl")).is_err());
+    }
 }
@@ -0,0 +1,176 @@
+//! `ldproxy` linker of STD applications.
+//!
+//! `esp-idf-sys` builds the ESP-IDF and forwards its linker arguments through `ldproxy`, which
+//! must be installed and configured as the linker of the `espidf` targets. Otherwise builds fail
+//! with "linker not found" errors.
+
+use crate::{error::Error, sandbox::write_checked, targets::Target, toolchain::rust::Homes};
+use log::{debug, info};
+use std::{
+    collections::{BTreeSet, HashSet},
+    env::consts::EXE_SUFFIX,
+    fs::read_to_string,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+/// Name of the linker binary and crate.
+pub const LDPROXY: &str = "ldproxy";
+
+/// Returns whether `ldproxy` is installed in the Cargo binaries directory.
+pub fn ldproxy_installed(homes: &Homes) -> bool {
+    homes
+        .cargo_home()
+        .join("bin")
+        .join(format!("{LDPROXY}{EXE_SUFFIX}"))
+        .is_file()
+}
+
+/// Installs `ldproxy` with `cargo install`, unless it is already installed.
+pub fn install_ldproxy(homes: &Homes) -> Result<(), Error> {
+    if ldproxy_installed(homes) {
+        info!("'{}' is already installed", LDPROXY);
+        return Ok(());
+    }
+    info!("Installing '{}'", LDPROXY);
+    let output = Command::new("cargo")
+        .envs(homes.envs())
+        .args(["install", LDPROXY, "--locked"])
+        .output()
+        .map_err(|e| Error::LdproxyInstallation(e.to_string()))?;
+    if !output.status.success() {
+        return Err(Error::LdproxyInstallation(
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// Returns the `espidf` Rust targets of the chips, sorted.
+fn std_targets(targets: &HashSet<Target>) -> BTreeSet<&'static str> {
+    targets.iter().map(|t| t.std_rust_target()).collect()
+}
+
+/// Renders the linker configuration of the chips, in `.cargo/config.toml` syntax.
+pub fn linker_config(targets: &HashSet<Target>) -> String {
+    std_targets(targets)
+        .into_iter()
+        .map(|target| format!("[target.{target}]\nlinker = \"{LDPROXY}\"\n"))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Instructions to configure the linker of STD projects.
+pub fn print_linker_config(targets: &HashSet<Target>) {
+    println!(
+        "\n\tAdd the following linker configuration to the '.cargo/config.toml' file of your STD projects, or run 'espup pin --ldproxy' in them:\n"
+    );
+    for line in linker_config(targets).lines() {
+        println!("\t{line}");
+    }
+}
+
+/// Returns the content of the Cargo configuration with `ldproxy` as the linker of the chips,
+/// keeping everything else.
+fn update_linker_config(existing: Option<&str>, targets: &HashSet<Target>) -> String {
+    let setting = format!("linker = \"{LDPROXY}\"");
+    let mut missing = std_targets(targets);
+    let mut content = String::new();
+    // Target section being copied, if its linker still has to be set
+    let mut section: Option<&str> = None;
+    for line in existing.unwrap_or_default().lines() {
+        let trimmed = line.trim();
+        if trimmed.starts_with('[') {
+            if let Some(target) = section.take() {
+                // Insert the setting after the last line of the section
+                let blank_lines = content.split_off(content.trim_end().len());
+                content.push('\n');
+                content.push_str(&setting);
+                content.push_str(&blank_lines);
+                missing.remove(target);
+            }
+            section = missing
+                .iter()
+                .find(|target| trimmed == format!("[target.{target}]"))
+                .copied();
+        } else if let Some(target) = section.filter(|_| trimmed.starts_with("linker")) {
+            content.push_str(&setting);
+            content.push('\n');
+            missing.remove(target);
+            section = None;
+            continue;
+        }
+        content.push_str(line);
+        content.push('\n');
+    }
+    if let Some(target) = section {
+        content.push_str(&setting);
+        content.push('\n');
+        missing.remove(target);
+    }
+    for target in missing {
+        if !content.is_empty() && !content.ends_with("\n\n") {
+            content.push('\n');
+        }
+        content.push_str(&format!("[target.{target}]\n{setting}\n"));
+    }
+    content
+}
+
+/// Sets `ldproxy` as the linker of the chips in the `.cargo/config.toml` file of the given
+/// project, returning its path.
+pub fn write_linker_config(dir: &Path, targets: &HashSet<Target>) -> Result<PathBuf, Error> {
+    let path = dir.join(".cargo").join("config.toml");
+    let existing = if path.exists() {
+        debug!("Updating '{}'", path.display());
+        Some(read_to_string(&path)?)
+    } else {
+        None
+    };
+    write_checked(&path, update_linker_config(existing.as_deref(), targets))?;
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        ldproxy::{linker_config, update_linker_config},
+        targets::Target,
+    };
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_linker_config() {
+        let targets: HashSet<Target> = [Target::ESP32S3, Target::ESP32C3, Target::ESP32C2]
+            .into_iter()
+            .collect();
+        assert_eq!(
+            linker_config(&targets),
+            "[target.riscv32imc-esp-espidf]\nlinker = \"ldproxy\"\n\n[target.xtensa-esp32s3-espidf]\nlinker = \"ldproxy\"\n"
+        );
+
+        let targets: HashSet<Target> = [Target::ESP32].into_iter().collect();
+        assert_eq!(
+            update_linker_config(None, &targets),
+            "[target.xtensa-esp32-espidf]\nlinker = \"ldproxy\"\n"
+        );
+        assert_eq!(
+            update_linker_config(
+                Some("[build]\ntarget = \"xtensa-esp32-espidf\"\n\n[target.xtensa-esp32-espidf]\nlinker = \"cc\"\nrunner = \"espflash flash --monitor\"\n\n[env]\nMCU = \"esp32\"\n"),
+                &targets
+            ),
+            "[build]\ntarget = \"xtensa-esp32-espidf\"\n\n[target.xtensa-esp32-espidf]\nlinker = \"ldproxy\"\nrunner = \"espflash flash --monitor\"\n\n[env]\nMCU = \"esp32\"\n"
+        );
+        assert_eq!(
+            update_linker_config(
+                Some("[target.xtensa-esp32-espidf]\nrunner = \"espflash flash --monitor\"\n\n[env]\nMCU = \"esp32\"\n"),
+                &targets
+            ),
+            "[target.xtensa-esp32-espidf]\nrunner = \"espflash flash --monitor\"\nlinker = \"ldproxy\"\n\n[env]\nMCU = \"esp32\"\n"
+        );
+        assert_eq!(
+            update_linker_config(Some("[env]\nMCU = \"esp32\"\n"), &targets),
+            "[env]\nMCU = \"esp32\"\n\n[target.xtensa-esp32-espidf]\nlinker = \"ldproxy\"\n"
+        );
+    }
+}
@@ -1,16 +1,102 @@
+pub mod auth;
+pub mod ci;
 pub mod cli;
+#[cfg(feature = "cli")]
+pub mod completions;
+pub mod daemon;
+pub mod device;
 pub mod env;
 pub mod error;
+pub mod generate;
 pub mod host_triple;
+pub mod ldproxy;
+pub mod logs;
+pub mod manifest;
+pub mod matrix;
+pub mod permissions;
+pub mod pin;
+pub mod releases;
+pub mod report;
+pub mod sandbox;
+#[cfg(feature = "serve")]
+pub mod serve;
+pub mod target_dir;
 pub mod targets;
 pub mod toolchain;
+#[cfg(feature = "cli")]
+pub mod update;
+pub mod version;
+pub mod which;
+pub mod wsl;
 
+#[cfg(feature = "cli")]
 pub mod logging {
-    use env_logger::{Builder, Env, WriteStyle};
+    use crate::logs::{create_log, logs_dir, retained_logs};
+    use env_logger::{Builder, Env, Logger, WriteStyle};
+    use indicatif::MultiProgress;
+    use log::{LevelFilter, Log, Metadata, Record};
+    use std::{
+        env,
+        fs::File,
+        io::Write,
+        sync::{Mutex, OnceLock},
+        time::Instant,
+    };
 
-    use crate::toolchain::PROCESS_BARS;
+    /// Progress bars drawn on the terminal, suspended by the logger while logging.
+    static TERMINAL_BARS: OnceLock<MultiProgress> = OnceLock::new();
+
+    /// Returns the progress bars drawn on the terminal.
+    pub fn terminal_bars() -> MultiProgress {
+        TERMINAL_BARS.get_or_init(MultiProgress::new).clone()
+    }
+
+    /// Logger also writing the debug logs of espup to the log file of the run.
+    struct RetainedLogger {
+        console: Logger,
+        file: Mutex<File>,
+        started: Instant,
+    }
+
+    impl RetainedLogger {
+        /// Returns whether the record is written to the log file, the debug logs of the
+        /// dependencies being too verbose.
+        fn retains(metadata: &Metadata) -> bool {
+            metadata.level() <= LevelFilter::Info || metadata.target().starts_with("espup")
+        }
+    }
+
+    impl Log for RetainedLogger {
+        fn enabled(&self, metadata: &Metadata) -> bool {
+            self.console.enabled(metadata) || Self::retains(metadata)
+        }
+
+        fn log(&self, record: &Record) {
+            if self.console.matches(record) {
+                self.console.log(record);
+            }
+            if Self::retains(record.metadata()) {
+                // Failing to write the log file must not fail the run
+                let _ = writeln!(
+                    self.file.lock().unwrap(),
+                    "[{:>8.3}s] [{}] {}",
+                    self.started.elapsed().as_secs_f64(),
+                    record.level().to_string().to_lowercase(),
+                    record.args()
+                );
+            }
+        }
+
+        fn flush(&self) {
+            self.console.flush();
+            let _ = self.file.lock().unwrap().flush();
+        }
+    }
 
     /// Initializes the logger
+    ///
+    /// With `--retain-logs`, the debug logs are also written to the log file of the run, whatever
+    /// the level of the console.
     pub fn initialize_logger(log_level: &str) {
         let logger = Builder::from_env(Env::default().default_filter_or(log_level))
             .format(|buf, record| {
@@ -25,28 +111,40 @@ pub mod logging {
             .write_style(WriteStyle::Always)
             .build();
         let level = logger.filter();
+        let retained = match retained_logs() {
+            0 => None,
+            count => match create_log(&logs_dir(), count) {
+                Ok((_, mut file)) => {
+                    let args: Vec<String> = env::args().collect();
+                    let _ = writeln!(
+                        file,
+                        "espup {}: {}",
+                        env!("CARGO_PKG_VERSION"),
+                        args.join(" ")
+                    );
+                    Some(file)
+                }
+                Err(e) => {
+                    eprintln!("[warn]: Failed to create the log file of the run: {e}");
+                    None
+                }
+            },
+        };
+        let (logger, level): (Box<dyn Log>, LevelFilter) = match retained {
+            Some(file) => (
+                Box::new(RetainedLogger {
+                    console: logger,
+                    file: Mutex::new(file),
+                    started: Instant::now(),
+                }),
+                level.max(LevelFilter::Debug),
+            ),
+            None => (Box::new(logger), level),
+        };
         // make logging and process bar no longer mixed up
-        indicatif_log_bridge::LogWrapper::new(PROCESS_BARS.clone(), logger)
+        indicatif_log_bridge::LogWrapper::new(terminal_bars(), logger)
             .try_init()
             .unwrap();
         log::set_max_level(level);
     }
 }
-
-pub mod update {
-    use log::warn;
-    use std::time::Duration;
-    use update_informer::{registry, Check};
-
-    /// Check crates.io for a new version of the application
-    pub fn check_for_update(name: &str, version: &str) {
-        // By setting the interval to 0 seconds we invalidate the cache with each
-        // invocation and ensure we're getting up-to-date results
-        let informer =
-            update_informer::new(registry::Crates, name, version).interval(Duration::ZERO);
-
-        if let Some(version) = informer.check_version().ok().flatten() {
-            warn!("A new version of {name} ('{version}') is available");
-        }
-    }
-}
@@ -0,0 +1,91 @@
+//! Logs of the last runs, kept with `--retain-logs` and shown by `espup logs`.
+//!
+//! Each run writes its debug logs to a file of the logs directory, named after its start time so
+//! the files sort chronologically. Only the files of the last runs are kept.
+
+use crate::{env::get_espup_dir, error::Error, sandbox::check_write};
+use log::debug;
+use std::{
+    fs::{create_dir_all, read_dir, remove_file, File, OpenOptions},
+    path::{Path, PathBuf},
+    process,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Number of runs whose logs are kept, none by default.
+static RETAINED_LOGS: AtomicUsize = AtomicUsize::new(0);
+
+/// Keeps the logs of the last `count` runs, including the current one.
+pub fn set_retained_logs(count: usize) {
+    RETAINED_LOGS.store(count, Ordering::Relaxed);
+}
+
+/// Returns the number of runs whose logs are kept.
+pub fn retained_logs() -> usize {
+    RETAINED_LOGS.load(Ordering::Relaxed)
+}
+
+/// Returns the directory of the logs.
+pub fn logs_dir() -> PathBuf {
+    get_espup_dir().join("logs")
+}
+
+/// Returns the log files of the directory, oldest first.
+pub fn list_logs(dir: &Path) -> Vec<PathBuf> {
+    let mut logs: Vec<PathBuf> = read_dir(dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|extension| extension == "log"))
+        .collect();
+    logs.sort();
+    logs
+}
+
+/// Creates the log file of the current run, removing the oldest ones so at most `retain` are
+/// kept.
+pub fn create_log(dir: &Path, retain: usize) -> Result<(PathBuf, File), Error> {
+    check_write(dir)?;
+    create_dir_all(dir)?;
+    let started = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    let path = dir.join(format!("{:020}-{}.log", started.as_nanos(), process::id()));
+    let file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&path)?;
+    let logs = list_logs(dir);
+    for old in &logs[..logs.len().saturating_sub(retain.max(1))] {
+        if let Err(e) = remove_file(old) {
+            debug!("Failed to remove the log '{}': {}", old.display(), e);
+        }
+    }
+    Ok((path, file))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::logs::{create_log, list_logs};
+    use std::io::Write;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_create_log() {
+        let temp_dir = TempDir::new().unwrap();
+        let dir = temp_dir.path().join("logs");
+        assert!(list_logs(&dir).is_empty());
+
+        let mut created = Vec::new();
+        for run in 0..3 {
+            let (path, mut file) = create_log(&dir, 2).unwrap();
+            writeln!(file, "run {run}").unwrap();
+            created.push(path);
+        }
+        // Only the last runs are kept
+        assert_eq!(list_logs(&dir), created[1..]);
+        assert_eq!(std::fs::read_to_string(&created[2]).unwrap(), "run 2\n");
+    }
+}
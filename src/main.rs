@@ -1,39 +1,218 @@
-use clap::{CommandFactory, Parser};
+use clap::{builder::FalseyValueParser, CommandFactory, Parser};
 use espup::{
-    cli::{CompletionsOpts, InstallOpts, UninstallOpts},
+    auth::{
+        clear_github_token, clear_mirror_credentials, set_github_token, set_mirror_credentials,
+        MirrorCredentials,
+    },
+    ci::ci_install_opts,
+    cli::{
+        AuthOpts, AuthSubcommand, CiInstallOpts, CompletionsOpts, DaemonOpts, EnvOpts, FetchOpts,
+        GcOpts, GenerateOpts, GenerateSubcommand, InstallOpts, LlvmOpts, LlvmSubcommand, LogsOpts,
+        MatrixOpts, PinOpts, PreflightOpts, ReinstallOpts, ReinstallSubcommand, SchemaOpts,
+        TargetsOpts, TargetsSubcommand, ToolchainOpts, ToolchainSubcommand, UninstallOpts,
+        VerifyOpts, VersionOpts, WhichToolchainOpts,
+    },
+    completions::{install_completions, uninstall_completions, CompletionEnv},
+    daemon::serve,
+    device::detect_target,
+    env::render_environment,
+    error::Error,
+    generate::generate_docker,
     logging::initialize_logger,
+    logs::{list_logs, logs_dir, set_retained_logs},
+    manifest::{Manifest, MANIFEST_SCHEMA},
+    matrix::SupportMatrix,
+    pin::pin as toolchain_pin,
+    report::{render, VerifyReport},
     toolchain::{
-        gcc::uninstall_gcc_toolchains,
+        add_targets,
+        clone::clone_toolchain,
+        gc::{find_unreferenced, remove_unreferenced},
+        hashes::verify_hashes,
         install as toolchain_install,
-        llvm::Llvm,
-        remove_dir,
-        rust::{get_rustup_home, XtensaRust},
+        llvm::use_llvm,
+        offline::prefetch,
+        preflight::preflight as release_preflight,
+        reinstall_rust_src, remove_components, remove_targets,
+        rust::{select_toolchain, validate_toolchain_name, Homes},
+        uninstall as toolchain_uninstall,
+        verify::check_rust_src,
         InstallMode,
     },
-    update::check_for_update,
+    update::{check_for_update, disable_update_check},
+    version::VersionReport,
+    which::which_toolchain,
+};
+#[cfg(feature = "serve")]
+use espup::{cli::ServeOpts, serve::serve_ui};
+use log::{info, warn};
+use miette::{IntoDiagnostic, Result};
+use std::{
+    env,
+    io::{stdin, stdout, IsTerminal, Write},
+    path::PathBuf,
 };
-use log::info;
-use miette::Result;
-use std::{env, io::stdout};
 
 #[derive(Parser)]
 #[command(about, version)]
 struct Cli {
+    /// Skips the weekly check for new versions of espup and of the installed toolchains.
+    #[arg(long, global = true, env = "ESPUP_NO_UPDATE_CHECK", value_parser = FalseyValueParser::new())]
+    no_update_check: bool,
+    /// Rustup home directory, taking precedence over `RUSTUP_HOME`.
+    ///
+    /// Installations export it in their export file, and later commands on them reuse it.
+    #[arg(long, global = true, value_name = "DIR")]
+    rustup_home: Option<PathBuf>,
+    /// Cargo home directory, taking precedence over `CARGO_HOME`.
+    ///
+    /// Installations export it in their export file, and later commands on them reuse it.
+    #[arg(long, global = true, value_name = "DIR")]
+    cargo_home: Option<PathBuf>,
+    /// Keeps the debug logs of the last N runs, so failures can be investigated afterwards with `espup logs`.
+    ///
+    /// Ignored by sandboxed installations, which don't write to the espup directory.
+    #[arg(
+        long,
+        global = true,
+        value_name = "N",
+        env = "ESPUP_RETAIN_LOGS",
+        default_value_t = 0
+    )]
+    retain_logs: usize,
     #[command(subcommand)]
     subcommand: SubCommand,
 }
 
 #[derive(Parser)]
 pub enum SubCommand {
+    /// Stores the mirror and GitHub credentials in the credential store of the OS, so they are
+    /// not passed as flags.
+    ///
+    /// On Linux, the credentials are kept in the kernel keyring and are lost on reboot.
+    Auth(AuthOpts),
+    /// Installs the toolchains with the stable flags of the `esp-rs/xtensa-toolchain` GitHub Action.
+    ///
+    /// The flags of an `--api-version` are never changed or removed, unlike the ones of `install`.
+    CiInstall(CiInstallOpts),
     /// Generate completions for the given shell.
     Completions(CompletionsOpts),
+    /// Drives espup from another process, e.g. a GUI installer, through JSON-RPC messages.
+    Daemon(DaemonOpts),
+    /// Prints the environment variables of an installation.
+    Env(EnvOpts),
+    /// Downloads the artifacts of several hosts for offline installations.
+    Fetch(FetchOpts),
+    /// Removes artifacts not referenced by any installed toolchain.
+    Gc(GcOpts),
+    /// Generates files reproducing an installation elsewhere.
+    Generate(GenerateOpts),
     /// Installs Espressif Rust ecosystem.
     // We use a Box here to make clippy happy (see https://rust-lang.github.io/rust-clippy/master/index.html#large_enum_variant)
     Install(Box<InstallOpts>),
+    /// Manages the LLVM versions used by the installations.
+    Llvm(LlvmOpts),
+    /// Lists the logs kept with `--retain-logs`, or prints the last one.
+    Logs(LogsOpts),
+    /// Prints the support matrix of the targets, hosts and component versions, for documentation.
+    Matrix(MatrixOpts),
+    /// Pins the toolchain of an installation in the `rust-toolchain.toml` file of a project.
+    Pin(PinOpts),
+    /// Checks that the artifacts of an Xtensa Rust release, and their checksums, are published for
+    /// every host.
+    Preflight(PreflightOpts),
+    /// Reinstalls a single component of an existing installation.
+    Reinstall(ReinstallOpts),
+    /// Prints the JSON schema of the installation manifests, for tools reading them.
+    Schema(SchemaOpts),
+    /// Serves a local web page guiding the installation, e.g. for workshops.
+    #[cfg(feature = "serve")]
+    Serve(ServeOpts),
+    /// Adds or removes targets of an existing installation.
+    Targets(TargetsOpts),
+    /// Manages the installed toolchains.
+    Toolchain(ToolchainOpts),
     /// Uninstalls Espressif Rust ecosystem.
     Uninstall(UninstallOpts),
     /// Updates Xtensa Rust toolchain.
     Update(Box<InstallOpts>),
+    /// Checks the `rust-src` component, and the installed files against the hashes recorded with
+    /// `install --record-hashes`.
+    Verify(VerifyOpts),
+    /// Prints the versions of espup and of the installed toolchains.
+    Version(VersionOpts),
+    /// Prints the toolchain, Rust target and `cargo build` command building the applications of a chip.
+    WhichToolchain(WhichToolchainOpts),
+}
+
+/// Reads a secret from stdin, prompting for it in terminals
+fn read_secret(prompt: &str) -> Result<String> {
+    if stdin().is_terminal() {
+        eprint!("{prompt}: ");
+    }
+    let mut secret = String::new();
+    stdin().read_line(&mut secret).into_diagnostic()?;
+    let secret = secret.trim_end_matches(['\r', '\n']).to_string();
+    if secret.is_empty() {
+        return Err(Error::MissingSecret(prompt.to_lowercase()).into());
+    }
+    Ok(secret)
+}
+
+/// Manages the credentials stored in the credential store of the OS
+async fn auth(args: AuthOpts) -> Result<()> {
+    match args.subcommand {
+        AuthSubcommand::Clear(args) => {
+            initialize_logger(&args.log_level);
+
+            let (cleared, what) = match &args.mirror {
+                Some(mirror) => (
+                    clear_mirror_credentials(mirror)?,
+                    format!("credentials of '{mirror}'"),
+                ),
+                None => (clear_github_token()?, "GitHub token".to_string()),
+            };
+            if cleared {
+                info!("Removed the {}", what);
+            } else {
+                warn!("No {} was stored", what);
+            }
+        }
+        AuthSubcommand::SetGithub(args) => {
+            initialize_logger(&args.log_level);
+
+            set_github_token(&read_secret("GitHub token")?)?;
+            info!("Stored the GitHub token, 'GITHUB_TOKEN' still takes precedence over it");
+        }
+        AuthSubcommand::SetMirror(args) => {
+            initialize_logger(&args.log_level);
+
+            let credentials = MirrorCredentials {
+                username: args.username,
+                password: read_secret("Password")?,
+            };
+            set_mirror_credentials(&args.mirror, &credentials)?;
+            info!("Stored the credentials of '{}'", args.mirror);
+        }
+    }
+    Ok(())
+}
+
+/// Installs the toolchains with the flags of the GitHub Action
+async fn ci_install(args: CiInstallOpts, homes: Homes) -> Result<()> {
+    let opts = InstallOpts {
+        homes: homes.clone(),
+        ..ci_install_opts(&args)
+    };
+    let name = opts.name.clone();
+    install(opts, InstallMode::Install).await?;
+    if args.default {
+        select_toolchain(&name, false, &homes).await?;
+    }
+    if args.override_toolchain {
+        select_toolchain(&name, true, &homes).await?;
+    }
+    Ok(())
 }
 
 /// Updates Xtensa Rust toolchain.
@@ -41,52 +220,393 @@ async fn completions(args: CompletionsOpts) -> Result<()> {
     initialize_logger(&args.log_level);
     check_for_update(env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
 
-    info!("Generating completions for {} shell", args.shell);
+    let Some(shell) = args.shell else {
+        let shell_env = CompletionEnv::from_env();
+        if args.uninstall {
+            if uninstall_completions(&shell_env)?.is_empty() {
+                info!("No installed completions found");
+            }
+            return Ok(());
+        }
+        if install_completions(&mut Cli::command(), &shell_env)?.is_empty() {
+            warn!("No supported shell was detected");
+        } else {
+            info!("Completions successfully installed! Shells may need to be restarted");
+        }
+        return Ok(());
+    };
+
+    info!("Generating completions for {} shell", shell);
 
-    clap_complete::generate(args.shell, &mut Cli::command(), "espup", &mut stdout());
+    clap_complete::generate(shell, &mut Cli::command(), "espup", &mut stdout());
 
     info!("Completions successfully generated!");
 
     Ok(())
 }
 
-/// Installs or updates the Rust for ESP chips environment
-async fn install(args: InstallOpts, install_mode: InstallMode) -> Result<()> {
+/// Serves JSON-RPC requests over stdin and stdout
+async fn daemon(args: DaemonOpts) -> Result<()> {
+    initialize_logger(&args.log_level);
+
+    let espup = env::current_exe().into_diagnostic()?;
+    serve(
+        tokio::io::BufReader::new(tokio::io::stdin()),
+        tokio::io::stdout(),
+        &espup,
+    )
+    .await?;
+    Ok(())
+}
+
+/// Prints the environment variables of an installation
+async fn env(args: EnvOpts) -> Result<()> {
+    initialize_logger(&args.log_level);
+
+    let manifest =
+        Manifest::load(&args.name)?.ok_or_else(|| Error::MissingManifest(args.name.clone()))?;
+    print!(
+        "{}",
+        render_environment(&manifest.environment(args.pure), args.format)
+    );
+    Ok(())
+}
+
+/// Downloads the artifacts of the given hosts
+async fn fetch(args: FetchOpts) -> Result<()> {
     initialize_logger(&args.log_level);
     check_for_update(env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
 
+    prefetch(args).await
+}
+
+/// Removes the artifacts that are not referenced by any installed toolchain
+async fn gc(args: GcOpts, homes: &Homes) -> Result<()> {
+    initialize_logger(&args.log_level);
+    check_for_update(env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
+
+    let manifests = Manifest::list()?;
+    if manifests.is_empty() {
+        warn!("No toolchain installed by espup was found, nothing to collect");
+        return Ok(());
+    }
+    let unreferenced = find_unreferenced(&homes.rustup_home(), &manifests)?;
+    if unreferenced.is_empty() {
+        info!("No unreferenced artifacts found");
+        return Ok(());
+    }
+
+    info!("Found {} unreferenced artifacts:", unreferenced.len());
+    for path in &unreferenced {
+        info!("  {}", path.display());
+    }
+    if args.dry_run || !(args.yes || confirm("Remove these artifacts?")?) {
+        return Ok(());
+    }
+
+    let freed = remove_unreferenced(&unreferenced)?;
+    info!(
+        "Garbage collection completed, {:.2} MB freed",
+        freed as f64 / 1_000_000.0
+    );
+    Ok(())
+}
+
+/// Generates files reproducing an installation elsewhere
+async fn generate(args: GenerateOpts) -> Result<()> {
+    match args.subcommand {
+        GenerateSubcommand::Docker(args) => {
+            initialize_logger(&args.log_level);
+            check_for_update(env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
+
+            generate_docker(&args)?;
+            Ok(())
+        }
+    }
+}
+
+/// Asks the user for confirmation, defaulting to no
+fn confirm(prompt: &str) -> Result<bool> {
+    print!("{prompt} [y/N] ");
+    stdout().flush().into_diagnostic()?;
+    let mut answer = String::new();
+    stdin().read_line(&mut answer).into_diagnostic()?;
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
+
+/// Installs or updates the Rust for ESP chips environment
+async fn install(mut args: InstallOpts, install_mode: InstallMode) -> Result<()> {
+    if args.quiet || args.summary_only {
+        initialize_logger("off");
+    } else {
+        initialize_logger(&args.log_level);
+    }
+    if args.detect_device {
+        let target = detect_target()?;
+        info!("Detected a '{}' device", target);
+        // Without a terminal, e.g. in the daemon, the detected target is accepted
+        if stdin().is_terminal()
+            && !confirm(&format!("Install the toolchains of '{target}' only?"))?
+        {
+            return Err(Error::DeviceDetection("installation declined".to_string()).into());
+        }
+        args.targets = [target].into_iter().collect();
+    }
+    // The update check writes to the cache directories
+    if args.sandbox {
+        disable_update_check();
+    }
+    check_for_update(env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
+
     toolchain_install(args, install_mode).await?;
     Ok(())
 }
 
-/// Uninstalls the Rust for ESP chips environment
-async fn uninstall(args: UninstallOpts) -> Result<()> {
+/// Manages the LLVM versions used by the installations
+async fn llvm(args: LlvmOpts) -> Result<()> {
+    match args.subcommand {
+        LlvmSubcommand::Use(args) => {
+            initialize_logger(&args.log_level);
+
+            use_llvm(&args.name, &args.version).await?;
+            Ok(())
+        }
+    }
+}
+
+/// Lists the logs of the last runs, or prints the last one
+async fn logs(args: LogsOpts) -> Result<()> {
+    initialize_logger(&args.log_level);
+
+    let logs = list_logs(&logs_dir());
+    if logs.is_empty() {
+        info!("No logs were kept, run espup with '--retain-logs <N>' to keep the logs of the last runs");
+        return Ok(());
+    }
+    if args.last {
+        let last = logs.last().unwrap();
+        info!("Log of the last run: '{}'", last.display());
+        print!("{}", std::fs::read_to_string(last).into_diagnostic()?);
+    } else {
+        for log in logs.iter().rev() {
+            println!("{}", log.display());
+        }
+    }
+    Ok(())
+}
+
+/// Prints the support matrix
+async fn matrix(args: MatrixOpts) -> Result<()> {
+    initialize_logger(&args.log_level);
+
+    print!("{}", SupportMatrix::new().render(args.format));
+    Ok(())
+}
+
+/// Pins the toolchain of an existing installation in a project
+async fn pin(args: PinOpts) -> Result<()> {
     initialize_logger(&args.log_level);
     check_for_update(env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
 
-    info!("Uninstalling the Espressif Rust ecosystem");
-    let toolchain_dir = get_rustup_home().join("toolchains").join(args.name);
+    toolchain_pin(
+        &args.dir,
+        &args.name,
+        args.targets,
+        args.target_dir_style,
+        args.ldproxy,
+    )?;
+    Ok(())
+}
+
+/// Checks the artifacts of an Xtensa Rust release
+async fn preflight(args: PreflightOpts) -> Result<()> {
+    initialize_logger(&args.log_level);
+
+    let report = release_preflight(&args.toolchain_version, &args.hosts).await?;
+    print!("{}", render(&report, args.format));
+    match report.failures() {
+        0 => Ok(()),
+        failures => Err(Error::PreflightFailed(report.toolchain_version, failures).into()),
+    }
+}
+
+/// Reinstalls a single component of an existing installation
+async fn reinstall(args: ReinstallOpts) -> Result<()> {
+    match args.subcommand {
+        ReinstallSubcommand::RustSrc(args) => {
+            initialize_logger(&args.log_level);
+            check_for_update(env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
+
+            reinstall_rust_src(&args.name).await
+        }
+    }
+}
+
+/// Serves the installation page
+#[cfg(feature = "serve")]
+async fn serve_page(args: ServeOpts) -> Result<()> {
+    initialize_logger(&args.log_level);
+
+    let listener = tokio::net::TcpListener::bind(("127.0.0.1", args.port))
+        .await
+        .into_diagnostic()?;
+    let espup = env::current_exe().into_diagnostic()?;
+    serve_ui(listener, espup).await?;
+    Ok(())
+}
+
+/// Prints the JSON schema of the installation manifests
+async fn schema(args: SchemaOpts) -> Result<()> {
+    initialize_logger(&args.log_level);
+
+    print!("{MANIFEST_SCHEMA}");
+    Ok(())
+}
+
+/// Adds or removes targets of an existing installation
+async fn targets(args: TargetsOpts) -> Result<()> {
+    match args.subcommand {
+        TargetsSubcommand::Add(args) => {
+            initialize_logger(&args.log_level);
+            check_for_update(env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
+
+            add_targets(&args.name, &args.targets, &args.log_level).await
+        }
+        TargetsSubcommand::Remove(args) => {
+            initialize_logger(&args.log_level);
+            check_for_update(env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
 
-    if toolchain_dir.exists() {
-        Llvm::uninstall(&toolchain_dir).await?;
+            remove_targets(&args.name, &args.targets, &args.log_level).await
+        }
+    }
+}
 
-        uninstall_gcc_toolchains(&toolchain_dir).await?;
+/// Manages the installed toolchains
+async fn toolchain(args: ToolchainOpts) -> Result<()> {
+    match args.subcommand {
+        ToolchainSubcommand::Clone(args) => {
+            initialize_logger(&args.log_level);
+            check_for_update(env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
+
+            clone_toolchain(
+                &args.source,
+                &args.name,
+                args.export_file,
+                args.hardlink,
+                args.force,
+            )
+            .await
+        }
+    }
+}
 
-        XtensaRust::uninstall(&toolchain_dir).await?;
+/// Uninstalls the Rust for ESP chips environment
+async fn uninstall(args: UninstallOpts, homes: &Homes) -> Result<()> {
+    initialize_logger(&args.log_level);
+    check_for_update(env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
 
-        remove_dir(&toolchain_dir).await?;
+    if let Some(targets) = &args.targets {
+        validate_toolchain_name(&args.name, args.force, homes)?;
+        return remove_targets(&args.name, targets, &args.log_level).await;
+    }
+    if let Some(components) = &args.components {
+        validate_toolchain_name(&args.name, args.force, homes)?;
+        return remove_components(&args.name, &components.iter().copied().collect()).await;
+    }
+    let report = toolchain_uninstall(&args, homes).await?;
+    if !report.removed_toolchain && !report.removal_on_reboot {
+        info!(
+            "Toolchain directory '{}' not found, nothing to remove",
+            report.toolchain_dir.display()
+        );
     }
 
     info!("Uninstallation successfully completed!");
     Ok(())
 }
 
+/// Checks the installed files of a toolchain against their recorded hashes
+async fn verify(args: VerifyOpts) -> Result<()> {
+    initialize_logger(&args.log_level);
+
+    check_rust_src(&args.name)?;
+    info!("Verifying the files of the '{}' toolchain", args.name);
+    let (recorded, changes) = verify_hashes(&args.name).await?;
+    let report = VerifyReport {
+        name: args.name,
+        recorded,
+        changes,
+    };
+    print!("{}", render(&report, args.format));
+    match report.changed() {
+        0 => Ok(()),
+        changed => Err(Error::ModifiedToolchain(report.name, changed).into()),
+    }
+}
+
+/// Prints the versions of espup and of the installed toolchains
+async fn version(args: VersionOpts) -> Result<()> {
+    initialize_logger(&args.log_level);
+
+    print!(
+        "{}",
+        render(&VersionReport::new(args.check).await, args.format)
+    );
+    Ok(())
+}
+
+/// Prints the toolchain building the applications of a chip
+async fn which(args: WhichToolchainOpts) -> Result<()> {
+    initialize_logger(&args.log_level);
+
+    print!("{}", which_toolchain(&args.name, args.target)?);
+    Ok(())
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
-    match Cli::parse().subcommand {
+    let cli = Cli::parse();
+    if cli.no_update_check {
+        disable_update_check();
+    }
+    let homes = Homes::new(cli.rustup_home, cli.cargo_home);
+    // Viewing the logs must not rotate them, and sandboxed installations must not write them
+    let sandboxed = matches!(
+        &cli.subcommand,
+        SubCommand::Install(args) | SubCommand::Update(args) if args.sandbox
+    );
+    if !matches!(cli.subcommand, SubCommand::Logs(_)) && !sandboxed {
+        set_retained_logs(cli.retain_logs);
+    }
+    match cli.subcommand {
+        SubCommand::Auth(args) => auth(args).await,
+        SubCommand::CiInstall(args) => ci_install(args, homes).await,
         SubCommand::Completions(args) => completions(args).await,
-        SubCommand::Install(args) => install(*args, InstallMode::Install).await,
-        SubCommand::Update(args) => install(*args, InstallMode::Update).await,
-        SubCommand::Uninstall(args) => uninstall(args).await,
+        SubCommand::Daemon(args) => daemon(args).await,
+        SubCommand::Env(args) => env(args).await,
+        SubCommand::Fetch(args) => fetch(args).await,
+        SubCommand::Gc(args) => gc(args, &homes).await,
+        SubCommand::Generate(args) => generate(args).await,
+        SubCommand::Install(args) => {
+            install(InstallOpts { homes, ..*args }, InstallMode::Install).await
+        }
+        SubCommand::Llvm(args) => llvm(args).await,
+        SubCommand::Logs(args) => logs(args).await,
+        SubCommand::Matrix(args) => matrix(args).await,
+        SubCommand::Pin(args) => pin(args).await,
+        SubCommand::Preflight(args) => preflight(args).await,
+        SubCommand::Reinstall(args) => reinstall(args).await,
+        SubCommand::Schema(args) => schema(args).await,
+        #[cfg(feature = "serve")]
+        SubCommand::Serve(args) => serve_page(args).await,
+        SubCommand::Targets(args) => targets(args).await,
+        SubCommand::Toolchain(args) => toolchain(args).await,
+        SubCommand::Update(args) => {
+            install(InstallOpts { homes, ..*args }, InstallMode::Update).await
+        }
+        SubCommand::Uninstall(args) => uninstall(args, &homes).await,
+        SubCommand::Verify(args) => verify(args).await,
+        SubCommand::Version(args) => version(args).await,
+        SubCommand::WhichToolchain(args) => which(args).await,
     }
 }
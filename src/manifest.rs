@@ -0,0 +1,437 @@
+//! Installation manifest support.
+//!
+//! The manifest records how a toolchain was installed so later commands can modify the
+//! installation without requiring all the original arguments again.
+//!
+//! Manifests are also meant to be read by other tools, their format is described by the JSON
+//! schema printed by `espup schema`. Adding fields keeps the schema version, while removing,
+//! renaming or changing the type of a field increases it.
+
+#[cfg(windows)]
+use crate::env::EnvScope;
+use crate::{
+    cli::InstallOpts,
+    env::{environment, export_files, get_espup_dir, Export, ExportShell},
+    error::Error,
+    report::OutputFormat,
+    sandbox::write_checked,
+    target_dir::TargetDirStyle,
+    targets::Target,
+    toolchain::{
+        hashes::hashes_path,
+        rust::{Homes, XtensaComponent},
+        rustup::ConcurrentRustup,
+    },
+};
+use log::{debug, warn};
+use miette::Result;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashSet},
+    env,
+    fs::{read_dir, read_to_string, remove_file},
+    path::PathBuf,
+};
+
+/// Version of the manifest schema written by this espup version.
+pub const MANIFEST_SCHEMA_VERSION: u32 = 1;
+/// JSON schema of the manifest.
+pub const MANIFEST_SCHEMA: &str = include_str!("manifest.schema.json");
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Manifest {
+    /// Version of the manifest schema, manifests written before it was introduced use 1.
+    #[serde(default = "default_schema_version")]
+    pub schema_version: u32,
+    /// Xtensa Rust toolchain name.
+    pub name: String,
+    /// Target triple of the installed GCC and LLVM artifacts, if different from the host one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub artifact_arch: Option<String>,
+    /// Target triple of the host.
+    pub host_triple: String,
+    /// Install Espressif RISC-V toolchain.
+    pub esp_riscv_gcc: bool,
+    /// Path of the generated export file.
+    pub export_file: PathBuf,
+    /// Additional shells export files are generated for, next to the export file.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub export_shells: Vec<ExportShell>,
+    /// Version of the templates of the generated export files, 0 for manifests written before it
+    /// was recorded.
+    #[serde(default)]
+    pub export_template_version: u32,
+    /// Environment changes required by the installed components.
+    #[serde(default)]
+    pub exports: Vec<Export>,
+    /// Whether the whole LLVM was installed instead of only the libs.
+    pub extended_llvm: bool,
+    /// Whether the GCC toolchains installed by ESP-IDF were reused.
+    #[serde(default)]
+    pub from_espidf: bool,
+    /// Nightly Rust toolchain version.
+    pub nightly_version: String,
+    /// Directory of the toolchain when installed without rustup.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prefix: Option<PathBuf>,
+    /// Additional rustup components of the RISC-V toolchain.
+    #[serde(default)]
+    pub riscv_components: Vec<String>,
+    /// Rustup profile of the RISC-V toolchain.
+    #[serde(default = "default_riscv_profile")]
+    pub riscv_profile: String,
+    /// Whether only the toolchains required for STD applications were installed.
+    pub std: bool,
+    /// Location of the Cargo target directory.
+    #[serde(default)]
+    pub target_dir_style: TargetDirStyle,
+    /// Installed targets.
+    pub targets: HashSet<Target>,
+    /// Xtensa Rust toolchain version.
+    pub toolchain_version: String,
+    /// Optional components of the Xtensa Rust toolchain, all the ones shipped in the dist if
+    /// `None`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub xtensa_components: Option<Vec<XtensaComponent>>,
+}
+
+impl Manifest {
+    /// Returns the path of the manifest for the given toolchain name.
+    pub fn path(name: &str) -> PathBuf {
+        get_espup_dir()
+            .join("toolchains")
+            .join(format!("{name}.json"))
+    }
+
+    /// Loads the manifest of the given toolchain, if any.
+    pub fn load(name: &str) -> Result<Option<Self>, Error> {
+        let path = Self::path(name);
+        if !path.exists() {
+            return Ok(None);
+        }
+        debug!("Loading manifest: '{}'", path.display());
+        let content = read_to_string(&path)?;
+        let manifest: Self = serde_json::from_str(&content)
+            .map_err(|_| Error::InvalidManifest(path.display().to_string()))?;
+        if manifest.schema_version > MANIFEST_SCHEMA_VERSION {
+            return Err(Error::UnsupportedManifestVersion(
+                path.display().to_string(),
+                manifest.schema_version,
+            ));
+        }
+        Ok(Some(manifest))
+    }
+
+    /// Loads the manifests of every installed toolchain.
+    pub fn list() -> Result<Vec<Self>, Error> {
+        let manifests_dir = get_espup_dir().join("toolchains");
+        if !manifests_dir.exists() {
+            return Ok(Vec::new());
+        }
+        let mut manifests = Vec::new();
+        for entry in read_dir(&manifests_dir)? {
+            let path = entry?.path();
+            if path
+                .extension()
+                .is_some_and(|extension| extension == "json")
+            {
+                let name = path.file_stem().unwrap().to_string_lossy();
+                if let Some(manifest) = Self::load(&name)? {
+                    manifests.push(manifest);
+                }
+            }
+        }
+        manifests.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(manifests)
+    }
+
+    /// Writes the manifest to disk.
+    pub fn save(&self) -> Result<(), Error> {
+        let path = Self::path(&self.name);
+        debug!("Writing manifest: '{}'", path.display());
+        let content = serde_json::to_string_pretty(self).map_err(|_| Error::SerializeJson)?;
+        write_checked(&path, content)
+    }
+
+    /// Deletes the manifest of the given toolchain, if any.
+    pub fn remove(name: &str) -> Result<(), Error> {
+        let path = Self::path(name);
+        if path.exists() {
+            debug!("Removing manifest: '{}'", path.display());
+            remove_file(&path)?;
+        }
+        let hashes = hashes_path(name);
+        if hashes.exists() {
+            remove_file(&hashes)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the export files: the export file and the ones of the additional shells.
+    pub fn export_files(&self) -> Vec<PathBuf> {
+        export_files(&self.export_file, &self.export_shells)
+    }
+
+    /// Returns the rustup and Cargo homes the toolchain was installed with.
+    pub fn homes(&self) -> Homes {
+        Homes::from_exports(&self.exports)
+    }
+
+    /// Returns the directory of the toolchain.
+    pub fn toolchain_dir(&self) -> PathBuf {
+        self.prefix.clone().unwrap_or_else(|| {
+            self.homes()
+                .rustup_home()
+                .join("toolchains")
+                .join(&self.name)
+        })
+    }
+
+    /// Returns the environment variables of the installation.
+    ///
+    /// Pure environments don't inherit `PATH`, which gets the binaries of the Xtensa Rust
+    /// toolchain instead of the rustup proxies, so builds only depend on the installation.
+    pub fn environment(&self, pure: bool) -> BTreeMap<String, String> {
+        let mut exports = Vec::new();
+        if pure {
+            let bin_dir = self.toolchain_dir().join("bin");
+            if bin_dir.is_dir() {
+                exports.push(Export::prepend_path(
+                    "xtensa-rust",
+                    &bin_dir.display().to_string(),
+                ));
+            } else {
+                warn!(
+                    "No Xtensa Rust toolchain found in '{}', the environment won't provide 'cargo'",
+                    bin_dir.display()
+                );
+            }
+        }
+        exports.extend(self.exports.iter().cloned());
+        let path = (!pure).then(|| env::var("PATH").unwrap_or_default());
+        environment(&exports, path.as_deref())
+    }
+
+    /// Returns the installation arguments that reproduce this installation for the given targets.
+    pub fn to_install_opts(&self, targets: HashSet<Target>, log_level: &str) -> InstallOpts {
+        InstallOpts {
+            // The host was checked when installing
+            allow_foreign_host: true,
+            artifact_arch: self.artifact_arch.clone(),
+            artifacts_dir: None,
+            asset_host_override: None,
+            ci: None,
+            compat: None,
+            concurrent_rustup: ConcurrentRustup::Wait,
+            default_host: Some(self.host_triple.clone()),
+            detect_device: false,
+            diff_only: false,
+            #[cfg(windows)]
+            env_scope: EnvScope::User,
+            #[cfg(windows)]
+            cmd_autorun: false,
+            esp_riscv_gcc: self.esp_riscv_gcc,
+            export_file: Some(self.export_file.clone()),
+            export_shells: self.export_shells.clone(),
+            experimental_targets: self.targets.iter().any(|t| t.is_experimental()),
+            extended_llvm: self.extended_llvm,
+            // The name was already validated when the toolchain was installed
+            force: true,
+            from_espidf: self.from_espidf,
+            homes: self.homes(),
+            io_parallelism: None,
+            jobs: None,
+            ldproxy: false,
+            llvm_only_libs_path: None,
+            log_level: log_level.to_string(),
+            name: self.name.clone(),
+            nightly_version: self.nightly_version.clone(),
+            no_codesign: false,
+            no_fallback: false,
+            no_modify_env: false,
+            permissive: false,
+            no_progress: false,
+            #[cfg(windows)]
+            no_ps1: false,
+            no_rustup: self.prefix.is_some(),
+            prefix: self.prefix.clone(),
+            print_av_exclusion: false,
+            progress_events: false,
+            mirrors: Vec::new(),
+            override_artifact: Vec::new(),
+            record_hashes: false,
+            proxy_for: Vec::new(),
+            quiet: false,
+            regenerate_exports: false,
+            retries: 5,
+            reuse_exports: false,
+            riscv_components: self.riscv_components.clone(),
+            riscv_profile: self.riscv_profile.clone(),
+            sandbox: false,
+            skip_version_parse: true,
+            std: self.std,
+            summary_only: false,
+            summary_format: OutputFormat::Text,
+            system_proxy: false,
+            target_dir_style: self.target_dir_style,
+            targets,
+            timeout: 30,
+            tmp_dir: None,
+            toolchain_version: Some(self.toolchain_version.clone()),
+            toolchain_version_file: None,
+            verify_build: false,
+            xtensa_components: self.xtensa_components.clone(),
+        }
+    }
+}
+
+fn default_schema_version() -> u32 {
+    1
+}
+
+fn default_riscv_profile() -> String {
+    "minimal".to_string()
+}
+
+/// Returns the manifest of an `esp` installation of the ESP32 targets, for the tests to update
+/// the fields they need.
+#[cfg(test)]
+pub(crate) fn manifest_fixture() -> Manifest {
+    Manifest {
+        schema_version: MANIFEST_SCHEMA_VERSION,
+        name: "esp".to_string(),
+        artifact_arch: None,
+        host_triple: "x86_64-unknown-linux-gnu".to_string(),
+        esp_riscv_gcc: false,
+        export_file: PathBuf::from("/home/user/export-esp.sh"),
+        export_shells: Vec::new(),
+        export_template_version: crate::env::EXPORT_TEMPLATE_VERSION,
+        exports: Vec::new(),
+        extended_llvm: false,
+        from_espidf: false,
+        nightly_version: "nightly".to_string(),
+        prefix: None,
+        riscv_components: Vec::new(),
+        riscv_profile: default_riscv_profile(),
+        std: false,
+        target_dir_style: TargetDirStyle::Default,
+        targets: [Target::ESP32].into_iter().collect(),
+        toolchain_version: "1.82.0.3".to_string(),
+        xtensa_components: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        env::Export,
+        manifest::{manifest_fixture, Manifest, MANIFEST_SCHEMA},
+        targets::Target,
+    };
+    use std::{collections::HashSet, fs::create_dir_all, path::PathBuf};
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_manifest_to_install_opts() {
+        let manifest = Manifest {
+            esp_riscv_gcc: true,
+            targets: [Target::ESP32C3].into_iter().collect(),
+            ..manifest_fixture()
+        };
+        let targets = [Target::ESP32C3, Target::ESP32S3].into_iter().collect();
+        let opts = manifest.to_install_opts(targets, "info");
+        assert_eq!(opts.toolchain_version.as_deref(), Some("1.82.0.3"));
+        assert!(opts.skip_version_parse);
+        assert!(opts.esp_riscv_gcc);
+        assert_eq!(opts.targets.len(), 2);
+        assert_eq!(
+            opts.export_file,
+            Some(PathBuf::from("/home/user/export-esp.sh"))
+        );
+
+        assert!(!opts.no_rustup);
+
+        let json = serde_json::to_string(&manifest).unwrap();
+        assert!(json.contains("\"esp32c3\""));
+        assert!(!json.contains("\"prefix\""));
+        assert_eq!(serde_json::from_str::<Manifest>(&json).unwrap(), manifest);
+
+        let manifest = Manifest {
+            prefix: Some(PathBuf::from("/opt/esp")),
+            ..manifest
+        };
+        assert_eq!(manifest.toolchain_dir(), PathBuf::from("/opt/esp"));
+        let opts = manifest.to_install_opts(HashSet::new(), "info");
+        assert!(opts.no_rustup);
+        assert_eq!(opts.prefix, Some(PathBuf::from("/opt/esp")));
+    }
+
+    #[test]
+    fn test_manifest_environment() {
+        let temp_dir = TempDir::new().unwrap();
+        let prefix = temp_dir.path().join("esp");
+        create_dir_all(prefix.join("bin")).unwrap();
+        let gcc_bin = prefix.join("xtensa-esp-elf/bin").display().to_string();
+        let libclang = prefix.join("esp-clang/lib").display().to_string();
+        let manifest = Manifest {
+            exports: vec![
+                Export::set("llvm", "LIBCLANG_PATH", &libclang),
+                Export::prepend_path("xtensa-gcc", &gcc_bin),
+            ],
+            prefix: Some(prefix.clone()),
+            ..manifest_fixture()
+        };
+
+        let separator = if cfg!(windows) { ";" } else { ":" };
+        let pure = manifest.environment(true);
+        assert_eq!(pure.len(), 2);
+        assert_eq!(pure["LIBCLANG_PATH"], libclang);
+        assert_eq!(
+            pure["PATH"],
+            format!("{gcc_bin}{separator}{}", prefix.join("bin").display())
+        );
+
+        let inherited = manifest.environment(false);
+        assert!(inherited["PATH"].starts_with(&format!("{gcc_bin}{separator}")));
+        assert_ne!(inherited["PATH"], pure["PATH"]);
+    }
+
+    #[test]
+    fn test_manifest_schema() {
+        let manifest = Manifest {
+            artifact_arch: Some("x86_64-unknown-linux-gnu".to_string()),
+            host_triple: "aarch64-unknown-linux-gnu".to_string(),
+            exports: vec![Export::set(
+                "llvm",
+                "LIBCLANG_PATH",
+                "/home/user/.rustup/toolchains/esp/xtensa-esp32-elf-clang/lib",
+            )],
+            prefix: Some(PathBuf::from("/opt/esp")),
+            riscv_components: vec!["clippy".to_string()],
+            ..manifest_fixture()
+        };
+        let schema: serde_json::Value = serde_json::from_str(MANIFEST_SCHEMA).unwrap();
+        let json = serde_json::to_value(&manifest).unwrap();
+        // Every field must be documented in the schema
+        for key in json.as_object().unwrap().keys() {
+            assert!(
+                schema["properties"].get(key).is_some(),
+                "'{key}' is missing"
+            );
+        }
+        assert_eq!(json["exports"][0]["kind"], "set");
+
+        // Manifests written before the schema version was introduced
+        let mut legacy = json.clone();
+        legacy.as_object_mut().unwrap().remove("schema_version");
+        legacy.as_object_mut().unwrap().remove("exports");
+        legacy
+            .as_object_mut()
+            .unwrap()
+            .remove("export_template_version");
+        let legacy: Manifest = serde_json::from_value(legacy).unwrap();
+        assert_eq!(legacy.schema_version, 1);
+        assert!(legacy.exports.is_empty());
+        assert_eq!(legacy.export_template_version, 0);
+    }
+}
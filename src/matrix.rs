@@ -0,0 +1,257 @@
+//! Support matrix of the targets, hosts and component versions, printed by `espup matrix` to
+//! generate documentation from the metadata tables of espup.
+
+use crate::{
+    host_triple::HostTriple,
+    report::OutputFormat,
+    targets::Target,
+    toolchain::{
+        compatibility::{LATEST_LLVM_VERSION, LLVM_RANGES},
+        gcc::DEFAULT_GCC_RELEASE,
+        ToolchainComponent,
+    },
+};
+use serde::Serialize;
+use std::{collections::HashSet, fmt::Write};
+use strum::IntoEnumIterator;
+
+/// Identifier of the RISC-V Rust targets installed with rustup.
+const RISCV_RUST_TARGETS: &str = "riscv-rust-targets";
+
+/// Support of a chip.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TargetSupport {
+    /// Chip name, e.g. `esp32`.
+    pub target: String,
+    /// Architecture, `xtensa` or `riscv`.
+    pub architecture: &'static str,
+    /// Rust target of `no_std` applications.
+    pub rust_target: &'static str,
+    /// Rust target of `std` applications.
+    pub std_rust_target: &'static str,
+    /// Whether the support is experimental.
+    pub experimental: bool,
+    /// Components installed for `no_std` applications.
+    pub components: Vec<&'static str>,
+    /// Components installed for `std` applications.
+    pub std_components: Vec<&'static str>,
+}
+
+/// Support of a host.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct HostSupport {
+    /// Host triple.
+    pub host: String,
+    /// Supported chips.
+    pub targets: Vec<String>,
+}
+
+/// LLVM version of a range of Xtensa Rust versions.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct LlvmSupport {
+    /// Xtensa Rust versions, e.g. `<= 1.69.0.0`.
+    pub xtensa_rust: String,
+    /// Espressif LLVM version.
+    pub llvm: &'static str,
+}
+
+/// Support matrix of espup.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SupportMatrix {
+    /// Supported chips.
+    pub targets: Vec<TargetSupport>,
+    /// Supported hosts.
+    pub hosts: Vec<HostSupport>,
+    /// LLVM versions of the Xtensa Rust versions.
+    pub llvm: Vec<LlvmSupport>,
+    /// Espressif GCC version.
+    pub gcc: &'static str,
+}
+
+/// Returns the components installed for a chip.
+fn components(target: Target, std: bool) -> Vec<&'static str> {
+    let targets: HashSet<Target> = [target].into_iter().collect();
+    let mut components: Vec<&str> = [
+        ToolchainComponent::XtensaRust,
+        ToolchainComponent::Llvm,
+        ToolchainComponent::GccXtensa,
+    ]
+    .into_iter()
+    .filter(|component| component.required_by(&targets, std))
+    .map(|component| component.id())
+    .collect();
+    if target.is_riscv() {
+        components.push(RISCV_RUST_TARGETS);
+    }
+    components
+}
+
+impl SupportMatrix {
+    /// Returns the support matrix of this espup version.
+    pub fn new() -> Self {
+        let targets: Vec<Target> = Target::iter().collect();
+        let hosts = HostTriple::iter()
+            .map(|host| HostSupport {
+                host: host.to_string(),
+                targets: targets
+                    .iter()
+                    .filter(|target| target.is_riscv() || host.has_espressif_artifacts())
+                    .map(|target| target.to_string())
+                    .collect(),
+            })
+            .collect();
+        let mut llvm: Vec<LlvmSupport> = Vec::new();
+        for (last, version) in LLVM_RANGES {
+            let (major, minor, patch, subpatch) = last;
+            llvm.push(LlvmSupport {
+                xtensa_rust: format!("<= {major}.{minor}.{patch}.{subpatch}"),
+                llvm: version,
+            });
+        }
+        if let Some(((major, minor, patch, subpatch), _)) = LLVM_RANGES.last() {
+            llvm.push(LlvmSupport {
+                xtensa_rust: format!("> {major}.{minor}.{patch}.{subpatch}"),
+                llvm: LATEST_LLVM_VERSION,
+            });
+        }
+        Self {
+            targets: targets
+                .into_iter()
+                .map(|target| {
+                    let metadata = target.metadata();
+                    TargetSupport {
+                        target: target.to_string(),
+                        architecture: if target.is_xtensa() {
+                            "xtensa"
+                        } else {
+                            "riscv"
+                        },
+                        rust_target: metadata.rust_target,
+                        std_rust_target: metadata.std_rust_target,
+                        experimental: metadata.experimental,
+                        components: components(target, false),
+                        std_components: components(target, true),
+                    }
+                })
+                .collect(),
+            hosts,
+            llvm,
+            gcc: DEFAULT_GCC_RELEASE,
+        }
+    }
+
+    /// Renders the matrix in the given format.
+    pub fn render(&self, format: OutputFormat) -> String {
+        match format {
+            OutputFormat::Json => serde_json::to_string_pretty(self).unwrap() + "\n",
+            OutputFormat::Text => self.to_markdown(),
+        }
+    }
+
+    /// Renders the matrix as Markdown tables.
+    fn to_markdown(&self) -> String {
+        let mut md = String::from("## Targets\n\n");
+        md.push_str("| Target | Architecture | `no_std` target | `std` target | `no_std` components | `std` components |\n");
+        md.push_str("| --- | --- | --- | --- | --- | --- |\n");
+        for target in &self.targets {
+            let _ = writeln!(
+                md,
+                "| {}{} | {} | `{}` | `{}` | {} | {} |",
+                target.target,
+                if target.experimental {
+                    " (experimental)"
+                } else {
+                    ""
+                },
+                target.architecture,
+                target.rust_target,
+                target.std_rust_target,
+                target.components.join(", "),
+                target.std_components.join(", ")
+            );
+        }
+
+        md.push_str("\n## Hosts\n\n| Host |");
+        for target in &self.targets {
+            let _ = write!(md, " {} |", target.target);
+        }
+        md.push_str("\n| --- |");
+        md.push_str(&" :---: |".repeat(self.targets.len()));
+        md.push('\n');
+        for host in &self.hosts {
+            let _ = write!(md, "| `{}` |", host.host);
+            for target in &self.targets {
+                let supported = host.targets.contains(&target.target);
+                md.push_str(if supported { " ✓ |" } else { " ✗ |" });
+            }
+            md.push('\n');
+        }
+
+        md.push_str("\n## Versions\n\n| Xtensa Rust | LLVM | GCC |\n| --- | --- | --- |\n");
+        for llvm in &self.llvm {
+            let _ = writeln!(
+                md,
+                "| {} | {} | {} |",
+                llvm.xtensa_rust, llvm.llvm, self.gcc
+            );
+        }
+        md
+    }
+}
+
+impl Default for SupportMatrix {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        matrix::SupportMatrix,
+        report::OutputFormat,
+        toolchain::llvm::{DEFAULT_LLVM_15_VERSION, DEFAULT_LLVM_18_VERSION},
+    };
+
+    #[test]
+    fn test_support_matrix() {
+        let matrix = SupportMatrix::new();
+        let esp32 = &matrix.targets[0];
+        assert_eq!(esp32.target, "esp32");
+        assert_eq!(esp32.components, ["xtensa-rust", "llvm", "xtensa-gcc"]);
+        assert_eq!(esp32.std_components, ["xtensa-rust", "llvm"]);
+        let esp32c3 = matrix
+            .targets
+            .iter()
+            .find(|target| target.target == "esp32c3")
+            .unwrap();
+        assert_eq!(esp32c3.components, ["riscv-rust-targets"]);
+        assert!(matrix
+            .targets
+            .iter()
+            .any(|target| target.target == "esp32c61" && target.experimental));
+
+        // Espressif doesn't publish Xtensa artifacts for every host
+        let riscv64 = matrix
+            .hosts
+            .iter()
+            .find(|host| host.host == "riscv64gc-unknown-linux-gnu")
+            .unwrap();
+        assert!(riscv64.targets.contains(&"esp32c3".to_string()));
+        assert!(!riscv64.targets.contains(&"esp32".to_string()));
+
+        assert_eq!(matrix.llvm[0].xtensa_rust, "<= 1.69.0.0");
+        assert_eq!(matrix.llvm[0].llvm, DEFAULT_LLVM_15_VERSION);
+        assert_eq!(matrix.llvm.last().unwrap().xtensa_rust, "> 1.81.0.0");
+        assert_eq!(matrix.llvm.last().unwrap().llvm, DEFAULT_LLVM_18_VERSION);
+
+        let json: serde_json::Value =
+            serde_json::from_str(&matrix.render(OutputFormat::Json)).unwrap();
+        assert_eq!(json["targets"][0]["rust_target"], "xtensa-esp32-none-elf");
+        let md = matrix.render(OutputFormat::Text);
+        assert!(md.contains(
+            "| esp32 | xtensa | `xtensa-esp32-none-elf` | `xtensa-esp32-espidf` | xtensa-rust, llvm, xtensa-gcc | xtensa-rust, llvm |"
+        ));
+        assert!(md.contains("| `riscv64gc-unknown-linux-gnu` | ✗ | ✓ |"));
+    }
+}
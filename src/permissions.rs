@@ -0,0 +1,166 @@
+//! Least-privilege permissions of the extracted toolchains and generated files.
+//!
+//! Depending on the umask and on the archive contents, files could end up group or world
+//! writable, which security scanners flag. Unless disabled with `--permissive`, directories get
+//! `755` and files `644`, or `755` when executable.
+
+#[cfg(unix)]
+use log::debug;
+use std::{
+    ffi::OsString,
+    fs::{remove_file, rename},
+    io,
+    path::Path,
+    sync::atomic::{AtomicBool, Ordering},
+};
+#[cfg(unix)]
+use std::{
+    fs::{read_dir, set_permissions, symlink_metadata, OpenOptions, Permissions},
+    io::Write,
+    os::unix::fs::{OpenOptionsExt, PermissionsExt},
+};
+
+/// Mode of the generated files and of the non executable extracted files.
+#[cfg(any(unix, test))]
+const FILE_MODE: u32 = 0o644;
+/// Mode of the directories and of the executable extracted files.
+#[cfg(any(unix, test))]
+const EXECUTABLE_MODE: u32 = 0o755;
+
+/// Whether the permissions are left as created.
+static PERMISSIVE: AtomicBool = AtomicBool::new(false);
+
+/// Leaves the permissions of the extracted and generated files as created.
+pub fn set_permissive() {
+    PERMISSIVE.store(true, Ordering::Relaxed);
+}
+
+/// Returns whether the permissions are left as created.
+pub fn permissive() -> bool {
+    PERMISSIVE.load(Ordering::Relaxed)
+}
+
+/// Returns the least-privilege mode of an extracted file or directory: executables stay
+/// executable, but only the owner can write.
+#[cfg(any(unix, test))]
+fn normalized_mode(mode: u32, is_dir: bool) -> u32 {
+    if is_dir || mode & 0o111 != 0 {
+        EXECUTABLE_MODE
+    } else {
+        FILE_MODE
+    }
+}
+
+/// Normalizes the permissions of the files and directories extracted into `dir`, symbolic links
+/// excluded.
+#[cfg(unix)]
+pub fn normalize_permissions(dir: &Path) -> io::Result<()> {
+    if permissive() {
+        return Ok(());
+    }
+    debug!("Normalizing the permissions of '{}'", dir.display());
+    let mut pending = vec![dir.to_path_buf()];
+    while let Some(path) = pending.pop() {
+        let metadata = symlink_metadata(&path)?;
+        if metadata.is_symlink() {
+            continue;
+        }
+        if metadata.is_dir() {
+            for entry in read_dir(&path)? {
+                pending.push(entry?.path());
+            }
+        }
+        let mode = metadata.permissions().mode() & 0o7777;
+        let normalized = normalized_mode(mode, metadata.is_dir());
+        if mode != normalized {
+            set_permissions(&path, Permissions::from_mode(normalized))?;
+        }
+    }
+    Ok(())
+}
+
+/// Writes a generated file, readable by everyone but only writable by its owner regardless of
+/// the umask.
+pub fn write_file(path: &Path, contents: impl AsRef<[u8]>) -> io::Result<()> {
+    #[cfg(unix)]
+    if !permissive() {
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(FILE_MODE)
+            .open(path)?;
+        // The mode only applies to new files, and is restricted by the umask
+        file.set_permissions(Permissions::from_mode(FILE_MODE))?;
+        return file.write_all(contents.as_ref());
+    }
+    std::fs::write(path, contents)
+}
+
+/// Replaces a generated file through a temporary file renamed over it, so it is never left
+/// partially written. Symbolic links are followed, replacing the file they point to.
+pub fn replace_file(path: &Path, contents: impl AsRef<[u8]>) -> io::Result<()> {
+    let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+    let mut tmp_name = OsString::from(".");
+    tmp_name.push(path.file_name().unwrap_or_default());
+    tmp_name.push(".tmp");
+    let tmp_path = path.with_file_name(tmp_name);
+    write_file(&tmp_path, contents)?;
+    rename(&tmp_path, &path).map_err(|e| {
+        let _ = remove_file(&tmp_path);
+        e
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::permissions::normalized_mode;
+
+    #[test]
+    fn test_normalized_mode() {
+        assert_eq!(normalized_mode(0o777, true), 0o755);
+        assert_eq!(normalized_mode(0o700, true), 0o755);
+        assert_eq!(normalized_mode(0o777, false), 0o755);
+        assert_eq!(normalized_mode(0o4755, false), 0o755);
+        assert_eq!(normalized_mode(0o666, false), 0o644);
+        assert_eq!(normalized_mode(0o600, false), 0o644);
+
+        #[cfg(unix)]
+        {
+            use crate::permissions::{normalize_permissions, write_file};
+            use std::{
+                fs::{create_dir_all, metadata, set_permissions, Permissions},
+                os::unix::fs::{symlink, PermissionsExt},
+            };
+            use tempfile::TempDir;
+
+            let temp_dir = TempDir::new().unwrap();
+            let bin_dir = temp_dir.path().join("esp-clang/bin");
+            create_dir_all(&bin_dir).unwrap();
+            set_permissions(&bin_dir, Permissions::from_mode(0o777)).unwrap();
+            write_file(&bin_dir.join("clang"), "clang").unwrap();
+            set_permissions(bin_dir.join("clang"), Permissions::from_mode(0o777)).unwrap();
+            write_file(&bin_dir.join("README"), "clang").unwrap();
+            set_permissions(bin_dir.join("README"), Permissions::from_mode(0o666)).unwrap();
+            symlink("clang", bin_dir.join("clang++")).unwrap();
+            normalize_permissions(temp_dir.path()).unwrap();
+
+            let mode = |path: &str| {
+                metadata(temp_dir.path().join(path))
+                    .unwrap()
+                    .permissions()
+                    .mode()
+                    & 0o7777
+            };
+            assert_eq!(mode("esp-clang/bin"), 0o755);
+            assert_eq!(mode("esp-clang/bin/clang"), 0o755);
+            assert_eq!(mode("esp-clang/bin/README"), 0o644);
+
+            // Existing files get the mode as well
+            write_file(&bin_dir.join("README"), "clang").unwrap();
+            set_permissions(bin_dir.join("README"), Permissions::from_mode(0o666)).unwrap();
+            write_file(&bin_dir.join("README"), "llvm").unwrap();
+            assert_eq!(mode("esp-clang/bin/README"), 0o644);
+        }
+    }
+}
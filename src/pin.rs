@@ -0,0 +1,338 @@
+//! Project toolchain pinning through `rust-toolchain.toml`, and Xtensa Rust versions pinned in
+//! project files.
+
+use crate::{
+    error::Error,
+    ldproxy::{install_ldproxy, write_linker_config, LDPROXY},
+    manifest::Manifest,
+    sandbox::write_checked,
+    target_dir::{write_cargo_config, TargetDirStyle},
+    targets::Target,
+};
+use log::{debug, info, warn};
+use miette::Result;
+use std::{
+    collections::{BTreeSet, HashSet},
+    fs::read_to_string,
+    path::{Path, PathBuf},
+};
+
+/// Name of the toolchain file read by rustup.
+const TOOLCHAIN_FILE: &str = "rust-toolchain.toml";
+/// Legacy toolchain file, takes precedence over `rust-toolchain.toml` when both exist.
+const LEGACY_TOOLCHAIN_FILE: &str = "rust-toolchain";
+/// File holding the Xtensa Rust version of a project.
+pub const VERSION_FILE: &str = ".xtensa-rust-version";
+/// Section of `rust-toolchain.toml` holding the Xtensa Rust version, ignored by rustup.
+const VERSION_SECTION: &str = "[espup]";
+/// Key of the Xtensa Rust version in the `[espup]` section.
+const VERSION_KEY: &str = "toolchain-version";
+
+/// `[toolchain]` section of a `rust-toolchain.toml` file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ToolchainPin {
+    /// Toolchain channel.
+    pub channel: String,
+    /// Additional components.
+    pub components: Vec<String>,
+    /// Additional targets.
+    pub targets: Vec<String>,
+}
+
+impl ToolchainPin {
+    /// Returns the toolchain required to build the given targets of an installation.
+    ///
+    /// Xtensa targets require the Xtensa Rust toolchain, RISC-V ones use the stable channel
+    /// (nightly for STD applications) with the `rust-src` component.
+    pub fn new(manifest: &Manifest, targets: &HashSet<Target>) -> Self {
+        if targets.iter().any(|t| t.is_xtensa()) {
+            return Self {
+                channel: manifest.name.clone(),
+                components: Vec::new(),
+                targets: Vec::new(),
+            };
+        }
+        let channel = if manifest.std {
+            manifest.nightly_version.clone()
+        } else {
+            "stable".to_string()
+        };
+        let rust_targets: BTreeSet<&str> = targets.iter().map(|t| t.rust_target()).collect();
+        Self {
+            channel,
+            components: vec!["rust-src".to_string()],
+            targets: rust_targets.into_iter().map(String::from).collect(),
+        }
+    }
+
+    /// Renders the `[toolchain]` section.
+    fn to_toml(&self) -> String {
+        let list = |values: &[String]| {
+            values
+                .iter()
+                .map(|value| format!("\"{value}\""))
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        let mut toml = format!("[toolchain]\nchannel = \"{}\"\n", self.channel);
+        if !self.components.is_empty() {
+            toml.push_str(&format!("components = [{}]\n", list(&self.components)));
+        }
+        if !self.targets.is_empty() {
+            toml.push_str(&format!("targets = [{}]\n", list(&self.targets)));
+        }
+        toml
+    }
+
+    /// Returns the content of the toolchain file, replacing the `[toolchain]` section of the
+    /// existing content, if any, and keeping everything else.
+    fn update(&self, existing: Option<&str>) -> String {
+        let Some(existing) = existing else {
+            return self.to_toml();
+        };
+        let mut content = String::new();
+        let mut in_toolchain_section = false;
+        let mut replaced = false;
+        for line in existing.lines() {
+            let trimmed = line.trim();
+            if trimmed.starts_with('[') {
+                if in_toolchain_section {
+                    // Keep the sections separated
+                    content.push('\n');
+                }
+                in_toolchain_section = trimmed == "[toolchain]";
+                if in_toolchain_section {
+                    content.push_str(&self.to_toml());
+                    replaced = true;
+                    continue;
+                }
+            }
+            if !in_toolchain_section {
+                content.push_str(line);
+                content.push('\n');
+            }
+        }
+        if !replaced {
+            if !content.is_empty() && !content.ends_with("\n\n") {
+                content.push('\n');
+            }
+            content.push_str(&self.to_toml());
+        }
+        content
+    }
+
+    /// Writes or updates the `rust-toolchain.toml` file of the given directory, returning its
+    /// path.
+    pub fn write(&self, dir: &Path) -> Result<PathBuf, Error> {
+        if dir.join(LEGACY_TOOLCHAIN_FILE).exists() {
+            warn!(
+                "'{}' exists in '{}' and takes precedence over '{}', consider removing it",
+                LEGACY_TOOLCHAIN_FILE,
+                dir.display(),
+                TOOLCHAIN_FILE
+            );
+        }
+        let path = dir.join(TOOLCHAIN_FILE);
+        let existing = if path.exists() {
+            debug!("Updating '{}'", path.display());
+            Some(read_to_string(&path)?)
+        } else {
+            None
+        };
+        write_checked(&path, self.update(existing.as_deref()))?;
+        Ok(path)
+    }
+}
+
+/// Pins the toolchain of an installation in the project located in `dir`.
+///
+/// When no targets are given, all the installed targets are considered.
+///
+/// The `short` target directory style also sets a short target directory in the project Cargo
+/// configuration, and `ldproxy` sets it as the linker of the STD targets.
+pub fn pin(
+    dir: &Path,
+    name: &str,
+    targets: Option<HashSet<Target>>,
+    target_dir_style: TargetDirStyle,
+    ldproxy: bool,
+) -> Result<(), Error> {
+    let manifest = Manifest::load(name)?.ok_or_else(|| Error::MissingManifest(name.to_string()))?;
+    let targets = targets.unwrap_or_else(|| manifest.targets.clone());
+    let missing: Vec<String> = targets
+        .difference(&manifest.targets)
+        .map(|t| t.to_string())
+        .collect();
+    if !missing.is_empty() {
+        return Err(Error::TargetsNotInstalled(
+            name.to_string(),
+            missing.join(", "),
+        ));
+    }
+
+    let pin = ToolchainPin::new(&manifest, &targets);
+    let path = pin.write(dir)?;
+    info!("Pinned '{}' toolchain in '{}'", pin.channel, path.display());
+    if let Some(target_dir) = target_dir_style.target_dir() {
+        let path = write_cargo_config(dir, target_dir)?;
+        info!(
+            "Set '{}' target directory in '{}'",
+            target_dir,
+            path.display()
+        );
+    }
+    if ldproxy {
+        install_ldproxy(&manifest.homes())?;
+        let path = write_linker_config(dir, &targets)?;
+        info!("Set '{}' linker in '{}'", LDPROXY, path.display());
+    }
+    Ok(())
+}
+
+/// Returns the Xtensa Rust version pinned in a version file: the first line of a
+/// `.xtensa-rust-version` file, comments excepted, or the `toolchain-version` key of the `[espup]`
+/// section of a TOML file.
+fn parse_toolchain_version(content: &str, toml: bool) -> Option<String> {
+    let mut lines = content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'));
+    if !toml {
+        return lines.next().map(String::from);
+    }
+    let mut in_version_section = false;
+    for line in lines {
+        if line.starts_with('[') {
+            in_version_section = line == VERSION_SECTION;
+        } else if let Some((key, value)) = line.split_once('=').filter(|_| in_version_section) {
+            if key.trim() == VERSION_KEY {
+                let value = value.trim().trim_matches(|c| c == '"' || c == '\'');
+                return (!value.is_empty()).then(|| value.to_string());
+            }
+        }
+    }
+    None
+}
+
+/// Reads the Xtensa Rust version pinned in a `.xtensa-rust-version` or `rust-toolchain.toml`
+/// file.
+pub fn read_toolchain_version(path: &Path) -> Result<String, Error> {
+    let content = read_to_string(path).map_err(|e| {
+        Error::InvalidToolchainVersionFile(path.display().to_string(), e.to_string())
+    })?;
+    let toml = path
+        .extension()
+        .is_some_and(|extension| extension == "toml");
+    parse_toolchain_version(&content, toml).ok_or_else(|| {
+        Error::InvalidToolchainVersionFile(
+            path.display().to_string(),
+            if toml {
+                format!("no '{VERSION_KEY}' key in the '{VERSION_SECTION}' section")
+            } else {
+                "the file is empty".to_string()
+            },
+        )
+    })
+}
+
+/// Returns the closest file pinning the Xtensa Rust version in `dir` or its parents: a
+/// `.xtensa-rust-version` file, or a `rust-toolchain.toml` file with an `[espup]` section.
+pub fn find_toolchain_version_file(dir: &Path) -> Option<PathBuf> {
+    dir.ancestors().find_map(|dir| {
+        let version_file = dir.join(VERSION_FILE);
+        if version_file.is_file() {
+            return Some(version_file);
+        }
+        let toolchain_file = dir.join(TOOLCHAIN_FILE);
+        read_to_string(&toolchain_file)
+            .ok()
+            .and_then(|content| parse_toolchain_version(&content, true))
+            .map(|_| toolchain_file)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        manifest::{manifest_fixture, Manifest},
+        pin::{find_toolchain_version_file, read_toolchain_version, ToolchainPin, VERSION_FILE},
+        targets::Target,
+    };
+    use std::fs::{create_dir_all, write};
+    use tempfile::TempDir;
+
+    fn manifest() -> Manifest {
+        Manifest {
+            targets: [Target::ESP32, Target::ESP32C3, Target::ESP32C6]
+                .into_iter()
+                .collect(),
+            ..manifest_fixture()
+        }
+    }
+
+    #[test]
+    fn test_toolchain_pin() {
+        let manifest = manifest();
+        let xtensa = ToolchainPin::new(&manifest, &manifest.targets);
+        assert_eq!(xtensa.update(None), "[toolchain]\nchannel = \"esp\"\n");
+
+        let riscv = ToolchainPin::new(
+            &manifest,
+            &[Target::ESP32C3, Target::ESP32C6].into_iter().collect(),
+        );
+        assert_eq!(
+            riscv.update(None),
+            "[toolchain]\nchannel = \"stable\"\ncomponents = [\"rust-src\"]\ntargets = [\"riscv32imac-unknown-none-elf\", \"riscv32imc-unknown-none-elf\"]\n"
+        );
+
+        // Only the `[toolchain]` section is replaced
+        let existing = "# Pinned toolchain\n[toolchain]\nchannel = \"nightly\"\nprofile = \"minimal\"\n\n[other]\nkey = 1\n";
+        assert_eq!(
+            xtensa.update(Some(existing)),
+            "# Pinned toolchain\n[toolchain]\nchannel = \"esp\"\n\n[other]\nkey = 1\n"
+        );
+        assert_eq!(
+            xtensa.update(Some("# Pinned toolchain\n")),
+            "# Pinned toolchain\n\n[toolchain]\nchannel = \"esp\"\n"
+        );
+    }
+
+    #[test]
+    fn test_toolchain_version_file() {
+        let temp_dir = TempDir::new().unwrap();
+        let project = temp_dir.path().join("firmware/app");
+        create_dir_all(&project).unwrap();
+        assert_eq!(find_toolchain_version_file(&project), None);
+
+        // `rust-toolchain.toml` files without an `[espup]` section are not version files
+        write(
+            project.join("rust-toolchain.toml"),
+            "[toolchain]\nchannel = \"esp\"\n",
+        )
+        .unwrap();
+        assert_eq!(find_toolchain_version_file(&project), None);
+        assert!(read_toolchain_version(&project.join("rust-toolchain.toml")).is_err());
+
+        write(
+            temp_dir.path().join(VERSION_FILE),
+            "# Xtensa Rust version\n\n1.82.0.3\n",
+        )
+        .unwrap();
+        let version_file = find_toolchain_version_file(&project).unwrap();
+        assert_eq!(version_file, temp_dir.path().join(VERSION_FILE));
+        assert_eq!(read_toolchain_version(&version_file).unwrap(), "1.82.0.3");
+
+        // The closest file wins
+        write(
+            project.join("rust-toolchain.toml"),
+            "[toolchain]\nchannel = \"esp\"\n\n[espup]\ntoolchain-version = \"1.85.0.0\"\n",
+        )
+        .unwrap();
+        let toolchain_file = find_toolchain_version_file(&project).unwrap();
+        assert_eq!(toolchain_file, project.join("rust-toolchain.toml"));
+        assert_eq!(read_toolchain_version(&toolchain_file).unwrap(), "1.85.0.0");
+
+        write(temp_dir.path().join(VERSION_FILE), "\n").unwrap();
+        assert!(read_toolchain_version(&temp_dir.path().join(VERSION_FILE)).is_err());
+    }
+}
@@ -0,0 +1,189 @@
+//! GitHub releases of the toolchain repositories.
+//!
+//! Typed access to the releases of `esp-rs/rust-build` and `espressif/llvm-project`, shared with
+//! other tools. Responses are cached for the lifetime of the process, and queries retry on
+//! failures and wait for short rate limit windows (see [`github_query`]).
+
+use crate::{
+    error::Error,
+    toolchain::fetch::{github_query, GithubQueryOpts},
+};
+use log::debug;
+use miette::Result;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    sync::{Mutex, OnceLock},
+};
+
+/// Repository of the Xtensa Rust toolchain releases.
+pub const RUST_BUILD_REPOSITORY: &str = "esp-rs/rust-build";
+/// Repository of the Espressif LLVM releases.
+pub const LLVM_REPOSITORY: &str = "espressif/llvm-project";
+/// Releases requested per page, the maximum allowed by the GitHub API.
+const PER_PAGE: usize = 100;
+/// Maximum number of pages requested when listing releases.
+const MAX_PAGES: usize = 10;
+
+/// Responses of the GitHub API queries, by URL.
+static RESPONSES: OnceLock<Mutex<HashMap<String, serde_json::Value>>> = OnceLock::new();
+
+/// GitHub release.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Release {
+    /// Tag of the release, e.g. `v1.82.0.3`.
+    pub tag_name: String,
+    /// Title of the release.
+    #[serde(default)]
+    pub name: Option<String>,
+    /// Whether the release is a draft.
+    #[serde(default)]
+    pub draft: bool,
+    /// Whether the release is a pre-release.
+    #[serde(default)]
+    pub prerelease: bool,
+    /// Publication date, in ISO 8601 format.
+    #[serde(default)]
+    pub published_at: Option<String>,
+    /// Files of the release.
+    #[serde(default)]
+    pub assets: Vec<Asset>,
+}
+
+impl Release {
+    /// Returns the version of the release, its tag without the `v` prefix.
+    pub fn version(&self) -> &str {
+        self.tag_name.strip_prefix('v').unwrap_or(&self.tag_name)
+    }
+
+    /// Returns the file of the release with the given name.
+    pub fn asset(&self, name: &str) -> Option<&Asset> {
+        self.assets.iter().find(|asset| asset.name == name)
+    }
+}
+
+/// File of a GitHub release.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Asset {
+    /// File name.
+    pub name: String,
+    /// Download URL.
+    pub browser_download_url: String,
+    /// Size in bytes.
+    #[serde(default)]
+    pub size: u64,
+    /// Checksum computed by GitHub, e.g. `sha256:<hex>`, missing for older assets.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub digest: Option<String>,
+}
+
+/// Queries the GitHub API, reusing the response of previous identical queries.
+fn cached_query(url: &str, opts: &GithubQueryOpts) -> Result<serde_json::Value, Error> {
+    let responses = RESPONSES.get_or_init(Default::default);
+    if let Some(response) = responses.lock().unwrap().get(url) {
+        debug!("Reusing GitHub API response: '{}'", url);
+        return Ok(response.clone());
+    }
+    let response = github_query(url, opts)?;
+    responses
+        .lock()
+        .unwrap()
+        .insert(url.to_string(), response.clone());
+    Ok(response)
+}
+
+/// Returns the latest release of the repository (`owner/name`), blocking the current thread.
+pub fn fetch_latest_release(repository: &str, opts: &GithubQueryOpts) -> Result<Release, Error> {
+    let url = format!("https://api.github.com/repos/{repository}/releases/latest");
+    serde_json::from_value(cached_query(&url, opts)?).map_err(|_| Error::SerializeJson)
+}
+
+/// Returns the release of the repository (`owner/name`) with the given tag, blocking the current
+/// thread.
+pub fn fetch_release(
+    repository: &str,
+    tag: &str,
+    opts: &GithubQueryOpts,
+) -> Result<Release, Error> {
+    let url = format!("https://api.github.com/repos/{repository}/releases/tags/{tag}");
+    serde_json::from_value(cached_query(&url, opts)?).map_err(|_| Error::SerializeJson)
+}
+
+/// Returns the releases of the repository (`owner/name`), newest first, blocking the current
+/// thread.
+pub fn fetch_releases(repository: &str, opts: &GithubQueryOpts) -> Result<Vec<Release>, Error> {
+    let mut releases = Vec::new();
+    for page in 1..=MAX_PAGES {
+        let url = format!(
+            "https://api.github.com/repos/{repository}/releases?page={page}&per_page={PER_PAGE}"
+        );
+        let page: Vec<Release> =
+            serde_json::from_value(cached_query(&url, opts)?).map_err(|_| Error::SerializeJson)?;
+        let last_page = page.len() < PER_PAGE;
+        releases.extend(page);
+        if last_page {
+            break;
+        }
+    }
+    Ok(releases)
+}
+
+/// Returns the latest release of the repository (`owner/name`).
+pub async fn latest_release(repository: &str, opts: GithubQueryOpts) -> Result<Release, Error> {
+    let repository = repository.to_string();
+    tokio::task::spawn_blocking(move || fetch_latest_release(&repository, &opts))
+        .await
+        .expect("Join blocking task error")
+}
+
+/// Returns the release of the repository (`owner/name`) with the given tag.
+pub async fn release(repository: &str, tag: &str, opts: GithubQueryOpts) -> Result<Release, Error> {
+    let (repository, tag) = (repository.to_string(), tag.to_string());
+    tokio::task::spawn_blocking(move || fetch_release(&repository, &tag, &opts))
+        .await
+        .expect("Join blocking task error")
+}
+
+/// Returns the releases of the repository (`owner/name`), newest first.
+pub async fn releases(repository: &str, opts: GithubQueryOpts) -> Result<Vec<Release>, Error> {
+    let repository = repository.to_string();
+    tokio::task::spawn_blocking(move || fetch_releases(&repository, &opts))
+        .await
+        .expect("Join blocking task error")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::releases::Release;
+
+    #[test]
+    fn test_release() {
+        let release: Release = serde_json::from_str(
+            r#"{
+                "tag_name": "v1.82.0.3",
+                "name": "Rust 1.82.0.3",
+                "draft": false,
+                "prerelease": false,
+                "published_at": "2024-11-04T10:00:00Z",
+                "assets": [
+                    {
+                        "name": "rust-src-1.82.0.3.tar.xz",
+                        "browser_download_url": "https://github.com/esp-rs/rust-build/releases/download/v1.82.0.3/rust-src-1.82.0.3.tar.xz",
+                        "size": 42,
+                        "content_type": "application/x-xz"
+                    }
+                ],
+                "body": "Release notes"
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(release.version(), "1.82.0.3");
+        assert_eq!(release.asset("rust-src-1.82.0.3.tar.xz").unwrap().size, 42);
+        assert!(release.asset("rust-1.82.0.3.zip").is_none());
+
+        let release: Release =
+            serde_json::from_str(r#"{"tag_name": "esp-19.1.2_20250225"}"#).unwrap();
+        assert_eq!(release.version(), "esp-19.1.2_20250225");
+        assert!(release.assets.is_empty());
+    }
+}
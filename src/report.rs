@@ -0,0 +1,118 @@
+//! Reports of the read-only subcommands, printed as text for humans or as JSON for scripts and
+//! IDE integrations.
+
+use crate::toolchain::hashes::ComponentChanges;
+use serde::Serialize;
+use std::{collections::BTreeMap, fmt};
+
+/// Output format of the reports, the support matrix and the environments.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum OutputFormat {
+    /// Human readable text: Markdown tables for the support matrix, `NAME=value` lines for the
+    /// environments.
+    #[default]
+    #[cfg_attr(feature = "cli", value(alias = "md", alias = "env"))]
+    Text,
+    /// JSON document.
+    Json,
+}
+
+/// Renders a report in the given format.
+pub fn render<R: Serialize + fmt::Display>(report: &R, format: OutputFormat) -> String {
+    match format {
+        OutputFormat::Text => report.to_string(),
+        OutputFormat::Json => serde_json::to_string_pretty(report).unwrap() + "\n",
+    }
+}
+
+/// Result of the verification of the files of an installation against their recorded hashes.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct VerifyReport {
+    /// Xtensa Rust toolchain name.
+    pub name: String,
+    /// Number of recorded files.
+    pub recorded: usize,
+    /// Changes of the components with changed files.
+    pub changes: BTreeMap<String, ComponentChanges>,
+}
+
+impl VerifyReport {
+    /// Returns the number of changed files.
+    pub fn changed(&self) -> usize {
+        self.changes.values().map(ComponentChanges::len).sum()
+    }
+}
+
+impl fmt::Display for VerifyReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.changes.is_empty() {
+            return writeln!(f, "All the {} recorded files are unchanged", self.recorded);
+        }
+        for (component, changes) in &self.changes {
+            writeln!(f, "{component}")?;
+            for (status, paths) in [
+                ("modified", &changes.modified),
+                ("missing", &changes.missing),
+                ("extra", &changes.extra),
+            ] {
+                for path in paths {
+                    writeln!(f, "  {status:<8}  {path}")?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        report::{render, OutputFormat, VerifyReport},
+        toolchain::hashes::ComponentChanges,
+    };
+    use serde_json::json;
+
+    #[test]
+    fn test_verify_report() {
+        let mut report = VerifyReport {
+            name: "esp".to_string(),
+            recorded: 42,
+            changes: Default::default(),
+        };
+        assert_eq!(
+            render(&report, OutputFormat::Text),
+            "All the 42 recorded files are unchanged\n"
+        );
+
+        report.changes.insert(
+            "esp-clang".to_string(),
+            ComponentChanges {
+                modified: vec!["esp-clang/lib/libclang.so".to_string()],
+                missing: Vec::new(),
+                extra: vec!["esp-clang/lib/libclang.so.bak".to_string()],
+            },
+        );
+        assert_eq!(report.changed(), 2);
+        assert_eq!(
+            render(&report, OutputFormat::Text),
+            "esp-clang\n  modified  esp-clang/lib/libclang.so\n  extra     esp-clang/lib/libclang.so.bak\n"
+        );
+        let json: serde_json::Value =
+            serde_json::from_str(&render(&report, OutputFormat::Json)).unwrap();
+        assert_eq!(
+            json,
+            json!({
+                "name": "esp",
+                "recorded": 42,
+                "changes": {
+                    "esp-clang": {
+                        "modified": ["esp-clang/lib/libclang.so"],
+                        "missing": [],
+                        "extra": ["esp-clang/lib/libclang.so.bak"]
+                    }
+                }
+            })
+        );
+    }
+}
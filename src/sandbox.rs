@@ -0,0 +1,146 @@
+//! Sandbox mode, restricting the writes to declared directories.
+//!
+//! Package managers like Nix or Guix, and sandboxed CI jobs, require espup to never touch the
+//! home directory or the registry. In sandbox mode only the installation prefix, the export files
+//! and the espup directories set through environment variables are writable: the planned writes
+//! are checked before doing any work and every write is checked again with [`check_write`].
+
+use crate::{
+    env::{declared_dirs, export_files, get_espup_dir, ExportShell},
+    error::Error,
+    manifest::Manifest,
+};
+use log::debug;
+use std::{
+    fs::{create_dir_all, write},
+    path::{Component, Path, PathBuf},
+    sync::Mutex,
+};
+
+/// Writable directories and files, when the sandbox mode is enabled.
+static ALLOWED_PATHS: Mutex<Option<Vec<PathBuf>>> = Mutex::new(None);
+
+/// Installation whose writes are restricted.
+#[derive(Debug, Clone)]
+pub struct SandboxPlan<'a> {
+    /// Toolchain name.
+    pub name: &'a str,
+    /// Export file, if the environment is modified.
+    pub export_file: Option<&'a Path>,
+    /// Additional shells export files are generated for.
+    pub export_shells: &'a [ExportShell],
+    /// Whether LLVM is installed, which links its libraries into the espup directory on Unix.
+    pub llvm: bool,
+}
+
+/// Normalizes a path lexically, resolving the `.` and `..` components.
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            component => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+/// Returns whether the path is one of the allowed ones, or below one of them.
+fn is_allowed(path: &Path, allowed: &[PathBuf]) -> bool {
+    let path = normalize(path);
+    allowed.iter().any(|dir| path.starts_with(normalize(dir)))
+}
+
+/// Enables the sandbox mode, where only the prefix, the `--tmp-dir` directory and the directories
+/// set through environment variables are writable.
+pub fn enable_sandbox(prefix: &Path, tmp_dir: Option<&Path>) {
+    let mut allowed = vec![prefix.to_path_buf()];
+    allowed.extend(tmp_dir.map(Path::to_path_buf));
+    allowed.extend(declared_dirs());
+    *ALLOWED_PATHS.lock().unwrap() = Some(allowed);
+}
+
+/// Allows writing the export files of the installation, then fails if any planned write is
+/// outside the writable paths.
+pub fn check_plan(plan: &SandboxPlan) -> Result<(), Error> {
+    let export_files = plan
+        .export_file
+        .map(|export_file| export_files(export_file, plan.export_shells))
+        .unwrap_or_default();
+    if let Some(allowed) = ALLOWED_PATHS.lock().unwrap().as_mut() {
+        allowed.extend(export_files.iter().cloned());
+        debug!("Sandbox mode, writable paths: {:?}", allowed);
+    }
+
+    let mut planned = vec![Manifest::path(plan.name)];
+    planned.extend(export_files);
+    if cfg!(unix) && plan.llvm {
+        planned.push(get_espup_dir().join("esp-clang"));
+    }
+    planned.iter().try_for_each(|path| check_write(path))
+}
+
+/// Returns whether the sandbox mode is enabled.
+pub fn sandboxed() -> bool {
+    ALLOWED_PATHS.lock().unwrap().is_some()
+}
+
+/// Fails if the path is not writable in sandbox mode.
+pub fn check_write(path: &Path) -> Result<(), Error> {
+    match ALLOWED_PATHS.lock().unwrap().as_deref() {
+        Some(allowed) if !is_allowed(path, allowed) => {
+            Err(Error::WriteOutsideSandbox(path.display().to_string()))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Writes a file, creating its directory if needed, once checked that it's writable in sandbox
+/// mode. Every file espup writes outside of the extracted toolchains goes through it.
+pub fn write_checked(path: &Path, contents: impl AsRef<[u8]>) -> Result<(), Error> {
+    check_write(path)?;
+    if let Some(parent) = path
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty() && !parent.exists())
+    {
+        create_dir_all(parent).map_err(|_| Error::CreateDirectory(parent.display().to_string()))?;
+    }
+    write(path, contents)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::sandbox::{is_allowed, normalize, write_checked};
+    use std::{
+        fs::read_to_string,
+        path::{Path, PathBuf},
+    };
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_is_allowed() {
+        let allowed = vec![
+            PathBuf::from("/nix/store/esp"),
+            PathBuf::from("/build/export-esp.sh"),
+        ];
+        assert!(is_allowed(Path::new("/nix/store/esp/bin/rustc"), &allowed));
+        assert!(is_allowed(Path::new("/build/export-esp.sh"), &allowed));
+        assert!(is_allowed(Path::new("/nix/store/esp/./lib"), &allowed));
+        assert!(!is_allowed(Path::new("/nix/store/esp/../other"), &allowed));
+        assert!(!is_allowed(Path::new("/nix/store/esp-other"), &allowed));
+        assert!(!is_allowed(Path::new("/build/export-esp.fish"), &allowed));
+        assert_eq!(normalize(Path::new("/a/b/../c/.")), PathBuf::from("/a/c"));
+    }
+
+    #[test]
+    fn test_write_checked() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join(".cargo/config.toml");
+        write_checked(&path, "[build]\n").unwrap();
+        assert_eq!(read_to_string(&path).unwrap(), "[build]\n");
+    }
+}
@@ -0,0 +1,345 @@
+//! `espup serve`, a local web page guiding the installation, e.g. for workshops.
+//!
+//! The page drives a [daemon](crate::daemon) through its JSON-RPC messages: the requests are
+//! posted to `/rpc`, and its responses and notifications are streamed back as server-sent events
+//! from `/events`. Download progress bars are drawn from the `progress` notifications of the
+//! daemon. Once installed, `/environment` returns the export file to source and the environment
+//! variables of the installation.
+//!
+//! Only requests addressed to a loopback host are served, so other websites can't reach the
+//! server through DNS rebinding.
+
+use crate::{daemon, error::Error, manifest::Manifest, targets::Target};
+use log::{debug, info};
+use serde_json::{json, Value};
+use std::{io, path::PathBuf, sync::Arc};
+use strum::IntoEnumIterator;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader, DuplexStream},
+    net::{TcpListener, TcpStream},
+    sync::{broadcast, Mutex},
+};
+
+/// Page of the installation, with its styles and scripts.
+const INDEX_HTML: &str = include_str!("serve.html");
+/// Daemon methods available to the page.
+const METHODS: [&str; 3] = ["install", "cancel", "list"];
+/// Maximum size of a request body.
+const MAX_BODY: usize = 64 * 1024;
+/// Hosts the requests must be addressed to.
+const LOOPBACK_HOSTS: [&str; 3] = ["127.0.0.1", "localhost", "[::1]"];
+
+/// Request line and headers of an HTTP request.
+#[derive(Debug, PartialEq, Eq)]
+struct Request {
+    method: String,
+    path: String,
+    headers: Vec<(String, String)>,
+}
+
+impl Request {
+    /// Returns the value of a header, whose name is case insensitive.
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Returns whether the request is addressed to a loopback host, with any port.
+    fn is_loopback(&self) -> bool {
+        self.header("host").is_some_and(|host| {
+            let host = match host.rsplit_once(':') {
+                Some((host, port)) if !port.contains(']') => host,
+                _ => host,
+            };
+            LOOPBACK_HOSTS.contains(&host)
+        })
+    }
+}
+
+/// Parses the request line and headers, e.g. `GET / HTTP/1.1`.
+fn parse_request(head: &[String]) -> Option<Request> {
+    let mut request_line = head.first()?.split_whitespace();
+    let method = request_line.next()?.to_string();
+    let path = request_line.next()?.to_string();
+    let headers = head[1..]
+        .iter()
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, value)| (name.trim().to_string(), value.trim().to_string()))
+        .collect();
+    Some(Request {
+        method,
+        path,
+        headers,
+    })
+}
+
+/// Returns the value of a query parameter of the path.
+fn query_param<'a>(path: &'a str, name: &str) -> Option<&'a str> {
+    path.split_once('?')?
+        .1
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| value)
+}
+
+/// Returns the daemon request line of a request posted to `/rpc`, only the [`METHODS`] of the
+/// page being allowed.
+fn rpc_line(body: &[u8]) -> Result<String, String> {
+    let request: Value = serde_json::from_slice(body).map_err(|e| e.to_string())?;
+    match request.get("method").and_then(Value::as_str) {
+        Some(method) if METHODS.contains(&method) => Ok(format!("{request}\n")),
+        Some(method) => Err(format!("Method '{method}' is not available")),
+        None => Err("Missing method".to_string()),
+    }
+}
+
+/// Returns the targets offered by the page.
+fn targets() -> Value {
+    Target::iter()
+        .map(|target| {
+            json!({
+                "name": target.to_string(),
+                "xtensa": target.is_xtensa(),
+                "experimental": target.is_experimental(),
+            })
+        })
+        .collect()
+}
+
+/// Returns the export file and the environment variables of an installation.
+fn environment(name: &str) -> Result<Value, Error> {
+    let manifest = Manifest::load(name)?.ok_or_else(|| Error::MissingManifest(name.to_string()))?;
+    Ok(json!({
+        "export_file": manifest.export_file,
+        "variables": manifest.environment(false),
+    }))
+}
+
+/// Writes a complete response.
+async fn respond(
+    stream: &mut TcpStream,
+    status: &str,
+    content_type: &str,
+    body: &str,
+) -> io::Result<()> {
+    let response = format!(
+        "HTTP/1.1 {status}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\nCache-Control: no-store\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    );
+    stream.write_all(response.as_bytes()).await
+}
+
+/// Writes a JSON response.
+async fn respond_json(stream: &mut TcpStream, status: &str, body: &Value) -> io::Result<()> {
+    respond(stream, status, "application/json", &body.to_string()).await
+}
+
+/// Streams the daemon messages as server-sent events until the page is closed.
+async fn stream_events(
+    stream: &mut TcpStream,
+    mut messages: broadcast::Receiver<String>,
+) -> io::Result<()> {
+    stream
+        .write_all(
+            b"HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-store\r\nConnection: close\r\n\r\n",
+        )
+        .await?;
+    loop {
+        match messages.recv().await {
+            Ok(message) => {
+                stream
+                    .write_all(format!("data: {message}\n\n").as_bytes())
+                    .await?
+            }
+            Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                debug!("Skipped {} messages of a slow page", skipped)
+            }
+            Err(broadcast::error::RecvError::Closed) => return Ok(()),
+        }
+    }
+}
+
+/// Handles a connection, serving a single request.
+async fn handle_connection(
+    mut stream: TcpStream,
+    daemon_input: Arc<Mutex<DuplexStream>>,
+    messages: broadcast::Sender<String>,
+) -> io::Result<()> {
+    let mut reader = BufReader::new(&mut stream);
+    let mut head = Vec::new();
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).await? == 0 || line.trim_end().is_empty() {
+            break;
+        }
+        head.push(line.trim_end().to_string());
+    }
+    let Some(request) = parse_request(&head) else {
+        return Ok(());
+    };
+    let length = request
+        .header("content-length")
+        .and_then(|length| length.parse().ok())
+        .unwrap_or(0usize);
+    if length > MAX_BODY {
+        return respond(&mut stream, "413 Payload Too Large", "text/plain", "").await;
+    }
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body).await?;
+    debug!("{} {}", request.method, request.path);
+
+    if !request.is_loopback() {
+        return respond(&mut stream, "403 Forbidden", "text/plain", "").await;
+    }
+    let route = request.path.split('?').next().unwrap_or_default();
+    match (request.method.as_str(), route) {
+        ("GET", "/") => {
+            respond(
+                &mut stream,
+                "200 OK",
+                "text/html; charset=utf-8",
+                INDEX_HTML,
+            )
+            .await
+        }
+        ("GET", "/targets") => respond_json(&mut stream, "200 OK", &targets()).await,
+        ("GET", "/events") => stream_events(&mut stream, messages.subscribe()).await,
+        ("GET", "/environment") => {
+            let name = query_param(&request.path, "name").unwrap_or("esp");
+            match environment(name) {
+                Ok(environment) => respond_json(&mut stream, "200 OK", &environment).await,
+                Err(e) => {
+                    let error = json!({ "error": e.to_string() });
+                    respond_json(&mut stream, "404 Not Found", &error).await
+                }
+            }
+        }
+        // Forms of other websites can't send JSON, only scripts of this page can
+        ("POST", "/rpc") if request.header("content-type") == Some("application/json") => {
+            match rpc_line(&body) {
+                Ok(line) => {
+                    daemon_input.lock().await.write_all(line.as_bytes()).await?;
+                    respond_json(&mut stream, "202 Accepted", &json!({})).await
+                }
+                Err(e) => {
+                    respond_json(&mut stream, "400 Bad Request", &json!({ "error": e })).await
+                }
+            }
+        }
+        _ => respond(&mut stream, "404 Not Found", "text/plain", "").await,
+    }
+}
+
+/// Serves the installation page on the listener, running the operations with the `espup`
+/// executable, until the process is stopped.
+pub async fn serve_ui(listener: TcpListener, espup: PathBuf) -> Result<(), Error> {
+    let (daemon_input, input) = tokio::io::duplex(MAX_BODY);
+    let (output, daemon_output) = tokio::io::duplex(MAX_BODY);
+    tokio::spawn(async move { daemon::serve(BufReader::new(input), output, &espup).await });
+
+    let (messages, _) = broadcast::channel(1024);
+    let sender = messages.clone();
+    tokio::spawn(async move {
+        let mut lines = BufReader::new(daemon_output).lines();
+        while let Ok(Some(line)) = lines.next_line().await {
+            // Nobody may be listening yet
+            let _ = sender.send(line);
+        }
+    });
+
+    info!(
+        "Open http://{} in a browser to install the toolchains, press Ctrl+C to stop",
+        listener.local_addr()?
+    );
+    let daemon_input = Arc::new(Mutex::new(daemon_input));
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let (daemon_input, messages) = (daemon_input.clone(), messages.clone());
+        tokio::spawn(async move {
+            if let Err(e) = handle_connection(stream, daemon_input, messages).await {
+                debug!("Failed to serve a request: {}", e);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::serve::{parse_request, query_param, rpc_line, serve_ui};
+    use std::path::PathBuf;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::{TcpListener, TcpStream},
+    };
+
+    #[test]
+    fn test_parse_request() {
+        let head = [
+            "GET /environment?name=esp HTTP/1.1",
+            "Host: localhost:8177",
+            "content-type: application/json",
+        ]
+        .map(String::from);
+        let request = parse_request(&head).unwrap();
+        assert_eq!(request.method, "GET");
+        assert_eq!(request.header("Content-Type"), Some("application/json"));
+        assert!(request.is_loopback());
+        assert_eq!(query_param(&request.path, "name"), Some("esp"));
+        assert_eq!(query_param(&request.path, "format"), None);
+
+        for (host, loopback) in [
+            ("127.0.0.1", true),
+            ("[::1]:8177", true),
+            ("localhost.example.com", false),
+            ("attacker.example.com:8177", false),
+        ] {
+            let head = ["GET / HTTP/1.1".to_string(), format!("Host: {host}")];
+            assert_eq!(parse_request(&head).unwrap().is_loopback(), loopback);
+        }
+        assert!(parse_request(&[]).is_none());
+    }
+
+    #[test]
+    fn test_rpc_line() {
+        assert_eq!(
+            rpc_line(br#"{"jsonrpc": "2.0", "id": 1, "method": "cancel"}"#).unwrap(),
+            "{\"id\":1,\"jsonrpc\":\"2.0\",\"method\":\"cancel\"}\n"
+        );
+        assert!(rpc_line(br#"{"jsonrpc": "2.0", "id": 1, "method": "uninstall"}"#).is_err());
+        assert!(rpc_line(br#"{"jsonrpc": "2.0", "id": 1}"#).is_err());
+        assert!(rpc_line(b"not json").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_serve_ui() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(serve_ui(listener, PathBuf::from("espup")));
+
+        let get = |path: &'static str, host: &'static str| async move {
+            let mut stream = TcpStream::connect(addr).await.unwrap();
+            stream
+                .write_all(format!("GET {path} HTTP/1.1\r\nHost: {host}\r\n\r\n").as_bytes())
+                .await
+                .unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).await.unwrap();
+            response
+        };
+        let page = get("/", "127.0.0.1").await;
+        assert!(page.starts_with("HTTP/1.1 200 OK"));
+        assert!(page.contains("<html"));
+        assert!(get("/targets", "localhost")
+            .await
+            .contains("\"name\":\"esp32\""));
+        assert!(get("/", "attacker.example.com")
+            .await
+            .starts_with("HTTP/1.1 403"));
+        assert!(get("/missing", "localhost")
+            .await
+            .starts_with("HTTP/1.1 404"));
+    }
+}
@@ -0,0 +1,127 @@
+//! Short Cargo target directories.
+//!
+//! Building `esp-idf-sys` on Windows often exceeds the maximum path length, as its `OUT_DIR`
+//! is nested deep inside the project target directory. A short global target directory avoids
+//! those failures.
+
+use crate::{error::Error, sandbox::write_checked};
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::read_to_string,
+    path::{Path, PathBuf},
+};
+
+/// Target directory used by the `short` style.
+pub const SHORT_TARGET_DIR: &str = "C:\\t";
+/// Component of the `CARGO_TARGET_DIR` export.
+pub const TARGET_DIR_EXPORT_ID: &str = "target-dir";
+
+/// Location of the Cargo target directory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[serde(rename_all = "lowercase")]
+pub enum TargetDirStyle {
+    /// Keep the Cargo default, `target` inside the project.
+    #[default]
+    Default,
+    /// Use a short global target directory (`C:\t`). Only applies to Windows hosts.
+    Short,
+}
+
+impl TargetDirStyle {
+    /// Returns the target directory to configure, if any.
+    pub fn target_dir(&self) -> Option<&'static str> {
+        match self {
+            TargetDirStyle::Default => None,
+            TargetDirStyle::Short if cfg!(windows) => Some(SHORT_TARGET_DIR),
+            TargetDirStyle::Short => {
+                warn!("Short target directories are only needed on Windows, ignoring '--target-dir-style short'");
+                None
+            }
+        }
+    }
+}
+
+/// Sets the target directory in the `.cargo/config.toml` file of the given project, returning
+/// its path.
+pub fn write_cargo_config(dir: &Path, target_dir: &str) -> Result<PathBuf, Error> {
+    let path = dir.join(".cargo").join("config.toml");
+    let existing = if path.exists() {
+        debug!("Updating '{}'", path.display());
+        Some(read_to_string(&path)?)
+    } else {
+        None
+    };
+    write_checked(&path, update_cargo_config(existing.as_deref(), target_dir))?;
+    Ok(path)
+}
+
+/// Returns the content of the Cargo configuration with the given target directory, keeping
+/// everything else.
+fn update_cargo_config(existing: Option<&str>, target_dir: &str) -> String {
+    // Literal strings don't need the Windows separators to be escaped
+    let setting = format!("target-dir = '{target_dir}'");
+    let mut content = String::new();
+    let mut in_build_section = false;
+    let mut replaced = false;
+    for line in existing.unwrap_or_default().lines() {
+        let trimmed = line.trim();
+        if trimmed.starts_with('[') {
+            if in_build_section && !replaced {
+                // Insert the setting after the last line of the section
+                let blank_lines = content.split_off(content.trim_end().len());
+                content.push('\n');
+                content.push_str(&setting);
+                content.push_str(&blank_lines);
+                replaced = true;
+            }
+            in_build_section = trimmed == "[build]";
+        } else if in_build_section && trimmed.starts_with("target-dir") {
+            content.push_str(&setting);
+            content.push('\n');
+            replaced = true;
+            continue;
+        }
+        content.push_str(line);
+        content.push('\n');
+    }
+    if in_build_section && !replaced {
+        content.push_str(&setting);
+        content.push('\n');
+    } else if !replaced {
+        if !content.is_empty() && !content.ends_with("\n\n") {
+            content.push('\n');
+        }
+        content.push_str(&format!("[build]\n{setting}\n"));
+    }
+    content
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::target_dir::update_cargo_config;
+
+    #[test]
+    fn test_update_cargo_config() {
+        assert_eq!(
+            update_cargo_config(None, "C:\\t"),
+            "[build]\ntarget-dir = 'C:\\t'\n"
+        );
+        assert_eq!(
+            update_cargo_config(Some("[env]\nESP_IDF_VERSION = \"v5.2\"\n"), "C:\\t"),
+            "[env]\nESP_IDF_VERSION = \"v5.2\"\n\n[build]\ntarget-dir = 'C:\\t'\n"
+        );
+        assert_eq!(
+            update_cargo_config(
+                Some("[build]\ntarget = \"xtensa-esp32-espidf\"\n\n[env]\nMCU = \"esp32\"\n"),
+                "C:\\t"
+            ),
+            "[build]\ntarget = \"xtensa-esp32-espidf\"\ntarget-dir = 'C:\\t'\n\n[env]\nMCU = \"esp32\"\n"
+        );
+        assert_eq!(
+            update_cargo_config(Some("[build]\ntarget-dir = \"target\"\n"), "C:\\t"),
+            "[build]\ntarget-dir = 'C:\\t'\n"
+        );
+    }
+}
@@ -1,13 +1,20 @@
 //! ESP32 chip variants support.
 
-use crate::error::Error;
-use log::debug;
+use crate::{
+    error::Error,
+    toolchain::gcc::{RISCV_GCC, XTENSA_GCC},
+};
+use log::{debug, warn};
 use miette::Result;
+use serde::{Deserialize, Serialize};
 use std::{collections::HashSet, str::FromStr};
 use strum::{Display, EnumIter, EnumString, IntoEnumIterator};
 
-#[derive(Clone, Copy, EnumIter, EnumString, PartialEq, Hash, Eq, Debug, Display)]
+#[derive(
+    Clone, Copy, EnumIter, EnumString, PartialEq, Hash, Eq, Debug, Display, Serialize, Deserialize,
+)]
 #[strum(serialize_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
 pub enum Target {
     /// Xtensa LX6 based dual core
     ESP32 = 0,
@@ -25,6 +32,22 @@ pub enum Target {
     ESP32S3,
     /// RISC-V based dual core
     ESP32P4,
+    /// RISC-V based single core (experimental)
+    ESP32C61,
+}
+
+/// Toolchain metadata of a chip.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TargetMetadata {
+    /// Rust target triple used to build `no_std` applications.
+    pub rust_target: &'static str,
+    /// Rust target triple used to build `std` applications, on top of the ESP-IDF.
+    pub std_rust_target: &'static str,
+    /// Architecture of the GCC toolchain.
+    pub gcc: &'static str,
+    /// Whether the toolchain support of the chip is still incomplete, the metadata is a best
+    /// effort guess.
+    pub experimental: bool,
 }
 
 impl Target {
@@ -37,6 +60,195 @@ impl Target {
     pub fn is_xtensa(&self) -> bool {
         matches!(self, Target::ESP32 | Target::ESP32S2 | Target::ESP32S3)
     }
+
+    /// Returns the toolchain metadata of the chip.
+    pub fn metadata(&self) -> TargetMetadata {
+        let (rust_target, std_rust_target, experimental) = match self {
+            Target::ESP32 => ("xtensa-esp32-none-elf", "xtensa-esp32-espidf", false),
+            Target::ESP32S2 => ("xtensa-esp32s2-none-elf", "xtensa-esp32s2-espidf", false),
+            Target::ESP32S3 => ("xtensa-esp32s3-none-elf", "xtensa-esp32s3-espidf", false),
+            Target::ESP32C2 | Target::ESP32C3 => (
+                "riscv32imc-unknown-none-elf",
+                "riscv32imc-esp-espidf",
+                false,
+            ),
+            Target::ESP32C6 | Target::ESP32H2 => (
+                "riscv32imac-unknown-none-elf",
+                "riscv32imac-esp-espidf",
+                false,
+            ),
+            Target::ESP32P4 => (
+                "riscv32imafc-unknown-none-elf",
+                "riscv32imafc-esp-espidf",
+                false,
+            ),
+            Target::ESP32C61 => (
+                "riscv32imac-unknown-none-elf",
+                "riscv32imac-esp-espidf",
+                true,
+            ),
+        };
+        TargetMetadata {
+            rust_target,
+            std_rust_target,
+            gcc: if self.is_xtensa() {
+                XTENSA_GCC
+            } else {
+                RISCV_GCC
+            },
+            experimental,
+        }
+    }
+
+    /// Returns the Rust target triple used to build `no_std` applications for the chip.
+    pub fn rust_target(&self) -> &'static str {
+        self.metadata().rust_target
+    }
+
+    /// Returns the Rust target triple used to build `std` applications for the chip.
+    pub fn std_rust_target(&self) -> &'static str {
+        self.metadata().std_rust_target
+    }
+
+    /// Returns true if the toolchain support of the chip is experimental.
+    pub fn is_experimental(&self) -> bool {
+        self.metadata().experimental
+    }
+}
+
+/// Checks that experimental targets are only selected when allowed, warning about their support
+/// status.
+pub fn check_experimental_targets(
+    targets: &HashSet<Target>,
+    allow_experimental: bool,
+) -> Result<(), Error> {
+    let mut experimental: Vec<&Target> = targets.iter().filter(|t| t.is_experimental()).collect();
+    if experimental.is_empty() {
+        return Ok(());
+    }
+    experimental.sort_by_key(|t| t.to_string());
+    if !allow_experimental {
+        return Err(Error::ExperimentalTargets(
+            experimental
+                .iter()
+                .map(|t| t.to_string())
+                .collect::<Vec<_>>()
+                .join(", "),
+        ));
+    }
+    for target in experimental {
+        let metadata = target.metadata();
+        warn!(
+            "Support for '{}' is experimental, using the best effort '{}' Rust target and '{}' GCC toolchain",
+            target, metadata.rust_target, metadata.gcc
+        );
+    }
+    Ok(())
+}
+
+/// Development boards and modules whose names don't start with the one of their chip, by
+/// normalized name.
+const BOARD_ALIASES: [(&str, Target); 12] = [
+    ("esp32wroom32", Target::ESP32),
+    ("esp32wrover", Target::ESP32),
+    ("esp32pico", Target::ESP32),
+    ("esp8684", Target::ESP32C2),
+    ("esprustboard", Target::ESP32C3),
+    ("esp32s2saola1", Target::ESP32S2),
+    ("esp32s2kaluga1", Target::ESP32S2),
+    ("esp32s3box", Target::ESP32S3),
+    ("esp32s3box3", Target::ESP32S3),
+    ("esp32s3eye", Target::ESP32S3),
+    ("esp32s3korvo2", Target::ESP32S3),
+    ("esp32p4functionevboard", Target::ESP32P4),
+];
+/// Maximum edit distance of the targets suggested for unknown ones.
+const MAX_SUGGESTION_DISTANCE: usize = 1;
+
+/// Returns the lowercase name without separators, e.g. `esp32s3devkitc` for `ESP32-S3-DevKitC`.
+fn normalize(target: &str) -> String {
+    target
+        .trim()
+        .chars()
+        .filter(|c| !matches!(c, '-' | '_' | ' '))
+        .collect::<String>()
+        .to_lowercase()
+}
+
+/// Returns the chip of a normalized name: a chip (`esp32s3`), its shorthand (`s3`), one of its
+/// devkits (`esp32s3devkitc1`) or one of the [`BOARD_ALIASES`].
+fn resolve_alias(normalized: &str) -> Option<Target> {
+    let name = if normalized.starts_with("esp") {
+        normalized.to_string()
+    } else {
+        format!("esp32{normalized}")
+    };
+    if let Ok(target) = Target::from_str(&name) {
+        return Some(target);
+    }
+    if let Some((_, target)) = BOARD_ALIASES.iter().find(|(alias, _)| *alias == name) {
+        return Some(*target);
+    }
+    Target::iter().find(|target| {
+        name.strip_prefix(&target.to_string())
+            .is_some_and(|devkit| devkit.starts_with("devkit"))
+    })
+}
+
+/// Returns the number of single character insertions, deletions, substitutions or transpositions
+/// of adjacent characters turning `a` into `b`, each substring being edited once at most.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    // Rows of the distances of the prefixes of `a`, the last two being kept for transpositions
+    let mut before: Vec<usize> = Vec::new();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for i in 0..a.len() {
+        let mut current = vec![i + 1];
+        for j in 0..b.len() {
+            let substitution = previous[j] + usize::from(a[i] != b[j]);
+            let mut distance = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+            if i > 0 && j > 0 && a[i] == b[j - 1] && a[i - 1] == b[j] {
+                distance = distance.min(before[j - 1] + 1);
+            }
+            current.push(distance);
+        }
+        before = previous;
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+/// Returns the error of an unknown target, suggesting the closest chip, if only one is close
+/// enough.
+fn unknown_target(target: &str) -> Error {
+    let normalized = normalize(target);
+    let shorthand = format!("esp32{normalized}");
+    let distances: Vec<(usize, String)> = Target::iter()
+        .map(|candidate| {
+            let name = candidate.to_string();
+            let distance = edit_distance(&normalized, &name).min(edit_distance(&shorthand, &name));
+            (distance, name)
+        })
+        .collect();
+    let closest = distances.iter().map(|(distance, _)| *distance).min();
+    let mut suggestions = distances
+        .into_iter()
+        .filter(|(distance, _)| Some(*distance) == closest && *distance <= MAX_SUGGESTION_DISTANCE);
+    match (suggestions.next(), suggestions.next()) {
+        (Some((_, name)), None) => Error::UnknownTarget(target.to_string(), name),
+        _ => Error::UnsupportedTarget(target.to_string()),
+    }
+}
+
+/// Parses a single chip, accepting its marketing name, shorthand and devkit names too, e.g.
+/// `ESP32-C3`, `c3` or `esp32-c3-devkitm-1`.
+pub fn parse_target(target: &str) -> Result<Target, Error> {
+    let normalized = normalize(target);
+    if normalized.is_empty() {
+        return Err(Error::UnsupportedTarget(target.to_string()));
+    }
+    resolve_alias(&normalized).ok_or_else(|| unknown_target(target))
 }
 
 /// Returns a vector of Chips from a comma or space separated string.
@@ -46,14 +258,13 @@ pub fn parse_targets(targets_str: &str) -> Result<HashSet<Target>, Error> {
     let targets_str = targets_str.to_lowercase();
     let targets_str = targets_str.trim();
 
+    // Experimental targets must be selected explicitly
     let targets: HashSet<Target> = if targets_str.contains("all") {
-        Target::iter().collect()
+        Target::iter().filter(|t| !t.is_experimental()).collect()
     } else {
         let mut targets = HashSet::new();
-        for target in targets_str.split([',', ' ']) {
-            targets.insert(
-                Target::from_str(target).map_err(|_| Error::UnsupportedTarget(target.into()))?,
-            );
+        for target in targets_str.split([',', ' ']).filter(|t| !t.is_empty()) {
+            targets.insert(parse_target(target)?);
         }
 
         targets
@@ -65,9 +276,65 @@ pub fn parse_targets(targets_str: &str) -> Result<HashSet<Target>, Error> {
 
 #[cfg(test)]
 mod tests {
-    use crate::targets::{parse_targets, Target};
+    use crate::{
+        error::Error,
+        targets::{check_experimental_targets, edit_distance, parse_target, parse_targets, Target},
+    };
     use std::collections::HashSet;
 
+    #[test]
+    fn test_parse_target() {
+        assert_eq!(parse_target("esp32c3").unwrap(), Target::ESP32C3);
+        assert_eq!(parse_target("ESP32-S3").unwrap(), Target::ESP32S3);
+        assert_eq!(parse_target("esp32_s3").unwrap(), Target::ESP32S3);
+        assert_eq!(parse_target("s3").unwrap(), Target::ESP32S3);
+        assert_eq!(parse_target("C61").unwrap(), Target::ESP32C61);
+        assert_eq!(parse_target("esp32-s3-devkitc-1").unwrap(), Target::ESP32S3);
+        assert_eq!(parse_target("ESP32-C6-DevKitM-1").unwrap(), Target::ESP32C6);
+        assert_eq!(parse_target("esp32-devkitc").unwrap(), Target::ESP32);
+        assert_eq!(parse_target("ESP32-S3-BOX-3").unwrap(), Target::ESP32S3);
+        assert_eq!(parse_target("esp-rust-board").unwrap(), Target::ESP32C3);
+        assert!(matches!(
+            parse_target("esp8266"),
+            Err(Error::UnsupportedTarget(_))
+        ));
+        assert!(parse_target("esp32s3box4").is_err());
+        assert!(parse_target("").is_err());
+        assert!(parse_target(" - ").is_err());
+    }
+
+    #[test]
+    fn test_target_suggestions() {
+        assert_eq!(edit_distance("esp32s4", "esp32s3"), 1);
+        assert_eq!(edit_distance("", "esp32"), 5);
+        assert_eq!(edit_distance("esp23c3", "esp32c3"), 1);
+        assert_eq!(edit_distance("ca", "abc"), 3);
+        match parse_target("esp23c3") {
+            Err(Error::UnknownTarget(target, suggestion)) => {
+                assert_eq!(target, "esp23c3");
+                assert_eq!(suggestion, "esp32c3");
+            }
+            other => panic!("unexpected result: {other:?}"),
+        }
+        // Nothing is suggested when several chips are as close
+        assert!(matches!(
+            parse_target("esp32-c5"),
+            Err(Error::UnsupportedTarget(_))
+        ));
+        assert!(matches!(
+            parse_targets("esp32,esp32s4"),
+            Err(Error::UnsupportedTarget(_))
+        ));
+        assert!(matches!(
+            parse_targets("esp32,esp32s33"),
+            Err(Error::UnknownTarget(_, suggestion)) if suggestion == "esp32s3"
+        ));
+        assert!(matches!(
+            parse_target("h22"),
+            Err(Error::UnknownTarget(_, suggestion)) if suggestion == "esp32h2"
+        ));
+    }
+
     #[test]
     #[allow(unused_variables)]
     fn test_parse_targets() {
@@ -98,4 +365,18 @@ mod tests {
         .collect();
         assert!(matches!(parse_targets("all"), Ok(targets)));
     }
+
+    #[test]
+    fn test_experimental_targets() {
+        assert!(!parse_targets("all").unwrap().contains(&Target::ESP32C61));
+        let targets = parse_targets("esp32c3,esp32c61").unwrap();
+        assert!(targets.contains(&Target::ESP32C61));
+        assert_eq!(
+            Target::ESP32C61.rust_target(),
+            "riscv32imac-unknown-none-elf"
+        );
+        assert!(check_experimental_targets(&targets, false).is_err());
+        assert!(check_experimental_targets(&targets, true).is_ok());
+        assert!(check_experimental_targets(&parse_targets("all").unwrap(), false).is_ok());
+    }
 }
@@ -0,0 +1,129 @@
+//! Detection of antivirus interference with the extracted toolchains.
+//!
+//! Real-time scanners (e.g. Windows Defender) may quarantine freshly extracted binaries or slow
+//! the extraction down to a crawl, which leads to obscure failures later on. The binaries are
+//! listed from the archive entries, as the ones quarantined while extracting are already gone
+//! from the disk afterwards, and checked once every component is installed.
+
+use crate::error::Error;
+use log::{debug, warn};
+use std::{
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::Duration,
+};
+
+/// Extractions writing less than this many bytes per second are considered slowed down.
+const MIN_THROUGHPUT: f64 = 2_000_000.0;
+/// Smaller extractions are too short to measure their throughput reliably.
+const MIN_MEASURED_SIZE: u64 = 20_000_000;
+/// Time given to the antivirus to quarantine the extracted binaries.
+const QUARANTINE_DELAY: Duration = Duration::from_secs(2);
+
+/// Binaries extracted by the installation.
+static EXTRACTED_BINARIES: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+
+/// Returns whether the extraction of `bytes` in `elapsed` time is suspiciously slow.
+fn is_slow(bytes: u64, elapsed: Duration) -> bool {
+    bytes >= MIN_MEASURED_SIZE && (bytes as f64 / elapsed.as_secs_f64()) < MIN_THROUGHPUT
+}
+
+/// Warns when the extraction into `dir` was slow enough to suspect real-time scanning.
+pub fn warn_slow_extraction(dir: &Path, bytes: u64, elapsed: Duration) {
+    if is_slow(bytes, elapsed) {
+        warn!(
+            "Extracting into '{}' ran at {:.2} MB/s, an antivirus may be scanning every extracted file. Consider excluding '{}' from real-time scanning",
+            dir.display(),
+            bytes as f64 / 1_000_000.0 / elapsed.as_secs_f64(),
+            dir.display()
+        );
+    }
+}
+
+/// Returns the binaries (`.exe` and `.dll` files) among the files, which are the files
+/// antiviruses usually quarantine.
+fn binaries(files: &[PathBuf]) -> impl Iterator<Item = &PathBuf> {
+    files.iter().filter(|path| {
+        path.extension()
+            .is_some_and(|extension| extension == "exe" || extension == "dll")
+    })
+}
+
+/// Records the binaries among the files of an extracted archive, to be checked by
+/// [`check_quarantine`].
+pub fn record_extracted(files: &[PathBuf]) {
+    EXTRACTED_BINARIES
+        .lock()
+        .unwrap()
+        .extend(binaries(files).cloned());
+}
+
+/// Fails when some of the binaries extracted by the installation into `dir` disappeared shortly
+/// after their extraction.
+pub async fn check_quarantine(dir: &Path) -> Result<(), Error> {
+    let binaries = std::mem::take(&mut *EXTRACTED_BINARIES.lock().unwrap());
+    if binaries.is_empty() {
+        return Ok(());
+    }
+    tokio::time::sleep(QUARANTINE_DELAY).await;
+    let missing = missing_files(&binaries);
+    if missing.is_empty() {
+        debug!("No extracted binary was quarantined in '{}'", dir.display());
+        return Ok(());
+    }
+    Err(Error::Quarantined(
+        dir.display().to_string(),
+        missing
+            .iter()
+            .map(|path| format!("'{}'", path.display()))
+            .collect::<Vec<_>>()
+            .join(", "),
+    ))
+}
+
+fn missing_files(files: &[PathBuf]) -> Vec<&PathBuf> {
+    files.iter().filter(|file| !file.exists()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::toolchain::antivirus::{binaries, is_slow, missing_files};
+    use std::{
+        fs::{create_dir_all, remove_file, write},
+        path::PathBuf,
+        time::Duration,
+    };
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_is_slow() {
+        assert!(is_slow(100_000_000, Duration::from_secs(100)));
+        assert!(!is_slow(100_000_000, Duration::from_secs(10)));
+        // Too small to be measured
+        assert!(!is_slow(1_000_000, Duration::from_secs(100)));
+    }
+
+    #[test]
+    fn test_quarantined_binaries() {
+        let temp_dir = TempDir::new().unwrap();
+        let bin_dir = temp_dir.path().join("esp-clang/bin");
+        create_dir_all(&bin_dir).unwrap();
+        write(bin_dir.join("clang.exe"), "").unwrap();
+        write(bin_dir.join("libclang.dll"), "").unwrap();
+        write(bin_dir.join("README.md"), "").unwrap();
+
+        let extracted: Vec<PathBuf> = ["clang.exe", "libclang.dll", "README.md"]
+            .iter()
+            .map(|file| bin_dir.join(file))
+            .collect();
+        let found: Vec<PathBuf> = binaries(&extracted).cloned().collect();
+        assert_eq!(
+            found,
+            vec![bin_dir.join("clang.exe"), bin_dir.join("libclang.dll")]
+        );
+        assert!(missing_files(&found).is_empty());
+
+        remove_file(bin_dir.join("clang.exe")).unwrap();
+        assert_eq!(missing_files(&found), vec![&bin_dir.join("clang.exe")]);
+    }
+}
@@ -0,0 +1,662 @@
+//! Archive extraction (zip, tar.gz and tar.xz).
+//!
+//! Compressed tarballs are decompressed on a separate thread, so decompression, which is CPU
+//! bound, runs concurrently with the disk writes of the unpacking. xz archives are decoded on a
+//! single thread, `benches/xz.rs` compares it with the multi-threaded liblzma decoder.
+
+use crate::error::Error;
+use flate2::bufread::GzDecoder;
+use liblzma::read::XzDecoder;
+use log::{debug, info, warn};
+use miette::Result;
+use std::{
+    fs::{create_dir_all, File, OpenOptions},
+    io::{self, copy, Cursor, Read, Seek, SeekFrom, Write},
+    path::{Component, Path, PathBuf},
+    sync::mpsc::{sync_channel, Receiver, SyncSender},
+    thread,
+    time::Instant,
+};
+use tar::Archive;
+use zip::ZipArchive;
+
+/// Size of the chunks handed from the decompression thread to the unpacking one.
+const CHUNK_SIZE: usize = 1024 * 1024;
+/// Size of the chunks compared with the existing files.
+const COMPARE_CHUNK_SIZE: usize = 64 * 1024;
+/// Maximum number of decompressed chunks waiting to be unpacked.
+const MAX_PENDING_CHUNKS: usize = 16;
+/// Directories whose files are executables.
+#[cfg(any(unix, test))]
+const EXECUTABLE_DIRS: [&str; 2] = ["bin", "libexec"];
+
+/// Files of an extracted archive.
+#[derive(Debug, Default)]
+pub struct Extracted {
+    /// Regular files of the archive, including the ones that were already present.
+    pub files: Vec<PathBuf>,
+    /// Number of bytes written.
+    pub written_bytes: u64,
+    /// Number of bytes of the files that were already present, and not rewritten.
+    pub skipped_bytes: u64,
+}
+
+/// Extracts an archive into `output_directory`, the format is deduced from the `file_name`
+/// extension.
+///
+/// If `strip_prefix` is provided, only the entries under that directory are extracted and the
+/// prefix is removed from their paths. Entries that would be written outside of
+/// `output_directory` are skipped.
+pub fn extract(
+    bytes: &[u8],
+    file_name: &str,
+    output_directory: &Path,
+    strip_prefix: Option<&str>,
+) -> Result<Extracted, Error> {
+    let extension = Path::new(file_name)
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .unwrap_or_default();
+    match extension {
+        "zip" => {
+            debug!("Extracting zip file to '{}'", output_directory.display());
+            extract_zip(bytes, output_directory, strip_prefix)
+        }
+        "gz" => {
+            debug!("Extracting tar.gz file to '{}'", output_directory.display());
+            unpack_pipelined(GzDecoder::new(bytes), output_directory, strip_prefix)
+        }
+        "xz" => {
+            debug!("Extracting tar.xz file to '{}'", output_directory.display());
+            unpack_pipelined(XzDecoder::new(bytes), output_directory, strip_prefix)
+        }
+        _ => Err(Error::UnsuportedFileExtension(extension.to_string())),
+    }
+}
+
+/// Returns the relative path where an archive entry should be extracted, or `None` if the entry
+/// must be skipped (outside of `strip_prefix`, absolute or escaping the output directory).
+fn sanitize_entry_path(path: &Path, strip_prefix: Option<&str>) -> Option<PathBuf> {
+    let mut sanitized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(part) => sanitized.push(part),
+            Component::CurDir => {}
+            Component::ParentDir | Component::RootDir | Component::Prefix(_) => return None,
+        }
+    }
+    match strip_prefix {
+        Some(prefix) => sanitized.strip_prefix(prefix).ok().map(Path::to_path_buf),
+        None => Some(sanitized),
+    }
+}
+
+/// Returns whether `path`, once the symlinks of its existing ancestors are resolved, is inside
+/// `root`, so symlinks extracted by earlier entries can't redirect writes outside of it.
+fn is_contained(root: &Path, path: &Path) -> bool {
+    let mut existing = path;
+    while existing.symlink_metadata().is_err() {
+        match existing.parent() {
+            Some(parent) => existing = parent,
+            None => return false,
+        }
+    }
+    existing
+        .canonicalize()
+        .is_ok_and(|existing| existing.starts_with(root))
+}
+
+/// Overwrites `destination`, a file with the size of the entry, from the first byte that differs
+/// from the entry, comparing them while streaming. Returns whether they were identical, in which
+/// case nothing is written.
+fn overwrite_changed<R: Read>(entry: &mut R, destination: &Path) -> io::Result<bool> {
+    let mut file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(destination)?;
+    let mut expected = vec![0; COMPARE_CHUNK_SIZE];
+    let mut existing = vec![0; COMPARE_CHUNK_SIZE];
+    let mut offset = 0;
+    loop {
+        let len = entry.read(&mut expected)?;
+        if len == 0 {
+            return Ok(true);
+        }
+        file.read_exact(&mut existing[..len])?;
+        if existing[..len] != expected[..len] {
+            file.seek(SeekFrom::Start(offset))?;
+            file.write_all(&expected[..len])?;
+            copy(entry, &mut file)?;
+            return Ok(false);
+        }
+        offset += len as u64;
+    }
+}
+
+/// Returns the permissions of an extracted zip entry.
+///
+/// The mode recorded in the archive is honored, but archives created on Windows (e.g. by
+/// mirrors repackaging the artifacts) don't record the executable bits, so they are added to the
+/// files of the `bin` directories and to shell scripts when missing.
+#[cfg(any(unix, test))]
+fn zip_entry_mode(relative_path: &Path, unix_mode: Option<u32>) -> u32 {
+    let mode = unix_mode.unwrap_or(0o644) & 0o7777;
+    let executable = relative_path.parent().is_some_and(|parent| {
+        parent.components().any(|component| {
+            EXECUTABLE_DIRS
+                .iter()
+                .any(|dir| component.as_os_str() == *dir)
+        })
+    }) || relative_path
+        .extension()
+        .is_some_and(|extension| extension == "sh");
+    if executable && mode & 0o111 == 0 {
+        // Executable by whoever can read it
+        mode | (mode & 0o444) >> 2
+    } else {
+        mode
+    }
+}
+
+/// Extracts a zip archive.
+fn extract_zip(
+    bytes: &[u8],
+    output_directory: &Path,
+    strip_prefix: Option<&str>,
+) -> Result<Extracted, Error> {
+    let mut extracted = Extracted::default();
+    let mut zipfile = ZipArchive::new(Cursor::new(bytes))
+        .map_err(|e| Error::IoError(std::io::Error::other(e)))?;
+    for i in 0..zipfile.len() {
+        let mut file = zipfile
+            .by_index(i)
+            .map_err(|e| Error::IoError(std::io::Error::other(e)))?;
+        let Some(relative_path) = sanitize_entry_path(Path::new(file.name()), strip_prefix) else {
+            if strip_prefix.is_none() {
+                warn!("Skipping unsafe archive entry '{}'", file.name());
+            }
+            continue;
+        };
+        let outpath = output_directory.join(&relative_path);
+
+        if file.is_dir() {
+            create_dir_all(&outpath)?;
+        } else {
+            create_dir_all(outpath.parent().unwrap())?;
+            let mut outfile = File::create(&outpath)?;
+            extracted.written_bytes += copy(&mut file, &mut outfile)?;
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::PermissionsExt;
+                let mode = zip_entry_mode(&relative_path, file.unix_mode());
+                outfile.set_permissions(std::fs::Permissions::from_mode(mode))?;
+            }
+            extracted.files.push(outpath);
+        }
+    }
+    Ok(extracted)
+}
+
+/// Unpacks a compressed tar archive, decompressing it on a separate thread.
+fn unpack_pipelined<D: Read + Send>(
+    decoder: D,
+    output_directory: &Path,
+    strip_prefix: Option<&str>,
+) -> Result<Extracted, Error> {
+    let started = Instant::now();
+    let extracted = thread::scope(|scope| {
+        let (sender, receiver) = sync_channel(MAX_PENDING_CHUNKS);
+        scope.spawn(move || decompress(decoder, sender));
+        // The receiver is dropped on errors, which stops the decompression thread
+        let mut archive = Archive::new(ChannelReader::new(receiver));
+        unpack_deduplicated(&mut archive, output_directory, strip_prefix)
+    })?;
+    debug!(
+        "Extracted '{}' in {:.2}s",
+        output_directory.display(),
+        started.elapsed().as_secs_f64()
+    );
+    Ok(extracted)
+}
+
+/// Decompresses the whole stream, sending it in chunks until the receiver hangs up.
+fn decompress<D: Read>(mut decoder: D, sender: SyncSender<io::Result<Vec<u8>>>) {
+    loop {
+        let mut chunk = vec![0; CHUNK_SIZE];
+        match decoder.read(&mut chunk) {
+            Ok(0) => return,
+            Ok(len) => {
+                chunk.truncate(len);
+                if sender.send(Ok(chunk)).is_err() {
+                    return;
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => {
+                let _ = sender.send(Err(e));
+                return;
+            }
+        }
+    }
+}
+
+/// Reads the chunks sent by the decompression thread.
+struct ChannelReader {
+    receiver: Receiver<io::Result<Vec<u8>>>,
+    chunk: Vec<u8>,
+    position: usize,
+}
+
+impl ChannelReader {
+    fn new(receiver: Receiver<io::Result<Vec<u8>>>) -> Self {
+        Self {
+            receiver,
+            chunk: Vec::new(),
+            position: 0,
+        }
+    }
+}
+
+impl Read for ChannelReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.position == self.chunk.len() {
+            match self.receiver.recv() {
+                Ok(chunk) => {
+                    self.chunk = chunk?;
+                    self.position = 0;
+                }
+                // The sender hangs up once the whole stream was decompressed
+                Err(_) => return Ok(0),
+            }
+        }
+        let len = buf.len().min(self.chunk.len() - self.position);
+        buf[..len].copy_from_slice(&self.chunk[self.position..self.position + len]);
+        self.position += len;
+        Ok(len)
+    }
+}
+
+/// Unpacks a tar archive into `output_directory`, skipping regular files that already exist
+/// with identical content.
+///
+/// Some artifacts (e.g. the LLVM "libs" and "full" tarballs) are extracted into the same tree and
+/// share part of their content, so rewriting those files is just wasted disk I/O. Entries that
+/// symlinks of the archive would redirect outside of `output_directory` are skipped.
+fn unpack_deduplicated<R: Read>(
+    archive: &mut Archive<R>,
+    output_directory: &Path,
+    strip_prefix: Option<&str>,
+) -> Result<Extracted, Error> {
+    create_dir_all(output_directory)?;
+    let root = output_directory.canonicalize()?;
+    let mut extracted = Extracted::default();
+    let mut skipped_files = 0;
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let entry_path = entry.path()?.to_path_buf();
+        let Some(relative_path) = sanitize_entry_path(&entry_path, strip_prefix) else {
+            if strip_prefix.is_none() {
+                warn!("Skipping unsafe archive entry '{}'", entry_path.display());
+            }
+            continue;
+        };
+        if relative_path.as_os_str().is_empty() {
+            continue;
+        }
+        let destination = output_directory.join(&relative_path);
+        if !destination
+            .parent()
+            .is_some_and(|parent| is_contained(&root, parent))
+        {
+            warn!("Skipping unsafe archive entry '{}'", entry_path.display());
+            continue;
+        }
+        let size = entry.header().size()?;
+        let is_duplicate_candidate = entry.header().entry_type().is_file()
+            && destination
+                .symlink_metadata()
+                .is_ok_and(|metadata| metadata.is_file() && metadata.len() == size);
+
+        if !is_duplicate_candidate {
+            if let Some(parent) = destination.parent() {
+                create_dir_all(parent)?;
+            }
+            if entry.header().entry_type().is_hard_link() {
+                // Hard link targets are relative to the archive root, not the current directory
+                let link_name = entry.link_name()?.map(|link| link.to_path_buf());
+                let link = link_name
+                    .and_then(|link| sanitize_entry_path(&link, strip_prefix))
+                    .map(|link| output_directory.join(link))
+                    .filter(|link| is_contained(&root, link));
+                match link {
+                    Some(link) => {
+                        if destination.symlink_metadata().is_ok() {
+                            std::fs::remove_file(&destination)?;
+                        }
+                        std::fs::hard_link(link, &destination)?;
+                        extracted.files.push(destination);
+                    }
+                    None => warn!("Skipping unsafe archive entry '{}'", entry_path.display()),
+                }
+            } else {
+                entry.unpack(&destination)?;
+                if entry.header().entry_type().is_file() {
+                    extracted.written_bytes += size;
+                    extracted.files.push(destination);
+                }
+            }
+            continue;
+        }
+
+        extracted.files.push(destination.clone());
+        if overwrite_changed(&mut entry, &destination)? {
+            skipped_files += 1;
+            extracted.skipped_bytes += size;
+            continue;
+        }
+        extracted.written_bytes += size;
+        #[cfg(unix)]
+        if let Ok(mode) = entry.header().mode() {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(&destination, std::fs::Permissions::from_mode(mode))?;
+        }
+    }
+
+    if skipped_files > 0 {
+        info!(
+            "Skipped {} files already present in '{}' ({:.2} MB saved)",
+            skipped_files,
+            output_directory.display(),
+            extracted.skipped_bytes as f64 / 1_000_000.0
+        );
+    }
+    Ok(extracted)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::toolchain::archive::{
+        extract, sanitize_entry_path, unpack_deduplicated, zip_entry_mode, COMPARE_CHUNK_SIZE,
+    };
+    use flate2::{write::GzEncoder, Compression};
+    use liblzma::{stream::MtStreamBuilder, write::XzEncoder};
+    use std::{
+        fs::{read_to_string, write},
+        io::{Cursor, Write},
+        path::{Path, PathBuf},
+    };
+    #[cfg(unix)]
+    use tar::EntryType;
+    use tar::{Archive, Builder, Header};
+    use tempfile::TempDir;
+    use zip::{write::SimpleFileOptions, ZipWriter};
+
+    fn tarball(files: &[(&str, &str)]) -> Vec<u8> {
+        let mut builder = Builder::new(Vec::new());
+        for (path, content) in files {
+            let mut header = Header::new_gnu();
+            header.set_size(content.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder
+                .append_data(&mut header, path, content.as_bytes())
+                .unwrap();
+        }
+        builder.into_inner().unwrap()
+    }
+
+    fn zipfile(files: &[(&str, &str)]) -> Vec<u8> {
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+        for (path, content) in files {
+            writer
+                .start_file(*path, SimpleFileOptions::default())
+                .unwrap();
+            writer.write_all(content.as_bytes()).unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn test_sanitize_entry_path() {
+        assert_eq!(
+            sanitize_entry_path(Path::new("esp/bin/clang"), None),
+            Some(PathBuf::from("esp/bin/clang"))
+        );
+        assert_eq!(
+            sanitize_entry_path(Path::new("./esp/bin/clang"), Some("esp")),
+            Some(PathBuf::from("bin/clang"))
+        );
+        assert_eq!(
+            sanitize_entry_path(Path::new("esp/bin/clang"), Some("esp")),
+            Some(PathBuf::from("bin/clang"))
+        );
+        assert_eq!(
+            sanitize_entry_path(Path::new("other/clang"), Some("esp")),
+            None
+        );
+        assert_eq!(sanitize_entry_path(Path::new("../clang"), None), None);
+        assert_eq!(sanitize_entry_path(Path::new("/etc/passwd"), None), None);
+    }
+
+    #[test]
+    fn test_extract() {
+        let files = [("esp/bin/clang", "clang"), ("readme.txt", "readme")];
+
+        // tar.gz
+        let temp_dir = TempDir::new().unwrap();
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&tarball(&files)).unwrap();
+        let bytes = encoder.finish().unwrap();
+        extract(&bytes, "llvm.tar.gz", temp_dir.path(), None).unwrap();
+        assert_eq!(
+            read_to_string(temp_dir.path().join("esp/bin/clang")).unwrap(),
+            "clang"
+        );
+
+        // tar.xz
+        let temp_dir = TempDir::new().unwrap();
+        let mut encoder = XzEncoder::new(Vec::new(), 6);
+        encoder.write_all(&tarball(&files)).unwrap();
+        let bytes = encoder.finish().unwrap();
+        extract(&bytes, "llvm.tar.xz", temp_dir.path(), None).unwrap();
+        assert_eq!(
+            read_to_string(temp_dir.path().join("readme.txt")).unwrap(),
+            "readme"
+        );
+
+        // tar.xz compressed in several blocks, larger than a decompressed chunk
+        let temp_dir = TempDir::new().unwrap();
+        let large = "x".repeat(3 * 1024 * 1024);
+        let stream = MtStreamBuilder::new()
+            .threads(2)
+            .block_size(1024 * 1024)
+            .encoder()
+            .unwrap();
+        let mut encoder = XzEncoder::new_stream(Vec::new(), stream);
+        encoder
+            .write_all(&tarball(&[
+                ("esp/lib/libclang.so", &large),
+                ("readme.txt", "readme"),
+            ]))
+            .unwrap();
+        let bytes = encoder.finish().unwrap();
+        extract(&bytes, "llvm.tar.xz", temp_dir.path(), None).unwrap();
+        assert_eq!(
+            read_to_string(temp_dir.path().join("esp/lib/libclang.so")).unwrap(),
+            large
+        );
+
+        // Corrupted archives fail
+        let temp_dir = TempDir::new().unwrap();
+        assert!(extract(
+            &bytes[..bytes.len() / 2],
+            "llvm.tar.xz",
+            temp_dir.path(),
+            None
+        )
+        .is_err());
+
+        // zip with stripped prefix
+        let temp_dir = TempDir::new().unwrap();
+        let bytes = zipfile(&files);
+        extract(&bytes, "rust.zip", temp_dir.path(), Some("esp")).unwrap();
+        assert_eq!(
+            read_to_string(temp_dir.path().join("bin/clang")).unwrap(),
+            "clang"
+        );
+        assert!(!temp_dir.path().join("readme.txt").exists());
+
+        // Unsupported extension
+        assert!(extract(&bytes, "rust.rar", temp_dir.path(), None).is_err());
+    }
+
+    #[test]
+    fn test_zip_entry_mode() {
+        // Modes recorded on Unix are honored
+        assert_eq!(
+            zip_entry_mode(Path::new("bin/clang"), Some(0o100750)),
+            0o750
+        );
+        assert_eq!(
+            zip_entry_mode(Path::new("lib/libclang.so"), Some(0o100755)),
+            0o755
+        );
+        // Modes derived from the MS-DOS attributes lack the executable bits
+        assert_eq!(
+            zip_entry_mode(Path::new("esp/bin/clang"), Some(0o100664)),
+            0o775
+        );
+        assert_eq!(
+            zip_entry_mode(Path::new("esp/bin/clang"), Some(0o100444)),
+            0o555
+        );
+        assert_eq!(zip_entry_mode(Path::new("libexec/gcc/cc1"), None), 0o755);
+        assert_eq!(zip_entry_mode(Path::new("install.sh"), None), 0o755);
+        assert_eq!(
+            zip_entry_mode(Path::new("lib/libclang.so"), Some(0o100664)),
+            0o664
+        );
+        assert_eq!(zip_entry_mode(Path::new("bin"), None), 0o644);
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+            for (path, mode) in [("esp/bin/clang", 0o644), ("esp/lib/tool", 0o750)] {
+                writer
+                    .start_file(path, SimpleFileOptions::default().unix_permissions(mode))
+                    .unwrap();
+                writer.write_all(b"binary").unwrap();
+            }
+            let bytes = writer.finish().unwrap().into_inner();
+            let temp_dir = TempDir::new().unwrap();
+            extract(&bytes, "llvm.zip", temp_dir.path(), None).unwrap();
+            let mode = |path: &str| {
+                std::fs::metadata(temp_dir.path().join(path))
+                    .unwrap()
+                    .permissions()
+                    .mode()
+                    & 0o777
+            };
+            assert_eq!(mode("esp/bin/clang"), 0o755);
+            assert_eq!(mode("esp/lib/tool"), 0o750);
+        }
+    }
+
+    #[test]
+    fn test_unpack_deduplicated() {
+        let temp_dir = TempDir::new().unwrap();
+        let libs = tarball(&[("esp-clang/lib/libclang.so", "libclang")]);
+        let full = tarball(&[
+            ("esp-clang/lib/libclang.so", "libclang"),
+            ("esp-clang/bin/clang", "clang"),
+        ]);
+
+        // Nothing to skip on a clean directory
+        let extracted =
+            unpack_deduplicated(&mut Archive::new(libs.as_slice()), temp_dir.path(), None).unwrap();
+        assert_eq!(extracted.skipped_bytes, 0);
+        assert_eq!(extracted.written_bytes, "libclang".len() as u64);
+
+        // Identical files are skipped
+        let extracted =
+            unpack_deduplicated(&mut Archive::new(full.as_slice()), temp_dir.path(), None).unwrap();
+        assert_eq!(extracted.skipped_bytes, "libclang".len() as u64);
+        // Skipped files are still listed
+        assert_eq!(
+            extracted.files,
+            vec![
+                temp_dir.path().join("esp-clang/lib/libclang.so"),
+                temp_dir.path().join("esp-clang/bin/clang")
+            ]
+        );
+        assert_eq!(
+            read_to_string(temp_dir.path().join("esp-clang/bin/clang")).unwrap(),
+            "clang"
+        );
+
+        // Files with the same size but different content are overwritten
+        write(
+            temp_dir.path().join("esp-clang/lib/libclang.so"),
+            "LIBCLANG",
+        )
+        .unwrap();
+        let extracted =
+            unpack_deduplicated(&mut Archive::new(full.as_slice()), temp_dir.path(), None).unwrap();
+        assert_eq!(extracted.skipped_bytes, "clang".len() as u64);
+        assert_eq!(
+            read_to_string(temp_dir.path().join("esp-clang/lib/libclang.so")).unwrap(),
+            "libclang"
+        );
+
+        // Differences past the first compared chunk are overwritten too
+        let large = format!("{}a", "x".repeat(COMPARE_CHUNK_SIZE));
+        let changed = format!("{}b", "x".repeat(COMPARE_CHUNK_SIZE));
+        write(temp_dir.path().join("esp-clang/lib/libLLVM.so"), &changed).unwrap();
+        let archive = tarball(&[("esp-clang/lib/libLLVM.so", &large)]);
+        let extracted =
+            unpack_deduplicated(&mut Archive::new(archive.as_slice()), temp_dir.path(), None)
+                .unwrap();
+        assert_eq!(extracted.skipped_bytes, 0);
+        assert_eq!(
+            read_to_string(temp_dir.path().join("esp-clang/lib/libLLVM.so")).unwrap(),
+            large
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_unpack_symlink_escape() {
+        let temp_dir = TempDir::new().unwrap();
+        let outside = temp_dir.path().join("outside");
+        let output_directory = temp_dir.path().join("esp-clang");
+        std::fs::create_dir_all(&outside).unwrap();
+        write(outside.join("libclang.so"), "original").unwrap();
+
+        // A symlink to a directory outside of the output directory, followed by entries through it
+        let mut builder = Builder::new(Vec::new());
+        let mut header = Header::new_gnu();
+        header.set_entry_type(EntryType::Symlink);
+        header.set_size(0);
+        header.set_mode(0o777);
+        header.set_cksum();
+        builder.append_link(&mut header, "lib", &outside).unwrap();
+        for (path, content) in [("lib/libclang.so", "replaced"), ("lib/evil", "evil")] {
+            let mut header = Header::new_gnu();
+            header.set_size(content.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder
+                .append_data(&mut header, path, content.as_bytes())
+                .unwrap();
+        }
+        let bytes = builder.into_inner().unwrap();
+
+        unpack_deduplicated(&mut Archive::new(bytes.as_slice()), &output_directory, None).unwrap();
+        assert_eq!(
+            read_to_string(outside.join("libclang.so")).unwrap(),
+            "original"
+        );
+        assert!(!outside.join("evil").exists());
+    }
+}
@@ -0,0 +1,299 @@
+//! Duplication of an installed toolchain under a new name, e.g. to keep a known-good toolchain
+//! while updating the default one.
+//!
+//! Toolchains installed with rustup are copied into its `toolchains` directory, where rustup
+//! finds them by name, and toolchains installed with `--no-rustup` next to the original.
+
+use crate::{
+    env::{
+        dir_mentions, get_export_file, try_create_export_file, ExportFileMode,
+        EXPORT_TEMPLATE_VERSION,
+    },
+    error::Error,
+    manifest::Manifest,
+    toolchain::rust::validate_toolchain_name,
+};
+use log::{debug, info};
+use miette::Result;
+use std::{
+    fs::{copy, create_dir_all, hard_link, read, read_dir, remove_file, write},
+    io,
+    path::{Path, PathBuf},
+};
+
+/// Largest file checked for paths of the original toolchain, larger ones are binaries.
+const MAX_FIXUP_SIZE: u64 = 1024 * 1024;
+
+/// Copies the `source` directory into `destination`, hard linking the files when requested and
+/// possible, and returns the number of copied files.
+///
+/// Symbolic links are recreated, pointing into `destination` when they pointed into `source`.
+fn copy_dir(source: &Path, destination: &Path, hardlink: bool) -> io::Result<usize> {
+    create_dir_all(destination)?;
+    let mut count = 0;
+    for entry in read_dir(source)? {
+        let entry = entry?;
+        let from = entry.path();
+        let to = destination.join(entry.file_name());
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            count += copy_dir(&from, &to, hardlink)?;
+            continue;
+        }
+        #[cfg(unix)]
+        if file_type.is_symlink() {
+            let target = std::fs::read_link(&from)?;
+            let target = match target.strip_prefix(source) {
+                Ok(relative) => destination.join(relative),
+                Err(_) => target,
+            };
+            std::os::unix::fs::symlink(target, &to)?;
+            continue;
+        }
+        if !hardlink || hard_link(&from, &to).is_err() {
+            copy(&from, &to)?;
+        }
+        count += 1;
+    }
+    Ok(count)
+}
+
+/// Replaces the mentions of the `from` directory, or of paths inside it, with `to`, returning
+/// `None` when there are none.
+fn replace_dir(content: &str, from: &str, to: &str) -> Option<String> {
+    let mentions = dir_mentions(content, from);
+    if mentions.is_empty() {
+        return None;
+    }
+    let mut replaced = String::with_capacity(content.len());
+    let mut end = 0;
+    for start in mentions {
+        replaced.push_str(&content[end..start]);
+        replaced.push_str(to);
+        end = start + from.len();
+    }
+    replaced.push_str(&content[end..]);
+    Some(replaced)
+}
+
+/// Replaces the paths of the original toolchain directory in the text files of the copy, e.g.
+/// the uninstall scripts of Xtensa Rust, returning the fixed files.
+fn fix_paths(dir: &Path, from: &str, to: &str) -> io::Result<Vec<PathBuf>> {
+    let mut fixed = Vec::new();
+    for entry in read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            fixed.extend(fix_paths(&path, from, to)?);
+            continue;
+        }
+        if !file_type.is_file() || entry.metadata()?.len() > MAX_FIXUP_SIZE {
+            continue;
+        }
+        let Ok(content) = String::from_utf8(read(&path)?) else {
+            continue;
+        };
+        if let Some(replaced) = replace_dir(&content, from, to) {
+            // Hard links would modify the original as well
+            let permissions = entry.metadata()?.permissions();
+            remove_file(&path)?;
+            write(&path, replaced)?;
+            std::fs::set_permissions(&path, permissions)?;
+            fixed.push(path);
+        }
+    }
+    Ok(fixed)
+}
+
+/// Returns the manifest of the copy of an installation named `name`, whose toolchain directory
+/// is `toolchain_dir`.
+fn cloned_manifest(
+    manifest: &Manifest,
+    name: &str,
+    toolchain_dir: &Path,
+    export_file: Option<PathBuf>,
+) -> Manifest {
+    let from = manifest.toolchain_dir().display().to_string();
+    let to = toolchain_dir.display().to_string();
+    let mut exports = manifest.exports.clone();
+    for export in &mut exports {
+        if let Some(replaced) = replace_dir(&export.value, &from, &to) {
+            export.value = replaced;
+        }
+    }
+    let export_file = export_file.unwrap_or_else(|| {
+        let extension = manifest
+            .export_file
+            .extension()
+            .map(|extension| format!(".{}", extension.to_string_lossy()))
+            .unwrap_or_default();
+        manifest
+            .export_file
+            .with_file_name(format!("export-{name}{extension}"))
+    });
+    Manifest {
+        name: name.to_string(),
+        export_file,
+        export_template_version: EXPORT_TEMPLATE_VERSION,
+        exports,
+        prefix: manifest
+            .prefix
+            .as_ref()
+            .map(|_| toolchain_dir.to_path_buf()),
+        ..manifest.clone()
+    }
+}
+
+/// Copies the `source` installation under the `name` toolchain name, with its own export files.
+///
+/// The export file is `export-<name>` next to the one of the original, unless `export_file` is
+/// given.
+pub async fn clone_toolchain(
+    source: &str,
+    name: &str,
+    export_file: Option<PathBuf>,
+    hardlink: bool,
+    force: bool,
+) -> Result<()> {
+    let manifest =
+        Manifest::load(source)?.ok_or_else(|| Error::MissingManifest(source.to_string()))?;
+    validate_toolchain_name(name, force, &manifest.homes())?;
+    let source_dir = manifest.toolchain_dir();
+    let toolchain_dir = source_dir.with_file_name(name);
+    if Manifest::path(name).exists() || toolchain_dir.exists() {
+        return Err(Error::InvalidToolchainName(
+            name.to_string(),
+            "it is already installed. Please, use a different name or uninstall it first"
+                .to_string(),
+        )
+        .into());
+    }
+    let clone = cloned_manifest(
+        &manifest,
+        name,
+        &toolchain_dir,
+        export_file
+            .map(|export_file| get_export_file(Some(export_file)))
+            .transpose()?,
+    );
+
+    info!(
+        "Copying '{}' to '{}'",
+        source_dir.display(),
+        toolchain_dir.display()
+    );
+    let (from, to) = (source_dir.clone(), toolchain_dir.clone());
+    let copied = tokio::task::spawn_blocking(move || -> io::Result<usize> {
+        let copied = copy_dir(&from, &to, hardlink)?;
+        for path in fix_paths(&to, &from.display().to_string(), &to.display().to_string())? {
+            debug!("Updated the paths of '{}'", path.display());
+        }
+        Ok(copied)
+    })
+    .await
+    .unwrap()
+    .map_err(Error::IoError)?;
+    debug!("Copied {} files", copied);
+
+    for export_file in clone.export_files() {
+        try_create_export_file(&export_file, &clone.exports, ExportFileMode::Regenerate)?;
+    }
+    clone.save()?;
+    info!(
+        "Toolchain '{}' successfully cloned as '{}', set up its environment with '{}'",
+        source,
+        name,
+        clone.export_file.display()
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        env::Export,
+        manifest::{manifest_fixture, Manifest},
+        toolchain::clone::{cloned_manifest, copy_dir, fix_paths},
+    };
+    use std::{
+        fs::{create_dir_all, read_to_string, write},
+        path::{Path, PathBuf},
+    };
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_clone_toolchain() {
+        let temp_dir = TempDir::new().unwrap();
+        let source = temp_dir.path().join("esp");
+        let destination = temp_dir.path().join("esp-stable");
+        create_dir_all(source.join("lib/rustlib")).unwrap();
+        create_dir_all(source.join("esp-clang/lib")).unwrap();
+        write(
+            source.join("lib/rustlib/uninstall.sh"),
+            format!("rm -rf '{}/lib/rustlib'\n", source.display()),
+        )
+        .unwrap();
+        write(source.join("esp-clang/lib/libclang.so"), b"\x7fELF\xff").unwrap();
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(
+            source.join("esp-clang/lib/libclang.so"),
+            source.join("esp-clang/lib/libclang.so.18"),
+        )
+        .unwrap();
+
+        assert_eq!(copy_dir(&source, &destination, true).unwrap(), 2);
+        let fixed = fix_paths(
+            &destination,
+            &source.display().to_string(),
+            &destination.display().to_string(),
+        )
+        .unwrap();
+        assert_eq!(fixed, vec![destination.join("lib/rustlib/uninstall.sh")]);
+        assert_eq!(
+            read_to_string(destination.join("lib/rustlib/uninstall.sh")).unwrap(),
+            format!("rm -rf '{}/lib/rustlib'\n", destination.display())
+        );
+        // The original is left untouched, even when hard linked
+        assert_eq!(
+            read_to_string(source.join("lib/rustlib/uninstall.sh")).unwrap(),
+            format!("rm -rf '{}/lib/rustlib'\n", source.display())
+        );
+        #[cfg(unix)]
+        assert_eq!(
+            std::fs::read_link(destination.join("esp-clang/lib/libclang.so.18")).unwrap(),
+            destination.join("esp-clang/lib/libclang.so")
+        );
+    }
+
+    #[test]
+    fn test_cloned_manifest() {
+        let manifest = Manifest {
+            exports: vec![
+                Export::set("llvm", "LIBCLANG_PATH", "/opt/esp/esp-clang/lib"),
+                Export::prepend_path("xtensa-gcc", "/opt/esp2/xtensa-esp-elf/bin"),
+            ],
+            prefix: Some(PathBuf::from("/opt/esp")),
+            ..manifest_fixture()
+        };
+        let clone = cloned_manifest(&manifest, "esp-stable", Path::new("/opt/esp-stable"), None);
+        assert_eq!(clone.name, "esp-stable");
+        assert_eq!(clone.prefix, Some(PathBuf::from("/opt/esp-stable")));
+        assert_eq!(
+            clone.export_file,
+            PathBuf::from("/home/user/export-esp-stable.sh")
+        );
+        assert_eq!(clone.exports[0].value, "/opt/esp-stable/esp-clang/lib");
+        // Paths of other directories sharing the prefix are kept
+        assert_eq!(clone.exports[1].value, "/opt/esp2/xtensa-esp-elf/bin");
+        assert_eq!(clone.toolchain_version, manifest.toolchain_version);
+
+        let clone = cloned_manifest(
+            &manifest,
+            "esp-stable",
+            Path::new("/opt/esp-stable"),
+            Some(PathBuf::from("/home/user/stable.sh")),
+        );
+        assert_eq!(clone.export_file, PathBuf::from("/home/user/stable.sh"));
+    }
+}
@@ -0,0 +1,206 @@
+//! Ad-hoc signing of the extracted binaries on macOS.
+//!
+//! On Apple Silicon, the kernel kills arm64 binaries without a code signature (`Killed: 9`).
+//! Binaries extracted from unsigned toolchain archives are ad-hoc signed with `codesign -s -`,
+//! unless disabled with `--no-codesign`.
+
+use log::{debug, info, warn};
+use std::{
+    fs::{read_dir, File},
+    io::Read,
+    path::{Path, PathBuf},
+    process::Command,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+/// Magic number of 64-bit Mach-O binaries, little-endian.
+const MH_MAGIC_64: u32 = 0xfeed_facf;
+/// CPU type of arm64 Mach-O binaries.
+const CPU_TYPE_ARM64: u32 = 0x0100_000c;
+/// Load command of the code signature.
+const LC_CODE_SIGNATURE: u32 = 0x1d;
+/// Size of the 64-bit Mach-O header, followed by the load commands.
+const MACH_HEADER_64_SIZE: usize = 32;
+
+/// Whether the extracted binaries are signed.
+static CODESIGN: AtomicBool = AtomicBool::new(true);
+
+/// Disables the signing of the extracted binaries.
+pub fn disable_codesign() {
+    CODESIGN.store(false, Ordering::Relaxed);
+}
+
+/// Returns whether the extracted binaries are signed.
+pub fn codesign_enabled() -> bool {
+    CODESIGN.load(Ordering::Relaxed)
+}
+
+/// Returns whether the header and load commands are the ones of an arm64 Mach-O binary without
+/// code signature.
+fn is_unsigned_arm64(header: &[u8]) -> bool {
+    let word = |offset: usize| {
+        header
+            .get(offset..offset + 4)
+            .map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()))
+    };
+    if word(0) != Some(MH_MAGIC_64) || word(4) != Some(CPU_TYPE_ARM64) {
+        return false;
+    }
+    let Some(ncmds) = word(16) else {
+        return false;
+    };
+    let mut offset = MACH_HEADER_64_SIZE;
+    for _ in 0..ncmds {
+        match (word(offset), word(offset + 4)) {
+            (Some(LC_CODE_SIGNATURE), _) => return false,
+            (Some(_), Some(size)) if size > 0 => offset += size as usize,
+            // Truncated or corrupted, leave it alone
+            _ => return false,
+        }
+    }
+    true
+}
+
+/// Returns whether the file is an arm64 Mach-O binary without code signature, reading only its
+/// header and load commands.
+fn is_unsigned_arm64_file(path: &Path) -> bool {
+    let Ok(mut file) = File::open(path) else {
+        return false;
+    };
+    let mut header = vec![0; MACH_HEADER_64_SIZE];
+    if file.read_exact(&mut header).is_err() {
+        return false;
+    }
+    let word = |offset: usize| u32::from_le_bytes(header[offset..offset + 4].try_into().unwrap());
+    if word(0) != MH_MAGIC_64 || word(4) != CPU_TYPE_ARM64 {
+        return false;
+    }
+    let sizeofcmds = word(20);
+    let mut commands = Vec::new();
+    if file
+        .take(sizeofcmds.into())
+        .read_to_end(&mut commands)
+        .is_err()
+    {
+        return false;
+    }
+    header.extend(commands);
+    is_unsigned_arm64(&header)
+}
+
+/// Returns the unsigned arm64 Mach-O binaries of the directory, symbolic links excluded.
+fn unsigned_binaries(dir: &Path) -> Vec<PathBuf> {
+    let Ok(entries) = read_dir(dir) else {
+        return Vec::new();
+    };
+    entries
+        .filter_map(|entry| entry.ok())
+        .flat_map(|entry| {
+            let path = entry.path();
+            match entry.file_type() {
+                Ok(file_type) if file_type.is_dir() => unsigned_binaries(&path),
+                Ok(file_type) if file_type.is_file() && is_unsigned_arm64_file(&path) => {
+                    vec![path]
+                }
+                _ => Vec::new(),
+            }
+        })
+        .collect()
+}
+
+/// Ad-hoc signs the unsigned arm64 binaries extracted into `dir`.
+///
+/// Failures are only reported, as the binaries may still run, e.g. when the kernel doesn't
+/// enforce signatures.
+pub fn sign_binaries(dir: &Path) {
+    if !codesign_enabled() {
+        return;
+    }
+    let binaries = unsigned_binaries(dir);
+    if binaries.is_empty() {
+        debug!("No unsigned binary in '{}'", dir.display());
+        return;
+    }
+    info!(
+        "Ad-hoc signing {} binaries in '{}'",
+        binaries.len(),
+        dir.display()
+    );
+    for binary in binaries {
+        match Command::new("codesign")
+            .arg("-s")
+            .arg("-")
+            .arg(&binary)
+            .output()
+        {
+            Ok(output) if output.status.success() => {}
+            Ok(output) => warn!(
+                "Failed to sign '{}': {}",
+                binary.display(),
+                String::from_utf8_lossy(&output.stderr).trim()
+            ),
+            Err(e) => warn!("Failed to sign '{}': {}", binary.display(), e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::toolchain::codesign::{
+        is_unsigned_arm64, unsigned_binaries, CPU_TYPE_ARM64, LC_CODE_SIGNATURE, MH_MAGIC_64,
+    };
+    use std::fs::{create_dir_all, write};
+    use tempfile::TempDir;
+
+    /// Returns a 64-bit Mach-O header followed by the given load commands.
+    fn macho(cpu_type: u32, commands: &[u32]) -> Vec<u8> {
+        let mut header = Vec::new();
+        for word in [
+            MH_MAGIC_64,
+            cpu_type,
+            0,
+            2,
+            commands.len() as u32,
+            commands.len() as u32 * 16,
+            0,
+            0,
+        ] {
+            header.extend(word.to_le_bytes());
+        }
+        for command in commands {
+            for word in [*command, 16, 0, 0] {
+                header.extend(word.to_le_bytes());
+            }
+        }
+        header
+    }
+
+    #[test]
+    fn test_unsigned_binaries() {
+        assert!(is_unsigned_arm64(&macho(CPU_TYPE_ARM64, &[0x19, 0x2])));
+        assert!(!is_unsigned_arm64(&macho(
+            CPU_TYPE_ARM64,
+            &[0x19, LC_CODE_SIGNATURE]
+        )));
+        // x86_64
+        assert!(!is_unsigned_arm64(&macho(0x0100_0007, &[0x19])));
+        // Truncated load commands
+        assert!(!is_unsigned_arm64(&macho(CPU_TYPE_ARM64, &[0x19])[..36]));
+        assert!(!is_unsigned_arm64(b"#!/bin/sh\n"));
+
+        let temp_dir = TempDir::new().unwrap();
+        let bin_dir = temp_dir.path().join("esp-clang/bin");
+        create_dir_all(&bin_dir).unwrap();
+        write(bin_dir.join("clang"), macho(CPU_TYPE_ARM64, &[0x19])).unwrap();
+        write(
+            bin_dir.join("ld.lld"),
+            macho(CPU_TYPE_ARM64, &[LC_CODE_SIGNATURE]),
+        )
+        .unwrap();
+        write(bin_dir.join("README"), "clang").unwrap();
+        assert_eq!(
+            unsigned_binaries(temp_dir.path()),
+            vec![bin_dir.join("clang")]
+        );
+    }
+}
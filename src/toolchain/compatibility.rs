@@ -0,0 +1,179 @@
+//! Compatibility of the Xtensa Rust, LLVM and GCC versions.
+//!
+//! Every Xtensa Rust release is built with a given Espressif LLVM fork, which must be installed
+//! alongside it. The ranges below are the single source of truth of that mapping.
+//!
+//! The ESP-IDF release lines are mapped to the Xtensa Rust release that was current when they
+//! were released, and to the GCC version they ship, for `espup install --compat`.
+
+use crate::{
+    error::Error,
+    toolchain::{
+        gcc::DEFAULT_GCC_RELEASE,
+        llvm::{
+            DEFAULT_LLVM_15_VERSION, DEFAULT_LLVM_16_VERSION, DEFAULT_LLVM_17_VERSION,
+            DEFAULT_LLVM_18_VERSION,
+        },
+        rust::parse_extended_version,
+    },
+};
+
+/// Components of an extended version (`major.minor.patch.subpatch`).
+type ExtendedVersion = (u64, u64, u64, u64);
+
+/// Last Xtensa Rust version built with each LLVM version, oldest first.
+pub(crate) const LLVM_RANGES: [(ExtendedVersion, &str); 3] = [
+    ((1, 69, 0, 0), DEFAULT_LLVM_15_VERSION),
+    ((1, 77, 0, 0), DEFAULT_LLVM_16_VERSION),
+    ((1, 81, 0, 0), DEFAULT_LLVM_17_VERSION),
+];
+/// LLVM version of the Xtensa Rust versions newer than the last range.
+pub(crate) const LATEST_LLVM_VERSION: &str = DEFAULT_LLVM_18_VERSION;
+
+/// Xtensa Rust version and GCC version of each supported ESP-IDF release line, oldest first.
+const ESP_IDF_LINES: [(&str, &str, &str); 4] = [
+    ("5.1", "1.71.0.1", "12.2.0_20230208"),
+    ("5.2", "1.76.0.1", "13.2.0_20230928"),
+    ("5.3", "1.79.0.0", "13.2.0_20240530"),
+    ("5.4", "1.83.0.1", "14.2.0_20241119"),
+];
+
+/// Toolchain versions compatible with an Xtensa Rust version.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ToolchainSet {
+    /// Espressif LLVM version, e.g. `esp-18.1.2_20240912`.
+    pub llvm: &'static str,
+    /// Espressif GCC version, e.g. `14.2.0_20240906`.
+    pub gcc: &'static str,
+}
+
+/// Returns the toolchain versions to install with an Xtensa Rust version
+/// (`major.minor.patch.subpatch`).
+pub fn resolve(xtensa_rust_version: &str) -> Result<ToolchainSet, Error> {
+    let version = parse_extended_version(xtensa_rust_version)
+        .ok_or_else(|| Error::InvalidVersion(xtensa_rust_version.to_string()))?;
+    let llvm = LLVM_RANGES
+        .iter()
+        .find(|(last, _)| version <= *last)
+        .map_or(LATEST_LLVM_VERSION, |(_, llvm)| *llvm);
+    Ok(ToolchainSet {
+        llvm,
+        gcc: DEFAULT_GCC_RELEASE,
+    })
+}
+
+/// Versions selected for an ESP-IDF release line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EspIdfCompat {
+    /// ESP-IDF release line, e.g. `5.2`.
+    pub esp_idf: &'static str,
+    /// Xtensa Rust version, e.g. `1.76.0.1`.
+    pub xtensa_rust: &'static str,
+    /// Toolchain versions of the Xtensa Rust version.
+    pub toolchains: ToolchainSet,
+    /// GCC version shipped with the ESP-IDF, used by `esp-idf-sys` for STD applications.
+    pub esp_idf_gcc: &'static str,
+}
+
+/// Returns the versions to install for an ESP-IDF version (`5.2`, `v5.2.1`, `release/v5.2`...).
+pub fn resolve_esp_idf(esp_idf_version: &str) -> Result<EspIdfCompat, Error> {
+    let unsupported = || {
+        Error::UnsupportedEspIdfVersion(
+            esp_idf_version.to_string(),
+            ESP_IDF_LINES
+                .iter()
+                .map(|(line, _, _)| *line)
+                .collect::<Vec<_>>()
+                .join(", "),
+        )
+    };
+    let version = esp_idf_version
+        .trim_start_matches("release/")
+        .trim_start_matches('v');
+    let mut parts = version.split('.');
+    let line = match (parts.next(), parts.next()) {
+        (Some(major), Some(minor)) => format!("{major}.{minor}"),
+        _ => return Err(unsupported()),
+    };
+    // The patch release, if any, must be a number
+    if parts
+        .next()
+        .is_some_and(|patch| patch.parse::<u64>().is_err())
+        || parts.next().is_some()
+    {
+        return Err(unsupported());
+    }
+    let (esp_idf, xtensa_rust, esp_idf_gcc) = ESP_IDF_LINES
+        .iter()
+        .find(|(esp_idf, _, _)| *esp_idf == line)
+        .ok_or_else(unsupported)?;
+    Ok(EspIdfCompat {
+        esp_idf,
+        xtensa_rust,
+        toolchains: resolve(xtensa_rust)?,
+        esp_idf_gcc,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::toolchain::{
+        compatibility::{resolve, resolve_esp_idf},
+        gcc::DEFAULT_GCC_RELEASE,
+        llvm::{
+            DEFAULT_LLVM_15_VERSION, DEFAULT_LLVM_16_VERSION, DEFAULT_LLVM_17_VERSION,
+            DEFAULT_LLVM_18_VERSION,
+        },
+    };
+
+    #[test]
+    fn test_resolve() {
+        let cases = [
+            ("1.65.0.0", DEFAULT_LLVM_15_VERSION),
+            ("1.69.0.0", DEFAULT_LLVM_15_VERSION),
+            ("1.69.0.1", DEFAULT_LLVM_16_VERSION),
+            ("1.70.0.0", DEFAULT_LLVM_16_VERSION),
+            ("1.77.0.0", DEFAULT_LLVM_16_VERSION),
+            ("1.77.0.1", DEFAULT_LLVM_17_VERSION),
+            ("1.77.2.0", DEFAULT_LLVM_17_VERSION),
+            ("1.81.0.0", DEFAULT_LLVM_17_VERSION),
+            ("1.81.0.1", DEFAULT_LLVM_18_VERSION),
+            ("1.82.0.3", DEFAULT_LLVM_18_VERSION),
+            ("1.84.0.0", DEFAULT_LLVM_18_VERSION),
+            ("1.84.0.1", DEFAULT_LLVM_18_VERSION),
+            ("1.85.0.0", DEFAULT_LLVM_18_VERSION),
+            ("2.0.0.0", DEFAULT_LLVM_18_VERSION),
+        ];
+        for (version, llvm) in cases {
+            let set = resolve(version).unwrap();
+            assert_eq!(set.llvm, llvm, "LLVM of Xtensa Rust {version}");
+            assert_eq!(set.gcc, DEFAULT_GCC_RELEASE, "GCC of Xtensa Rust {version}");
+        }
+
+        for version in ["1.82.0", "1.82.0.3-rc1", "nightly", "1.300.0.0.0"] {
+            assert!(resolve(version).is_err(), "{version} should be invalid");
+        }
+    }
+
+    #[test]
+    fn test_resolve_esp_idf() {
+        for version in ["5.2", "v5.2", "v5.2.3", "release/v5.2"] {
+            let compat = resolve_esp_idf(version).unwrap();
+            assert_eq!(compat.esp_idf, "5.2");
+            assert_eq!(compat.xtensa_rust, "1.76.0.1");
+            assert_eq!(compat.toolchains.llvm, DEFAULT_LLVM_16_VERSION);
+            assert_eq!(compat.esp_idf_gcc, "13.2.0_20230928");
+        }
+        assert_eq!(
+            resolve_esp_idf("5.4").unwrap().toolchains.llvm,
+            DEFAULT_LLVM_18_VERSION
+        );
+
+        for version in ["4.4", "5", "v5.2.x", "5.2.1.0", "master"] {
+            assert!(
+                resolve_esp_idf(version).is_err(),
+                "{version} should be unsupported"
+            );
+        }
+    }
+}
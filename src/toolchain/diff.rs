@@ -0,0 +1,133 @@
+//! Preview of the changes an installation or update would make, printed with `--diff-only`.
+
+use crate::toolchain::{fetch::content_length, Component, ComponentState};
+
+/// Change of a component.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ComponentDiff {
+    /// Name of the component.
+    pub name: String,
+    /// Currently installed version, if any.
+    pub installed: Option<String>,
+    /// Version that would be installed.
+    pub target: String,
+    /// Amount of bytes that would be downloaded, if known.
+    pub download_size: Option<u64>,
+}
+
+impl ComponentDiff {
+    /// Compares the installed version of a component with the one espup installs, querying the
+    /// size of the artifacts that would be downloaded.
+    pub async fn new(component: &(dyn Component + Send + Sync)) -> Self {
+        let state = component.state();
+        let download_size = if state == ComponentState::Installed {
+            Some(0)
+        } else {
+            let urls = component.artifact_urls();
+            // Components installed through rustup don't expose their artifacts
+            let mut size = (!urls.is_empty()).then_some(0);
+            for url in urls {
+                size = match (size, content_length(&url).await) {
+                    (Some(size), Some(length)) => Some(size + length),
+                    _ => None,
+                };
+            }
+            size
+        };
+        Self {
+            name: component.name(),
+            installed: component.installed_version(),
+            target: component.latest_version(),
+            download_size,
+        }
+    }
+
+    fn change(&self) -> String {
+        match &self.installed {
+            Some(installed) if installed == &self.target => format!("{installed} (up to date)"),
+            Some(installed) => format!("{installed} -> {}", self.target),
+            None => format!("not installed -> {}", self.target),
+        }
+    }
+
+    fn download(&self) -> String {
+        match self.download_size {
+            Some(0) => "-".to_string(),
+            Some(size) => format!("{:.2} MB", size as f64 / 1_000_000.0),
+            None => "unknown".to_string(),
+        }
+    }
+}
+
+/// Formats the changes as a table.
+pub fn format_table(diffs: &[ComponentDiff]) -> String {
+    let rows: Vec<[String; 3]> = diffs
+        .iter()
+        .map(|diff| [diff.name.clone(), diff.change(), diff.download()])
+        .collect();
+    let header = [
+        "Component".to_string(),
+        "Version".to_string(),
+        "Download".to_string(),
+    ];
+    let widths: Vec<usize> = (0..3)
+        .map(|column| {
+            rows.iter()
+                .chain([&header])
+                .map(|row| row[column].chars().count())
+                .max()
+                .unwrap_or_default()
+        })
+        .collect();
+
+    let mut table = String::new();
+    for row in [&header].into_iter().chain(rows.iter()) {
+        let line = format!(
+            "{:<name$}  {:<change$}  {}",
+            row[0],
+            row[1],
+            row[2],
+            name = widths[0],
+            change = widths[1]
+        );
+        table.push_str(line.trim_end());
+        table.push('\n');
+    }
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::toolchain::diff::{format_table, ComponentDiff};
+
+    #[test]
+    fn test_format_table() {
+        let diffs = [
+            ComponentDiff {
+                name: "Xtensa Rust".to_string(),
+                installed: Some("1.84.0.0".to_string()),
+                target: "1.85.0.0".to_string(),
+                download_size: Some(512_340_000),
+            },
+            ComponentDiff {
+                name: "LLVM".to_string(),
+                installed: Some("esp-18.1.2_20240912".to_string()),
+                target: "esp-18.1.2_20240912".to_string(),
+                download_size: Some(0),
+            },
+            ComponentDiff {
+                name: "RISC-V Rust target".to_string(),
+                installed: None,
+                target: "nightly".to_string(),
+                download_size: None,
+            },
+        ];
+        assert_eq!(
+            format_table(&diffs),
+            "Component           Version                           Download\n\
+             Xtensa Rust         1.84.0.0 -> 1.85.0.0              512.34 MB\n\
+             LLVM                esp-18.1.2_20240912 (up to date)  -\n\
+             RISC-V Rust target  not installed -> nightly          unknown\n"
+        );
+    }
+}
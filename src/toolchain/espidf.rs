@@ -0,0 +1,105 @@
+//! Discovery of the toolchains installed by ESP-IDF.
+//!
+//! ESP-IDF installs its tools with `idf_tools.py` into `$IDF_TOOLS_PATH/tools/<tool>/<version>`,
+//! using the same GCC archives that espup downloads.
+
+use crate::toolchain::gcc::DEFAULT_GCC_RELEASE;
+use directories::BaseDirs;
+use log::debug;
+use std::{
+    env,
+    fs::read_dir,
+    path::{Path, PathBuf},
+};
+
+/// Gets the ESP-IDF tools directory.
+pub fn get_idf_tools_path() -> PathBuf {
+    env::var_os("IDF_TOOLS_PATH")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| BaseDirs::new().unwrap().home_dir().join(".espressif"))
+}
+
+/// Returns the version directory of the GCC toolchain of the given architecture installed by
+/// ESP-IDF, if any.
+///
+/// The version used by espup is preferred, otherwise the most recent one is returned.
+pub fn find_gcc(arch: &str) -> Option<PathBuf> {
+    find_gcc_in(&get_idf_tools_path().join("tools"), arch)
+}
+
+fn find_gcc_in(tools_dir: &Path, arch: &str) -> Option<PathBuf> {
+    let gcc_binary = format!("{arch}-gcc{}", env::consts::EXE_SUFFIX);
+    let mut versions: Vec<PathBuf> = read_dir(tools_dir.join(arch))
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| {
+            // Versions without the binary are leftovers of interrupted installations
+            path.join(arch).join("bin").join(&gcc_binary).exists()
+        })
+        .collect();
+    versions.sort();
+    debug!(
+        "GCC ({}) versions installed by ESP-IDF: {:?}",
+        arch, versions
+    );
+    let preferred = format!("esp-{DEFAULT_GCC_RELEASE}");
+    versions
+        .iter()
+        .find(|path| {
+            path.file_name()
+                .is_some_and(|name| name == preferred.as_str())
+        })
+        .or(versions.last())
+        .cloned()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::toolchain::{
+        espidf::find_gcc_in,
+        gcc::{DEFAULT_GCC_RELEASE, RISCV_GCC, XTENSA_GCC},
+    };
+    use std::{
+        env,
+        fs::{create_dir_all, write},
+        path::Path,
+    };
+    use tempfile::TempDir;
+
+    fn create_gcc(tools_dir: &Path, arch: &str, version: &str, with_binary: bool) {
+        let bin_dir = tools_dir.join(arch).join(version).join(arch).join("bin");
+        create_dir_all(&bin_dir).unwrap();
+        if with_binary {
+            write(
+                bin_dir.join(format!("{arch}-gcc{}", env::consts::EXE_SUFFIX)),
+                "",
+            )
+            .unwrap();
+        }
+    }
+
+    #[test]
+    fn test_find_gcc() {
+        let tools_dir = TempDir::new().unwrap();
+        let tools_dir = tools_dir.path();
+        assert_eq!(find_gcc_in(tools_dir, XTENSA_GCC), None);
+
+        create_gcc(tools_dir, XTENSA_GCC, "esp-13.2.0_20230928", true);
+        create_gcc(tools_dir, XTENSA_GCC, "esp-15.1.0_20250607", false);
+        assert_eq!(
+            find_gcc_in(tools_dir, XTENSA_GCC),
+            Some(tools_dir.join(XTENSA_GCC).join("esp-13.2.0_20230928"))
+        );
+
+        // The release used by espup is preferred over newer ones
+        let preferred = format!("esp-{DEFAULT_GCC_RELEASE}");
+        create_gcc(tools_dir, XTENSA_GCC, &preferred, true);
+        create_gcc(tools_dir, XTENSA_GCC, "esp-99.0.0_20990101", true);
+        assert_eq!(
+            find_gcc_in(tools_dir, XTENSA_GCC),
+            Some(tools_dir.join(XTENSA_GCC).join(preferred))
+        );
+        assert_eq!(find_gcc_in(tools_dir, RISCV_GCC), None);
+    }
+}
@@ -0,0 +1,1145 @@
+//! HTTP downloads with proxy support, progress reporting and retries.
+
+use crate::{
+    auth::{github_token, mirror_credentials},
+    error::Error,
+    toolchain::{metrics::record, offline::cached_artifact, progress::Progress},
+};
+use bytes::Bytes;
+use log::{debug, info, warn};
+use miette::Result;
+use reqwest::{
+    blocking::Client,
+    header::{self, HeaderMap},
+    redirect::Policy,
+    RequestBuilder, StatusCode, Url,
+};
+#[cfg(target_os = "macos")]
+use std::process::Command;
+use std::{
+    collections::HashSet,
+    future::Future,
+    path::Path,
+    sync::{
+        atomic::{self, AtomicBool},
+        Mutex,
+    },
+    thread::sleep,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+use tokio::{
+    io::{AsyncWrite, AsyncWriteExt},
+    task::JoinSet,
+};
+use tokio_retry::{strategy::FixedInterval, Retry};
+use tokio_stream::StreamExt;
+
+/// Number of attempts made to download a file.
+const DOWNLOAD_ATTEMPTS: usize = 3;
+/// Delay between download attempts, in milliseconds.
+const DOWNLOAD_RETRY_DELAY: u64 = 500;
+/// Timeout of the connectivity check requests.
+const PREFLIGHT_TIMEOUT: Duration = Duration::from_secs(15);
+/// Delay between GitHub API query attempts that were not rate limited.
+const GITHUB_RETRY_DELAY: Duration = Duration::from_millis(100);
+/// Longest rate limit window, in seconds, that is waited for instead of failing.
+const MAX_RATE_LIMIT_WAIT: u64 = 60;
+/// Maximum number of redirects followed by a download.
+const MAX_REDIRECTS: usize = 10;
+/// Size of the range downloaded from each source to compare their speed.
+const PROBE_SIZE: u64 = 256 * 1024;
+/// Timeout of the speed probes, slower sources are only used as fallbacks.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(10);
+/// Time without receiving any data after which a download is considered stalled.
+const STALL_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Whether the proxy configured in the system settings is used when no proxy environment
+/// variable is set.
+static SYSTEM_PROXY: AtomicBool = AtomicBool::new(false);
+/// Proxies of specific hosts, overriding the global proxy.
+static PROXY_ROUTES: Mutex<Vec<ProxyRoute>> = Mutex::new(Vec::new());
+/// Mirror replacing the hosts the downloads are redirected to, e.g. the GitHub release assets
+/// host.
+static ASSET_HOST_OVERRIDE: Mutex<Option<Url>> = Mutex::new(None);
+/// Mirrors serving the artifacts along with their original hosts.
+static MIRRORS: Mutex<Vec<Url>> = Mutex::new(Vec::new());
+
+/// Proxy of the requests to a host and its subdomains.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProxyRoute {
+    /// Host, e.g. `github.com`.
+    pub host: String,
+    /// Proxy URL, `None` to connect directly.
+    pub proxy: Option<Url>,
+}
+
+impl ProxyRoute {
+    /// Returns whether the route applies to the host.
+    fn matches(&self, host: &str) -> bool {
+        host.eq_ignore_ascii_case(&self.host)
+            || host
+                .to_ascii_lowercase()
+                .ends_with(&format!(".{}", self.host.to_ascii_lowercase()))
+    }
+}
+
+/// Parses a `host=url` proxy route, where `url` is `direct` to bypass the proxies.
+pub fn parse_proxy_route(route: &str) -> Result<ProxyRoute, Error> {
+    let invalid = || Error::InvalidProxyRoute(route.to_string());
+    let (host, proxy) = route.split_once('=').ok_or_else(invalid)?;
+    let (host, proxy) = (host.trim(), proxy.trim());
+    if host.is_empty() || host.contains(['/', ':']) {
+        return Err(invalid());
+    }
+    let proxy = if proxy.eq_ignore_ascii_case("direct") {
+        None
+    } else {
+        Some(Url::parse(proxy).map_err(|_| invalid())?)
+    };
+    Ok(ProxyRoute {
+        host: host.to_string(),
+        proxy,
+    })
+}
+
+/// Sets the proxies of specific hosts.
+pub fn set_proxy_routes(routes: &[ProxyRoute]) {
+    *PROXY_ROUTES.lock().unwrap() = routes.to_vec();
+}
+
+/// Returns the most specific route of the host, if any.
+fn find_route<'a>(routes: &'a [ProxyRoute], host: &str) -> Option<&'a ProxyRoute> {
+    routes
+        .iter()
+        .filter(|route| route.matches(host))
+        .max_by_key(|route| route.host.len())
+}
+
+/// Retry policy of the GitHub API queries.
+#[derive(Debug, Clone, Copy)]
+pub struct GithubQueryOpts {
+    /// Number of retries after the first attempt.
+    pub retries: usize,
+    /// Timeout of each request.
+    pub timeout: Duration,
+}
+
+impl Default for GithubQueryOpts {
+    fn default() -> Self {
+        Self {
+            retries: 5,
+            timeout: Duration::from_secs(30),
+        }
+    }
+}
+
+/// Get https proxy from environment variables(if any)
+///
+/// sadly there is not standard on the environment variable name for the proxy, but it seems
+/// that the most common are:
+///
+/// - https_proxy(or http_proxy for http)
+/// - HTTPS_PROXY(or HTTP_PROXY for http)
+/// - all_proxy
+/// - ALL_PROXY
+///
+/// hence we will check for all of them. If none is set and the system proxy is enabled, the proxy
+/// from the system settings is used.
+fn https_proxy() -> Option<String> {
+    for proxy in ["https_proxy", "HTTPS_PROXY", "all_proxy", "ALL_PROXY"] {
+        if let Ok(proxy_addr) = std::env::var(proxy) {
+            info!("Get Proxy from env var: {}={}", proxy, proxy_addr);
+            return Some(proxy_addr);
+        }
+    }
+    if SYSTEM_PROXY.load(atomic::Ordering::Relaxed) {
+        if let Some(proxy_addr) = system_proxy() {
+            info!("Get Proxy from system settings: {}", proxy_addr);
+            return Some(proxy_addr);
+        }
+        debug!("No proxy found in the system settings");
+    }
+    None
+}
+
+/// Enables the discovery of the proxy configured in the system settings.
+pub fn enable_system_proxy() {
+    SYSTEM_PROXY.store(true, atomic::Ordering::Relaxed);
+}
+
+/// Get the proxy from the Internet Settings of the current user (WinINET).
+#[cfg(windows)]
+fn system_proxy() -> Option<String> {
+    use winreg::{enums::HKEY_CURRENT_USER, RegKey};
+
+    let settings = RegKey::predef(HKEY_CURRENT_USER)
+        .open_subkey("Software\\Microsoft\\Windows\\CurrentVersion\\Internet Settings")
+        .ok()?;
+    let enabled: u32 = settings.get_value("ProxyEnable").ok()?;
+    if enabled == 0 {
+        return None;
+    }
+    let proxy_server: String = settings.get_value("ProxyServer").ok()?;
+    parse_proxy_server(&proxy_server)
+}
+
+/// Get the proxy from the network settings (`scutil --proxy`).
+#[cfg(target_os = "macos")]
+fn system_proxy() -> Option<String> {
+    let output = Command::new("scutil").arg("--proxy").output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    parse_scutil_proxy(&String::from_utf8_lossy(&output.stdout))
+}
+
+#[cfg(not(any(windows, target_os = "macos")))]
+fn system_proxy() -> Option<String> {
+    None
+}
+
+/// Parses the WinINET `ProxyServer` value, either `host:port` or a per-protocol list like
+/// `http=host:port;https=host:port`.
+#[cfg(any(windows, test))]
+fn parse_proxy_server(proxy_server: &str) -> Option<String> {
+    let proxy_server = proxy_server.trim();
+    let proxy = if proxy_server.contains('=') {
+        let proxies: Vec<(&str, &str)> = proxy_server
+            .split(';')
+            .filter_map(|proxy| proxy.split_once('='))
+            .collect();
+        ["https", "http"].iter().find_map(|protocol| {
+            proxies
+                .iter()
+                .find(|(name, _)| name.trim().eq_ignore_ascii_case(protocol))
+                .map(|(_, address)| address.trim())
+        })?
+    } else {
+        proxy_server
+    };
+    if proxy.is_empty() {
+        None
+    } else if proxy.contains("://") {
+        Some(proxy.to_string())
+    } else {
+        Some(format!("http://{proxy}"))
+    }
+}
+
+/// Parses the output of `scutil --proxy`, preferring the HTTPS proxy over the HTTP one.
+#[cfg(any(target_os = "macos", test))]
+fn parse_scutil_proxy(output: &str) -> Option<String> {
+    let value = |key: &str| {
+        output.lines().find_map(|line| {
+            let (name, value) = line.split_once(" : ")?;
+            (name.trim() == key).then(|| value.trim().to_string())
+        })
+    };
+    ["HTTPS", "HTTP"].iter().find_map(|protocol| {
+        if value(&format!("{protocol}Enable")).as_deref() != Some("1") {
+            return None;
+        }
+        let host = value(&format!("{protocol}Proxy"))?;
+        match value(&format!("{protocol}Port")) {
+            Some(port) => Some(format!("http://{host}:{port}")),
+            None => Some(format!("http://{host}")),
+        }
+    })
+}
+
+/// Returns the proxy of the clients: the routes of specific hosts, if any, take precedence over
+/// the global proxy of HTTPS requests.
+fn proxy() -> Option<reqwest::Proxy> {
+    let routes = PROXY_ROUTES.lock().unwrap().clone();
+    let global = https_proxy();
+    if routes.is_empty() {
+        return global.map(|proxy| reqwest::Proxy::https(&proxy).unwrap());
+    }
+    let global = global.and_then(|proxy| {
+        // Like `reqwest::Proxy::https`, default to HTTP proxies when no scheme is given
+        let proxy = if proxy.contains("://") {
+            proxy
+        } else {
+            format!("http://{proxy}")
+        };
+        Url::parse(&proxy).ok()
+    });
+    Some(reqwest::Proxy::custom(move |url| {
+        match find_route(&routes, url.host_str().unwrap_or_default()) {
+            Some(route) => route.proxy.clone(),
+            None if url.scheme() == "https" => global.clone(),
+            None => None,
+        }
+    }))
+}
+
+/// Build a reqwest client with proxy if env var is set
+pub(crate) fn build_proxy_blocking_client() -> Result<Client, Error> {
+    let mut builder = reqwest::blocking::Client::builder();
+    if let Some(proxy) = proxy() {
+        builder = builder.proxy(proxy);
+    }
+    let client = builder.build()?;
+    Ok(client)
+}
+
+/// Build a reqwest client with proxy if env var is set
+pub(crate) fn build_proxy_async_client() -> Result<reqwest::Client, Error> {
+    let mut builder = reqwest::Client::builder();
+    if let Some(proxy) = proxy() {
+        builder = builder.proxy(proxy);
+    }
+    let client = builder.build()?;
+    Ok(client)
+}
+
+/// Downloads the assets redirected to other hosts from the given mirror instead.
+pub fn set_asset_host_override(mirror: Option<Url>) {
+    *ASSET_HOST_OVERRIDE.lock().unwrap() = mirror;
+}
+
+/// Downloads the artifacts from the fastest of their original host and the given mirrors.
+pub fn set_mirrors(mirrors: &[Url]) {
+    *MIRRORS.lock().unwrap() = mirrors.to_vec();
+}
+
+/// Returns the URL of an artifact on a mirror, which keeps the path and query of the original.
+fn mirror_url(url: &Url, mirror: &Url) -> Url {
+    let mut mirrored = mirror.clone();
+    mirrored.set_path(&format!(
+        "{}{}",
+        mirror.path().trim_end_matches('/'),
+        url.path()
+    ));
+    mirrored.set_query(url.query());
+    mirrored
+}
+
+/// Returns the URL to follow a redirect to: redirects leaving the origin of the request are
+/// moved to the mirror, if any, keeping their path and query.
+fn redirect_target(location: Url, from: &Url, mirror: Option<&Url>) -> Url {
+    match mirror {
+        Some(mirror) if location.origin() != from.origin() => {
+            let mut target = mirror.clone();
+            target.set_path(&format!(
+                "{}{}",
+                mirror.path().trim_end_matches('/'),
+                location.path()
+            ));
+            target.set_query(location.query());
+            target
+        }
+        _ => location,
+    }
+}
+
+/// Adds the credentials stored with `espup auth set-mirror` for the host of the request, if any.
+fn with_credentials(request: RequestBuilder, url: &Url) -> RequestBuilder {
+    match mirror_credentials(url) {
+        Some(credentials) => request.basic_auth(credentials.username, Some(credentials.password)),
+        None => request,
+    }
+}
+
+/// Sends a `GET` request, following the redirects.
+///
+/// Failures to connect to the host of a redirect, e.g. the GitHub release assets host being
+/// blocked while `github.com` is not, are reported as such.
+///
+/// The `headers` are sent with every request.
+async fn get_following_redirects(
+    url: &str,
+    headers: HeaderMap,
+) -> Result<reqwest::Response, Error> {
+    let mut builder = reqwest::Client::builder().redirect(Policy::none());
+    if let Some(proxy) = proxy() {
+        builder = builder.proxy(proxy);
+    }
+    let client = builder.build()?;
+    let mirror = ASSET_HOST_OVERRIDE.lock().unwrap().clone();
+    let mut request = client.get(url).headers(headers.clone());
+    if let Ok(url) = Url::parse(url) {
+        request = with_credentials(request, &url);
+    }
+    let mut response = request.send().await?;
+    for _ in 0..MAX_REDIRECTS {
+        if !response.status().is_redirection() {
+            break;
+        }
+        let Some(location) = response
+            .headers()
+            .get(header::LOCATION)
+            .and_then(|location| location.to_str().ok())
+            .and_then(|location| response.url().join(location).ok())
+        else {
+            break;
+        };
+        let from = response.url().clone();
+        let target = redirect_target(location, &from, mirror.as_ref());
+        debug!("Following the redirect of '{}' to '{}'", from, target);
+        let request =
+            with_credentials(client.get(target.clone()).headers(headers.clone()), &target);
+        response = request.send().await.map_err(|e| {
+            if target.origin() != from.origin() && (e.is_connect() || e.is_timeout()) {
+                let reason = match std::error::Error::source(&e) {
+                    Some(source) => source.to_string(),
+                    None => e.to_string(),
+                };
+                Error::RedirectTargetUnreachable(
+                    target.host_str().unwrap_or_default().to_string(),
+                    from.host_str().unwrap_or_default().to_string(),
+                    reason,
+                )
+            } else {
+                e.into()
+            }
+        })?;
+    }
+    Ok(response)
+}
+
+/// Checks that the hosts of the given URLs are reachable, failing fast with a single network
+/// diagnostic otherwise.
+///
+/// Any HTTP response is considered a success, only connection errors (DNS, proxy, TLS,
+/// timeouts...) are reported. The check is skipped when mirrors are configured, as the artifacts
+/// can be downloaded from them when the original hosts are unreachable: [`check_artifacts`]
+/// checks that each of them is reachable from at least one source.
+pub async fn check_connectivity(urls: &[String]) -> Result<(), Error> {
+    if !MIRRORS.lock().unwrap().is_empty() {
+        debug!("Mirrors are configured, skipping the connectivity check of the original hosts");
+        return Ok(());
+    }
+    let client = build_proxy_async_client()?;
+    let mut checked_hosts = HashSet::new();
+    for url in urls {
+        let Ok(parsed_url) = Url::parse(url) else {
+            continue;
+        };
+        let Some(host) = parsed_url.host_str() else {
+            continue;
+        };
+        let origin = parsed_url.origin().ascii_serialization();
+        if !checked_hosts.insert(origin.clone()) {
+            continue;
+        }
+
+        debug!("Checking connectivity with '{}'", origin);
+        if let Err(e) = client.head(&origin).timeout(PREFLIGHT_TIMEOUT).send().await {
+            let reason = match std::error::Error::source(&e) {
+                Some(source) => source.to_string(),
+                None => e.to_string(),
+            };
+            return Err(Error::NetworkUnreachable(host.to_string(), reason));
+        }
+    }
+    Ok(())
+}
+
+/// Checks that every artifact about to be downloaded is published, so unavailable components
+/// are reported before starting any download.
+///
+/// Takes `(component name, URL)` pairs. Each artifact is checked on its original host and on the
+/// mirrors, any of them answering is enough. Missing artifacts (`404 Not Found` everywhere) are
+/// reported. Without mirrors, other failures are left to the download itself, with mirrors an
+/// artifact none of its sources answers for is reported as unreachable.
+pub async fn check_artifacts(artifacts: &[(String, String)]) -> Result<(), Error> {
+    let mirrors = MIRRORS.lock().unwrap().clone();
+    check_artifacts_on(artifacts, &mirrors).await
+}
+
+async fn check_artifacts_on(artifacts: &[(String, String)], mirrors: &[Url]) -> Result<(), Error> {
+    let client = build_proxy_async_client()?;
+    for (name, url) in artifacts {
+        let sources = mirrored_sources(url, mirrors);
+        let mut missing = false;
+        let mut unreachable = None;
+        for source in &sources {
+            debug!("Checking that '{}' is published", source);
+            match client.head(source).timeout(PREFLIGHT_TIMEOUT).send().await {
+                Ok(response) if response.status() == StatusCode::NOT_FOUND => missing = true,
+                Ok(_) => {
+                    missing = false;
+                    unreachable = None;
+                    break;
+                }
+                Err(e) => {
+                    debug!("Unable to check '{}': {}", source, e);
+                    unreachable = Some(e);
+                }
+            }
+        }
+        if missing {
+            return Err(Error::ArtifactNotPublished(name.clone(), url.clone()));
+        }
+        if let Some(e) = unreachable.filter(|_| !mirrors.is_empty()) {
+            let hosts: Vec<String> = sources
+                .iter()
+                .filter_map(|source| Url::parse(source).ok()?.host_str().map(String::from))
+                .collect();
+            let reason = match std::error::Error::source(&e) {
+                Some(source) => source.to_string(),
+                None => e.to_string(),
+            };
+            return Err(Error::NetworkUnreachable(hosts.join("', '"), reason));
+        }
+    }
+    Ok(())
+}
+
+/// Returns true if the artifact is not published, i.e. its URL returns `404 Not Found`.
+///
+/// Other failures are left to the download itself.
+pub async fn artifact_missing(url: &str) -> Result<bool, Error> {
+    let client = build_proxy_async_client()?;
+    debug!("Checking that '{}' is published", url);
+    match client.head(url).timeout(PREFLIGHT_TIMEOUT).send().await {
+        Ok(response) => Ok(response.status() == StatusCode::NOT_FOUND),
+        Err(e) => {
+            debug!("Unable to check '{}': {}", url, e);
+            Ok(false)
+        }
+    }
+}
+
+/// Returns the size of the content of a URL, if the server reports it.
+pub async fn content_length(url: &str) -> Option<u64> {
+    let client = build_proxy_async_client().ok()?;
+    let response = client
+        .head(url)
+        .timeout(PREFLIGHT_TIMEOUT)
+        .send()
+        .await
+        .ok()?
+        .error_for_status()
+        .ok()?;
+    // `Response::content_length` is always zero for `HEAD` requests, read the header instead
+    response
+        .headers()
+        .get(header::CONTENT_LENGTH)?
+        .to_str()
+        .ok()?
+        .parse()
+        .ok()
+}
+
+/// Queries the GitHub API and returns the JSON response.
+///
+/// Rate limited responses are retried once the limit resets if that happens within
+/// `MAX_RATE_LIMIT_WAIT` seconds, otherwise [`Error::GithubRateLimit`] is returned right away.
+pub fn github_query(url: &str, opts: &GithubQueryOpts) -> Result<serde_json::Value, Error> {
+    debug!("Querying GitHub API: '{}'", url);
+    let mut headers = HeaderMap::new();
+    headers.insert(header::USER_AGENT, "espup".parse().unwrap());
+    headers.insert(
+        header::ACCEPT,
+        "application/vnd.github+json".parse().unwrap(),
+    );
+
+    headers.insert("X-GitHub-Api-Version", "2022-11-28".parse().unwrap());
+    if let Some(token) = github_token() {
+        debug!("Auth header added");
+        headers.insert("Authorization", format!("Bearer {token}").parse().unwrap());
+    }
+    let client = build_proxy_blocking_client()?;
+    let mut attempt = 0;
+    loop {
+        let result = github_query_once(&client, url, &headers, opts.timeout);
+        let delay = match &result {
+            Ok(_) | Err(Error::GithubTokenInvalid) => return result,
+            Err(Error::GithubRateLimit(wait)) if *wait > MAX_RATE_LIMIT_WAIT => return result,
+            Err(Error::GithubRateLimit(wait)) => Duration::from_secs(*wait),
+            Err(_) => GITHUB_RETRY_DELAY,
+        };
+        if attempt >= opts.retries {
+            return result;
+        }
+        attempt += 1;
+        if let Err(err) = &result {
+            warn!(
+                "GitHub API query failed: {}. Retrying in {:.1}s ({}/{})",
+                err,
+                delay.as_secs_f32(),
+                attempt,
+                opts.retries
+            );
+        }
+        sleep(delay);
+    }
+}
+
+/// Sends a single GitHub API query.
+fn github_query_once(
+    client: &Client,
+    url: &str,
+    headers: &HeaderMap,
+    timeout: Duration,
+) -> Result<serde_json::Value, Error> {
+    let response = client
+        .get(url)
+        .headers(headers.clone())
+        .timeout(timeout)
+        .send()?;
+    if let Some(wait) = rate_limit_wait(response.status(), response.headers()) {
+        return Err(Error::GithubRateLimit(wait));
+    }
+    let res = response.text()?;
+    if res.contains("https://docs.github.com/rest/overview/resources-in-the-rest-api#rate-limiting")
+    {
+        return Err(Error::GithubRateLimit(MAX_RATE_LIMIT_WAIT));
+    }
+
+    if res.contains("Bad credentials") {
+        return Err(Error::GithubTokenInvalid);
+    }
+
+    serde_json::from_str(&res).map_err(|_| Error::SerializeJson)
+}
+
+/// Returns the number of seconds to wait before a rate limited response can be retried, or `None`
+/// if the response is not rate limited.
+///
+/// See https://docs.github.com/en/rest/using-the-rest-api/rate-limits-for-the-rest-api#exceeding-the-rate-limit
+fn rate_limit_wait(status: StatusCode, headers: &HeaderMap) -> Option<u64> {
+    if status != StatusCode::FORBIDDEN && status != StatusCode::TOO_MANY_REQUESTS {
+        return None;
+    }
+    let header_value = |name: &str| {
+        headers
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.trim().parse::<u64>().ok())
+    };
+    if let Some(retry_after) = header_value("retry-after") {
+        return Some(retry_after);
+    }
+    if header_value("x-ratelimit-remaining") == Some(0) {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        return Some(
+            header_value("x-ratelimit-reset")
+                .map(|reset| reset.saturating_sub(now))
+                .unwrap_or(MAX_RATE_LIMIT_WAIT),
+        );
+    }
+    // Secondary rate limits without headers should wait at least one minute
+    (status == StatusCode::TOO_MANY_REQUESTS).then_some(MAX_RATE_LIMIT_WAIT)
+}
+
+/// Downloads the content of a URL, retrying on failure.
+///
+/// `name` is only used for display purposes in the progress bar. Artifacts prefetched with
+/// `espup fetch` are read from disk instead.
+pub async fn fetch(url: &str, name: &str, progress: &Progress) -> Result<Bytes, Error> {
+    if let Some(path) = cached_artifact(url) {
+        info!("Using prefetched '{}'", path.display());
+        record(|metrics| metrics.cache_hits += 1);
+        return Ok(Bytes::from(tokio::fs::read(path).await?));
+    }
+    let sources = download_sources(url).await;
+    let bytes = fetch_from(&sources, name, progress, || async { Ok(Vec::new()) }).await?;
+    Ok(Bytes::from(bytes))
+}
+
+/// Downloads the content of a URL into a file, retrying on failure.
+///
+/// The content is streamed to disk instead of being held in memory, the file is truncated
+/// before each attempt.
+pub async fn fetch_to_file(
+    url: &str,
+    name: &str,
+    progress: &Progress,
+    destination: &Path,
+) -> Result<(), Error> {
+    if let Some(path) = cached_artifact(url) {
+        info!("Using prefetched '{}'", path.display());
+        record(|metrics| metrics.cache_hits += 1);
+        tokio::fs::copy(path, destination).await?;
+        return Ok(());
+    }
+    let sources = download_sources(url).await;
+    fetch_from(&sources, name, progress, || async {
+        Ok(tokio::fs::File::create(destination).await?)
+    })
+    .await?;
+    Ok(())
+}
+
+/// Returns the URL followed by its URLs on the mirrors.
+fn mirrored_sources(url: &str, mirrors: &[Url]) -> Vec<String> {
+    let mut sources = vec![url.to_string()];
+    if let Ok(parsed) = Url::parse(url) {
+        sources.extend(
+            mirrors
+                .iter()
+                .map(|mirror| mirror_url(&parsed, mirror).to_string()),
+        );
+    }
+    sources
+}
+
+/// Returns the sources of a URL, the mirrors ranked by throughput when there are some.
+async fn download_sources(url: &str) -> Vec<String> {
+    let mirrors = MIRRORS.lock().unwrap().clone();
+    let sources = mirrored_sources(url, &mirrors);
+    if sources.len() > 1 {
+        rank_sources(sources).await
+    } else {
+        sources
+    }
+}
+
+/// Downloads the content of the first source that succeeds into the writer returned by `open`,
+/// retrying on failure.
+///
+/// A new writer is opened for each attempt.
+async fn fetch_from<W, F, Fut>(
+    sources: &[String],
+    name: &str,
+    progress: &Progress,
+    open: F,
+) -> Result<W, Error>
+where
+    W: AsyncWrite + Unpin,
+    F: Fn() -> Fut,
+    Fut: Future<Output = Result<W, Error>>,
+{
+    let retry_strategy =
+        FixedInterval::from_millis(DOWNLOAD_RETRY_DELAY).take(DOWNLOAD_ATTEMPTS - 1);
+    Retry::spawn(retry_strategy, || async {
+        let mut last_error = None;
+        for source in sources {
+            let mut writer = open().await?;
+            match fetch_once(source, name, progress, &mut writer).await {
+                Ok(size) => {
+                    record(|metrics| metrics.bytes_downloaded += size);
+                    return Ok(writer);
+                }
+                Err(err) => {
+                    warn!("Download of '{}' failed: {}", source, err);
+                    record(|metrics| metrics.retries += 1);
+                    last_error = Some(err);
+                }
+            }
+        }
+        Err(last_error.expect("No download source"))
+    })
+    .await
+}
+
+/// Downloads the first bytes of a source, returning its throughput in bytes per second.
+///
+/// Only [`PROBE_SIZE`] bytes are read, even if the source ignores the range.
+async fn probe(url: &str) -> Result<f64, Error> {
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        header::RANGE,
+        format!("bytes=0-{}", PROBE_SIZE - 1).parse().unwrap(),
+    );
+    let started = Instant::now();
+    let size = tokio::time::timeout(PROBE_TIMEOUT, async {
+        let response = get_following_redirects(url, headers).await?;
+        let mut stream = response.error_for_status()?.bytes_stream();
+        let mut size = 0;
+        while let Some(chunk) = stream.next().await {
+            size += chunk?.len() as u64;
+            if size >= PROBE_SIZE {
+                break;
+            }
+        }
+        Ok::<_, Error>(size)
+    })
+    .await
+    .map_err(|_| Error::DownloadStalled(url.to_string(), PROBE_TIMEOUT.as_secs()))??;
+    Ok(size as f64 / started.elapsed().as_secs_f64().max(f64::EPSILON))
+}
+
+/// Orders the sources by throughput, the unreachable ones last.
+fn order_sources(sources: Vec<String>, throughputs: &[Option<f64>]) -> Vec<String> {
+    let mut ranked: Vec<(String, Option<f64>)> = sources
+        .into_iter()
+        .zip(throughputs.iter().copied())
+        .collect();
+    // The sort is stable, so equivalent sources keep their configured order
+    ranked.sort_by(|(_, a), (_, b)| b.partial_cmp(a).unwrap_or(std::cmp::Ordering::Equal));
+    ranked.into_iter().map(|(source, _)| source).collect()
+}
+
+/// Races short range requests to the sources of an artifact, returning them from the fastest to
+/// the slowest.
+async fn rank_sources(sources: Vec<String>) -> Vec<String> {
+    let mut probes = JoinSet::new();
+    for (index, source) in sources.iter().cloned().enumerate() {
+        probes.spawn(async move { (index, probe(&source).await) });
+    }
+    let mut throughputs = vec![None; sources.len()];
+    while let Some(Ok((index, result))) = probes.join_next().await {
+        match result {
+            Ok(throughput) => {
+                debug!(
+                    "'{}' downloads at {:.0} KB/s",
+                    sources[index],
+                    throughput / 1000.0
+                );
+                throughputs[index] = Some(throughput);
+            }
+            Err(err) => debug!("'{}' is unreachable: {}", sources[index], err),
+        }
+    }
+    let ranked = order_sources(sources, &throughputs);
+    debug!("Downloading from '{}'", ranked[0]);
+    ranked
+}
+
+/// Downloads the content of a URL into a writer, reporting its progress and returning its size.
+///
+/// Downloads receiving no data for [`STALL_TIMEOUT`] fail, so another source can be tried.
+async fn fetch_once<W: AsyncWrite + Unpin>(
+    url: &str,
+    name: &str,
+    progress: &Progress,
+    writer: &mut W,
+) -> Result<u64, Error> {
+    let resp = get_following_redirects(url, HeaderMap::new())
+        .await?
+        .error_for_status()?;
+    let len = resp.content_length();
+
+    let mut download = progress.start_download(name, len);
+
+    let mut stream = resp.bytes_stream();
+    let mut size = 0;
+    let mut result = Ok(());
+    loop {
+        let Ok(chunk_result) = tokio::time::timeout(STALL_TIMEOUT, stream.next()).await else {
+            result = Err(Error::DownloadStalled(
+                url.to_string(),
+                STALL_TIMEOUT.as_secs(),
+            ));
+            break;
+        };
+        let Some(chunk_result) = chunk_result else {
+            break;
+        };
+        match chunk_result {
+            Ok(chunk) => {
+                if let Err(e) = writer.write_all(&chunk).await {
+                    result = Err(e.into());
+                    break;
+                }
+                download.advance(chunk.len() as u64);
+                size += chunk.len() as u64;
+            }
+            Err(e) => {
+                result = Err(e.into());
+                break;
+            }
+        }
+    }
+    if result.is_ok() {
+        result = writer.flush().await.map_err(Error::from);
+    }
+    download.finish(result.is_ok());
+    result?;
+
+    Ok(size)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        error::Error,
+        toolchain::{
+            fetch::{
+                check_artifacts_on, check_connectivity, fetch, fetch_from, fetch_to_file,
+                find_route, get_following_redirects, mirror_url, order_sources, parse_proxy_route,
+                parse_proxy_server, parse_scutil_proxy, rank_sources, rate_limit_wait,
+                redirect_target, set_asset_host_override,
+            },
+            progress::Progress,
+        },
+    };
+    use reqwest::{header::HeaderMap, StatusCode, Url};
+    use std::{
+        fs::read,
+        time::{SystemTime, UNIX_EPOCH},
+    };
+    use tempfile::TempDir;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    /// Serves a single canned HTTP response per connection, returning the base URL.
+    async fn serve(status: &'static str, body: &'static str) -> String {
+        serve_with_headers(status, "", body).await
+    }
+
+    /// Serves a canned HTTP response with additional header lines, returning the base URL.
+    async fn serve_with_headers(
+        status: &'static str,
+        headers: &'static str,
+        body: &'static str,
+    ) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((mut socket, _)) = listener.accept().await {
+                let mut buf = [0; 1024];
+                let _ = socket.read(&mut buf).await;
+                let response = format!(
+                    "HTTP/1.1 {status}\r\n{headers}Content-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                    body.len()
+                );
+                let _ = socket.write_all(response.as_bytes()).await;
+            }
+        });
+        format!("http://{addr}")
+    }
+
+    #[tokio::test]
+    async fn test_fetch() {
+        let url = serve("200 OK", "espup").await;
+        let bytes = fetch(&format!("{url}/file.txt"), "file.txt", &Progress::hidden())
+            .await
+            .unwrap();
+        assert_eq!(bytes.as_ref(), b"espup");
+
+        let temp_dir = TempDir::new().unwrap();
+        let destination = temp_dir.path().join("file.txt");
+        fetch_to_file(
+            &format!("{url}/file.txt"),
+            "file.txt",
+            &Progress::hidden(),
+            &destination,
+        )
+        .await
+        .unwrap();
+        assert_eq!(read(&destination).unwrap(), b"espup");
+
+        let url = serve("404 Not Found", "").await;
+        assert!(fetch(
+            &format!("{url}/missing.txt"),
+            "missing.txt",
+            &Progress::hidden()
+        )
+        .await
+        .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_mirrors() {
+        let artifact = Url::parse(
+            "https://github.com/espressif/crosstool-NG/releases/download/esp-14.2.0_20240906/xtensa-esp-elf-14.2.0_20240906-x86_64-linux-gnu.tar.xz?raw=1",
+        )
+        .unwrap();
+        assert_eq!(
+            mirror_url(&artifact, &Url::parse("https://mirror.example.com/github/").unwrap()).as_str(),
+            "https://mirror.example.com/github/espressif/crosstool-NG/releases/download/esp-14.2.0_20240906/xtensa-esp-elf-14.2.0_20240906-x86_64-linux-gnu.tar.xz?raw=1"
+        );
+
+        let sources = vec!["github".to_string(), "slow".to_string(), "fast".to_string()];
+        assert_eq!(
+            order_sources(sources.clone(), &[None, Some(1000.0), Some(5000.0)]),
+            ["fast", "slow", "github"]
+        );
+        assert_eq!(
+            order_sources(sources, &[None, None, None]),
+            ["github", "slow", "fast"]
+        );
+
+        // Unreachable sources are tried last, and skipped when they fail
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let unreachable = format!("http://{}/file.txt", listener.local_addr().unwrap());
+        drop(listener);
+        let mirror = format!("{}/file.txt", serve("200 OK", "espup").await);
+        let sources = rank_sources(vec![unreachable.clone(), mirror.clone()]).await;
+        assert_eq!(sources, [mirror, unreachable.clone()]);
+        let bytes = fetch_from(
+            &[unreachable, sources[0].clone()],
+            "file.txt",
+            &Progress::hidden(),
+            || async { Ok(Vec::new()) },
+        )
+        .await
+        .unwrap();
+        assert_eq!(bytes, b"espup");
+    }
+
+    #[tokio::test]
+    async fn test_redirects() {
+        let github = Url::parse(
+            "https://github.com/esp-rs/rust-build/releases/download/v1.85.0.0/rust.tar.xz",
+        )
+        .unwrap();
+        let asset = Url::parse("https://objects.githubusercontent.com/github-production-release-asset-2e65be/123?X-Amz-Expires=300").unwrap();
+        let mirror = Url::parse("https://mirror.example.com/github/").unwrap();
+        assert_eq!(redirect_target(asset.clone(), &github, None), asset);
+        assert_eq!(
+            redirect_target(asset.clone(), &github, Some(&mirror)).as_str(),
+            "https://mirror.example.com/github/github-production-release-asset-2e65be/123?X-Amz-Expires=300"
+        );
+        // Redirects within the same host are followed as is
+        let latest = Url::parse("https://github.com/esp-rs/rust-build/releases/latest").unwrap();
+        assert_eq!(
+            redirect_target(latest.clone(), &github, Some(&mirror)),
+            latest
+        );
+
+        // Nothing listens on port 1
+        let url = serve_with_headers(
+            "302 Found",
+            "Location: http://127.0.0.1:1/asset?signature=1\r\n",
+            "",
+        )
+        .await;
+        match get_following_redirects(&format!("{url}/file.txt"), HeaderMap::new()).await {
+            Err(Error::RedirectTargetUnreachable(target, from, _)) => {
+                assert_eq!(target, "127.0.0.1");
+                assert_eq!(from, "127.0.0.1");
+            }
+            result => panic!("unexpected result: {result:?}"),
+        }
+
+        let mirror = serve("200 OK", "espup").await;
+        set_asset_host_override(Some(Url::parse(&mirror).unwrap()));
+        let response = get_following_redirects(&format!("{url}/file.txt"), HeaderMap::new()).await;
+        set_asset_host_override(None);
+        let response = response.unwrap();
+        assert_eq!(response.url().path(), "/asset");
+        assert_eq!(response.text().await.unwrap(), "espup");
+    }
+
+    #[tokio::test]
+    async fn test_check_connectivity() {
+        let url = serve("404 Not Found", "").await;
+        assert!(
+            check_connectivity(&[format!("{url}/releases/download/file.tar.xz")])
+                .await
+                .is_ok()
+        );
+
+        // Nothing listens on port 1
+        assert!(
+            check_connectivity(&["http://127.0.0.1:1/file.tar.xz".to_string()])
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_check_artifacts_on_mirrors() {
+        // Nothing listens on port 1
+        let origin = "http://127.0.0.1:1/releases/download/file.tar.xz".to_string();
+        let artifacts = [("llvm".to_string(), origin)];
+        let mirror = Url::parse(&serve("200 OK", "").await).unwrap();
+        check_artifacts_on(&artifacts, &[mirror]).await.unwrap();
+
+        // Missing from every source that answers
+        let mirror = Url::parse(&serve("404 Not Found", "").await).unwrap();
+        assert!(matches!(
+            check_artifacts_on(&artifacts, &[mirror]).await,
+            Err(Error::ArtifactNotPublished(..))
+        ));
+
+        // Unreachable everywhere, which is only reported with mirrors
+        let mirror = Url::parse("http://127.0.0.1:1/mirror/").unwrap();
+        assert!(matches!(
+            check_artifacts_on(&artifacts, &[mirror]).await,
+            Err(Error::NetworkUnreachable(..))
+        ));
+        check_artifacts_on(&artifacts, &[]).await.unwrap();
+    }
+
+    #[test]
+    fn test_rate_limit_wait() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(rate_limit_wait(StatusCode::OK, &headers), None);
+        assert_eq!(rate_limit_wait(StatusCode::FORBIDDEN, &headers), None);
+        assert_eq!(
+            rate_limit_wait(StatusCode::TOO_MANY_REQUESTS, &headers),
+            Some(60)
+        );
+
+        let reset = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+            + 600;
+        headers.insert("x-ratelimit-remaining", "0".parse().unwrap());
+        headers.insert("x-ratelimit-reset", reset.to_string().parse().unwrap());
+        let wait = rate_limit_wait(StatusCode::FORBIDDEN, &headers).unwrap();
+        assert!((599..=600).contains(&wait));
+
+        headers.insert("retry-after", "30".parse().unwrap());
+        assert_eq!(rate_limit_wait(StatusCode::FORBIDDEN, &headers), Some(30));
+    }
+
+    #[test]
+    fn test_parse_proxy_server() {
+        assert_eq!(
+            parse_proxy_server("proxy.corp:8080").as_deref(),
+            Some("http://proxy.corp:8080")
+        );
+        assert_eq!(
+            parse_proxy_server("http=proxy.corp:80;https=secure.corp:443").as_deref(),
+            Some("http://secure.corp:443")
+        );
+        assert_eq!(
+            parse_proxy_server("ftp=ftp.corp:21;http=proxy.corp:80").as_deref(),
+            Some("http://proxy.corp:80")
+        );
+        assert_eq!(parse_proxy_server("ftp=ftp.corp:21"), None);
+        assert_eq!(parse_proxy_server(""), None);
+    }
+
+    #[test]
+    fn test_parse_scutil_proxy() {
+        let output = "<dictionary> {
+  ExceptionsList : <array> {
+    0 : *.local
+  }
+  HTTPEnable : 1
+  HTTPPort : 3128
+  HTTPProxy : proxy.corp
+  HTTPSEnable : 0
+}";
+        assert_eq!(
+            parse_scutil_proxy(output).as_deref(),
+            Some("http://proxy.corp:3128")
+        );
+        assert_eq!(
+            parse_scutil_proxy("<dictionary> {\n  HTTPEnable : 0\n}"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_proxy_routes() {
+        let routes = [
+            parse_proxy_route("github.com=http://proxy.corp:3128").unwrap(),
+            parse_proxy_route("api.github.com = direct").unwrap(),
+            parse_proxy_route("dl.espressif.com=socks5://127.0.0.1:1080").unwrap(),
+        ];
+        assert_eq!(routes[1].proxy, None);
+        let proxy = |host| find_route(&routes, host).and_then(|route| route.proxy.as_ref());
+        assert_eq!(
+            proxy("objects.github.com").map(|url| url.as_str()),
+            Some("http://proxy.corp:3128/")
+        );
+        assert_eq!(proxy("API.github.com"), None);
+        assert_eq!(
+            proxy("dl.espressif.com").map(|url| url.scheme()),
+            Some("socks5")
+        );
+        assert!(find_route(&routes, "notgithub.com").is_none());
+        assert!(find_route(&routes, "api.github.com").is_some());
+
+        assert!(parse_proxy_route("github.com").is_err());
+        assert!(parse_proxy_route("=http://proxy.corp:3128").is_err());
+        assert!(parse_proxy_route("https://github.com=http://proxy.corp:3128").is_err());
+        assert!(parse_proxy_route("github.com=not a url").is_err());
+    }
+}
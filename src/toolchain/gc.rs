@@ -0,0 +1,202 @@
+//! Garbage collection of artifacts no longer referenced by any installation manifest.
+
+#[cfg(windows)]
+use crate::toolchain::versions::{legacy_markers, VERSIONS_FILE};
+use crate::{
+    error::Error,
+    host_triple::HostTriple,
+    manifest::Manifest,
+    targets::Target,
+    toolchain::{
+        disk_usage,
+        gcc::{Gcc, RISCV_GCC, XTENSA_GCC},
+        llvm::{Llvm, CLANG_NAME},
+        tmp::{configured_tmp_dir, stale_tmp_dirs, LEGACY_TMP_PREFIX, TMP_PREFIX},
+    },
+};
+use log::{debug, info, warn};
+use miette::Result;
+use std::{
+    collections::HashSet,
+    fs::{read_dir, remove_dir_all, remove_file},
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::SystemTime,
+};
+
+/// Returns the paths inside espup-managed directories that are not referenced by any of the
+/// manifests.
+pub fn find_unreferenced(
+    rustup_home: &Path,
+    manifests: &[Manifest],
+) -> Result<Vec<PathBuf>, Error> {
+    let mut unreferenced = Vec::new();
+
+    for manifest in manifests {
+        let toolchain_dir = rustup_home.join("toolchains").join(&manifest.name);
+        if !toolchain_dir.exists() {
+            continue;
+        }
+        let Ok(host_triple) = HostTriple::from_str(&manifest.host_triple) else {
+            warn!(
+                "Skipping '{}' toolchain, unknown host triple '{}'",
+                manifest.name, manifest.host_triple
+            );
+            continue;
+        };
+        let has_xtensa = manifest.targets.iter().any(|t| t.is_xtensa());
+
+        // LLVM
+        let llvm_dir = toolchain_dir.join(CLANG_NAME);
+        if llvm_dir.exists() {
+            if has_xtensa {
+                match Llvm::new(
+                    &toolchain_dir,
+                    &host_triple,
+                    manifest.extended_llvm,
+                    &manifest.toolchain_version,
+                ) {
+                    Ok(llvm) => unreferenced.extend(unreferenced_versions(&llvm_dir, &llvm.path)?),
+                    Err(_) => debug!(
+                        "Unable to resolve the LLVM version of '{}' toolchain, keeping it",
+                        manifest.name
+                    ),
+                }
+            } else {
+                unreferenced.push(llvm_dir);
+            }
+        }
+
+        // GCC
+        for arch in [XTENSA_GCC, RISCV_GCC] {
+            let gcc_dir = toolchain_dir.join(arch);
+            if !gcc_dir.exists() {
+                continue;
+            }
+            let required = !manifest.std
+                && if arch == XTENSA_GCC {
+                    has_xtensa
+                } else {
+                    manifest.esp_riscv_gcc && manifest.targets.iter().any(|t| t != &Target::ESP32)
+                };
+            if required {
+                let gcc = Gcc::new(arch, &host_triple, &toolchain_dir);
+                unreferenced.extend(unreferenced_versions(&gcc_dir, &gcc.path)?);
+            } else {
+                unreferenced.push(gcc_dir);
+            }
+        }
+    }
+
+    let now = SystemTime::now();
+    unreferenced.extend(stale_tmp_dirs(
+        &rustup_home.join("tmp"),
+        LEGACY_TMP_PREFIX,
+        now,
+    )?);
+    if let Some(tmp_dir) = configured_tmp_dir() {
+        unreferenced.extend(stale_tmp_dirs(&tmp_dir, TMP_PREFIX, now)?);
+    }
+    Ok(unreferenced)
+}
+
+/// Returns the versioned entries of a component directory that don't belong to the installed
+/// version.
+#[cfg(unix)]
+fn unreferenced_versions(
+    component_dir: &Path,
+    installed_path: &Path,
+) -> Result<Vec<PathBuf>, Error> {
+    let mut unreferenced = Vec::new();
+    for entry in read_dir(component_dir)? {
+        let path = entry?.path();
+        if !installed_path.starts_with(&path) {
+            unreferenced.push(path);
+        }
+    }
+    Ok(unreferenced)
+}
+
+/// Returns the legacy version marker files of a component directory once they have been migrated
+/// to the versions file.
+#[cfg(windows)]
+fn unreferenced_versions(
+    component_dir: &Path,
+    _installed_path: &Path,
+) -> Result<Vec<PathBuf>, Error> {
+    // On Windows, components are extracted without a version directory and the installed
+    // version is tracked in the versions file
+    if !component_dir.join(VERSIONS_FILE).exists() {
+        return Ok(Vec::new());
+    }
+    Ok(legacy_markers(component_dir))
+}
+
+/// Removes the given paths, returning the amount of bytes freed.
+pub fn remove_unreferenced(paths: &[PathBuf]) -> Result<u64, Error> {
+    let mut freed = 0;
+    let mut removed = HashSet::new();
+    for path in paths {
+        if !removed.insert(path) || path.symlink_metadata().is_err() {
+            continue;
+        }
+        let size = disk_usage(path);
+        info!("Removing '{}'", path.display());
+        if path.is_dir() {
+            remove_dir_all(path).map_err(|_| Error::RemoveDirectory(path.display().to_string()))?;
+        } else {
+            remove_file(path)?;
+        }
+        freed += size;
+    }
+    Ok(freed)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        manifest::{manifest_fixture, Manifest},
+        targets::Target,
+        toolchain::{disk_usage, gc::find_unreferenced},
+    };
+    use std::fs::{create_dir_all, write};
+    use tempfile::TempDir;
+
+    #[test]
+    #[cfg(unix)]
+    fn test_find_unreferenced() {
+        let rustup_home = TempDir::new().unwrap();
+        let toolchain_dir = rustup_home.path().join("toolchains").join("esp");
+        let current_llvm = toolchain_dir
+            .join("xtensa-esp32-elf-clang")
+            .join("esp-18.1.2_20240912");
+        let old_llvm = toolchain_dir
+            .join("xtensa-esp32-elf-clang")
+            .join("esp-17.0.1_20240419");
+        let current_gcc = toolchain_dir
+            .join("xtensa-esp-elf")
+            .join("esp-14.2.0_20240906");
+        let old_gcc = toolchain_dir
+            .join("xtensa-esp-elf")
+            .join("esp-13.2.0_20230928");
+        let riscv_gcc = toolchain_dir.join("riscv32-esp-elf");
+        for dir in [&current_llvm, &old_llvm, &current_gcc, &old_gcc, &riscv_gcc] {
+            create_dir_all(dir).unwrap();
+        }
+        write(old_gcc.join("gcc"), "0123456789").unwrap();
+
+        let manifest = Manifest {
+            targets: [Target::ESP32, Target::ESP32C3].into_iter().collect(),
+            ..manifest_fixture()
+        };
+        let mut unreferenced = find_unreferenced(rustup_home.path(), &[manifest]).unwrap();
+        unreferenced.sort();
+        assert_eq!(unreferenced, vec![riscv_gcc, old_gcc.clone(), old_llvm]);
+        assert_eq!(disk_usage(&old_gcc), 10);
+
+        // Toolchains without a manifest are never touched
+        assert!(find_unreferenced(rustup_home.path(), &[])
+            .unwrap()
+            .is_empty());
+    }
+}
@@ -1,22 +1,33 @@
 //! GCC Toolchain source and installation tools.
 
+#[cfg(unix)]
+use crate::toolchain::integrity::is_complete;
 #[cfg(windows)]
-use crate::env::{get_windows_path_var, set_env_variable};
+use crate::{env::remove_windows_path_entries, toolchain::versions::Versions};
 use crate::{
+    env::Export,
     error::Error,
     host_triple::HostTriple,
-    toolchain::{download_file, Installable},
+    toolchain::{
+        disk_usage, download_file,
+        espidf::{find_gcc, get_idf_tools_path},
+        overrides::is_overridden,
+        progress::Progress,
+        remove::remove_dir_all,
+        verify::check_executable,
+        Component, ComponentState,
+    },
 };
 use async_trait::async_trait;
 use log::{debug, info, warn};
 use miette::Result;
+#[cfg(unix)]
+use std::fs::read_dir;
 use std::path::{Path, PathBuf};
-#[cfg(windows)]
-use std::{env, fs::File};
-use tokio::fs::remove_dir_all;
 
-const DEFAULT_GCC_REPOSITORY: &str = "https://github.com/espressif/crosstool-NG/releases/download";
-const DEFAULT_GCC_RELEASE: &str = "14.2.0_20240906";
+pub(crate) const DEFAULT_GCC_REPOSITORY: &str =
+    "https://github.com/espressif/crosstool-NG/releases/download";
+pub(crate) const DEFAULT_GCC_RELEASE: &str = "14.2.0_20240906";
 pub const RISCV_GCC: &str = "riscv32-esp-elf";
 pub const XTENSA_GCC: &str = "xtensa-esp-elf";
 
@@ -28,12 +39,16 @@ pub struct Gcc {
     pub arch: String,
     /// GCC Toolchain path.
     pub path: PathBuf,
+    /// Whether the GCC Toolchain was installed by ESP-IDF and is only reused.
+    pub from_espidf: bool,
+    /// Path of the toolchain the GCC belongs to.
+    pub toolchain_path: PathBuf,
 }
 
 impl Gcc {
     /// Gets the binary path.
     pub fn get_bin_path(&self) -> String {
-        let bin_path = format!("{}/{}/bin", &self.path.to_str().unwrap(), &self.arch);
+        let bin_path = format!("{}/{}/bin", self.path.display(), &self.arch);
         match std::cfg!(windows) {
             true => bin_path.replace('/', "\\"),
             false => bin_path,
@@ -53,81 +68,180 @@ impl Gcc {
             host_triple: host_triple.clone(),
             arch: arch.to_string(),
             path,
+            from_espidf: false,
+            toolchain_path: toolchain_path.to_path_buf(),
+        }
+    }
+
+    /// Gets the URL of the GCC Toolchain artifact for the host.
+    fn dist_url(&self) -> Result<String, Error> {
+        let gcc_file = format!(
+            "{}-{}-{}.{}",
+            self.arch,
+            DEFAULT_GCC_RELEASE,
+            get_arch(&self.host_triple)?,
+            get_artifact_extension(&self.host_triple)
+        );
+        Ok(format!(
+            "{}/esp-{}/{}",
+            DEFAULT_GCC_REPOSITORY, DEFAULT_GCC_RELEASE, gcc_file
+        ))
+    }
+
+    /// Create a new instance reusing the toolchain installed by ESP-IDF when `from_espidf` is set
+    /// and one is found.
+    pub fn resolve(
+        arch: &str,
+        host_triple: &HostTriple,
+        toolchain_path: &Path,
+        from_espidf: bool,
+    ) -> Self {
+        let gcc = Self::new(arch, host_triple, toolchain_path);
+        if !from_espidf {
+            return gcc;
+        }
+        match find_gcc(arch) {
+            Some(path) => Self {
+                path,
+                from_espidf: true,
+                ..gcc
+            },
+            None => {
+                warn!(
+                    "No GCC ({}) installed by ESP-IDF found in '{}', it will be downloaded",
+                    arch,
+                    get_idf_tools_path().display()
+                );
+                gcc
+            }
         }
     }
 }
 
 #[async_trait]
-impl Installable for Gcc {
-    async fn install(&self) -> Result<Vec<String>, Error> {
+impl Component for Gcc {
+    async fn install(&self, progress: &Progress) -> Result<Vec<Export>, Error> {
         let extension = get_artifact_extension(&self.host_triple);
         info!("Installing GCC ({})", self.arch);
         debug!("GCC path: {}", self.path.display());
 
-        #[cfg(unix)]
-        let is_installed = self.path.exists();
         #[cfg(windows)]
-        let is_installed = self
-            .path
-            .join(&self.arch)
-            .join(DEFAULT_GCC_RELEASE)
-            .exists();
+        if !self.from_espidf {
+            let gcc_dir = self.path.join(&self.arch);
+            Versions::repair(&gcc_dir, gcc_dir.join("bin").exists())?;
+        }
 
-        if is_installed {
+        if self.from_espidf {
+            info!(
+                "Reusing GCC ({}) installed by ESP-IDF in: '{}'",
+                self.arch,
+                &self.path.display()
+            );
+        } else if self.state() == ComponentState::Installed && !is_overridden(&[&self.id()]) {
             warn!(
                 "Previous installation of GCC exists in: '{}'. Reusing this installation",
                 &self.path.display()
             );
         } else {
-            let gcc_file = format!(
-                "{}-{}-{}.{}",
-                self.arch,
-                DEFAULT_GCC_RELEASE,
-                get_arch(&self.host_triple).unwrap(),
-                extension
-            );
-            let gcc_dist_url = format!(
-                "{}/esp-{}/{}",
-                DEFAULT_GCC_REPOSITORY, DEFAULT_GCC_RELEASE, gcc_file
-            );
+            // Start from scratch, e.g. when a local artifact replaces the installation
+            #[cfg(unix)]
+            if self.path.exists() {
+                remove_dir_all(&self.path).await?;
+            }
             download_file(
-                gcc_dist_url,
+                self.dist_url()?,
                 &format!("{}.{}", &self.arch, extension),
                 &self.path.display().to_string(),
                 true,
                 false,
+                progress,
             )
             .await?;
         }
-        let mut exports: Vec<String> = Vec::new();
-
         #[cfg(windows)]
-        if cfg!(windows) {
-            File::create(self.path.join(&self.arch).join(DEFAULT_GCC_RELEASE))?;
-
-            exports.push(format!(
-                "$Env:PATH = \"{};\" + $Env:PATH",
-                &self.get_bin_path()
-            ));
-            if self.arch == RISCV_GCC {
-                env::set_var("RISCV_GCC", self.get_bin_path());
-            } else {
-                env::set_var("XTENSA_GCC", self.get_bin_path());
-            }
+        if !self.from_espidf {
+            Versions::record(&self.path.join(&self.arch), DEFAULT_GCC_RELEASE)?;
         }
-        #[cfg(unix)]
-        exports.push(format!("export PATH=\"{}:$PATH\"", &self.get_bin_path()));
+        let exports = vec![Export::prepend_path(&self.id(), &self.get_bin_path())];
+
+        let gcc_binary = format!("{}-gcc{}", self.arch, std::env::consts::EXE_SUFFIX);
+        check_executable(
+            &self.name(),
+            &PathBuf::from(self.get_bin_path()).join(gcc_binary),
+        )?;
 
         Ok(exports)
     }
 
+    async fn uninstall(&self) -> Result<(), Error> {
+        uninstall_gcc_toolchain(&self.toolchain_path, &self.arch).await
+    }
+
     fn name(&self) -> String {
         format!("GCC ({})", self.arch)
     }
+
+    fn id(&self) -> String {
+        if self.arch == RISCV_GCC {
+            "riscv-gcc".to_string()
+        } else {
+            "xtensa-gcc".to_string()
+        }
+    }
+
+    fn path(&self) -> PathBuf {
+        self.path.join(&self.arch)
+    }
+
+    fn installed_version(&self) -> Option<String> {
+        if self.from_espidf {
+            // ESP-IDF extracts every release into `<arch>/esp-<release>` directories
+            let name = self.path.file_name()?.to_string_lossy().to_string();
+            return name.strip_prefix("esp-").map(String::from);
+        }
+        // Unix installations are extracted into `<arch>/esp-<release>` directories
+        #[cfg(unix)]
+        {
+            if is_complete(&self.path) {
+                return Some(DEFAULT_GCC_RELEASE.to_string());
+            }
+            read_dir(self.toolchain_path.join(&self.arch))
+                .ok()?
+                .filter_map(|entry| entry.ok())
+                .filter(|entry| is_complete(&entry.path()))
+                .filter_map(|entry| {
+                    let name = entry.file_name().to_string_lossy().to_string();
+                    let version = name.strip_prefix("esp-")?;
+                    version
+                        .starts_with(|c: char| c.is_ascii_digit())
+                        .then(|| version.to_string())
+                })
+                .max()
+        }
+        // Windows installations are extracted into the toolchain directory, the version is
+        // tracked in the `<arch>/versions.json` file
+        #[cfg(windows)]
+        Versions::load(&self.toolchain_path.join(&self.arch)).latest()
+    }
+
+    fn latest_version(&self) -> String {
+        DEFAULT_GCC_RELEASE.to_string()
+    }
+
+    fn artifact_urls(&self) -> Vec<String> {
+        if self.from_espidf {
+            return Vec::new();
+        }
+        self.dist_url().into_iter().collect()
+    }
+
+    fn size_on_disk(&self) -> u64 {
+        disk_usage(&self.toolchain_path.join(&self.arch))
+    }
 }
 
 /// Gets the name of the GCC arch based on the host triple.
-fn get_arch(host_triple: &HostTriple) -> Result<&str> {
+fn get_arch(host_triple: &HostTriple) -> Result<&str, Error> {
     match host_triple {
         HostTriple::X86_64AppleDarwin => Ok("x86_64-apple-darwin"),
         HostTriple::Aarch64AppleDarwin => Ok("aarch64-apple-darwin"),
@@ -136,6 +250,9 @@ fn get_arch(host_triple: &HostTriple) -> Result<&str> {
         HostTriple::X86_64PcWindowsMsvc | HostTriple::X86_64PcWindowsGnu => {
             Ok("x86_64-w64-mingw32")
         }
+        HostTriple::Riscv64gcUnknownLinuxGnu | HostTriple::Powerpc64leUnknownLinuxGnu => Err(
+            Error::UnsupportedArtifact("GCC".to_string(), host_triple.to_string()),
+        ),
     }
 }
 
@@ -147,35 +264,84 @@ fn get_artifact_extension(host_triple: &HostTriple) -> &str {
     }
 }
 
-/// Checks if the toolchain is pressent, if present uninstalls it.
+/// Checks if the toolchains are pressent, if present uninstalls them.
 pub async fn uninstall_gcc_toolchains(toolchain_path: &Path) -> Result<(), Error> {
     info!("Uninstalling GCC");
 
     let gcc_toolchains = vec![XTENSA_GCC, RISCV_GCC];
 
     for toolchain in gcc_toolchains {
-        let gcc_path = toolchain_path.join(toolchain);
-        if gcc_path.exists() {
-            #[cfg(windows)]
-            if cfg!(windows) {
-                let mut updated_path = get_windows_path_var()?;
-                let gcc_version_path = format!(
-                    "{}\\esp-{}\\{}\\bin",
-                    gcc_path.display(),
-                    DEFAULT_GCC_RELEASE,
-                    toolchain
-                );
-                updated_path = updated_path.replace(&format!("{gcc_version_path};"), "");
-                let bin_path = format!("{}\\bin", gcc_path.display());
-                updated_path = updated_path.replace(&format!("{bin_path};"), "");
+        uninstall_gcc_toolchain(toolchain_path, toolchain).await?;
+    }
 
-                set_env_variable("PATH", &updated_path)?;
-            }
-            remove_dir_all(&gcc_path)
-                .await
-                .map_err(|_| Error::RemoveDirectory(gcc_path.display().to_string()))?;
+    Ok(())
+}
+
+/// Checks if the toolchain of the given arch is pressent, if present uninstalls it.
+pub async fn uninstall_gcc_toolchain(toolchain_path: &Path, arch: &str) -> Result<(), Error> {
+    let gcc_path = toolchain_path.join(arch);
+    if gcc_path.exists() {
+        #[cfg(windows)]
+        if cfg!(windows) {
+            remove_windows_path_entries(&[gcc_path.display().to_string()])?;
         }
+        remove_dir_all(&gcc_path).await?;
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    #[cfg(unix)]
+    use crate::toolchain::integrity::IN_PROGRESS_MARKER;
+    use crate::{
+        host_triple::HostTriple,
+        toolchain::{
+            gcc::{Gcc, DEFAULT_GCC_RELEASE, XTENSA_GCC},
+            Component, ComponentState,
+        },
+    };
+    use std::fs::{create_dir_all, remove_file, write};
+    use tempfile::TempDir;
+
+    #[test]
+    #[cfg(unix)]
+    fn test_gcc_state() {
+        let toolchain_dir = TempDir::new().unwrap();
+        let gcc = Gcc::new(
+            XTENSA_GCC,
+            &HostTriple::X86_64UnknownLinuxGnu,
+            toolchain_dir.path(),
+        );
+        assert_eq!(gcc.state(), ComponentState::NotInstalled);
+        assert_eq!(gcc.size_on_disk(), 0);
+
+        let old_gcc = toolchain_dir
+            .path()
+            .join(XTENSA_GCC)
+            .join("esp-13.2.0_20230928");
+        create_dir_all(&old_gcc).unwrap();
+        write(old_gcc.join("gcc"), "gcc").unwrap();
+        assert_eq!(
+            gcc.state(),
+            ComponentState::Outdated("13.2.0_20230928".to_string())
+        );
+        assert_eq!(gcc.size_on_disk(), 3);
+
+        // Interrupted extractions don't count as installed
+        create_dir_all(&gcc.path).unwrap();
+        write(gcc.path.join("gcc"), "gcc").unwrap();
+        write(gcc.path.join(IN_PROGRESS_MARKER), "").unwrap();
+        assert_eq!(
+            gcc.state(),
+            ComponentState::Outdated("13.2.0_20230928".to_string())
+        );
+        remove_file(gcc.path.join(IN_PROGRESS_MARKER)).unwrap();
+        assert_eq!(gcc.state(), ComponentState::Installed);
+        assert_eq!(
+            gcc.installed_version().as_deref(),
+            Some(DEFAULT_GCC_RELEASE)
+        );
+    }
+}
@@ -0,0 +1,251 @@
+//! SHA-256 hashes of the installed files, recorded with `install --record-hashes` and checked by
+//! `espup verify` to detect tampered or accidentally modified toolchains.
+//!
+//! The hashes are stored next to the manifest, in the format of `sha256sum`, so they can also be
+//! checked with `sha256sum -c` from the toolchain directory.
+
+use crate::{
+    error::Error,
+    manifest::Manifest,
+    sandbox::write_checked,
+    toolchain::{
+        gcc::{RISCV_GCC, XTENSA_GCC},
+        llvm::CLANG_NAME,
+    },
+};
+use log::debug;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs::{read_dir, read_to_string, symlink_metadata, File},
+    io::{self, Read},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+    thread::{available_parallelism, scope},
+};
+
+/// Hashes of files, by path relative to the toolchain directory with `/` separators.
+pub type FileHashes = BTreeMap<String, String>;
+
+/// Returns the path of the hashes of the given toolchain.
+pub fn hashes_path(name: &str) -> PathBuf {
+    Manifest::path(name).with_extension("sha256")
+}
+
+/// Returns the files of `dir`, relative to `base`, following the symbolic links to files.
+fn list_files(dir: &Path, base: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+    for entry in read_dir(dir)? {
+        let path = entry?.path();
+        let metadata = symlink_metadata(&path)?;
+        if metadata.is_dir() {
+            list_files(&path, base, files)?;
+        } else if metadata.is_file() || path.is_file() {
+            files.push(path.strip_prefix(base).unwrap().to_path_buf());
+        }
+    }
+    Ok(())
+}
+
+/// Returns the SHA-256 hash of a file, in hexadecimal.
+fn hash_file(path: &Path) -> io::Result<String> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0; 64 * 1024];
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect())
+}
+
+/// Hashes the files of `dir` in parallel.
+pub fn hash_tree(dir: &Path) -> io::Result<FileHashes> {
+    let mut files = Vec::new();
+    list_files(dir, dir, &mut files)?;
+    let next = AtomicUsize::new(0);
+    let hashes = Mutex::new(Ok(FileHashes::new()));
+    let workers = available_parallelism()
+        .map_or(1, |n| n.get())
+        .min(files.len());
+    scope(|scope| {
+        for _ in 0..workers {
+            scope.spawn(|| {
+                while let Some(file) = files.get(next.fetch_add(1, Ordering::Relaxed)) {
+                    let hash = hash_file(&dir.join(file));
+                    let mut result = hashes.lock().unwrap();
+                    match (hash, result.as_mut()) {
+                        (Ok(hash), Ok(hashes)) => {
+                            let path = file.to_string_lossy().replace('\\', "/");
+                            hashes.insert(path, hash);
+                        }
+                        // The first failure stops every worker
+                        (Err(e), Ok(_)) => {
+                            *result = Err(e);
+                            return;
+                        }
+                        (_, Err(_)) => return,
+                    }
+                }
+            });
+        }
+    });
+    hashes.into_inner().unwrap()
+}
+
+/// Renders hashes in the format of `sha256sum`.
+fn render(hashes: &FileHashes) -> String {
+    hashes
+        .iter()
+        .map(|(path, hash)| format!("{hash}  {path}\n"))
+        .collect()
+}
+
+/// Parses hashes in the format of `sha256sum`, ignoring malformed lines.
+fn parse(content: &str) -> FileHashes {
+    content
+        .lines()
+        .filter_map(|line| line.split_once("  "))
+        .map(|(hash, path)| (path.to_string(), hash.to_string()))
+        .collect()
+}
+
+/// Records the hashes of the files of a toolchain, returning the number of hashed files.
+pub fn record_hashes(name: &str, toolchain_dir: &Path) -> Result<usize, Error> {
+    let hashes = hash_tree(toolchain_dir)?;
+    let path = hashes_path(name);
+    debug!(
+        "Writing the hashes of {} files: '{}'",
+        hashes.len(),
+        path.display()
+    );
+    write_checked(&path, render(&hashes))?;
+    Ok(hashes.len())
+}
+
+/// Returns the component a file belongs to, from its path relative to the toolchain directory.
+fn component_of(path: &str) -> &'static str {
+    match path.split('/').next() {
+        Some(CLANG_NAME) => "llvm",
+        Some(XTENSA_GCC) => "xtensa-gcc",
+        Some(RISCV_GCC) => "riscv-gcc",
+        _ => "xtensa-rust",
+    }
+}
+
+/// Differences between the recorded and the installed files of a component.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct ComponentChanges {
+    /// Files whose content changed.
+    pub modified: Vec<String>,
+    /// Recorded files that no longer exist.
+    pub missing: Vec<String>,
+    /// Files that weren't recorded.
+    pub extra: Vec<String>,
+}
+
+impl ComponentChanges {
+    /// Returns the number of changed files.
+    pub fn len(&self) -> usize {
+        self.modified.len() + self.missing.len() + self.extra.len()
+    }
+
+    /// Returns whether no file changed.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Compares the recorded hashes with the current ones, returning the changes of each component
+/// with changed files.
+pub fn compare(recorded: &FileHashes, current: &FileHashes) -> BTreeMap<String, ComponentChanges> {
+    let mut changes: BTreeMap<String, ComponentChanges> = BTreeMap::new();
+    let paths: BTreeSet<&String> = recorded.keys().chain(current.keys()).collect();
+    for path in paths {
+        let component = changes.entry(component_of(path).to_string()).or_default();
+        match (recorded.get(path), current.get(path)) {
+            (Some(recorded), Some(current)) if recorded != current => {
+                component.modified.push(path.clone())
+            }
+            (Some(_), None) => component.missing.push(path.clone()),
+            (None, Some(_)) => component.extra.push(path.clone()),
+            _ => {}
+        }
+    }
+    changes.retain(|_, changes| !changes.is_empty());
+    changes
+}
+
+/// Checks the files of a toolchain against its recorded hashes, returning the number of checked
+/// files and the changes of each component with changed files.
+pub async fn verify_hashes(
+    name: &str,
+) -> Result<(usize, BTreeMap<String, ComponentChanges>), Error> {
+    let manifest = Manifest::load(name)?.ok_or_else(|| Error::MissingManifest(name.to_string()))?;
+    let path = hashes_path(name);
+    if !path.exists() {
+        return Err(Error::MissingHashes(name.to_string()));
+    }
+    let recorded = parse(&read_to_string(&path)?);
+    let toolchain_dir = manifest.toolchain_dir();
+    let current = tokio::task::spawn_blocking(move || hash_tree(&toolchain_dir))
+        .await
+        .unwrap()?;
+    Ok((recorded.len(), compare(&recorded, &current)))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::toolchain::hashes::{compare, hash_tree, parse, render};
+    use std::fs::{create_dir_all, remove_file, write};
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_hashes() {
+        let temp_dir = TempDir::new().unwrap();
+        let dir = temp_dir.path();
+        create_dir_all(dir.join("bin")).unwrap();
+        create_dir_all(dir.join("xtensa-esp32-elf-clang/esp-clang/lib")).unwrap();
+        write(dir.join("bin/rustc"), "rustc").unwrap();
+        write(dir.join("bin/cargo"), "cargo").unwrap();
+        write(
+            dir.join("xtensa-esp32-elf-clang/esp-clang/lib/libclang.so"),
+            "libclang",
+        )
+        .unwrap();
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(
+            dir.join("xtensa-esp32-elf-clang/esp-clang/lib/libclang.so"),
+            dir.join("xtensa-esp32-elf-clang/esp-clang/lib/libclang.so.18"),
+        )
+        .unwrap();
+
+        let recorded = hash_tree(dir).unwrap();
+        assert_eq!(
+            recorded["bin/rustc"],
+            "6fcd351889eb0caf54c459904e996ac7480a7843a5e8a25c8c6b59a0f975ca19"
+        );
+        assert_eq!(parse(&render(&recorded)), recorded);
+        assert!(compare(&recorded, &hash_tree(dir).unwrap()).is_empty());
+
+        write(dir.join("bin/rustc"), "tampered").unwrap();
+        remove_file(dir.join("bin/cargo")).unwrap();
+        write(dir.join("xtensa-esp32-elf-clang/evil.so"), "evil").unwrap();
+        let changes = compare(&recorded, &hash_tree(dir).unwrap());
+        assert_eq!(changes.len(), 2);
+        assert_eq!(changes["xtensa-rust"].modified, ["bin/rustc"]);
+        assert_eq!(changes["xtensa-rust"].missing, ["bin/cargo"]);
+        assert_eq!(changes["llvm"].extra, ["xtensa-esp32-elf-clang/evil.so"]);
+        assert_eq!(changes["llvm"].len(), 1);
+    }
+}
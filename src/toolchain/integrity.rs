@@ -0,0 +1,106 @@
+//! Integrity markers of the extracted archives (Unix).
+//!
+//! An interrupted extraction leaves a directory that looks like a complete installation. An
+//! archive is extracted with an `.espup-extracting` marker in its directory, removed once the
+//! extraction completes, and directories still marked are extracted again from scratch. The
+//! directories extracted by previous versions have no marker and are kept as they are.
+//!
+//! Windows installations share their directories, their versions are recorded in the
+//! `versions.json` file once extracted instead.
+
+use crate::permissions::write_file;
+use log::warn;
+use std::{
+    fs::{create_dir_all, read_dir, remove_dir_all, remove_file},
+    io,
+    path::{Path, PathBuf},
+};
+
+/// Name of the marker of an extraction in progress.
+pub const IN_PROGRESS_MARKER: &str = ".espup-extracting";
+
+/// Returns whether the archives extracted into `dir` were completely extracted.
+pub fn is_complete(dir: &Path) -> bool {
+    !dir.join(IN_PROGRESS_MARKER).exists()
+        && read_dir(dir).is_ok_and(|mut entries| entries.next().is_some())
+}
+
+/// Extraction of an archive into a directory, marked until completed.
+#[derive(Debug)]
+pub struct Extraction {
+    /// Directory the archive is extracted into.
+    dir: PathBuf,
+}
+
+impl Extraction {
+    /// Prepares the extraction of an archive into `dir`, marking it as in progress.
+    ///
+    /// The files of an interrupted extraction are removed, the files of the archives already
+    /// extracted into `dir` are kept.
+    pub fn begin(dir: &Path) -> io::Result<Self> {
+        let marker = dir.join(IN_PROGRESS_MARKER);
+        if marker.exists() {
+            warn!(
+                "Found an incomplete extraction in '{}', extracting it again",
+                dir.display()
+            );
+            remove_dir_all(dir)?;
+        }
+        create_dir_all(dir)?;
+        write_file(&marker, "")?;
+        Ok(Self {
+            dir: dir.to_path_buf(),
+        })
+    }
+
+    /// Marks the extraction as completed.
+    pub fn complete(self) -> io::Result<()> {
+        remove_file(self.dir.join(IN_PROGRESS_MARKER))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::toolchain::integrity::{is_complete, Extraction, IN_PROGRESS_MARKER};
+    use std::fs::{create_dir_all, write};
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_extraction() {
+        let temp_dir = TempDir::new().unwrap();
+        let dir = temp_dir.path().join("esp-clang");
+        create_dir_all(dir.join("lib")).unwrap();
+        write(dir.join("lib/libclang.so"), "partial").unwrap();
+        write(dir.join(IN_PROGRESS_MARKER), "").unwrap();
+        assert!(!is_complete(&dir));
+
+        // Leftovers of an interrupted extraction are removed
+        let extraction = Extraction::begin(&dir).unwrap();
+        assert!(!dir.join("lib").exists());
+        write(dir.join("libs"), "libs").unwrap();
+        extraction.complete().unwrap();
+        assert!(is_complete(&dir));
+
+        // Further archives keep the extracted files, and are marked while extracting
+        let extraction = Extraction::begin(&dir).unwrap();
+        assert!(dir.join("libs").exists());
+        assert!(!is_complete(&dir));
+        extraction.complete().unwrap();
+        assert!(is_complete(&dir));
+    }
+
+    #[test]
+    fn test_unmarked_extraction() {
+        let temp_dir = TempDir::new().unwrap();
+        let dir = temp_dir.path().join("esp-clang");
+        assert!(!is_complete(&dir));
+        create_dir_all(&dir).unwrap();
+        assert!(!is_complete(&dir));
+
+        // Extractions of previous versions have no marker and are kept
+        write(dir.join("libclang.so"), "libclang").unwrap();
+        assert!(is_complete(&dir));
+        Extraction::begin(&dir).unwrap();
+        assert!(dir.join("libclang.so").exists());
+    }
+}
@@ -0,0 +1,111 @@
+//! Scheduling of the disk intensive installation steps.
+//!
+//! Downloads always run in parallel, but extracting several toolchains at the same time thrashes
+//! spinning disks and small virtual machines, where serial extraction is faster.
+
+use log::debug;
+use std::{
+    path::Path,
+    sync::{Arc, Mutex},
+    thread::available_parallelism,
+};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// Limits the number of concurrent extractions, unlimited unless configured.
+static EXTRACTIONS: Mutex<Option<Arc<Semaphore>>> = Mutex::new(None);
+
+/// Sets the maximum number of concurrent extractions, replacing the previous limit.
+///
+/// The extractions already started keep the permits of the previous limit.
+pub fn set_io_parallelism(io_parallelism: usize) {
+    debug!("Extracting up to {} archives at once", io_parallelism);
+    *EXTRACTIONS.lock().unwrap() = Some(Arc::new(Semaphore::new(
+        io_parallelism.clamp(1, Semaphore::MAX_PERMITS),
+    )));
+}
+
+/// Waits until an extraction can start, the returned permit must be held until it finishes.
+pub async fn extraction_permit() -> OwnedSemaphorePermit {
+    let extractions = EXTRACTIONS
+        .lock()
+        .unwrap()
+        .get_or_insert_with(|| Arc::new(Semaphore::new(Semaphore::MAX_PERMITS)))
+        .clone();
+    extractions
+        .acquire_owned()
+        .await
+        .expect("Extraction semaphore closed")
+}
+
+/// Returns the number of concurrent extractions suited to the disk of the given directory.
+///
+/// Extractions are serialized on rotational disks and hosts with two CPUs or less.
+pub fn default_io_parallelism(dir: &Path) -> usize {
+    let cpus = available_parallelism().map(|n| n.get()).unwrap_or(1);
+    if cpus <= 2 || is_rotational(dir) == Some(true) {
+        1
+    } else {
+        cpus
+    }
+}
+
+/// Returns whether the directory is stored on a rotational disk, if it can be detected.
+#[cfg(target_os = "linux")]
+fn is_rotational(dir: &Path) -> Option<bool> {
+    use std::{fs::read_to_string, os::unix::fs::MetadataExt};
+
+    // The directory may not be created yet
+    let existing = dir.ancestors().find(|path| path.exists())?;
+    let dev = existing.metadata().ok()?.dev();
+    let (major, minor) = (
+        ((dev >> 32) & 0xffff_f000) | ((dev >> 8) & 0xfff),
+        ((dev >> 12) & 0xffff_ff00) | (dev & 0xff),
+    );
+    let device = Path::new("/sys/dev/block").join(format!("{major}:{minor}"));
+    // Partitions don't have a queue, the one of their disk is used instead
+    [
+        device.join("queue/rotational"),
+        device.join("../queue/rotational"),
+    ]
+    .iter()
+    .find_map(|path| read_to_string(path).ok())
+    .map(|rotational| rotational.trim() == "1")
+}
+
+#[cfg(not(target_os = "linux"))]
+fn is_rotational(_dir: &Path) -> Option<bool> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::toolchain::io::{default_io_parallelism, extraction_permit, set_io_parallelism};
+    use std::time::Duration;
+    use tempfile::TempDir;
+    use tokio::time::timeout;
+
+    #[test]
+    fn test_default_io_parallelism() {
+        let temp_dir = TempDir::new().unwrap();
+        assert!(default_io_parallelism(temp_dir.path()) >= 1);
+        assert!(default_io_parallelism(&temp_dir.path().join("missing/toolchain")) >= 1);
+    }
+
+    #[tokio::test]
+    async fn test_set_io_parallelism() {
+        set_io_parallelism(1);
+        let _permit = extraction_permit().await;
+        let waiting = timeout(Duration::from_millis(50), extraction_permit()).await;
+        assert!(waiting.is_err());
+
+        // A later installation may raise the limit
+        set_io_parallelism(2);
+        let first = timeout(Duration::from_millis(50), extraction_permit()).await;
+        let second = timeout(Duration::from_millis(50), extraction_permit()).await;
+        assert!(first.is_ok() && second.is_ok());
+        set_io_parallelism(usize::MAX);
+        assert!(timeout(Duration::from_millis(50), extraction_permit())
+            .await
+            .is_ok());
+    }
+}
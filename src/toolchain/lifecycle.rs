@@ -0,0 +1,141 @@
+//! Deprecation warnings for outdated toolchains.
+//!
+//! Which releases are still supported, and the known issues of older ones, are described by the
+//! embedded `lifecycle.json` file, so they can be updated without touching the code.
+
+use crate::toolchain::rust::parse_extended_version;
+use log::warn;
+use regex::Regex;
+use serde::Deserialize;
+
+/// Embedded content of `lifecycle.json`.
+const LIFECYCLE: &str = include_str!("lifecycle.json");
+
+/// Support policy of the toolchains.
+#[derive(Debug, Clone, Deserialize)]
+struct Lifecycle {
+    /// Number of Xtensa Rust releases newer than a version after which it is outdated.
+    supported_releases: usize,
+    /// LLVM major versions no longer receiving Espressif patches.
+    unpatched_llvm_majors: Vec<u64>,
+    /// Known issues of specific Xtensa Rust versions.
+    known_issues: Vec<KnownIssue>,
+}
+
+/// Known issue of Xtensa Rust versions.
+#[derive(Debug, Clone, Deserialize)]
+struct KnownIssue {
+    /// Affected versions, either a full version or a prefix such as `1.75.0`.
+    versions: String,
+    /// Description of the issue.
+    description: String,
+}
+
+impl Lifecycle {
+    fn load() -> Self {
+        serde_json::from_str(LIFECYCLE).expect("Invalid lifecycle.json")
+    }
+
+    /// Returns the deprecation warnings of an Xtensa Rust version, given the released versions
+    /// (empty if unknown) and the LLVM version it uses.
+    fn warnings(&self, version: &str, llvm_version: &str, releases: &[String]) -> Vec<String> {
+        let mut warnings = Vec::new();
+        if let Some(current) = parse_extended_version(version) {
+            let newer: Vec<(u64, u64, u64, u64)> = releases
+                .iter()
+                .filter_map(|release| parse_extended_version(release))
+                .filter(|release| *release > current)
+                .collect();
+            if newer.len() >= self.supported_releases {
+                let (major, minor, patch, subpatch) = newer.iter().max().unwrap();
+                warnings.push(format!(
+                    "Xtensa Rust {version} is {} releases behind, consider updating to {major}.{minor}.{patch}.{subpatch}",
+                    newer.len()
+                ));
+            }
+        }
+        if let Some(major) = llvm_major(llvm_version) {
+            if self.unpatched_llvm_majors.contains(&major) {
+                warnings.push(format!(
+                    "Xtensa Rust {version} uses LLVM {major}, which no longer receives Espressif patches"
+                ));
+            }
+        }
+        for issue in &self.known_issues {
+            if version == issue.versions || version.starts_with(&format!("{}.", issue.versions)) {
+                warnings.push(format!(
+                    "Xtensa Rust {version} has a known issue: {}",
+                    issue.description
+                ));
+            }
+        }
+        warnings
+    }
+}
+
+/// Returns the major version of an Espressif LLVM version, e.g. `esp-18.1.2_20240912`.
+fn llvm_major(llvm_version: &str) -> Option<u64> {
+    let re_llvm = Regex::new(r"^esp-(\d+)\.").unwrap();
+    re_llvm.captures(llvm_version)?[1].parse().ok()
+}
+
+/// Warns when the pinned Xtensa Rust version is outdated.
+pub fn warn_deprecated(version: &str, llvm_version: &str, releases: &[String]) {
+    for warning in Lifecycle::load().warnings(version, llvm_version, releases) {
+        warn!("{}", warning);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::toolchain::lifecycle::{llvm_major, KnownIssue, Lifecycle};
+
+    #[test]
+    fn test_lifecycle() {
+        // The embedded policy is valid
+        Lifecycle::load();
+
+        let lifecycle = Lifecycle {
+            supported_releases: 2,
+            unpatched_llvm_majors: vec![15, 16],
+            known_issues: vec![KnownIssue {
+                versions: "1.77.0".to_string(),
+                description: "Broken atomics".to_string(),
+            }],
+        };
+        let releases: Vec<String> = [
+            "1.82.0.3",
+            "1.82.0.2",
+            "1.81.0.0",
+            "1.77.0.0",
+            "1.82.0.0-rc1",
+        ]
+        .iter()
+        .map(|release| release.to_string())
+        .collect();
+
+        assert!(lifecycle
+            .warnings("1.82.0.2", "esp-18.1.2_20240912", &releases)
+            .is_empty());
+        assert_eq!(
+            lifecycle.warnings("1.77.0.0", "esp-16.0.4-20231113", &releases),
+            vec![
+                "Xtensa Rust 1.77.0.0 is 3 releases behind, consider updating to 1.82.0.3",
+                "Xtensa Rust 1.77.0.0 uses LLVM 16, which no longer receives Espressif patches",
+                "Xtensa Rust 1.77.0.0 has a known issue: Broken atomics",
+            ]
+        );
+        // Unknown releases
+        assert_eq!(
+            lifecycle.warnings("1.81.0.0", "esp-17.0.1_20240419", &[]),
+            Vec::<String>::new()
+        );
+    }
+
+    #[test]
+    fn test_llvm_major() {
+        assert_eq!(llvm_major("esp-18.1.2_20240912"), Some(18));
+        assert_eq!(llvm_major("esp-16.0.4-20231113"), Some(16));
+        assert_eq!(llvm_major("18.1.2"), None);
+    }
+}
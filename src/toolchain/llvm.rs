@@ -1,57 +1,79 @@
 //! LLVM Toolchain source and installation tools.
 
+#[cfg(unix)]
+use crate::{
+    env::get_espup_dir, sandbox::check_write, toolchain::integrity::is_complete,
+    wsl::avoid_symlinks,
+};
 #[cfg(windows)]
-use crate::env::{delete_env_variable, get_windows_path_var, set_env_variable};
 use crate::{
+    env::{delete_env_variable, remove_windows_path_entries, set_env, unset_env},
+    toolchain::versions::Versions,
+};
+use crate::{
+    env::{
+        remove_component_exports, try_create_export_file, Export, ExportFileMode,
+        EXPORT_TEMPLATE_VERSION,
+    },
     error::Error,
     host_triple::HostTriple,
-    toolchain::{download_file, rust::RE_EXTENDED_SEMANTIC_VERSION, Installable},
+    manifest::Manifest,
+    toolchain::{
+        compatibility::resolve, disk_usage, download_file, overrides::is_overridden,
+        progress::Progress, remove, verify::check_executable, Component,
+    },
 };
 use async_trait::async_trait;
-#[cfg(unix)]
-use directories::BaseDirs;
 use log::{info, warn};
 use miette::Result;
-use regex::Regex;
-use std::path::{Path, PathBuf};
-#[cfg(windows)]
-use std::{env, fs::File};
+use std::{
+    collections::BTreeMap,
+    fs::read_dir,
+    path::{Path, PathBuf},
+};
+#[cfg(unix)]
+use std::{
+    fs::{copy, create_dir_all, remove_file, rename},
+    os::unix::fs::symlink,
+};
 #[cfg(unix)]
-use std::{fs::create_dir_all, os::unix::fs::symlink};
 use tokio::fs::remove_dir_all;
 
 const DEFAULT_LLVM_REPOSITORY: &str = "https://github.com/espressif/llvm-project/releases/download";
-const DEFAULT_LLVM_15_VERSION: &str = "esp-15.0.0-20221201";
-#[cfg(windows)]
-const OLD_LLVM_16_VERSION: &str = "esp-16.0.0-20230516";
-const DEFAULT_LLVM_16_VERSION: &str = "esp-16.0.4-20231113";
-const DEFAULT_LLVM_17_VERSION: &str = "esp-17.0.1_20240419";
-const DEFAULT_LLVM_18_VERSION: &str = "esp-18.1.2_20240912";
+pub(crate) const DEFAULT_LLVM_15_VERSION: &str = "esp-15.0.0-20221201";
+pub(crate) const DEFAULT_LLVM_16_VERSION: &str = "esp-16.0.4-20231113";
+pub(crate) const DEFAULT_LLVM_17_VERSION: &str = "esp-17.0.1_20240419";
+pub(crate) const DEFAULT_LLVM_18_VERSION: &str = "esp-18.1.2_20240912";
 pub const CLANG_NAME: &str = "xtensa-esp32-elf-clang";
 
 #[derive(Debug, Clone, Default)]
 pub struct Llvm {
     // /// If `true`, full LLVM, instead of only libraries, are installed.
-    extended: bool,
+    pub(crate) extended: bool,
     /// LLVM libs-only toolchain file name.
     pub file_name_libs: Option<String>,
     /// LLVM "full" toolchain file name.
     pub file_name_full: Option<String>,
+    /// If `true`, only the libraries are installed, outside of any toolchain, without updating
+    /// the `esp-clang` symlink and only exporting `LIBCLANG_PATH`.
+    pub(crate) libs_only: bool,
     /// Host triple.
     pub host_triple: HostTriple,
     /// LLVM Toolchain path.
     pub path: PathBuf,
     /// The repository containing LLVM sources.
     pub repository_url: String,
+    /// Path of the toolchain LLVM belongs to.
+    pub toolchain_path: PathBuf,
     /// LLVM Version ["15", "16", "17"].
     pub version: String,
 }
 
 impl Llvm {
     /// Gets the name of the LLVM arch based on the host triple.
-    fn get_arch(host_triple: &HostTriple, version: &str) -> String {
-        if version == DEFAULT_LLVM_17_VERSION || version == DEFAULT_LLVM_18_VERSION {
-            let arch = match host_triple {
+    fn get_arch(host_triple: &HostTriple, version: &str) -> Result<&'static str, Error> {
+        let arch = if version == DEFAULT_LLVM_17_VERSION || version == DEFAULT_LLVM_18_VERSION {
+            match host_triple {
                 HostTriple::Aarch64AppleDarwin => "aarch64-apple-darwin",
                 HostTriple::X86_64AppleDarwin => "x86_64-apple-darwin",
                 HostTriple::X86_64UnknownLinuxGnu => "x86_64-linux-gnu",
@@ -59,34 +81,44 @@ impl Llvm {
                 HostTriple::X86_64PcWindowsMsvc | HostTriple::X86_64PcWindowsGnu => {
                     "x86_64-w64-mingw32"
                 }
-            };
-            arch.to_string()
+                HostTriple::Riscv64gcUnknownLinuxGnu | HostTriple::Powerpc64leUnknownLinuxGnu => {
+                    return Err(Error::UnsupportedArtifact(
+                        "LLVM".to_string(),
+                        host_triple.to_string(),
+                    ))
+                }
+            }
         } else {
-            let arch = match host_triple {
+            match host_triple {
                 HostTriple::Aarch64AppleDarwin => "macos-arm64",
                 HostTriple::X86_64AppleDarwin => "macos",
                 HostTriple::X86_64UnknownLinuxGnu => "linux-amd64",
                 HostTriple::Aarch64UnknownLinuxGnu => "linux-arm64",
                 HostTriple::X86_64PcWindowsMsvc | HostTriple::X86_64PcWindowsGnu => "win64",
-            };
-            arch.to_string()
-        }
+                HostTriple::Riscv64gcUnknownLinuxGnu | HostTriple::Powerpc64leUnknownLinuxGnu => {
+                    return Err(Error::UnsupportedArtifact(
+                        "LLVM".to_string(),
+                        host_triple.to_string(),
+                    ))
+                }
+            }
+        };
+        Ok(arch)
     }
 
     /// Gets the binary path.
-    fn get_lib_path(&self) -> String {
+    pub(crate) fn get_lib_path(&self) -> String {
         match std::cfg!(windows) {
-            true => format!("{}/esp-clang/bin", self.path.to_str().unwrap()).replace('/', "\\"),
-            false => format!("{}/esp-clang/lib", self.path.to_str().unwrap()),
+            true => format!("{}/esp-clang/bin", self.path.display()).replace('/', "\\"),
+            false => format!("{}/esp-clang/lib", self.path.display()),
         }
     }
 
     /// Gets the binary path of clang
-    fn get_bin_path(&self) -> String {
+    pub(crate) fn get_bin_path(&self) -> String {
         match std::cfg!(windows) {
-            true => format!("{}/esp-clang/bin/clang.exe", self.path.to_str().unwrap())
-                .replace('/', "\\"),
-            false => format!("{}/esp-clang/bin/clang", self.path.to_str().unwrap()),
+            true => format!("{}/esp-clang/bin/clang.exe", self.path.display()).replace('/', "\\"),
+            false => format!("{}/esp-clang/bin/clang", self.path.display()),
         }
     }
 
@@ -97,33 +129,7 @@ impl Llvm {
         extended: bool,
         xtensa_rust_version: &str,
     ) -> Result<Self, Error> {
-        let re_extended: Regex = Regex::new(RE_EXTENDED_SEMANTIC_VERSION).unwrap();
-        let (major, minor, patch, subpatch) = match re_extended.captures(xtensa_rust_version) {
-            Some(version) => (
-                version.get(1).unwrap().as_str().parse::<u8>().unwrap(),
-                version.get(2).unwrap().as_str().parse::<u8>().unwrap(),
-                version.get(3).unwrap().as_str().parse::<u8>().unwrap(),
-                version.get(4).unwrap().as_str().parse::<u8>().unwrap(),
-            ),
-            None => return Err(Error::InvalidVersion(xtensa_rust_version.to_string())),
-        };
-
-        // Use LLVM 15 for versions 1.69.0.0 and below and LLVM 16 for versions 1.77.0 and bellow
-        let version = if (major == 1 && minor == 69 && patch == 0 && subpatch == 0)
-            || (major == 1 && minor < 69)
-        {
-            DEFAULT_LLVM_15_VERSION.to_string()
-        } else if (major == 1 && minor == 77 && patch == 0 && subpatch == 0)
-            || (major == 1 && minor < 77)
-        {
-            DEFAULT_LLVM_16_VERSION.to_string()
-        } else if (major == 1 && minor == 81 && patch == 0 && subpatch == 0)
-            || (major == 1 && minor < 81)
-        {
-            DEFAULT_LLVM_17_VERSION.to_string()
-        } else {
-            DEFAULT_LLVM_18_VERSION.to_string()
-        };
+        let version = resolve(xtensa_rust_version)?.llvm.to_string();
 
         let name = if version == DEFAULT_LLVM_17_VERSION || version == DEFAULT_LLVM_18_VERSION {
             "clang-"
@@ -136,7 +142,7 @@ impl Llvm {
                 "{}{}-{}.tar.xz",
                 name,
                 version,
-                Self::get_arch(host_triple, &version)
+                Self::get_arch(host_triple, &version)?
             );
 
             let file_name_libs =
@@ -175,13 +181,55 @@ impl Llvm {
             extended,
             file_name_libs,
             file_name_full,
+            libs_only: false,
             host_triple: host_triple.clone(),
             path,
             repository_url,
+            toolchain_path: toolchain_path.to_path_buf(),
             version,
         })
     }
 
+    /// Creates an instance installing only the LLVM libraries into a subdirectory of `path`, with
+    /// the layout of the toolchains so the other files of `path` are left untouched.
+    pub fn libs_only(
+        path: &Path,
+        host_triple: &HostTriple,
+        xtensa_rust_version: &str,
+    ) -> Result<Self, Error> {
+        Ok(Self {
+            libs_only: true,
+            ..Self::new(path, host_triple, false, xtensa_rust_version)?
+        })
+    }
+
+    /// Returns the environment variables pointing to this LLVM.
+    fn exports(&self) -> Vec<Export> {
+        let mut exports: Vec<Export> = Vec::new();
+        #[cfg(windows)]
+        if cfg!(windows) {
+            let libclang_dll = format!("{}\\libclang.dll", self.get_lib_path());
+            exports.push(Export::set(&self.id(), "LIBCLANG_PATH", &libclang_dll));
+            if !self.libs_only {
+                exports.push(Export::prepend_path(&self.id(), &self.get_lib_path()));
+            }
+        }
+        #[cfg(unix)]
+        if cfg!(unix) {
+            exports.push(Export::set(
+                &self.id(),
+                "LIBCLANG_PATH",
+                &self.get_lib_path(),
+            ));
+        }
+        if self.extended {
+            // On Windows `CLANG_PATH` is the path of `clang.exe`, which is not added to the PATH:
+            // its `esp-clang\bin` directory already is, as the library path
+            exports.push(Export::set(&self.id(), "CLANG_PATH", &self.get_bin_path()));
+        }
+        exports
+    }
+
     /// Uninstall LLVM toolchain.
     pub async fn uninstall(toolchain_path: &Path) -> Result<(), Error> {
         info!("Uninstalling Xtensa LLVM");
@@ -189,68 +237,19 @@ impl Llvm {
         if llvm_path.exists() {
             #[cfg(windows)]
             if cfg!(windows) {
-                let mut updated_path = get_windows_path_var()?.replace(
-                    &format!(
-                        "{}\\{}\\esp-clang\\bin;",
-                        llvm_path.display().to_string().replace('/', "\\"),
-                        DEFAULT_LLVM_15_VERSION,
-                    ),
-                    "",
-                );
-                updated_path = updated_path.replace(
-                    &format!(
-                        "{}\\{}\\esp-clang\\bin;",
-                        llvm_path.display().to_string().replace('/', "\\"),
-                        OLD_LLVM_16_VERSION,
-                    ),
-                    "",
-                );
-                updated_path = updated_path.replace(
-                    &format!(
-                        "{}\\{}\\esp-clang\\bin;",
-                        llvm_path.display().to_string().replace('/', "\\"),
-                        DEFAULT_LLVM_16_VERSION,
-                    ),
-                    "",
-                );
-                updated_path = updated_path.replace(
-                    &format!(
-                        "{}\\{}\\esp-clang\\bin;",
-                        llvm_path.display().to_string().replace('/', "\\"),
-                        DEFAULT_LLVM_17_VERSION,
-                    ),
-                    "",
-                );
-                updated_path = updated_path.replace(
-                    &format!(
-                        "{}\\{}\\esp-clang\\bin;",
-                        llvm_path.display().to_string().replace('/', "\\"),
-                        DEFAULT_LLVM_18_VERSION,
-                    ),
-                    "",
-                );
-                updated_path = updated_path.replace(
-                    &format!(
-                        "{}\\esp-clang\\bin;",
-                        llvm_path.display().to_string().replace('/', "\\"),
-                    ),
-                    "",
-                );
-                set_env_variable("PATH", &updated_path)?;
+                remove_windows_path_entries(&[llvm_path.display().to_string()])?;
                 delete_env_variable("LIBCLANG_PATH")?;
                 delete_env_variable("CLANG_PATH")?;
             }
-            remove_dir_all(&llvm_path)
-                .await
-                .map_err(|_| Error::RemoveDirectory(llvm_path.display().to_string()))?;
+            remove::remove_dir_all(&llvm_path).await?;
             #[cfg(unix)]
             if cfg!(unix) {
-                let espup_dir = BaseDirs::new().unwrap().home_dir().join(".espup");
+                let llvm_symlink_path = get_espup_dir().join("esp-clang");
 
-                if espup_dir.exists() {
-                    remove_dir_all(espup_dir.display().to_string())
-                        .await
-                        .map_err(|_| Error::RemoveDirectory(espup_dir.display().to_string()))?;
+                if llvm_symlink_path.symlink_metadata().is_ok() {
+                    remove_dir_all(&llvm_symlink_path).await.map_err(|_| {
+                        Error::RemoveDirectory(llvm_symlink_path.display().to_string())
+                    })?;
                 }
             }
         }
@@ -259,37 +258,42 @@ impl Llvm {
 }
 
 #[async_trait]
-impl Installable for Llvm {
-    async fn install(&self) -> Result<Vec<String>, Error> {
-        let mut exports: Vec<String> = Vec::new();
-
+impl Component for Llvm {
+    async fn install(&self, progress: &Progress) -> Result<Vec<Export>, Error> {
         #[cfg(unix)]
-        let install_path = if self.extended {
-            Path::new(&self.path).join("esp-clang").join("include")
-        } else {
-            Path::new(&self.path).to_path_buf()
-        };
+        let is_installed = is_complete(&self.path)
+            && (!self.extended || self.path.join("esp-clang").join("include").exists());
         #[cfg(windows)]
-        let install_path = if self.extended {
-            self.path.join(&self.version).join("include")
-        } else {
-            self.path.join(&self.version)
+        let is_installed = {
+            Versions::repair(&self.path, self.path.join("esp-clang").exists())?;
+            Versions::load(&self.path).contains(&self.version)
+                && (!self.extended || self.path.join("esp-clang").join("include").exists())
         };
 
-        if install_path.exists() {
+        let overridden = is_overridden(&["llvm", "llvm-full"]);
+
+        if is_installed && !overridden {
             warn!(
                 "Previous installation of LLVM exists in: '{}'. Reusing this installation",
-                self.path.to_str().unwrap()
+                self.path.display()
             );
         } else {
             info!("Installing Xtensa LLVM");
+            // Local artifacts replace the previous installation rather than being merged into it
+            #[cfg(unix)]
+            if overridden && self.path.exists() {
+                remove_dir_all(&self.path)
+                    .await
+                    .map_err(|_| Error::RemoveDirectory(self.path.display().to_string()))?;
+            }
             if let Some(file_name_libs) = &self.file_name_libs {
                 download_file(
                     format!("{}/{}", self.repository_url, file_name_libs),
                     "idf_tool_xtensa_elf_clang.libs.tar.xz",
-                    self.path.to_str().unwrap(),
+                    &self.path.display().to_string(),
                     true,
                     false,
+                    progress,
                 )
                 .await?;
             }
@@ -297,63 +301,343 @@ impl Installable for Llvm {
                 download_file(
                     format!("{}/{}", self.repository_url, file_name_full),
                     "idf_tool_xtensa_elf_clang.full.tar.xz",
-                    self.path.to_str().unwrap(),
+                    &self.path.display().to_string(),
                     true,
                     false,
+                    progress,
                 )
                 .await?;
             }
         }
-        // Set environment variables.
         #[cfg(windows)]
-        if cfg!(windows) {
-            File::create(self.path.join(&self.version))?;
-            let libclang_dll = format!("{}\\libclang.dll", self.get_lib_path());
-            exports.push(format!("$Env:LIBCLANG_PATH = \"{}\"", libclang_dll));
-            exports.push(format!(
-                "$Env:PATH = \"{};\" + $Env:PATH",
-                self.get_lib_path()
-            ));
-            env::set_var("LIBCLANG_BIN_PATH", self.get_lib_path());
-            env::set_var("LIBCLANG_PATH", libclang_dll);
-        }
+        Versions::record(&self.path, &self.version)?;
         #[cfg(unix)]
-        if cfg!(unix) {
-            exports.push(format!("export LIBCLANG_PATH=\"{}\"", self.get_lib_path()));
-            let espup_dir = BaseDirs::new().unwrap().home_dir().join(".espup");
+        if !self.libs_only {
+            link_esp_clang(&self.get_lib_path()).await?;
+        }
+        if self.extended {
+            check_executable(&self.name(), Path::new(&self.get_bin_path()))?;
+        }
 
-            if !espup_dir.exists() {
-                create_dir_all(espup_dir.display().to_string())
-                    .map_err(|_| Error::CreateDirectory(espup_dir.display().to_string()))?;
-            }
-            let llvm_symlink_path = espup_dir.join("esp-clang");
-            if llvm_symlink_path.exists() {
-                remove_dir_all(&llvm_symlink_path)
-                    .await
-                    .map_err(|_| Error::RemoveDirectory(llvm_symlink_path.display().to_string()))?;
+        Ok(self.exports())
+    }
+
+    async fn uninstall(&self) -> Result<(), Error> {
+        Self::uninstall(&self.toolchain_path).await
+    }
+
+    fn name(&self) -> String {
+        "LLVM".to_string()
+    }
+
+    fn id(&self) -> String {
+        "llvm".to_string()
+    }
+
+    fn path(&self) -> PathBuf {
+        self.path.clone()
+    }
+
+    fn installed_version(&self) -> Option<String> {
+        let llvm_path = self.toolchain_path.join(CLANG_NAME);
+        // Unix installations are extracted into `<version>` directories
+        #[cfg(unix)]
+        {
+            if is_complete(&llvm_path.join(&self.version)) {
+                return Some(self.version.clone());
             }
-            info!(
-                "Creating symlink between '{}' and '{}'",
-                self.get_lib_path(),
-                llvm_symlink_path.display()
-            );
-            symlink(self.get_lib_path(), llvm_symlink_path)?;
+            read_dir(llvm_path)
+                .ok()?
+                .filter_map(|entry| entry.ok())
+                .filter(|entry| is_complete(&entry.path()))
+                .map(|entry| entry.file_name().to_string_lossy().to_string())
+                .filter(|name| name.starts_with("esp-"))
+                .max()
         }
+        // Windows installations are extracted into the same directory, the version is tracked in
+        // the `versions.json` file
+        #[cfg(windows)]
+        Versions::load(&llvm_path).latest()
+    }
 
-        if self.extended {
-            #[cfg(windows)]
-            if cfg!(windows) {
-                exports.push(format!("$Env:CLANG_PATH = \"{}\"", self.get_bin_path()));
-                env::set_var("CLANG_PATH", self.get_bin_path());
+    fn latest_version(&self) -> String {
+        self.version.clone()
+    }
+
+    fn artifact_urls(&self) -> Vec<String> {
+        [&self.file_name_libs, &self.file_name_full]
+            .into_iter()
+            .flatten()
+            .map(|file_name| format!("{}/{}", self.repository_url, file_name))
+            .collect()
+    }
+
+    fn size_on_disk(&self) -> u64 {
+        disk_usage(&self.toolchain_path.join(CLANG_NAME))
+    }
+}
+
+/// Points the `esp-clang` symlink of the espup directory to the libraries of an LLVM.
+///
+/// The symlink is replaced atomically, so the previous LLVM stays in use if it fails.
+#[cfg(unix)]
+async fn link_esp_clang(lib_path: &str) -> Result<(), Error> {
+    let espup_dir = get_espup_dir();
+    if !espup_dir.exists() {
+        create_dir_all(espup_dir.display().to_string())
+            .map_err(|_| Error::CreateDirectory(espup_dir.display().to_string()))?;
+    }
+    let llvm_symlink_path = espup_dir.join("esp-clang");
+    check_write(&llvm_symlink_path)?;
+    // Copies made instead of the symlink can't be replaced atomically
+    if llvm_symlink_path.is_dir() && !llvm_symlink_path.is_symlink() {
+        remove_dir_all(&llvm_symlink_path)
+            .await
+            .map_err(|_| Error::RemoveDirectory(llvm_symlink_path.display().to_string()))?;
+    }
+    if avoid_symlinks(&espup_dir) {
+        warn!(
+            "Symlinks are not supported in Windows drives, copying '{}' to '{}' instead",
+            lib_path,
+            llvm_symlink_path.display()
+        );
+        if llvm_symlink_path.symlink_metadata().is_ok() {
+            remove_file(&llvm_symlink_path)?;
+        }
+        return copy_dir(Path::new(lib_path), &llvm_symlink_path);
+    }
+    info!(
+        "Creating symlink between '{}' and '{}'",
+        lib_path,
+        llvm_symlink_path.display()
+    );
+    let new_symlink_path = espup_dir.join(".esp-clang.new");
+    if new_symlink_path.symlink_metadata().is_ok() {
+        remove_file(&new_symlink_path)?;
+    }
+    symlink(lib_path, &new_symlink_path)?;
+    rename(&new_symlink_path, &llvm_symlink_path)?;
+    Ok(())
+}
+
+/// Returns the complete LLVM installations of the toolchain directories, by version.
+///
+/// Versions installed in several toolchains are taken from the last one.
+fn installed_llvms(toolchain_dirs: &[PathBuf]) -> BTreeMap<String, PathBuf> {
+    let mut installed = BTreeMap::new();
+    for llvm_path in toolchain_dirs.iter().map(|dir| dir.join(CLANG_NAME)) {
+        // Unix installations are extracted into `<version>` directories
+        #[cfg(unix)]
+        for entry in read_dir(&llvm_path).into_iter().flatten().flatten() {
+            let version = entry.file_name().to_string_lossy().to_string();
+            if version.starts_with("esp-") && is_complete(&entry.path()) {
+                installed.insert(version, entry.path());
             }
-            #[cfg(unix)]
-            exports.push(format!("export CLANG_PATH=\"{}\"", self.get_bin_path()));
         }
+        // Windows installations keep a single version
+        #[cfg(windows)]
+        if let Some(version) = Versions::load(&llvm_path).latest() {
+            installed.insert(version, llvm_path);
+        }
+    }
+    installed
+}
+
+/// Returns the newest installed LLVM matching the requested version, e.g. `18` or
+/// `esp-18.1.2_20240912`.
+fn find_llvm<'a>(
+    installed: &'a BTreeMap<String, PathBuf>,
+    requested: &str,
+) -> Option<(&'a String, &'a PathBuf)> {
+    let requested = requested.strip_prefix("esp-").unwrap_or(requested);
+    installed.iter().rev().find(|(version, _)| {
+        let version = version.strip_prefix("esp-").unwrap_or(version);
+        version == requested
+            || version
+                .strip_prefix(requested)
+                .is_some_and(|rest| rest.starts_with(['.', '_', '-']))
+    })
+}
 
-        Ok(exports)
+/// Switches the LLVM of an installation to another installed version, e.g. one of another
+/// toolchain, updating `LIBCLANG_PATH` and the `esp-clang` symlink without reinstalling.
+pub async fn use_llvm(name: &str, version: &str) -> Result<(), Error> {
+    let mut manifest =
+        Manifest::load(name)?.ok_or_else(|| Error::MissingManifest(name.to_string()))?;
+    let toolchain_dir = manifest.toolchain_dir();
+    // The versions of the installation are preferred over the ones of other installations
+    let mut toolchain_dirs: Vec<PathBuf> = Manifest::list()?
+        .iter()
+        .filter(|other| other.name != name)
+        .map(Manifest::toolchain_dir)
+        .collect();
+    toolchain_dirs.push(toolchain_dir.clone());
+    let installed = installed_llvms(&toolchain_dirs);
+    let (version, path) = find_llvm(&installed, version).ok_or_else(|| {
+        Error::MissingLlvmVersion(
+            version.to_string(),
+            installed.keys().cloned().collect::<Vec<_>>().join(", "),
+        )
+    })?;
+    let mut llvm = Llvm {
+        extended: manifest.extended_llvm,
+        path: path.clone(),
+        toolchain_path: toolchain_dir,
+        version: version.clone(),
+        ..Default::default()
+    };
+    if llvm.extended && !Path::new(&llvm.get_bin_path()).exists() {
+        warn!("LLVM {version} was installed without clang, 'CLANG_PATH' won't be set");
+        llvm.extended = false;
     }
 
-    fn name(&self) -> String {
-        "LLVM".to_string()
+    info!("Switching the LLVM of the '{name}' toolchain to {version}");
+    #[cfg(unix)]
+    link_esp_clang(&llvm.get_lib_path()).await?;
+    let exports = llvm.exports();
+    #[cfg(windows)]
+    {
+        unset_env(
+            &manifest
+                .exports
+                .iter()
+                .filter(|export| export.component == llvm.id())
+                .cloned()
+                .collect::<Vec<_>>(),
+        )?;
+        set_env(&exports)?;
+    }
+    remove_component_exports(&mut manifest.exports, &llvm.id());
+    manifest.exports.extend(exports);
+    for export_file in manifest.export_files() {
+        try_create_export_file(&export_file, &manifest.exports, ExportFileMode::Merge)?;
+    }
+    manifest.export_template_version = EXPORT_TEMPLATE_VERSION;
+    manifest.save()?;
+    info!(
+        "The '{name}' toolchain now uses LLVM {version}, 'espup update' restores the LLVM of its Xtensa Rust version"
+    );
+    Ok(())
+}
+
+/// Recursively copies a directory, following symlinks.
+#[cfg(unix)]
+fn copy_dir(source: &Path, destination: &Path) -> Result<(), Error> {
+    create_dir_all(destination)
+        .map_err(|_| Error::CreateDirectory(destination.display().to_string()))?;
+    for entry in read_dir(source)? {
+        let path = entry?.path();
+        let target = destination.join(path.file_name().unwrap());
+        if path.is_dir() {
+            copy_dir(&path, &target)?;
+        } else {
+            copy(&path, &target)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(unix)]
+    use crate::toolchain::{
+        integrity::{Extraction, IN_PROGRESS_MARKER},
+        llvm::installed_llvms,
+    };
+    use crate::{
+        host_triple::HostTriple,
+        toolchain::llvm::{find_llvm, Llvm, CLANG_NAME},
+    };
+    #[cfg(unix)]
+    use std::fs::{create_dir_all, write};
+    use std::{
+        collections::BTreeMap,
+        path::{Path, PathBuf},
+    };
+    #[cfg(unix)]
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_libs_only() {
+        let dir = Path::new("/opt/libclang");
+        let llvm = Llvm::libs_only(dir, &HostTriple::X86_64UnknownLinuxGnu, "1.82.0.3").unwrap();
+        assert!(llvm.path.starts_with(dir.join(CLANG_NAME)));
+        assert!(llvm.file_name_libs.is_some());
+        assert!(llvm.file_name_full.is_none());
+        let exports = llvm.exports();
+        assert_eq!(exports.len(), 1);
+        assert_eq!(exports[0].variable, "LIBCLANG_PATH");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_libs_only_existing_dir() {
+        let temp_dir = TempDir::new().unwrap();
+        write(temp_dir.path().join("notes.txt"), "notes").unwrap();
+        let llvm = Llvm::libs_only(
+            temp_dir.path(),
+            &HostTriple::X86_64UnknownLinuxGnu,
+            "1.82.0.3",
+        )
+        .unwrap();
+
+        // The files of the directory are not taken for an incomplete extraction
+        Extraction::begin(&llvm.path).unwrap();
+        assert!(temp_dir.path().join("notes.txt").exists());
+    }
+
+    #[test]
+    fn test_find_llvm() {
+        let installed: BTreeMap<String, PathBuf> = [
+            ("esp-17.0.1_20240419", "a"),
+            ("esp-18.1.2_20240912", "b"),
+            ("esp-18.1.2_20250101", "c"),
+        ]
+        .into_iter()
+        .map(|(version, path)| (version.to_string(), PathBuf::from(path)))
+        .collect();
+        let found =
+            |requested| find_llvm(&installed, requested).map(|(version, _)| version.as_str());
+        assert_eq!(found("esp-17.0.1_20240419"), Some("esp-17.0.1_20240419"));
+        assert_eq!(found("17"), Some("esp-17.0.1_20240419"));
+        assert_eq!(found("18"), Some("esp-18.1.2_20250101"));
+        assert_eq!(found("esp-18.1.2"), Some("esp-18.1.2_20250101"));
+        assert_eq!(found("1"), None);
+        assert_eq!(found("16"), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_installed_llvms() {
+        let temp_dir = TempDir::new().unwrap();
+        let esp = temp_dir.path().join("esp");
+        let other = temp_dir.path().join("other");
+        for (toolchain_dir, version, complete) in [
+            (&other, "esp-17.0.1_20240419", true),
+            (&other, "esp-18.1.2_20240912", true),
+            (&esp, "esp-18.1.2_20240912", true),
+            (&esp, "esp-19.1.2_20250225", false),
+        ] {
+            let dir = toolchain_dir.join(CLANG_NAME).join(version);
+            create_dir_all(&dir).unwrap();
+            write(dir.join("clang"), "clang").unwrap();
+            if !complete {
+                write(dir.join(IN_PROGRESS_MARKER), "").unwrap();
+            }
+        }
+        let installed = installed_llvms(&[other.clone(), esp.clone()]);
+        assert_eq!(
+            installed.into_iter().collect::<Vec<_>>(),
+            vec![
+                (
+                    "esp-17.0.1_20240419".to_string(),
+                    other.join(CLANG_NAME).join("esp-17.0.1_20240419")
+                ),
+                (
+                    "esp-18.1.2_20240912".to_string(),
+                    esp.join(CLANG_NAME).join("esp-18.1.2_20240912")
+                ),
+            ]
+        );
     }
 }
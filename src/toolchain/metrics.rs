@@ -0,0 +1,113 @@
+//! Timing and network metrics of the installation of each component, reported in the
+//! installation summary.
+//!
+//! The components are installed in their own tasks, the metrics are recorded into the ones of
+//! the component of the current task.
+
+use serde::Serialize;
+use std::{
+    collections::BTreeMap,
+    future::Future,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// Phase of the installation of a component.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Phase {
+    /// Resolution of the Xtensa Rust version, e.g. GitHub queries, reported for the Xtensa Rust
+    /// component only.
+    Resolve,
+    /// Download of the artifacts.
+    Download,
+    /// Extraction of the artifacts.
+    Extract,
+    /// Remaining installation steps, e.g. install scripts and rustup.
+    Configure,
+}
+
+/// Metrics of the installation of a component.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct ComponentMetrics {
+    /// Time spent in each phase, in milliseconds.
+    pub timings_ms: BTreeMap<Phase, u64>,
+    /// Bytes downloaded, excluding the prefetched artifacts.
+    pub bytes_downloaded: u64,
+    /// Artifacts read from the prefetched artifacts instead of being downloaded.
+    pub cache_hits: u32,
+    /// Failed download and installation attempts that were retried.
+    pub retries: u32,
+}
+
+impl ComponentMetrics {
+    /// Adds time spent in a phase.
+    pub fn add_time(&mut self, phase: Phase, elapsed: Duration) {
+        *self.timings_ms.entry(phase).or_default() += elapsed.as_millis() as u64;
+    }
+
+    /// Returns the time spent in the phases, in milliseconds.
+    pub fn total_ms(&self) -> u64 {
+        self.timings_ms.values().sum()
+    }
+}
+
+tokio::task_local! {
+    /// Metrics of the component installed by the current task.
+    static METRICS: Arc<Mutex<ComponentMetrics>>;
+}
+
+/// Runs the installation of a component, recording its metrics.
+pub async fn measure<F: Future>(metrics: Arc<Mutex<ComponentMetrics>>, install: F) -> F::Output {
+    METRICS.scope(metrics, install).await
+}
+
+/// Records metrics of the component of the current task, if any.
+pub fn record(update: impl FnOnce(&mut ComponentMetrics)) {
+    let _ = METRICS.try_with(|metrics| update(&mut metrics.lock().unwrap()));
+}
+
+/// Records the time spent in a phase since `started`.
+pub fn record_time(phase: Phase, started: Instant) {
+    let elapsed = started.elapsed();
+    record(|metrics| metrics.add_time(phase, elapsed));
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::toolchain::metrics::{measure, record, ComponentMetrics, Phase};
+    use serde_json::json;
+    use std::{
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    #[tokio::test]
+    async fn test_component_metrics() {
+        // Recording outside of a measured installation is a no-op
+        record(|metrics| metrics.retries += 1);
+
+        let metrics = Arc::new(Mutex::new(ComponentMetrics::default()));
+        measure(metrics.clone(), async {
+            record(|metrics| {
+                metrics.add_time(Phase::Download, Duration::from_millis(1500));
+                metrics.add_time(Phase::Extract, Duration::from_millis(200));
+                metrics.add_time(Phase::Download, Duration::from_millis(500));
+                metrics.bytes_downloaded += 1024;
+                metrics.retries += 1;
+            });
+        })
+        .await;
+        let metrics = metrics.lock().unwrap().clone();
+        assert_eq!(metrics.total_ms(), 2200);
+        assert_eq!(
+            serde_json::to_value(&metrics).unwrap(),
+            json!({
+                "timings_ms": {"download": 2000, "extract": 200},
+                "bytes_downloaded": 1024,
+                "cache_hits": 0,
+                "retries": 1
+            })
+        );
+    }
+}
@@ -1,100 +1,269 @@
 //! Different toolchains source and installation tools.
 
 #[cfg(windows)]
-use crate::env::set_env;
+use crate::env::{
+    print_process_scope_msg, register_cmd_autorun, set_env, unregister_cmd_autorun, unset_env,
+    without_powershell, EnvScope,
+};
+#[cfg(target_os = "macos")]
+use crate::toolchain::codesign::sign_binaries;
+#[cfg(windows)]
+use crate::toolchain::{
+    antivirus::{check_quarantine, record_extracted, warn_slow_extraction},
+    remove::schedule_removal_on_reboot,
+};
 use crate::{
-    cli::InstallOpts,
-    env::{create_export_file, get_export_file, print_post_install_msg},
+    ci::{CiMode, GithubActions},
+    cli::{InstallOpts, UninstallOpts},
+    env::{
+        export_files, get_export_file, print_manual_env_instructions, print_post_install_msg,
+        remove_managed_exports, try_create_export_file, Export, ExportFileMode, ExportShell,
+        EXPORT_TEMPLATE_VERSION,
+    },
     error::Error,
-    host_triple::get_host_triple,
-    targets::Target,
+    host_triple::{check_host_triple, get_artifact_triple, get_host_triple, HostTriple},
+    ldproxy::{install_ldproxy, print_linker_config},
+    manifest::{Manifest, MANIFEST_SCHEMA_VERSION},
+    permissions::set_permissive,
+    pin::{find_toolchain_version_file, read_toolchain_version},
+    releases::{fetch_releases, RUST_BUILD_REPOSITORY},
+    report::render,
+    sandbox::{check_plan, check_write, enable_sandbox, SandboxPlan},
+    target_dir::TARGET_DIR_EXPORT_ID,
+    targets::{check_experimental_targets, Target},
     toolchain::{
-        gcc::{Gcc, RISCV_GCC, XTENSA_GCC},
+        archive::extract,
+        codesign::disable_codesign,
+        compatibility::resolve_esp_idf,
+        diff::{format_table, ComponentDiff},
+        fetch::{
+            check_artifacts, check_connectivity, enable_system_proxy, fetch,
+            set_asset_host_override, set_mirrors, set_proxy_routes, GithubQueryOpts,
+        },
+        gcc::{
+            uninstall_gcc_toolchain, uninstall_gcc_toolchains, Gcc, DEFAULT_GCC_RELEASE,
+            DEFAULT_GCC_REPOSITORY, RISCV_GCC, XTENSA_GCC,
+        },
+        hashes::{hashes_path, record_hashes},
+        io::{default_io_parallelism, extraction_permit, set_io_parallelism},
+        lifecycle::warn_deprecated,
         llvm::Llvm,
-        rust::{check_rust_installation, get_rustup_home, RiscVTarget, XtensaRust},
+        metrics::{measure, record, record_time, ComponentMetrics, Phase},
+        offline::{cached_artifact, set_artifacts_dir},
+        overrides::{archive_name, artifact_override, set_artifact_overrides},
+        progress::Progress,
+        rust::{
+            check_rust_installation, validate_toolchain_name, Homes, RiscVTarget, XtensaRust,
+            RISCV_TARGETS,
+        },
+        rustup::set_concurrent_rustup,
+        summary::{ComponentStatus, ComponentSummary, InstallSummary},
+        tmp::{remove_stale_tmp_dirs, set_tmp_dir},
+        verify::verify_build,
     },
 };
+#[cfg(unix)]
+use crate::{env::get_espup_dir, wsl::warn_drvfs_paths};
+#[cfg(unix)]
+use crate::{permissions::normalize_permissions, toolchain::integrity::Extraction};
 use async_trait::async_trait;
-use flate2::bufread::GzDecoder;
-use log::{debug, info, warn};
+use bytes::Bytes;
+use log::{debug, info, log_enabled, warn, Level};
 use miette::Result;
-use reqwest::{blocking::Client, header};
-use retry::{delay::Fixed, retry};
 use std::{
-    env,
-    fs::{create_dir_all, remove_file, File},
-    io::{copy, Write},
+    collections::HashSet,
+    fs::{create_dir_all, read_dir, remove_file, File},
+    io::Write,
     path::{Path, PathBuf},
-    sync::atomic::{self, AtomicUsize},
+    process::{Command, Stdio},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
 };
-use tar::Archive;
-use tokio::{fs::remove_dir_all, sync::mpsc};
+use tokio::{sync::Semaphore, task::JoinSet};
 use tokio_retry::{strategy::FixedInterval, Retry};
-use tokio_stream::StreamExt;
-use xz2::read::XzDecoder;
-use zip::ZipArchive;
 
+pub mod antivirus;
+pub mod archive;
+pub mod clone;
+pub mod codesign;
+pub mod compatibility;
+pub mod diff;
+pub mod espidf;
+pub mod fetch;
+pub mod gc;
 pub mod gcc;
+pub mod hashes;
+#[cfg(unix)]
+pub mod integrity;
+pub mod io;
+pub mod lifecycle;
 pub mod llvm;
+pub mod metrics;
+pub mod offline;
+pub mod overrides;
+pub mod preflight;
+pub mod progress;
+pub mod remove;
 pub mod rust;
+pub mod rustup;
+pub mod rustup_cache;
+pub mod summary;
+pub mod tmp;
+pub mod verify;
+pub mod versions;
 
-lazy_static::lazy_static! {
-    pub static ref PROCESS_BARS: indicatif::MultiProgress = indicatif::MultiProgress::new();
-    pub static ref DOWNLOAD_CNT: AtomicUsize = AtomicUsize::new(0);
-}
+/// File marking the `--no-rustup` prefixes created by espup, the only ones it replaces or removes.
+pub const PREFIX_MARKER: &str = ".espup-prefix";
 
 pub enum InstallMode {
     Install,
     Update,
 }
 
+/// State of a component in the system.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ComponentState {
+    /// The component is not installed.
+    NotInstalled,
+    /// The version espup installs is present.
+    Installed,
+    /// A different version of the component is installed.
+    Outdated(String),
+}
+
+/// Component of an installation that can be uninstalled on its own.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum ToolchainComponent {
+    /// Xtensa Rust toolchain.
+    XtensaRust,
+    /// Espressif LLVM.
+    Llvm,
+    /// Xtensa GCC toolchain.
+    GccXtensa,
+    /// RISC-V GCC toolchain.
+    GccRiscv,
+}
+
+impl ToolchainComponent {
+    /// Returns the identifier of the component, see [`Component::id`].
+    pub fn id(&self) -> &'static str {
+        match self {
+            ToolchainComponent::XtensaRust => "xtensa-rust",
+            ToolchainComponent::Llvm => "llvm",
+            ToolchainComponent::GccXtensa => "xtensa-gcc",
+            ToolchainComponent::GccRiscv => "riscv-gcc",
+        }
+    }
+
+    /// Returns whether the component is required to build for the targets.
+    pub fn required_by(&self, targets: &HashSet<Target>, std: bool) -> bool {
+        match self {
+            ToolchainComponent::XtensaRust | ToolchainComponent::Llvm => {
+                targets.iter().any(|t| t.is_xtensa())
+            }
+            ToolchainComponent::GccXtensa => !std && targets.iter().any(|t| t.is_xtensa()),
+            // The Espressif RISC-V toolchain is optional
+            ToolchainComponent::GccRiscv => false,
+        }
+    }
+
+    /// Removes the component from the toolchain directory.
+    async fn uninstall(&self, toolchain_dir: &Path) -> Result<(), Error> {
+        match self {
+            ToolchainComponent::XtensaRust => XtensaRust::uninstall(toolchain_dir).await,
+            ToolchainComponent::Llvm => Llvm::uninstall(toolchain_dir).await,
+            ToolchainComponent::GccXtensa => {
+                uninstall_gcc_toolchain(toolchain_dir, XTENSA_GCC).await
+            }
+            ToolchainComponent::GccRiscv => uninstall_gcc_toolchain(toolchain_dir, RISCV_GCC).await,
+        }
+    }
+}
+
 #[async_trait]
-pub trait Installable {
+pub trait Component {
     /// Install some application, returning a vector of any required exports
-    async fn install(&self) -> Result<Vec<String>, Error>;
+    async fn install(&self, progress: &Progress) -> Result<Vec<Export>, Error>;
+    /// Removes the component, no matter which version is installed
+    async fn uninstall(&self) -> Result<(), Error>;
     /// Returns the name of the toolchain being installeds
     fn name(&self) -> String;
-}
-
-/// Get https proxy from environment variables(if any)
-///
-/// sadly there is not standard on the environment variable name for the proxy, but it seems
-/// that the most common are:
-///
-/// - https_proxy(or http_proxy for http)
-/// - HTTPS_PROXY(or HTTP_PROXY for http)
-/// - all_proxy
-/// - ALL_PROXY
-///
-/// hence we will check for all of them
-fn https_proxy() -> Option<String> {
-    for proxy in ["https_proxy", "HTTPS_PROXY", "all_proxy", "ALL_PROXY"] {
-        if let Ok(proxy_addr) = std::env::var(proxy) {
-            info!("Get Proxy from env var: {}={}", proxy, proxy_addr);
-            return Some(proxy_addr);
+    /// Returns a stable identifier of the component, used in machine readable output
+    fn id(&self) -> String;
+    /// Returns the directory the component is installed in
+    fn path(&self) -> PathBuf;
+    /// Returns the version of the component that is currently installed, if any
+    fn installed_version(&self) -> Option<String>;
+    /// Returns the version of the component that espup installs
+    fn latest_version(&self) -> String;
+    /// Returns the disk space used by the component, in bytes
+    fn size_on_disk(&self) -> u64;
+    /// Returns the URLs of the artifacts downloaded by the installation
+    fn artifact_urls(&self) -> Vec<String> {
+        Vec::new()
+    }
+    /// Returns the state of the component
+    fn state(&self) -> ComponentState {
+        match self.installed_version() {
+            None => ComponentState::NotInstalled,
+            Some(version) if version == self.latest_version() => ComponentState::Installed,
+            Some(version) => ComponentState::Outdated(version),
         }
     }
-    None
 }
 
-/// Build a reqwest client with proxy if env var is set
-fn build_proxy_blocking_client() -> Result<Client, Error> {
-    let mut builder = reqwest::blocking::Client::builder();
-    if let Some(proxy) = https_proxy() {
-        builder = builder.proxy(reqwest::Proxy::https(&proxy).unwrap());
+/// Returns the size in bytes of a file or directory.
+pub(crate) fn disk_usage(path: &Path) -> u64 {
+    let Ok(metadata) = path.symlink_metadata() else {
+        return 0;
+    };
+    if !metadata.is_dir() {
+        return metadata.len();
     }
-    let client = builder.build()?;
-    Ok(client)
+    read_dir(path)
+        .map(|entries| {
+            entries
+                .filter_map(|entry| entry.ok())
+                .map(|entry| disk_usage(&entry.path()))
+                .sum()
+        })
+        .unwrap_or_default()
 }
 
-/// Build a reqwest client with proxy if env var is set
-fn build_proxy_async_client() -> Result<reqwest::Client, Error> {
-    let mut builder = reqwest::Client::builder();
-    if let Some(proxy) = https_proxy() {
-        builder = builder.proxy(reqwest::Proxy::https(&proxy).unwrap());
+/// Runs a child process, failing with the error built by `error` from its standard error output.
+///
+/// When debug logging is enabled, the output of the process is also forwarded to the logs
+/// prefixed with `component`; otherwise its standard output is discarded.
+pub(crate) fn run_command(
+    component: &str,
+    cmd: &mut Command,
+    error: impl FnOnce(String) -> Error,
+) -> Result<(), Error> {
+    let debug = log_enabled!(Level::Debug);
+    if debug {
+        debug!("[{}] Running {:?}", component, cmd);
+        cmd.stdout(Stdio::piped());
+    } else {
+        cmd.stdout(Stdio::null());
+    }
+    let output = cmd.stderr(Stdio::piped()).output()?;
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    if debug {
+        for line in String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .chain(stderr.lines())
+        {
+            debug!("[{}] {}", component, line);
+        }
+    }
+    if output.status.success() {
+        Ok(())
+    } else if stderr.trim().is_empty() {
+        Err(error(output.status.to_string()))
+    } else {
+        Err(error(stderr.trim().to_string()))
     }
-    let client = builder.build()?;
-    Ok(client)
 }
 
 /// Downloads a file from a URL and uncompresses it, if necesary, to the output directory.
@@ -104,7 +273,9 @@ pub async fn download_file(
     output_directory: &str,
     uncompress: bool,
     strip: bool,
+    progress: &Progress,
 ) -> Result<String, Error> {
+    check_write(Path::new(output_directory))?;
     let file_path = format!("{output_directory}/{file_name}");
     if Path::new(&file_path).exists() {
         warn!(
@@ -118,149 +289,464 @@ pub async fn download_file(
             .map_err(|_| Error::CreateDirectory(output_directory.to_string()))?;
     }
 
-    let resp = {
-        let client = build_proxy_async_client()?;
-        client.get(&url).send().await?
+    let (bytes, archive) = match artifact_override(file_name) {
+        Some(path) => {
+            info!("Installing '{}' instead of '{}'", path.display(), url);
+            (
+                Bytes::from(tokio::fs::read(&path).await?),
+                archive_name(&path),
+            )
+        }
+        None => {
+            let started = Instant::now();
+            let bytes = fetch(&url, file_name, progress).await?;
+            record_time(Phase::Download, started);
+            (bytes, file_name.to_string())
+        }
     };
-    let bytes = {
-        let len = resp.content_length();
+    if uncompress {
+        let permit = extraction_permit().await;
+        #[cfg(unix)]
+        let extraction = Extraction::begin(Path::new(output_directory))?;
+        let started = Instant::now();
+        #[cfg_attr(not(windows), allow(unused_variables))]
+        let extracted = extract(
+            &bytes,
+            &archive,
+            Path::new(output_directory),
+            strip.then_some("esp"),
+        )?;
+        drop(permit);
+        #[cfg(unix)]
+        normalize_permissions(Path::new(output_directory))?;
+        #[cfg(target_os = "macos")]
+        sign_binaries(Path::new(output_directory));
+        record_time(Phase::Extract, started);
+        #[cfg(unix)]
+        extraction.complete()?;
+        #[cfg(windows)]
+        {
+            warn_slow_extraction(
+                Path::new(output_directory),
+                extracted.written_bytes,
+                started.elapsed(),
+            );
+            record_extracted(&extracted.files);
+        }
+    } else {
+        debug!("Creating file: '{}'", file_path);
+        let mut out = File::create(&file_path)?;
+        out.write_all(&bytes)?;
+    }
+    Ok(file_path)
+}
 
-        // draw a progress bar
-        let sty = indicatif::ProgressStyle::with_template(
-            "[{elapsed_precise}] {bar:40.cyan/blue} {pos:>7}/{len:7} {msg}",
-        )
-        .unwrap()
-        .progress_chars("##-");
-        let bar = len
-            .map(indicatif::ProgressBar::new)
-            .unwrap_or(indicatif::ProgressBar::no_length());
-        let bar = PROCESS_BARS.add(bar);
-        bar.set_style(sty);
-        bar.set_message(file_name.to_string());
-        DOWNLOAD_CNT.fetch_add(1, atomic::Ordering::Relaxed);
-
-        let mut size_downloaded = 0;
-        let mut stream = resp.bytes_stream();
-        let mut bytes = bytes::BytesMut::new();
-        while let Some(chunk_result) = stream.next().await {
-            let chunk = chunk_result?;
-            size_downloaded += chunk.len();
-            bar.set_position(size_downloaded as u64);
-
-            bytes.extend(&chunk);
-        }
-        bar.finish_with_message(format!("{} download complete", file_name));
-        // leave the progress bar after completion
-        if DOWNLOAD_CNT.fetch_sub(1, atomic::Ordering::Relaxed) == 1 {
-            // clear all progress bars
-            PROCESS_BARS.clear().unwrap();
-            info!("All downloads complete");
-        }
-        // wait while DOWNLOAD_CNT is not zero
-
-        bytes.freeze()
+/// Installs up to `jobs` components in parallel, returning their exports and, if requested,
+/// summaries. The `resolve` time spent resolving the Xtensa Rust version is reported in the
+/// metrics of the Xtensa Rust component.
+///
+/// The first failure aborts the installations still in progress, so they don't keep downloading,
+/// and returns the name of the failed component with the error. In GitHub Actions, the logs of
+/// each component are grouped, so the components are installed one at a time.
+async fn install_components(
+    to_install: Vec<Box<dyn Component + Send + Sync>>,
+    summary_only: bool,
+    jobs: Option<usize>,
+    github: bool,
+    resolve: Duration,
+    progress: &Progress,
+) -> Result<(Vec<Export>, Vec<ComponentSummary>), (String, Error)> {
+    let jobs = if github {
+        1
+    } else {
+        jobs.unwrap_or(Semaphore::MAX_PERMITS).max(1)
     };
-    if uncompress {
-        let extension = Path::new(file_name).extension().unwrap().to_str().unwrap();
-        match extension {
-            "zip" => {
-                let mut tmpfile = tempfile::tempfile()?;
-                tmpfile.write_all(&bytes)?;
-                let mut zipfile = ZipArchive::new(tmpfile).unwrap();
-                if strip {
-                    for i in 0..zipfile.len() {
-                        let mut file = zipfile.by_index(i).unwrap();
-                        if !file.name().starts_with("esp/") {
-                            continue;
-                        }
-
-                        let file_path = PathBuf::from(file.name().to_string());
-                        let stripped_name = file_path.strip_prefix("esp/").unwrap();
-                        let outpath = Path::new(output_directory).join(stripped_name);
-
-                        if file.name().ends_with('/') {
-                            create_dir_all(&outpath)?;
-                        } else {
-                            create_dir_all(outpath.parent().unwrap())?;
-                            let mut outfile = File::create(&outpath)?;
-                            copy(&mut file, &mut outfile)?;
-                        }
+    let permits = Arc::new(Semaphore::new(jobs));
+    let mut pending: Vec<String> = to_install.iter().map(|app| app.name()).collect();
+    let mut installs = JoinSet::new();
+    for app in to_install {
+        let retry_strategy = FixedInterval::from_millis(50).take(3);
+        let progress = progress.clone();
+        let permits = permits.clone();
+        installs.spawn(async move {
+            let _permit = permits
+                .acquire_owned()
+                .await
+                .expect("Install semaphore closed");
+            if github {
+                GithubActions::group(&format!("Installing {}", app.name()));
+            }
+            let status = summary_only.then(|| ComponentStatus::from(&app.state()));
+            let metrics = Arc::new(Mutex::new(ComponentMetrics::default()));
+            let started = Instant::now();
+            let res = measure(
+                metrics.clone(),
+                Retry::spawn(retry_strategy, || async {
+                    let res = app.install(&progress).await;
+                    if let Err(ref err) = res {
+                        warn!(
+                            "Installation for '{}' failed, retrying. Error: {}",
+                            app.name(),
+                            err
+                        );
+                        record(|metrics| metrics.retries += 1);
                     }
-                } else {
-                    zipfile.extract(output_directory).unwrap();
-                }
+                    res
+                }),
+            )
+            .await;
+            let mut metrics = metrics.lock().unwrap().clone();
+            // The time not spent downloading or extracting the artifacts
+            let configure = Duration::from_millis(
+                (started.elapsed().as_millis() as u64).saturating_sub(metrics.total_ms()),
+            );
+            metrics.add_time(Phase::Configure, configure);
+            if app.id() == ToolchainComponent::XtensaRust.id() {
+                metrics.add_time(Phase::Resolve, resolve);
             }
-            "gz" => {
-                debug!("Extracting tar.gz file to '{}'", output_directory);
-
-                let bytes = bytes.to_vec();
-                let tarfile = GzDecoder::new(bytes.as_slice());
-                let mut archive = Archive::new(tarfile);
-                archive.unpack(output_directory)?;
+            let summary = status
+                .filter(|_| res.is_ok())
+                .map(|status| ComponentSummary {
+                    component: app.id(),
+                    status,
+                    version: app.installed_version(),
+                    path: app.path(),
+                    metrics,
+                });
+            if github {
+                GithubActions::endgroup();
             }
-            "xz" => {
-                debug!("Extracting tar.xz file to '{}'", output_directory);
-                let bytes = bytes.to_vec();
-                let tarfile = XzDecoder::new(bytes.as_slice());
-                let mut archive = Archive::new(tarfile);
-                archive.unpack(output_directory)?;
+            (app.name(), res, summary)
+        });
+    }
+
+    // Read the results of the install tasks as they complete.
+    let mut exports = Vec::new();
+    let mut summaries = Vec::new();
+    let mut installed = Vec::new();
+    while let Some(joined) = installs.join_next().await {
+        let (name, res, summary) = joined.expect("Join install task error");
+        pending.retain(|pending| pending != &name);
+        match res {
+            Ok(component_exports) => {
+                exports.extend(component_exports);
+                summaries.extend(summary);
+                installed.push(name);
             }
-            _ => {
-                return Err(Error::UnsuportedFileExtension(extension.to_string()));
+            Err(e) => {
+                installs.shutdown().await;
+                if !installed.is_empty() {
+                    info!("Installed components: {}", installed.join(", "));
+                }
+                if !pending.is_empty() {
+                    warn!("Cancelled the installation of: {}", pending.join(", "));
+                }
+                return Err((name, e));
             }
         }
+    }
+    Ok((exports, summaries))
+}
+
+/// Installs only the LLVM libraries into `dir`, exporting `LIBCLANG_PATH` for bindgen.
+///
+/// The export is written to the export file when requested, and printed otherwise.
+async fn install_llvm_libs(
+    dir: &Path,
+    host_triple: &HostTriple,
+    xtensa_rust_version: &str,
+    export_file: &Path,
+    write_export_file: bool,
+    progress: &Progress,
+) -> Result<()> {
+    let dir = std::env::current_dir().map_err(Error::IoError)?.join(dir);
+    let llvm = Llvm::libs_only(&dir, host_triple, xtensa_rust_version)?;
+    check_connectivity(std::slice::from_ref(&llvm.repository_url)).await?;
+    info!(
+        "Installing the LLVM {} libraries into '{}'",
+        llvm.version,
+        llvm.path.display()
+    );
+    let exports = llvm.install(progress).await?;
+    if write_export_file {
+        // The export file may be shared with other installations
+        try_create_export_file(export_file, &exports, ExportFileMode::Merge)?;
+        info!("'LIBCLANG_PATH' exported in '{}'", export_file.display());
     } else {
-        debug!("Creating file: '{}'", file_path);
-        let mut out = File::create(&file_path)?;
-        out.write_all(&bytes)?;
+        print_manual_env_instructions(&exports, ExportShell::from_path(export_file));
+    }
+    Ok(())
+}
+
+/// Returns how the export files are updated.
+///
+/// `--reuse-exports` is respected even when the export files were written with older templates,
+/// with a warning since they may miss some exports.
+fn export_file_mode(reuse: bool, regenerate: bool, outdated: bool) -> ExportFileMode {
+    if reuse {
+        if outdated {
+            warn!(
+                "The export file templates changed since the installation, run without \
+                '--reuse-exports' to regenerate them"
+            );
+        }
+        ExportFileMode::Reuse
+    } else if regenerate {
+        ExportFileMode::Regenerate
+    } else {
+        ExportFileMode::Merge
     }
-    Ok(file_path)
 }
 
 /// Installs or updates the Espressif Rust ecosystem.
-pub async fn install(args: InstallOpts, install_mode: InstallMode) -> Result<()> {
+///
+/// The download progress belongs to the installation, but the network, sandbox, temporary
+/// directory, rustup, permissions, code signing and artifact settings of `args` are applied to the
+/// whole process: installations running concurrently in the same process must use the same ones.
+pub async fn install(mut args: InstallOpts, install_mode: InstallMode) -> Result<()> {
+    let started = Instant::now();
     match install_mode {
         InstallMode::Install => info!("Installing the Espressif Rust ecosystem"),
         InstallMode::Update => info!("Updating the Espressif Rust ecosystem"),
     }
+    validate_toolchain_name(&args.name, args.force, &args.homes)?;
+    let explicit_export_file = args.export_file.is_some();
+    let toolchain_dir = match (&args.prefix, args.no_rustup) {
+        (Some(prefix), true) => std::env::current_dir()
+            .map_err(Error::IoError)?
+            .join(prefix),
+        _ => args.homes.rustup_home().join("toolchains").join(&args.name),
+    };
+    if args.print_av_exclusion {
+        println!("{}", toolchain_dir.display());
+        return Ok(());
+    }
+    if args.no_rustup {
+        check_prefix(&toolchain_dir)?;
+    }
+    if args.sandbox {
+        enable_sandbox(&toolchain_dir, args.tmp_dir.as_deref());
+    }
+    #[cfg(not(windows))]
     let export_file = get_export_file(args.export_file)?;
-    let mut exports: Vec<String> = Vec::new();
-    let host_triple = get_host_triple(args.default_host)?;
-    let xtensa_rust_version = if let Some(toolchain_version) = &args.toolchain_version {
+    #[cfg(windows)]
+    let export_file = if args.no_ps1 {
+        let (export_file, shells) =
+            without_powershell(&get_export_file(args.export_file)?, &args.export_shells);
+        args.export_shells = shells;
+        export_file
+    } else {
+        get_export_file(args.export_file)?
+    };
+    #[cfg(windows)]
+    if args.cmd_autorun && !args.export_shells.contains(&ExportShell::Cmd) {
+        args.export_shells.push(ExportShell::Cmd);
+    }
+    #[cfg(windows)]
+    let no_modify_env = args.no_modify_env || args.env_scope == EnvScope::None;
+    #[cfg(not(windows))]
+    let no_modify_env = args.no_modify_env;
+    if args.sandbox {
+        // The user environment variables are written to the registry
+        #[cfg(windows)]
+        if !no_modify_env && args.env_scope == EnvScope::User {
+            return Err(
+                Error::WriteOutsideSandbox("HKEY_CURRENT_USER\\Environment".to_string()).into(),
+            );
+        }
+        check_plan(&SandboxPlan {
+            name: &args.name,
+            export_file: (!no_modify_env).then_some(export_file.as_path()),
+            export_shells: &args.export_shells,
+            llvm: args.targets.iter().any(|t| t.is_xtensa()),
+        })?;
+    }
+    let progress = if args.quiet || args.summary_only {
+        Progress::hidden()
+    } else if args.progress_events {
+        Progress::events()
+    } else {
+        Progress::new(args.no_progress)
+    };
+    if args.system_proxy {
+        enable_system_proxy();
+    }
+    set_asset_host_override(args.asset_host_override.clone());
+    if args.no_codesign {
+        disable_codesign();
+    }
+    if args.permissive {
+        set_permissive();
+    }
+    set_proxy_routes(&args.proxy_for);
+    set_tmp_dir(args.tmp_dir.clone());
+    set_concurrent_rustup(args.concurrent_rustup);
+    if !args.sandbox {
+        remove_stale_tmp_dirs(&args.homes.rustup_home().join("tmp"));
+    }
+    set_mirrors(&args.mirrors);
+    set_artifact_overrides(&args.override_artifact);
+    if let Some(artifacts_dir) = &args.artifacts_dir {
+        set_artifacts_dir(artifacts_dir);
+    }
+    #[cfg(unix)]
+    warn_drvfs_paths(&[
+        ("RUSTUP_HOME", &args.homes.rustup_home()),
+        ("CARGO_HOME", &args.homes.cargo_home()),
+        ("espup directory", &get_espup_dir()),
+    ]);
+    check_experimental_targets(&args.targets, args.experimental_targets)?;
+    let mut exports: Vec<Export> = Vec::new();
+    let host_triple = get_host_triple(args.default_host.clone())?;
+    if args.default_host.is_some() {
+        check_host_triple(&host_triple, args.allow_foreign_host)?;
+    }
+    let artifact_triple = get_artifact_triple(&host_triple, args.artifact_arch.as_deref())?;
+    // Report unavailable components before downloading anything
+    if args.targets.iter().any(|t| t.is_xtensa()) {
+        if !host_triple.has_espressif_artifacts() {
+            return Err(Error::UnsupportedArtifact(
+                "Xtensa Rust".to_string(),
+                host_triple.to_string(),
+            )
+            .into());
+        }
+        if !artifact_triple.has_espressif_artifacts() {
+            return Err(Error::UnsupportedArtifact(
+                "LLVM".to_string(),
+                artifact_triple.to_string(),
+            )
+            .into());
+        }
+    }
+    if args.esp_riscv_gcc && !args.std && !artifact_triple.has_espressif_artifacts() {
+        return Err(
+            Error::UnsupportedArtifact("GCC".to_string(), artifact_triple.to_string()).into(),
+        );
+    }
+    let version_file = match (&args.toolchain_version_file, &install_mode) {
+        (Some(path), _) => Some(path.clone()),
+        (None, InstallMode::Install)
+            if args.toolchain_version.is_none() && args.compat.is_none() =>
+        {
+            std::env::current_dir()
+                .ok()
+                .and_then(|dir| find_toolchain_version_file(&dir))
+        }
+        _ => None,
+    };
+    if let Some(path) = version_file {
+        let version = read_toolchain_version(&path)?;
+        info!(
+            "Using Xtensa Rust {} pinned in '{}'",
+            version,
+            path.display()
+        );
+        args.toolchain_version = Some(version);
+    }
+    if let Some(esp_idf_version) = &args.compat {
+        let compat = resolve_esp_idf(esp_idf_version)?;
+        info!("Versions compatible with ESP-IDF v{}:", compat.esp_idf);
+        info!("  Xtensa Rust  {}", compat.xtensa_rust);
+        info!("  LLVM         {}", compat.toolchains.llvm);
+        if args.std {
+            info!(
+                "  ESP-IDF GCC  {} (installed by esp-idf-sys)",
+                compat.esp_idf_gcc
+            );
+        } else {
+            info!("  GCC          {}", compat.toolchains.gcc);
+        }
+        args.toolchain_version = Some(compat.xtensa_rust.to_string());
+    }
+    let resolve_started = Instant::now();
+    let github_query_opts = GithubQueryOpts {
+        retries: args.retries,
+        timeout: Duration::from_secs(args.timeout),
+    };
+    let mut xtensa_rust_version = if let Some(toolchain_version) = &args.toolchain_version {
         if !args.skip_version_parse {
-            XtensaRust::parse_version(toolchain_version)?
+            XtensaRust::parse_version(toolchain_version, &github_query_opts)?
         } else {
             toolchain_version.clone()
         }
     } else {
         // Get the latest version of the Xtensa Rust toolchain. If that fails, return an error::GithubTokenInvalid
-        XtensaRust::get_latest_version()
+        // unless the rate limit was exceeded
+        XtensaRust::get_latest_version(github_query_opts)
             .await
-            .map_err(|_| Error::GithubTokenInvalid)?
+            .map_err(|e| match e {
+                Error::GithubRateLimit(_) => e,
+                _ => Error::GithubTokenInvalid,
+            })?
     };
-    let toolchain_dir = get_rustup_home().join("toolchains").join(args.name);
-    let llvm: Llvm = Llvm::new(
-        &toolchain_dir,
-        &host_triple,
-        args.extended_llvm,
-        &xtensa_rust_version,
-    )?;
-    let targets = args.targets;
+    if let Some(dir) = &args.llvm_only_libs_path {
+        return install_llvm_libs(
+            dir,
+            &artifact_triple,
+            &xtensa_rust_version,
+            &export_file,
+            explicit_export_file && !no_modify_env,
+            &progress,
+        )
+        .await;
+    }
+    // Releases occasionally lack the artifacts of a host, the fallback requires their assets.
+    // Versions selected by `--compat` are kept, an older one may not match the printed versions.
+    if args.targets.iter().any(|t| t.is_xtensa()) && !args.skip_version_parse {
+        xtensa_rust_version = XtensaRust::published_version(
+            &xtensa_rust_version,
+            &host_triple,
+            &github_query_opts,
+            !args.no_fallback && args.compat.is_none(),
+        )
+        .await?;
+    }
+    set_io_parallelism(
+        args.io_parallelism
+            .unwrap_or_else(|| default_io_parallelism(&toolchain_dir)),
+    );
+    let targets = &args.targets;
+    // LLVM is only required by the Xtensa targets
+    let llvm = if targets.iter().any(|t| t.is_xtensa()) {
+        Some(Llvm::new(
+            &toolchain_dir,
+            &artifact_triple,
+            args.extended_llvm,
+            &xtensa_rust_version,
+        )?)
+    } else {
+        None
+    };
+    // Only pinned versions may be outdated, the releases were already queried to resolve them
+    if let (Some(llvm), Some(_)) = (&llvm, &args.toolchain_version) {
+        let releases: Vec<String> = if args.skip_version_parse {
+            Vec::new()
+        } else {
+            fetch_releases(RUST_BUILD_REPOSITORY, &github_query_opts)
+                .map(|releases| releases.iter().map(|r| r.version().to_string()).collect())
+                .unwrap_or_default()
+        };
+        warn_deprecated(&xtensa_rust_version, &llvm.version, &releases);
+    }
+    let resolve = resolve_started.elapsed();
     let xtensa_rust = if targets.contains(&Target::ESP32)
         || targets.contains(&Target::ESP32S2)
         || targets.contains(&Target::ESP32S3)
     {
-        Some(XtensaRust::new(
-            &xtensa_rust_version,
-            &host_triple,
-            &toolchain_dir,
-        ))
+        Some(XtensaRust {
+            cargo_home: args.homes.cargo_home(),
+            components: args.xtensa_components.clone(),
+            rustup_home: args.homes.rustup_home(),
+            ..XtensaRust::new(&xtensa_rust_version, &host_triple, &toolchain_dir)
+        })
     } else {
         None
     };
 
     debug!(
         "Arguments:
+            - Artifact triple: {}
             - Export file: {:?}
             - Host triple: {}
             - LLVM Toolchain: {:?}
@@ -270,6 +756,7 @@ pub async fn install(args: InstallOpts, install_mode: InstallMode) -> Result<()>
             - Targets: {:?}
             - Toolchain path: {:?}
             - Toolchain version: {:?}",
+        artifact_triple,
         &export_file,
         host_triple,
         &llvm,
@@ -281,137 +768,815 @@ pub async fn install(args: InstallOpts, install_mode: InstallMode) -> Result<()>
         args.toolchain_version,
     );
 
-    check_rust_installation().await?;
+    if !args.no_rustup {
+        check_rust_installation(&args.homes).await?;
+    }
 
     // Build up a vector of installable applications, all of which implement the
-    // `Installable` async trait.
-    let mut to_install = Vec::<Box<dyn Installable + Send + Sync>>::new();
+    // `Component` async trait.
+    let mut to_install = Vec::<Box<dyn Component + Send + Sync>>::new();
+    // URLs whose hosts must be reachable before starting the installation.
+    let mut preflight_urls = Vec::new();
 
     if let Some(ref xtensa_rust) = xtensa_rust {
+        preflight_urls.push(xtensa_rust.dist_url.clone());
         to_install.push(Box::new(xtensa_rust.to_owned()));
     }
 
-    // Check if ther is any Xtensa target
-    if targets.iter().any(|t| t.is_xtensa()) {
+    if let Some(ref llvm) = llvm {
+        preflight_urls.push(llvm.repository_url.clone());
         to_install.push(Box::new(llvm.to_owned()));
     }
 
-    if targets.iter().any(|t| t.is_riscv()) {
-        let riscv_target = RiscVTarget::new(&args.nightly_version);
+    if args.no_rustup && targets.iter().any(|t| t.is_riscv()) {
+        warn!(
+            "RISC-V targets are installed through rustup, install the '{}' toolchain with the '{}' targets manually",
+            args.nightly_version,
+            RISCV_TARGETS.join("', '")
+        );
+    } else if targets.iter().any(|t| t.is_riscv()) {
+        let riscv_target = RiscVTarget::new(
+            &args.nightly_version,
+            &args.riscv_profile,
+            &args.riscv_components,
+            &args.homes,
+        );
         to_install.push(Box::new(riscv_target));
     }
 
-    if !args.std {
-        if targets
+    let xtensa_gcc = (!args.std
+        && targets
             .iter()
-            .any(|t| t == &Target::ESP32 || t == &Target::ESP32S2 || t == &Target::ESP32S3)
-        {
-            let xtensa_gcc = Gcc::new(XTENSA_GCC, &host_triple, &toolchain_dir);
-            to_install.push(Box::new(xtensa_gcc));
+            .any(|t| t == &Target::ESP32 || t == &Target::ESP32S2 || t == &Target::ESP32S3))
+    .then(|| {
+        Gcc::resolve(
+            XTENSA_GCC,
+            &artifact_triple,
+            &toolchain_dir,
+            args.from_espidf,
+        )
+    });
+    // By default only install the Espressif RISC-V toolchain if the user explicitly wants to
+    let riscv_gcc = (!args.std
+        && args.esp_riscv_gcc
+        && targets.iter().any(|t| t != &Target::ESP32))
+    .then(|| {
+        Gcc::resolve(
+            RISCV_GCC,
+            &artifact_triple,
+            &toolchain_dir,
+            args.from_espidf,
+        )
+    });
+    for gcc in xtensa_gcc.iter().chain(riscv_gcc.iter()) {
+        if !gcc.from_espidf {
+            preflight_urls.push(DEFAULT_GCC_REPOSITORY.to_string());
         }
+        to_install.push(Box::new(gcc.to_owned()));
+    }
 
-        // By default only install the Espressif RISC-V toolchain if the user explicitly wants to
-        if args.esp_riscv_gcc && targets.iter().any(|t| t != &Target::ESP32) {
-            let riscv_gcc = Gcc::new(RISCV_GCC, &host_triple, &toolchain_dir);
-            to_install.push(Box::new(riscv_gcc));
+    // Prefetched artifacts are neither checked nor downloaded
+    let artifacts: Vec<(String, String)> = to_install
+        .iter()
+        .flat_map(|app| {
+            let name = app.name();
+            app.artifact_urls()
+                .into_iter()
+                .filter(|url| cached_artifact(url).is_none())
+                .map(move |url| (name.clone(), url))
+        })
+        .collect();
+    if args.artifacts_dir.is_none() || !artifacts.is_empty() {
+        check_connectivity(&preflight_urls).await?;
+        check_artifacts(&artifacts).await?;
+    }
+
+    if args.diff_only {
+        let mut diffs = Vec::new();
+        for app in &to_install {
+            diffs.push(ComponentDiff::new(app.as_ref()).await);
         }
+        print!("{}", format_table(&diffs));
+        return Ok(());
     }
 
-    // With a list of applications to install, install them all in parallel.
-    let installable_items = to_install.len();
-    let (tx, mut rx) = mpsc::channel::<Result<Vec<String>, Error>>(installable_items);
-    for app in to_install {
-        let tx = tx.clone();
-        let retry_strategy = FixedInterval::from_millis(50).take(3);
-        tokio::spawn(async move {
-            let res = Retry::spawn(retry_strategy, || async {
-                let res = app.install().await;
-                if let Err(ref err) = res {
-                    warn!(
-                        "Installation for '{}' failed, retrying. Error: {}",
-                        app.name(),
-                        err
-                    );
-                }
-                res
-            })
-            .await;
-            tx.send(res).await.unwrap();
-        });
+    if args.no_rustup {
+        claim_prefix(&toolchain_dir)?;
+    }
+    let github = args.ci == Some(CiMode::Github);
+    let mut summaries = match install_components(
+        to_install,
+        args.summary_only,
+        args.jobs,
+        github,
+        resolve,
+        &progress,
+    )
+    .await
+    {
+        Ok((component_exports, summaries)) => {
+            exports.extend(component_exports);
+            summaries
+        }
+        Err((name, e)) => {
+            if github {
+                GithubActions::error(&format!("{name} installation failed"), &e.to_string());
+            }
+            return Err(e.into());
+        }
+    };
+    #[cfg(windows)]
+    check_quarantine(&toolchain_dir).await?;
+    if let (true, Some(xtensa_rust)) = (args.no_rustup, &xtensa_rust) {
+        // Without rustup the toolchain binaries are used directly instead of through the proxies
+        let bin_dir = toolchain_dir.join("bin");
+        exports.push(Export::prepend_path(
+            &xtensa_rust.id(),
+            &bin_dir.display().to_string(),
+        ));
+        exports.push(Export::set(
+            &xtensa_rust.id(),
+            "RUSTC",
+            &bin_dir
+                .join(format!("rustc{}", std::env::consts::EXE_SUFFIX))
+                .display()
+                .to_string(),
+        ));
+    }
+    if let Some(target_dir) = args.target_dir_style.target_dir() {
+        exports.push(Export::set(
+            TARGET_DIR_EXPORT_ID,
+            "CARGO_TARGET_DIR",
+            target_dir,
+        ));
     }
 
-    // Read the results of the install tasks as they complete.
-    for _ in 0..installable_items {
-        let names = rx.recv().await.unwrap()?;
-        exports.extend(names);
+    // The toolchains are only found in the overridden homes
+    exports.extend(args.homes.exports());
+
+    if github {
+        GithubActions::export(&exports)?;
+        GithubActions::set_output("toolchain-version", &xtensa_rust_version)?;
+        if let Some(ref llvm) = llvm {
+            GithubActions::set_output("llvm-version", &llvm.version)?;
+        }
+        GithubActions::set_output("gcc-version", DEFAULT_GCC_RELEASE)?;
+        GithubActions::set_output("nightly-version", &args.nightly_version)?;
+        GithubActions::set_output("toolchain-path", &toolchain_dir.display().to_string())?;
+        GithubActions::set_output("export-file", &export_file.display().to_string())?;
     }
 
-    create_export_file(&export_file, &exports)?;
-    #[cfg(windows)]
-    set_env()?;
+    let previous_template_version = Manifest::load(&args.name)
+        .ok()
+        .flatten()
+        .map(|manifest| manifest.export_template_version);
+    let outdated_exports = matches!(install_mode, InstallMode::Update)
+        && previous_template_version.is_some_and(|version| version < EXPORT_TEMPLATE_VERSION);
+    let modified_env = if no_modify_env {
+        if !args.quiet && !args.summary_only {
+            print_manual_env_instructions(&exports, ExportShell::from_path(&export_file));
+        }
+        false
+    } else {
+        #[cfg(windows)]
+        if args.env_scope == EnvScope::User {
+            set_env(&exports)?;
+        }
+        let mode = export_file_mode(
+            args.reuse_exports,
+            args.regenerate_exports,
+            outdated_exports,
+        );
+        let written = try_create_export_file(&export_file, &exports, mode)?;
+        for shell_export_file in export_files(&export_file, &args.export_shells)
+            .iter()
+            .skip(1)
+        {
+            try_create_export_file(shell_export_file, &exports, mode)?;
+        }
+        #[cfg(windows)]
+        if args.cmd_autorun {
+            let batch_file = export_file.with_extension(ExportShell::Cmd.extension());
+            match register_cmd_autorun(&batch_file) {
+                Ok(()) => info!("New 'cmd.exe' sessions will run '{}'", batch_file.display()),
+                Err(e) => warn!("Failed to register the 'cmd.exe' AutoRun command: {}", e),
+            }
+        }
+        written
+    };
+
+    Manifest {
+        schema_version: MANIFEST_SCHEMA_VERSION,
+        name: args.name.clone(),
+        artifact_arch: args.artifact_arch.clone(),
+        host_triple: host_triple.to_string(),
+        esp_riscv_gcc: args.esp_riscv_gcc,
+        export_file: export_file.clone(),
+        export_shells: args.export_shells.clone(),
+        export_template_version: match previous_template_version {
+            Some(version) if no_modify_env || args.reuse_exports => version,
+            None if no_modify_env => 0,
+            _ => EXPORT_TEMPLATE_VERSION,
+        },
+        exports: exports.clone(),
+        extended_llvm: args.extended_llvm,
+        from_espidf: args.from_espidf,
+        nightly_version: args.nightly_version.clone(),
+        prefix: args.no_rustup.then(|| toolchain_dir.clone()),
+        riscv_components: args.riscv_components.clone(),
+        riscv_profile: args.riscv_profile.clone(),
+        std: args.std,
+        target_dir_style: args.target_dir_style,
+        targets: targets.clone(),
+        toolchain_version: xtensa_rust_version.clone(),
+        xtensa_components: args.xtensa_components.clone(),
+    }
+    .save()?;
+    if args.verify_build {
+        // Once the export file and the manifest are written, so a failed verification leaves an
+        // installation that can be fixed or uninstalled. Xtensa targets are preferred, as they
+        // exercise every installed component
+        let target = targets
+            .iter()
+            .find(|t| t.is_xtensa())
+            .or_else(|| targets.iter().next())
+            .unwrap();
+        let gcc = if target.is_xtensa() {
+            xtensa_gcc.as_ref()
+        } else {
+            riscv_gcc.as_ref()
+        };
+        let toolchain = if target.is_xtensa() {
+            &args.name
+        } else {
+            &args.nightly_version
+        };
+        let llvm = llvm.as_ref().filter(|_| target.is_xtensa());
+        verify_build(target, toolchain, llvm, gcc, &args.homes)?;
+    }
+    if args.record_hashes || hashes_path(&args.name).exists() {
+        refresh_hashes(&args.name, &toolchain_dir).await?;
+    }
     match install_mode {
         InstallMode::Install => info!("Installation successfully completed!"),
         InstallMode::Update => info!("Update successfully completed!"),
     }
+    if args.ldproxy {
+        install_ldproxy(&args.homes)?;
+        if !args.quiet && !args.summary_only {
+            print_linker_config(targets);
+        }
+    }
+
+    if args.summary_only {
+        summaries.sort_by(|a, b| a.component.cmp(&b.component));
+        let summary = InstallSummary {
+            components: summaries,
+            duration_ms: started.elapsed().as_millis() as u64,
+        };
+        print!("{}", render(&summary, args.summary_format));
+        return Ok(());
+    }
+    if args.quiet {
+        return Ok(());
+    }
 
-    print_post_install_msg(&export_file)?;
+    if modified_env {
+        #[cfg(windows)]
+        if args.env_scope == EnvScope::Process {
+            print_process_scope_msg(&export_file);
+            return Ok(());
+        }
+        print_post_install_msg(&export_file)?;
+    }
     Ok(())
 }
 
-/// Queries the GitHub API and returns the JSON response.
-pub fn github_query(url: &str) -> Result<serde_json::Value, Error> {
-    debug!("Querying GitHub API: '{}'", url);
-    let mut headers = header::HeaderMap::new();
-    headers.insert(header::USER_AGENT, "espup".parse().unwrap());
-    headers.insert(
-        header::ACCEPT,
-        "application/vnd.github+json".parse().unwrap(),
+/// Adds targets to an existing installation, installing only the missing components.
+pub async fn add_targets(name: &str, targets: &HashSet<Target>, log_level: &str) -> Result<()> {
+    let manifest = Manifest::load(name)?.ok_or_else(|| Error::MissingManifest(name.to_string()))?;
+    let new_targets: HashSet<Target> = manifest.targets.union(targets).copied().collect();
+    if new_targets == manifest.targets {
+        info!("All the requested targets are already installed");
+        return Ok(());
+    }
+
+    info!(
+        "Adding targets {} to the '{}' toolchain",
+        target_names(targets.difference(&manifest.targets)),
+        name
     );
+    install(
+        manifest.to_install_opts(new_targets, log_level),
+        InstallMode::Install,
+    )
+    .await
+}
 
-    headers.insert("X-GitHub-Api-Version", "2022-11-28".parse().unwrap());
-    if let Some(token) = env::var_os("GITHUB_TOKEN") {
-        debug!("Auth header added");
-        headers.insert(
-            "Authorization",
-            format!("Bearer {}", token.to_string_lossy())
-                .parse()
-                .unwrap(),
-        );
+/// Returns the sorted, comma separated names of the targets.
+fn target_names<'a>(targets: impl Iterator<Item = &'a Target>) -> String {
+    let mut names: Vec<String> = targets.map(|target| target.to_string()).collect();
+    names.sort();
+    names.join(", ")
+}
+
+/// Reinstalls the `rust-src` component of an existing installation.
+pub async fn reinstall_rust_src(name: &str) -> Result<()> {
+    let manifest = Manifest::load(name)?.ok_or_else(|| Error::MissingManifest(name.to_string()))?;
+    let host_triple = get_host_triple(Some(manifest.host_triple.clone()))?;
+    let toolchain_dir = manifest.toolchain_dir();
+    let homes = manifest.homes();
+    XtensaRust {
+        cargo_home: homes.cargo_home(),
+        rustup_home: homes.rustup_home(),
+        ..XtensaRust::new(&manifest.toolchain_version, &host_triple, &toolchain_dir)
     }
-    let client = build_proxy_blocking_client()?;
-    let json: Result<serde_json::Value, Error> = retry(
-        Fixed::from_millis(100).take(5),
-        || -> Result<serde_json::Value, Error> {
-            let res = client.get(url).headers(headers.clone()).send()?.text()?;
-            if res.contains(
-                "https://docs.github.com/rest/overview/resources-in-the-rest-api#rate-limiting",
-            ) {
-                return Err(Error::GithubRateLimit);
-            }
+    .reinstall_src(&Progress::new(false))
+    .await?;
+    if hashes_path(name).exists() {
+        refresh_hashes(name, &toolchain_dir).await?;
+    }
+    info!("'rust-src' component successfully reinstalled!");
+    Ok(())
+}
 
-            if res.contains("Bad credentials") {
-                return Err(Error::GithubTokenInvalid);
-            }
+/// Records the hashes of the files of a toolchain, replacing the previously recorded ones.
+async fn refresh_hashes(name: &str, toolchain_dir: &Path) -> Result<()> {
+    let (name, dir) = (name.to_string(), toolchain_dir.to_path_buf());
+    let hashed = tokio::task::spawn_blocking(move || record_hashes(&name, &dir))
+        .await
+        .unwrap()?;
+    info!("Recorded the hashes of {} files", hashed);
+    Ok(())
+}
 
-            let json: serde_json::Value =
-                serde_json::from_str(&res).map_err(|_| Error::SerializeJson)?;
-            Ok(json)
-        },
+/// Removes targets from an existing installation, uninstalling the components that are no longer
+/// required.
+pub async fn remove_targets(name: &str, targets: &HashSet<Target>, log_level: &str) -> Result<()> {
+    let manifest = Manifest::load(name)?.ok_or_else(|| Error::MissingManifest(name.to_string()))?;
+    let new_targets: HashSet<Target> = manifest.targets.difference(targets).copied().collect();
+    if new_targets == manifest.targets {
+        info!("None of the requested targets are installed");
+        return Ok(());
+    }
+    if new_targets.is_empty() {
+        return Err(Error::NoTargetsLeft(name.to_string()).into());
+    }
+
+    info!(
+        "Removing targets {} from the '{}' toolchain",
+        target_names(targets.intersection(&manifest.targets)),
+        name
+    );
+    let toolchain_dir = manifest.toolchain_dir();
+    if manifest.prefix.is_some() {
+        check_prefix(&toolchain_dir)?;
+    }
+    if toolchain_dir.exists() {
+        if !new_targets.iter().any(|t| t.is_xtensa()) {
+            Llvm::uninstall(&toolchain_dir).await?;
+            uninstall_gcc_toolchain(&toolchain_dir, XTENSA_GCC).await?;
+            XtensaRust::uninstall(&toolchain_dir).await?;
+        }
+        if !new_targets.iter().any(|t| t.is_riscv()) {
+            uninstall_gcc_toolchain(&toolchain_dir, RISCV_GCC).await?;
+        }
+        if read_dir(&toolchain_dir)
+            .map_err(Error::IoError)?
+            .next()
+            .is_none()
+        {
+            remove_dir(&toolchain_dir).await?;
+        }
+    }
+
+    // Reinstalling with the remaining targets reuses the existing components and regenerates
+    // the export file and the manifest.
+    install(
+        manifest.to_install_opts(new_targets, log_level),
+        InstallMode::Install,
     )
-    .map_err(|err| err.error);
-    json
+    .await
+}
+
+/// Removes components from an existing installation, updating its manifest and export file.
+pub async fn remove_components(name: &str, components: &HashSet<ToolchainComponent>) -> Result<()> {
+    let mut manifest =
+        Manifest::load(name)?.ok_or_else(|| Error::MissingManifest(name.to_string()))?;
+    let toolchain_dir = manifest.toolchain_dir();
+    for component in components {
+        if component.required_by(&manifest.targets, manifest.std) {
+            warn!(
+                "'{}' is required by the installed targets, which won't build until it is reinstalled with 'espup update'",
+                component.id()
+            );
+        }
+        if toolchain_dir.exists() {
+            component.uninstall(&toolchain_dir).await?;
+        }
+    }
+    if components.contains(&ToolchainComponent::GccRiscv) {
+        manifest.esp_riscv_gcc = false;
+    }
+    #[cfg(windows)]
+    unset_env(
+        &manifest
+            .exports
+            .iter()
+            .filter(|export| components.iter().any(|c| c.id() == export.component))
+            .cloned()
+            .collect::<Vec<_>>(),
+    )?;
+    manifest
+        .exports
+        .retain(|export| !components.iter().any(|c| c.id() == export.component));
+    for export_file in manifest.export_files() {
+        try_create_export_file(&export_file, &manifest.exports, ExportFileMode::Merge)?;
+    }
+    manifest.export_template_version = EXPORT_TEMPLATE_VERSION;
+    manifest.save()?;
+    if hashes_path(name).exists() {
+        refresh_hashes(name, &toolchain_dir).await?;
+    }
+    info!(
+        "Components successfully removed from the '{}' toolchain",
+        name
+    );
+    Ok(())
+}
+
+/// Outcome of an uninstallation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UninstallReport {
+    /// Directory of the toolchain.
+    pub toolchain_dir: PathBuf,
+    /// Whether the toolchain directory was present and removed.
+    pub removed_toolchain: bool,
+    /// Whether the toolchain directory was in use and its removal was scheduled on the next reboot.
+    pub removal_on_reboot: bool,
+    /// Export files whose block managed by espup was removed.
+    pub export_files: Vec<PathBuf>,
+}
+
+/// Fails if the prefix of a `--no-rustup` installation is an existing directory that espup
+/// didn't create, as installing into it or uninstalling it would delete its contents.
+pub fn check_prefix(prefix: &Path) -> Result<(), Error> {
+    let empty = read_dir(prefix).map_or(true, |mut entries| entries.next().is_none());
+    if empty || prefix.join(PREFIX_MARKER).exists() {
+        Ok(())
+    } else {
+        Err(Error::ForeignPrefix(prefix.display().to_string()))
+    }
+}
+
+/// Creates the prefix of a `--no-rustup` installation and marks it as created by espup.
+fn claim_prefix(prefix: &Path) -> Result<(), Error> {
+    check_prefix(prefix)?;
+    let marker = prefix.join(PREFIX_MARKER);
+    if !marker.exists() {
+        create_dir_all(prefix)?;
+        File::create(marker)?;
+    }
+    Ok(())
+}
+
+/// Removes every component of the toolchain and the toolchain directory, returning whether it
+/// was present.
+pub async fn uninstall_toolchain(toolchain_dir: &Path) -> Result<bool, Error> {
+    if !toolchain_dir.exists() {
+        return Ok(false);
+    }
+    Llvm::uninstall(toolchain_dir).await?;
+    uninstall_gcc_toolchains(toolchain_dir).await?;
+    XtensaRust::uninstall(toolchain_dir).await?;
+    remove_dir(toolchain_dir).await?;
+    Ok(true)
+}
+
+/// Uninstalls a toolchain: its components, the block managed by espup in the export file and its
+/// manifest.
+pub async fn uninstall(args: &UninstallOpts, homes: &Homes) -> Result<UninstallReport> {
+    validate_toolchain_name(&args.name, args.force, homes)?;
+    info!("Uninstalling the Espressif Rust ecosystem");
+    // A broken manifest must not prevent the uninstallation
+    let manifest = Manifest::load(&args.name).ok().flatten();
+    let toolchain_dir = manifest
+        .as_ref()
+        .map(Manifest::toolchain_dir)
+        .unwrap_or_else(|| homes.rustup_home().join("toolchains").join(&args.name));
+    // The manifest records exactly which entries were added to the user environment
+    #[cfg(windows)]
+    if let Some(manifest) = &manifest {
+        unset_env(&manifest.exports)?;
+    }
+
+    if manifest
+        .as_ref()
+        .is_some_and(|manifest| manifest.prefix.is_some())
+    {
+        check_prefix(&toolchain_dir)?;
+    }
+    let result = uninstall_toolchain(&toolchain_dir).await;
+    #[cfg(windows)]
+    let result = match result {
+        Err(Error::DirectoryInUse(..)) if args.remove_on_reboot => {
+            schedule_removal_on_reboot(&toolchain_dir)?;
+            warn!(
+                "'{}' is in use and will be removed on the next reboot",
+                toolchain_dir.display()
+            );
+            return finish_uninstall(args, manifest, toolchain_dir, false, true);
+        }
+        result => result,
+    };
+    let removed_toolchain = result?;
+    finish_uninstall(args, manifest, toolchain_dir, removed_toolchain, false)
+}
+
+/// Removes the block managed by espup in the export files and the manifest once the toolchain
+/// directory is handled.
+fn finish_uninstall(
+    args: &UninstallOpts,
+    manifest: Option<Manifest>,
+    toolchain_dir: PathBuf,
+    removed_toolchain: bool,
+    removal_on_reboot: bool,
+) -> Result<UninstallReport> {
+    let mut export_files = Vec::new();
+    if !args.except_exports {
+        if let Some(manifest) = &manifest {
+            export_files.extend(manifest.export_files());
+        }
+        // Installations without manifest used the default export file
+        let export_file = if args.export_file.is_some() || manifest.is_none() {
+            Some(get_export_file(args.export_file.clone())?)
+        } else {
+            None
+        };
+        if let Some(export_file) = export_file.filter(|file| !export_files.contains(file)) {
+            export_files.push(export_file);
+        }
+    }
+    for export_file in &export_files {
+        remove_managed_exports(export_file, &toolchain_dir)?;
+        #[cfg(windows)]
+        if ExportShell::from_path(export_file) == ExportShell::Cmd {
+            unregister_cmd_autorun(export_file)?;
+        }
+    }
+    Manifest::remove(&args.name)?;
+    Ok(UninstallReport {
+        toolchain_dir,
+        removed_toolchain,
+        removal_on_reboot,
+        export_files,
+    })
 }
 
 /// Checks if the directory exists and deletes it if it does.
-pub async fn remove_dir(path: &Path) -> Result<()> {
+pub async fn remove_dir(path: &Path) -> Result<(), Error> {
     if path.exists() {
         debug!(
             "Deleting the Xtensa Rust toolchain located in '{}'",
             &path.display()
         );
-        remove_dir_all(&path)
-            .await
-            .map_err(|_| Error::RemoveDirectory(path.display().to_string()))?;
+        remove::remove_dir_all(path).await?;
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        env::{Export, ExportFileMode},
+        error::Error,
+        targets::Target,
+        toolchain::{
+            check_prefix, claim_prefix, export_file_mode, install_components, progress::Progress,
+            run_command, target_names, uninstall_toolchain, Component, ToolchainComponent,
+            PREFIX_MARKER,
+        },
+    };
+    use async_trait::async_trait;
+    use std::{
+        fs::{create_dir_all, write},
+        path::PathBuf,
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        },
+        time::{Duration, Instant},
+    };
+    use tempfile::TempDir;
+
+    /// Component whose installation takes `delay`, then fails or succeeds.
+    struct FakeComponent {
+        name: &'static str,
+        delay: Duration,
+        fail: bool,
+        finished: Arc<AtomicBool>,
+    }
+
+    #[async_trait]
+    impl Component for FakeComponent {
+        async fn install(&self, _progress: &Progress) -> Result<Vec<Export>, Error> {
+            tokio::time::sleep(self.delay).await;
+            if self.fail {
+                return Err(Error::UnsupportedComponent(self.name.to_string()));
+            }
+            self.finished.store(true, Ordering::Relaxed);
+            Ok(vec![Export::set(self.name, "VAR", "value")])
+        }
+
+        async fn uninstall(&self) -> Result<(), Error> {
+            Ok(())
+        }
+
+        fn name(&self) -> String {
+            self.name.to_string()
+        }
+
+        fn id(&self) -> String {
+            self.name.to_string()
+        }
+
+        fn path(&self) -> PathBuf {
+            PathBuf::new()
+        }
+
+        fn installed_version(&self) -> Option<String> {
+            None
+        }
+
+        fn latest_version(&self) -> String {
+            "1.0.0".to_string()
+        }
+
+        fn size_on_disk(&self) -> u64 {
+            0
+        }
+    }
+
+    fn fake(name: &'static str, delay: u64, fail: bool) -> (Box<FakeComponent>, Arc<AtomicBool>) {
+        let finished = Arc::new(AtomicBool::new(false));
+        let component = FakeComponent {
+            name,
+            delay: Duration::from_millis(delay),
+            fail,
+            finished: finished.clone(),
+        };
+        (Box::new(component), finished)
+    }
+
+    #[tokio::test]
+    async fn test_install_components_cancellation() {
+        let (quick, _) = fake("quick", 0, false);
+        let (failing, _) = fake("failing", 0, true);
+        let (slow, slow_finished) = fake("slow", 60_000, false);
+        let start = Instant::now();
+        let (name, _) = install_components(
+            vec![quick, failing, slow],
+            false,
+            None,
+            false,
+            Duration::ZERO,
+            &Progress::hidden(),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(name, "failing");
+        // The slow installation was aborted instead of awaited
+        assert!(start.elapsed() < Duration::from_secs(10));
+        assert!(!slow_finished.load(Ordering::Relaxed));
+
+        let (first, _) = fake("first", 0, false);
+        let (second, _) = fake("second", 10, false);
+        let (exports, summaries) = install_components(
+            vec![first, second],
+            true,
+            None,
+            false,
+            Duration::ZERO,
+            &Progress::hidden(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(exports.len(), 2);
+        assert_eq!(summaries.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_install_components_jobs() {
+        let (first, _) = fake("first", 200, false);
+        let (second, _) = fake("second", 200, false);
+        let start = Instant::now();
+        install_components(
+            vec![first, second],
+            false,
+            Some(1),
+            false,
+            Duration::ZERO,
+            &Progress::hidden(),
+        )
+        .await
+        .unwrap();
+        // The components were installed one after the other
+        assert!(start.elapsed() >= Duration::from_millis(400));
+    }
+
+    #[tokio::test]
+    async fn test_uninstall_toolchain() {
+        let temp_dir = TempDir::new().unwrap();
+        let toolchain_dir = temp_dir.path().join("esp");
+        assert!(!uninstall_toolchain(&toolchain_dir).await.unwrap());
+
+        // Partial layouts, e.g. after an interrupted installation
+        create_dir_all(toolchain_dir.join("riscv32-esp-elf/esp-14.2.0_20240906")).unwrap();
+        assert!(uninstall_toolchain(&toolchain_dir).await.unwrap());
+        assert!(!toolchain_dir.exists());
+
+        create_dir_all(toolchain_dir.join("lib/rustlib")).unwrap();
+        create_dir_all(toolchain_dir.join("xtensa-esp-elf")).unwrap();
+        write(toolchain_dir.join("lib/rustlib/components"), "rustc\n").unwrap();
+        assert!(uninstall_toolchain(&toolchain_dir).await.unwrap());
+        assert!(!toolchain_dir.exists());
+    }
+
+    #[test]
+    fn test_prefix_ownership() {
+        let temp_dir = TempDir::new().unwrap();
+        let prefix = temp_dir.path().join("tools");
+        check_prefix(&prefix).unwrap();
+        claim_prefix(&prefix).unwrap();
+        assert!(prefix.join(PREFIX_MARKER).exists());
+        write(prefix.join("rustc"), "").unwrap();
+        claim_prefix(&prefix).unwrap();
+
+        // Existing empty directories can be used, but not the ones with files of the user
+        let empty = temp_dir.path().join("empty");
+        create_dir_all(&empty).unwrap();
+        claim_prefix(&empty).unwrap();
+        write(temp_dir.path().join("notes.txt"), "").unwrap();
+        assert!(matches!(
+            claim_prefix(temp_dir.path()),
+            Err(Error::ForeignPrefix(_))
+        ));
+        assert!(!temp_dir.path().join(PREFIX_MARKER).exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_run_command() {
+        use std::process::Command;
+
+        run_command(
+            "sh",
+            Command::new("sh").args(["-c", "echo ok"]),
+            Error::XtensaRust,
+        )
+        .unwrap();
+        let error = run_command(
+            "sh",
+            Command::new("sh").args(["-c", "echo 'no space left' >&2; exit 3"]),
+            Error::XtensaRust,
+        );
+        assert!(matches!(error, Err(Error::XtensaRust(stderr)) if stderr == "no space left"));
+        let error = run_command(
+            "sh",
+            Command::new("sh").args(["-c", "exit 3"]),
+            Error::XtensaRust,
+        );
+        assert!(matches!(error, Err(Error::XtensaRust(status)) if status.contains('3')));
+    }
+
+    #[test]
+    fn test_export_file_mode() {
+        assert_eq!(export_file_mode(false, false, false), ExportFileMode::Merge);
+        assert_eq!(export_file_mode(false, false, true), ExportFileMode::Merge);
+        assert_eq!(
+            export_file_mode(false, true, false),
+            ExportFileMode::Regenerate
+        );
+        assert_eq!(export_file_mode(true, false, false), ExportFileMode::Reuse);
+        // Outdated export files are only reported when they are reused
+        assert_eq!(export_file_mode(true, false, true), ExportFileMode::Reuse);
+    }
+
+    #[test]
+    fn test_target_names() {
+        let targets = [Target::ESP32S3, Target::ESP32, Target::ESP32C3];
+        assert_eq!(target_names(targets.iter()), "esp32, esp32c3, esp32s3");
+        assert_eq!(target_names([].iter()), "");
+    }
+
+    #[test]
+    fn test_component_required_by() {
+        let targets = [Target::ESP32S3, Target::ESP32C3].into_iter().collect();
+        assert!(ToolchainComponent::Llvm.required_by(&targets, false));
+        assert!(ToolchainComponent::GccXtensa.required_by(&targets, false));
+        assert!(!ToolchainComponent::GccXtensa.required_by(&targets, true));
+        assert!(!ToolchainComponent::GccRiscv.required_by(&targets, false));
+        let targets = [Target::ESP32C3].into_iter().collect();
+        assert!(!ToolchainComponent::XtensaRust.required_by(&targets, false));
+    }
+}
@@ -0,0 +1,180 @@
+//! Offline installations from artifacts prefetched with `espup fetch`.
+//!
+//! Artifacts are stored as `<dir>/<host triple>/<file name>`. Installations look them up by file
+//! name in every host directory, as some artifacts (e.g. `rust-src`) are shared by all hosts.
+
+use crate::{
+    cli::FetchOpts,
+    error::Error,
+    host_triple::HostTriple,
+    targets::Target,
+    toolchain::{
+        fetch::{fetch_to_file, GithubQueryOpts},
+        gcc::{Gcc, RISCV_GCC, XTENSA_GCC},
+        llvm::Llvm,
+        progress::Progress,
+        rust::XtensaRust,
+        Component,
+    },
+};
+use log::{debug, info};
+use miette::Result;
+use std::{
+    collections::HashSet,
+    fs::{create_dir_all, read_dir, rename},
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{Arc, OnceLock},
+};
+use tokio::{sync::Semaphore, task::JoinSet};
+
+/// Directory of the prefetched artifacts used by the installation, if any.
+static ARTIFACTS_DIR: OnceLock<PathBuf> = OnceLock::new();
+
+/// Makes the installation use the artifacts prefetched into the given directory.
+pub fn set_artifacts_dir(dir: &Path) {
+    let _ = ARTIFACTS_DIR.set(dir.to_path_buf());
+}
+
+/// Returns the path of the prefetched artifact of the given URL, if any.
+pub fn cached_artifact(url: &str) -> Option<PathBuf> {
+    find_artifact(ARTIFACTS_DIR.get()?, url)
+}
+
+fn find_artifact(dir: &Path, url: &str) -> Option<PathBuf> {
+    let file_name = url.rsplit('/').next()?;
+    read_dir(dir)
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path().join(file_name))
+        .find(|path| path.is_file())
+}
+
+/// Returns the URLs of the artifacts installed on the given host.
+pub(crate) fn artifact_urls(
+    host_triple: &HostTriple,
+    toolchain_version: &str,
+    targets: &HashSet<Target>,
+    extended_llvm: bool,
+    std: bool,
+    esp_riscv_gcc: bool,
+) -> Result<Vec<String>, Error> {
+    // Artifacts are only downloaded, the toolchain directory is never created
+    let toolchain_dir = Path::new(".");
+    let mut urls = Vec::new();
+    if targets.iter().any(|t| t.is_xtensa()) {
+        let xtensa_rust = XtensaRust::new(toolchain_version, host_triple, toolchain_dir);
+        urls.push(xtensa_rust.dist_url);
+        // The sources are only installed on Unix hosts
+        if !matches!(
+            host_triple,
+            HostTriple::X86_64PcWindowsMsvc | HostTriple::X86_64PcWindowsGnu
+        ) {
+            urls.push(xtensa_rust.src_dist_url);
+        }
+        let llvm = Llvm::new(toolchain_dir, host_triple, extended_llvm, toolchain_version)?;
+        urls.extend(llvm.artifact_urls());
+        if !std {
+            urls.extend(Gcc::new(XTENSA_GCC, host_triple, toolchain_dir).artifact_urls());
+        }
+    }
+    if !std && esp_riscv_gcc && targets.iter().any(|t| t != &Target::ESP32) {
+        urls.extend(Gcc::new(RISCV_GCC, host_triple, toolchain_dir).artifact_urls());
+    }
+    Ok(urls)
+}
+
+/// Downloads the artifacts of every given host into the output directory.
+///
+/// Artifacts already present are kept, so interrupted prefetches can be resumed. At most
+/// `--jobs` artifacts are downloaded at once, each streamed to disk.
+pub async fn prefetch(args: FetchOpts) -> Result<()> {
+    let progress = Progress::new(args.no_progress);
+    let toolchain_version = match &args.toolchain_version {
+        Some(version) => version.clone(),
+        None => XtensaRust::get_latest_version(GithubQueryOpts::default()).await?,
+    };
+    info!(
+        "Fetching Xtensa Rust {} artifacts into '{}'",
+        toolchain_version,
+        args.output_dir.display()
+    );
+
+    let permits = Arc::new(Semaphore::new(args.jobs.max(1)));
+    let mut downloads = JoinSet::new();
+    for host in &args.hosts {
+        // Foreign hosts are expected here, so the host triple is not checked against the system
+        let host_triple =
+            HostTriple::from_str(host).map_err(|_| Error::UnsupportedHostTriple(host.clone()))?;
+        let host_dir = args.output_dir.join(host_triple.to_string());
+        create_dir_all(&host_dir)
+            .map_err(|_| Error::CreateDirectory(host_dir.display().to_string()))?;
+        for url in artifact_urls(
+            &host_triple,
+            &toolchain_version,
+            &args.targets,
+            args.extended_llvm,
+            args.std,
+            args.esp_riscv_gcc,
+        )? {
+            let file_name = url.rsplit('/').next().unwrap_or_default().to_string();
+            let destination = host_dir.join(&file_name);
+            if destination.exists() {
+                debug!("'{}' was already fetched", destination.display());
+                continue;
+            }
+            let progress = progress.clone();
+            let permits = permits.clone();
+            downloads.spawn(async move {
+                let _permit = permits
+                    .acquire_owned()
+                    .await
+                    .expect("Download semaphore closed");
+                // Partial downloads are never left with the final name
+                let partial = destination.with_extension("part");
+                fetch_to_file(&url, &file_name, &progress, &partial).await?;
+                rename(&partial, &destination)?;
+                Ok::<(), Error>(())
+            });
+        }
+    }
+    while let Some(result) = downloads.join_next().await {
+        result.map_err(|e| Error::DownloadTask(e.to_string()))??;
+    }
+
+    info!(
+        "Artifacts fetched, install them with 'espup install --artifacts-dir {}'",
+        args.output_dir.display()
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::toolchain::offline::find_artifact;
+    use std::fs::{create_dir_all, write};
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_find_artifact() {
+        let dir = TempDir::new().unwrap();
+        let host_dir = dir.path().join("x86_64-unknown-linux-gnu");
+        create_dir_all(&host_dir).unwrap();
+        write(host_dir.join("rust-src-1.85.0.0.tar.xz"), "").unwrap();
+
+        assert_eq!(
+            find_artifact(
+                dir.path(),
+                "https://github.com/esp-rs/rust-build/releases/download/v1.85.0.0/rust-src-1.85.0.0.tar.xz"
+            ),
+            Some(host_dir.join("rust-src-1.85.0.0.tar.xz"))
+        );
+        assert_eq!(
+            find_artifact(
+                dir.path(),
+                "https://github.com/esp-rs/rust-build/releases/download/v1.85.0.0/rust-1.85.0.0-x86_64-unknown-linux-gnu.tar.xz"
+            ),
+            None
+        );
+    }
+}
@@ -0,0 +1,159 @@
+//! Local artifacts installed instead of the downloaded ones, e.g. a locally built LLVM or a
+//! patched GCC, with `--override-artifact <artifact>=<path>`.
+//!
+//! Overridden artifacts are extracted, laid out and exported like the downloaded ones, and their
+//! components are always reinstalled.
+
+use crate::{
+    error::Error,
+    toolchain::gcc::{RISCV_GCC, XTENSA_GCC},
+};
+use std::{
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+/// Artifacts that can be overridden, with the name of their downloads without extension.
+pub const ARTIFACTS: [(&str, &str); 6] = [
+    ("xtensa-rust", "rust"),
+    ("rust-src", "rust-src"),
+    ("llvm", "idf_tool_xtensa_elf_clang.libs"),
+    ("llvm-full", "idf_tool_xtensa_elf_clang.full"),
+    ("xtensa-gcc", XTENSA_GCC),
+    ("riscv-gcc", RISCV_GCC),
+];
+/// Extensions of the supported archives.
+const ARCHIVE_EXTENSIONS: [&str; 3] = [".tar.xz", ".tar.gz", ".zip"];
+
+/// Artifacts overridden by the installation.
+static OVERRIDES: Mutex<Vec<ArtifactOverride>> = Mutex::new(Vec::new());
+
+/// Local archive installed instead of a downloaded artifact.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArtifactOverride {
+    /// Overridden artifact, one of [`ARTIFACTS`].
+    pub artifact: String,
+    /// Path of the archive.
+    pub path: PathBuf,
+}
+
+/// Parses an `artifact=path` override.
+pub fn parse_artifact_override(value: &str) -> Result<ArtifactOverride, Error> {
+    let invalid = |reason: &str| Error::InvalidArtifactOverride(value.to_string(), reason.into());
+    let (artifact, path) = value
+        .split_once('=')
+        .ok_or_else(|| invalid("expected 'artifact=path'"))?;
+    let artifact = artifact.trim().to_lowercase();
+    if !ARTIFACTS.iter().any(|(name, _)| *name == artifact) {
+        return Err(invalid(&format!(
+            "unknown artifact '{artifact}', expected one of: {}",
+            ARTIFACTS.map(|(name, _)| name).join(", ")
+        )));
+    }
+    let path = PathBuf::from(path.trim());
+    if !ARCHIVE_EXTENSIONS
+        .iter()
+        .any(|extension| path.to_string_lossy().ends_with(extension))
+    {
+        return Err(invalid(&format!(
+            "unsupported archive, expected one of: {}",
+            ARCHIVE_EXTENSIONS.join(", ")
+        )));
+    }
+    if !path.is_file() {
+        return Err(invalid("file not found"));
+    }
+    Ok(ArtifactOverride { artifact, path })
+}
+
+/// Sets the artifacts overridden by the installation.
+pub fn set_artifact_overrides(overrides: &[ArtifactOverride]) {
+    *OVERRIDES.lock().unwrap() = overrides.to_vec();
+}
+
+/// Returns whether one of the given artifacts is overridden.
+pub fn is_overridden(artifacts: &[&str]) -> bool {
+    OVERRIDES
+        .lock()
+        .unwrap()
+        .iter()
+        .any(|o| artifacts.contains(&o.artifact.as_str()))
+}
+
+/// Returns the artifact downloaded as `file_name`, if any.
+fn find_artifact(file_name: &str) -> Option<&'static str> {
+    let stem = ARCHIVE_EXTENSIONS
+        .iter()
+        .find_map(|extension| file_name.strip_suffix(extension))?;
+    ARTIFACTS
+        .iter()
+        .find(|(_, download)| *download == stem)
+        .map(|(artifact, _)| *artifact)
+}
+
+/// Returns the local archive installed instead of the download named `file_name`, if any.
+///
+/// The last override of an artifact wins.
+pub fn artifact_override(file_name: &str) -> Option<PathBuf> {
+    let artifact = find_artifact(file_name)?;
+    OVERRIDES
+        .lock()
+        .unwrap()
+        .iter()
+        .rev()
+        .find(|o| o.artifact == artifact)
+        .map(|o| o.path.clone())
+}
+
+/// Returns the file name of an archive, which determines how it's extracted.
+pub fn archive_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::toolchain::overrides::{
+        artifact_override, find_artifact, is_overridden, parse_artifact_override,
+        set_artifact_overrides,
+    };
+    use std::fs::write;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_artifact_overrides() {
+        let temp_dir = TempDir::new().unwrap();
+        let llvm = temp_dir.path().join("clang-custom.tar.xz");
+        write(&llvm, "llvm").unwrap();
+
+        let value = format!("LLVM={}", llvm.display());
+        let llvm_override = parse_artifact_override(&value).unwrap();
+        assert_eq!(llvm_override.artifact, "llvm");
+        assert_eq!(llvm_override.path, llvm);
+        assert!(parse_artifact_override(&llvm.display().to_string()).is_err());
+        assert!(parse_artifact_override(&format!("clang={}", llvm.display())).is_err());
+        assert!(parse_artifact_override("llvm=missing.tar.xz").is_err());
+        assert!(parse_artifact_override(&format!("llvm={}", temp_dir.path().display())).is_err());
+
+        assert_eq!(find_artifact("rust.tar.xz"), Some("xtensa-rust"));
+        assert_eq!(find_artifact("rust.zip"), Some("xtensa-rust"));
+        assert_eq!(find_artifact("rust-src.tar.xz"), Some("rust-src"));
+        assert_eq!(
+            find_artifact("idf_tool_xtensa_elf_clang.full.tar.xz"),
+            Some("llvm-full")
+        );
+        assert_eq!(find_artifact("riscv32-esp-elf.zip"), Some("riscv-gcc"));
+        assert_eq!(find_artifact("rust"), None);
+
+        set_artifact_overrides(&[llvm_override]);
+        assert_eq!(
+            artifact_override("idf_tool_xtensa_elf_clang.libs.tar.xz"),
+            Some(llvm)
+        );
+        assert_eq!(artifact_override("xtensa-esp-elf.tar.xz"), None);
+        assert!(is_overridden(&["llvm", "llvm-full"]));
+        assert!(!is_overridden(&["xtensa-gcc"]));
+        set_artifact_overrides(&[]);
+    }
+}
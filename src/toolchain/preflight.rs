@@ -0,0 +1,313 @@
+//! Checks of the artifacts of an Xtensa Rust release for every host, run by `espup preflight` in
+//! the release pipeline before announcing the version.
+
+use crate::{
+    error::Error,
+    host_triple::HostTriple,
+    releases::{release, Release},
+    targets::Target,
+    toolchain::{
+        fetch::{artifact_missing, GithubQueryOpts},
+        offline::artifact_urls,
+    },
+};
+use log::{debug, info};
+use miette::Result;
+use serde::Serialize;
+use std::{collections::BTreeMap, fmt, str::FromStr};
+use strum::IntoEnumIterator;
+
+/// Status of a release artifact.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ArtifactStatus {
+    /// Published, with its checksum.
+    Published,
+    /// Not published.
+    Missing,
+    /// Published, but without checksum.
+    NoChecksum,
+}
+
+impl fmt::Display for ArtifactStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(match self {
+            Self::Published => "published",
+            Self::Missing => "missing",
+            Self::NoChecksum => "no-checksum",
+        })
+    }
+}
+
+/// Check of a release artifact.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ArtifactCheck {
+    /// Download URL.
+    pub url: String,
+    /// Hosts installing the artifact.
+    pub hosts: Vec<String>,
+    /// Status of the artifact.
+    pub status: ArtifactStatus,
+}
+
+/// Checks of the artifacts of an Xtensa Rust release.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PreflightReport {
+    /// Xtensa Rust toolchain version.
+    pub toolchain_version: String,
+    /// Checks of the artifacts.
+    pub artifacts: Vec<ArtifactCheck>,
+}
+
+impl PreflightReport {
+    /// Returns the number of artifacts that are missing or without checksum.
+    pub fn failures(&self) -> usize {
+        self.artifacts
+            .iter()
+            .filter(|artifact| artifact.status != ArtifactStatus::Published)
+            .count()
+    }
+}
+
+impl fmt::Display for PreflightReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for artifact in &self.artifacts {
+            writeln!(f, "{:<11}  {}", artifact.status, artifact.url)?;
+            if artifact.status != ArtifactStatus::Published {
+                writeln!(f, "{:<11}  required by {}", "", artifact.hosts.join(", "))?;
+            }
+        }
+        match self.failures() {
+            0 => writeln!(
+                f,
+                "All the {} artifacts of Xtensa Rust {} are published",
+                self.artifacts.len(),
+                self.toolchain_version
+            ),
+            failures => writeln!(
+                f,
+                "{} of the {} artifacts of Xtensa Rust {} are not ready",
+                failures,
+                self.artifacts.len(),
+                self.toolchain_version
+            ),
+        }
+    }
+}
+
+/// Splits the URL of a GitHub release asset into the repository (`owner/name`), the tag and the
+/// file name.
+fn parse_release_url(url: &str) -> Option<(String, &str, &str)> {
+    let path = url.strip_prefix("https://github.com/")?;
+    let (repository, asset) = path.split_once("/releases/download/")?;
+    let (tag, file_name) = asset.split_once('/')?;
+    Some((repository.to_string(), tag, file_name))
+}
+
+/// Returns whether the checksum of an asset is published, either computed by GitHub or as a
+/// `<file name>.sha256` asset.
+fn checksum_published(release: &Release, file_name: &str) -> bool {
+    release
+        .asset(file_name)
+        .is_some_and(|asset| asset.digest.is_some())
+        || release.asset(&format!("{file_name}.sha256")).is_some()
+}
+
+/// Returns the artifacts of an Xtensa Rust version for the given hosts, by URL, with the hosts
+/// installing them.
+///
+/// Every optional component is included, as well as the LLVM artifacts of both the regular and
+/// the extended installations.
+fn release_artifacts(
+    toolchain_version: &str,
+    hosts: &[HostTriple],
+) -> Result<BTreeMap<String, Vec<String>>, Error> {
+    let targets = Target::iter().collect();
+    let mut artifacts: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for host in hosts {
+        for extended_llvm in [false, true] {
+            for url in artifact_urls(
+                host,
+                toolchain_version,
+                &targets,
+                extended_llvm,
+                false,
+                true,
+            )? {
+                let hosts = artifacts.entry(url).or_default();
+                if !hosts.contains(&host.to_string()) {
+                    hosts.push(host.to_string());
+                }
+            }
+        }
+    }
+    Ok(artifacts)
+}
+
+/// Checks that the artifacts of an Xtensa Rust version are published, with their checksums, for
+/// the given hosts, or for every host with Espressif artifacts.
+pub async fn preflight(
+    toolchain_version: &str,
+    hosts: &[String],
+) -> Result<PreflightReport, Error> {
+    let hosts: Vec<HostTriple> = if hosts.is_empty() {
+        HostTriple::iter()
+            .filter(HostTriple::has_espressif_artifacts)
+            .collect()
+    } else {
+        hosts
+            .iter()
+            .map(|host| {
+                HostTriple::from_str(host).map_err(|_| Error::UnsupportedHostTriple(host.clone()))
+            })
+            .collect::<Result<_, _>>()?
+    };
+    let artifacts = release_artifacts(toolchain_version, &hosts)?;
+    info!(
+        "Checking the {} artifacts of Xtensa Rust {}",
+        artifacts.len(),
+        toolchain_version
+    );
+
+    let mut releases: BTreeMap<(String, String), Option<Release>> = BTreeMap::new();
+    let mut checks = Vec::new();
+    for (url, hosts) in artifacts {
+        let status = if artifact_missing(&url).await? {
+            ArtifactStatus::Missing
+        } else {
+            match parse_release_url(&url) {
+                Some((repository, tag, file_name)) => {
+                    let key = (repository.clone(), tag.to_string());
+                    if !releases.contains_key(&key) {
+                        let found = match release(&repository, tag, GithubQueryOpts::default())
+                            .await
+                        {
+                            Ok(found) => Some(found),
+                            Err(e) => {
+                                debug!("Unable to query the '{tag}' release of {repository}: {e}");
+                                None
+                            }
+                        };
+                        releases.insert(key.clone(), found);
+                    }
+                    match &releases[&key] {
+                        Some(found) if checksum_published(found, file_name) => {
+                            ArtifactStatus::Published
+                        }
+                        _ => ArtifactStatus::NoChecksum,
+                    }
+                }
+                // Only GitHub releases publish checksums
+                None => ArtifactStatus::NoChecksum,
+            }
+        };
+        debug!("'{}' is {}", url, status);
+        checks.push(ArtifactCheck { url, hosts, status });
+    }
+    Ok(PreflightReport {
+        toolchain_version: toolchain_version.to_string(),
+        artifacts: checks,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        host_triple::HostTriple,
+        releases::Release,
+        toolchain::preflight::{
+            checksum_published, parse_release_url, release_artifacts, ArtifactCheck,
+            ArtifactStatus, PreflightReport,
+        },
+    };
+
+    #[test]
+    fn test_parse_release_url() {
+        assert_eq!(
+            parse_release_url(
+                "https://github.com/esp-rs/rust-build/releases/download/v1.85.0.0/rust-src-1.85.0.0.tar.xz"
+            ),
+            Some((
+                "esp-rs/rust-build".to_string(),
+                "v1.85.0.0",
+                "rust-src-1.85.0.0.tar.xz"
+            ))
+        );
+        assert_eq!(parse_release_url("https://example.com/rust.tar.xz"), None);
+    }
+
+    #[test]
+    fn test_checksum_published() {
+        let release: Release = serde_json::from_str(
+            r#"{
+                "tag_name": "v1.85.0.0",
+                "assets": [
+                    {"name": "a.tar.xz", "browser_download_url": "", "digest": "sha256:0123"},
+                    {"name": "b.tar.xz", "browser_download_url": ""},
+                    {"name": "b.tar.xz.sha256", "browser_download_url": ""},
+                    {"name": "c.tar.xz", "browser_download_url": ""}
+                ]
+            }"#,
+        )
+        .unwrap();
+        assert!(checksum_published(&release, "a.tar.xz"));
+        assert!(checksum_published(&release, "b.tar.xz"));
+        assert!(!checksum_published(&release, "c.tar.xz"));
+        assert!(!checksum_published(&release, "d.tar.xz"));
+    }
+
+    #[test]
+    fn test_release_artifacts() {
+        let artifacts = release_artifacts(
+            "1.85.0.0",
+            &[
+                HostTriple::X86_64UnknownLinuxGnu,
+                HostTriple::Aarch64AppleDarwin,
+            ],
+        )
+        .unwrap();
+        assert_eq!(
+            artifacts["https://github.com/esp-rs/rust-build/releases/download/v1.85.0.0/rust-src-1.85.0.0.tar.xz"],
+            vec!["x86_64-unknown-linux-gnu", "aarch64-apple-darwin"]
+        );
+        assert_eq!(
+            artifacts["https://github.com/esp-rs/rust-build/releases/download/v1.85.0.0/rust-1.85.0.0-aarch64-apple-darwin.tar.xz"],
+            vec!["aarch64-apple-darwin"]
+        );
+        // Both the libs and the full LLVM artifacts are checked
+        assert_eq!(
+            artifacts
+                .keys()
+                .filter(|url| url.contains("llvm-project") && url.contains("linux"))
+                .count(),
+            2
+        );
+    }
+
+    #[test]
+    fn test_preflight_report() {
+        let mut report = PreflightReport {
+            toolchain_version: "1.85.0.0".to_string(),
+            artifacts: vec![ArtifactCheck {
+                url: "https://example.com/rust-src-1.85.0.0.tar.xz".to_string(),
+                hosts: vec!["x86_64-unknown-linux-gnu".to_string()],
+                status: ArtifactStatus::Published,
+            }],
+        };
+        assert_eq!(report.failures(), 0);
+        assert_eq!(
+            report.to_string(),
+            "published    https://example.com/rust-src-1.85.0.0.tar.xz\n\
+             All the 1 artifacts of Xtensa Rust 1.85.0.0 are published\n"
+        );
+
+        report.artifacts[0].status = ArtifactStatus::NoChecksum;
+        assert_eq!(report.failures(), 1);
+        assert_eq!(
+            report.to_string(),
+            "no-checksum  https://example.com/rust-src-1.85.0.0.tar.xz\n\
+             \x20            required by x86_64-unknown-linux-gnu\n\
+             1 of the 1 artifacts of Xtensa Rust 1.85.0.0 are not ready\n"
+        );
+    }
+}
@@ -0,0 +1,520 @@
+//! Progress reporting of the downloads.
+//!
+//! Interactive terminals get a progress bar per download plus an aggregate one with a smoothed
+//! throughput and ETA. Otherwise (e.g. CI logs) periodic plain text lines are logged instead.
+//! Processes driving espup, e.g. the [daemon](crate::daemon), get [`ProgressEvent`]s.
+//!
+//! The progress state belongs to an installation session, see [`Progress`]. The other settings of
+//! an installation are process-wide, see [`install`](crate::toolchain::install).
+
+#[cfg(feature = "cli")]
+use crate::logging::terminal_bars;
+#[cfg(feature = "cli")]
+use indicatif::{
+    HumanBytes, HumanDuration, MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle,
+};
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::{
+    io::{stderr, IsTerminal},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// Weight of the latest sample in the throughput average, lower values smooth more.
+const SMOOTHING: f64 = 0.3;
+/// Minimum time between throughput samples, shorter samples are too noisy.
+const SAMPLE_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Maximum time between plain text progress lines.
+const PLAIN_INTERVAL: Duration = Duration::from_secs(10);
+/// Percentage of a download between plain text progress lines.
+const PLAIN_STEP: u64 = 25;
+
+/// Progress of a download, reported as a JSON line on stderr by [`Progress::events`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum ProgressEvent {
+    /// The download of `name` started, `len` is its size in bytes, if known.
+    Started { name: String, len: Option<u64> },
+    /// `downloaded` bytes of `name` were received.
+    Advanced {
+        name: String,
+        downloaded: u64,
+        len: Option<u64>,
+    },
+    /// The download of `name` finished.
+    Finished { name: String, success: bool },
+}
+
+/// Progress reporting of the downloads of an installation session.
+///
+/// Each session counts its own downloads and draws its own bars, so the progress of several
+/// installations running in the same process is reported separately. Clones report to the same
+/// session.
+#[derive(Clone)]
+pub struct Progress {
+    #[cfg(feature = "cli")]
+    bars: MultiProgress,
+    /// Whether plain text progress lines are logged instead of drawing progress bars.
+    plain: bool,
+    /// Whether progress events are written instead of drawing progress bars.
+    events: bool,
+    state: Arc<Mutex<SessionState>>,
+}
+
+/// Downloads of a session.
+#[derive(Default)]
+struct SessionState {
+    /// Number of downloads in progress.
+    downloads: usize,
+    /// Bars of the downloads, removed once every download finished.
+    #[cfg(feature = "cli")]
+    bars: Vec<ProgressBar>,
+    /// Progress bar of the total bytes of the running downloads.
+    #[cfg(feature = "cli")]
+    total: Option<TotalProgress>,
+}
+
+impl Progress {
+    /// Draws progress bars, or logs plain text progress lines when requested or when the output
+    /// is not a terminal.
+    ///
+    /// The progress bars require the `cli` feature, without it the plain text lines are always
+    /// logged.
+    pub fn new(no_progress: bool) -> Self {
+        let plain = no_progress || !stderr().is_terminal() || cfg!(not(feature = "cli"));
+        Self {
+            // The bars of the logger are suspended while logging, so logs don't tear them
+            #[cfg(feature = "cli")]
+            bars: if plain {
+                MultiProgress::with_draw_target(ProgressDrawTarget::hidden())
+            } else {
+                terminal_bars()
+            },
+            plain,
+            events: false,
+            state: Arc::default(),
+        }
+    }
+
+    /// Hides the progress bars and disables the plain text progress lines.
+    pub fn hidden() -> Self {
+        Self {
+            #[cfg(feature = "cli")]
+            bars: MultiProgress::with_draw_target(ProgressDrawTarget::hidden()),
+            plain: false,
+            events: false,
+            state: Arc::default(),
+        }
+    }
+
+    /// Writes the progress as [`ProgressEvent`] JSON lines on stderr instead of drawing progress
+    /// bars, for the processes driving espup.
+    pub fn events() -> Self {
+        Self {
+            events: true,
+            ..Self::hidden()
+        }
+    }
+
+    /// Writes a progress event, if requested.
+    fn emit(&self, event: ProgressEvent) {
+        if self.events {
+            eprintln!("{}", serde_json::to_string(&event).unwrap());
+        }
+    }
+
+    /// Returns whether plain text progress lines are logged instead of drawing progress bars.
+    pub fn is_plain(&self) -> bool {
+        self.plain
+    }
+
+    /// Starts reporting the progress of the download of `name`, of `len` bytes.
+    pub fn start_download(&self, name: &str, len: Option<u64>) -> DownloadProgress {
+        let mut state = self.state.lock().unwrap();
+        state.downloads += 1;
+        #[cfg(feature = "cli")]
+        let bar = {
+            let bar = self.bars.add(
+                len.map(ProgressBar::new)
+                    .unwrap_or(ProgressBar::no_length()),
+            );
+            bar.set_style(download_style());
+            bar.set_message(name.to_string());
+            state.bars.push(bar.clone());
+            let total = state
+                .total
+                .get_or_insert_with(|| TotalProgress::new(&self.bars));
+            total.bar.inc_length(len.unwrap_or_default());
+            bar
+        };
+        drop(state);
+        self.emit(ProgressEvent::Started {
+            name: name.to_string(),
+            len,
+        });
+        DownloadProgress {
+            session: self.clone(),
+            #[cfg(feature = "cli")]
+            bar,
+            name: name.to_string(),
+            len,
+            downloaded: 0,
+            last_event: Instant::now(),
+            plain: self
+                .plain
+                .then(|| PlainProgress::new(name, len, Instant::now())),
+        }
+    }
+
+    /// Ends a download, clearing the progress bars of the session once every download finished.
+    /// Returns whether it was the last download in progress.
+    fn finish_download(&self) -> bool {
+        let mut state = self.state.lock().unwrap();
+        state.downloads -= 1;
+        if state.downloads > 0 {
+            return false;
+        }
+        #[cfg(feature = "cli")]
+        {
+            if let Some(total) = state.total.take() {
+                total.bar.finish_and_clear();
+                self.bars.remove(&total.bar);
+            }
+            for bar in state.bars.drain(..) {
+                bar.finish_and_clear();
+                self.bars.remove(&bar);
+            }
+        }
+        true
+    }
+}
+
+/// Plain text progress of a download, logged every `PLAIN_INTERVAL` or `PLAIN_STEP` percent.
+#[derive(Debug)]
+pub struct PlainProgress {
+    name: String,
+    len: Option<u64>,
+    last_line: Instant,
+    last_step: u64,
+}
+
+impl PlainProgress {
+    /// Starts reporting the progress of a download of `len` bytes at `now`.
+    pub fn new(name: &str, len: Option<u64>, now: Instant) -> Self {
+        Self {
+            name: name.to_string(),
+            len: len.filter(|len| *len > 0),
+            last_line: now,
+            last_step: 0,
+        }
+    }
+
+    /// Returns the line to log once `downloaded` bytes were received at `now`, if one is due.
+    pub fn update(&mut self, downloaded: u64, now: Instant) -> Option<String> {
+        let step = self.len.map(|len| downloaded * 100 / len / PLAIN_STEP);
+        let due = now.saturating_duration_since(self.last_line) >= PLAIN_INTERVAL
+            || step.is_some_and(|step| step > self.last_step);
+        if !due {
+            return None;
+        }
+        self.last_line = now;
+        self.last_step = step.unwrap_or_default();
+        Some(match self.len {
+            Some(len) => format!(
+                "Downloading '{}': {}% ({} / {})",
+                self.name,
+                downloaded * 100 / len,
+                human_bytes(downloaded),
+                human_bytes(len)
+            ),
+            None => format!("Downloading '{}': {}", self.name, human_bytes(downloaded)),
+        })
+    }
+}
+
+/// Formats a number of bytes with binary prefixes, e.g. `1.95 KiB`.
+fn human_bytes(bytes: u64) -> String {
+    const PREFIXES: [&str; 6] = ["Ki", "Mi", "Gi", "Ti", "Pi", "Ei"];
+    if bytes < 1024 {
+        return format!("{bytes} B");
+    }
+    let mut value = bytes as f64 / 1024.0;
+    let mut prefix = 0;
+    while value >= 1024.0 && prefix < PREFIXES.len() - 1 {
+        value /= 1024.0;
+        prefix += 1;
+    }
+    format!("{value:.2} {}B", PREFIXES[prefix])
+}
+
+/// Progress of a download, drawn as a progress bar and added to the total one, or logged as
+/// plain text lines.
+pub struct DownloadProgress {
+    session: Progress,
+    #[cfg(feature = "cli")]
+    bar: ProgressBar,
+    name: String,
+    len: Option<u64>,
+    downloaded: u64,
+    /// Time of the last progress event.
+    last_event: Instant,
+    plain: Option<PlainProgress>,
+}
+
+impl DownloadProgress {
+    /// Records the bytes received.
+    pub fn advance(&mut self, bytes: u64) {
+        self.downloaded += bytes;
+        #[cfg(feature = "cli")]
+        {
+            self.bar.set_position(self.downloaded);
+            if let Some(total) = self.session.state.lock().unwrap().total.as_mut() {
+                total.advance(bytes);
+            }
+        }
+        if let Some(line) = self
+            .plain
+            .as_mut()
+            .and_then(|plain| plain.update(self.downloaded, Instant::now()))
+        {
+            info!("{}", line);
+        }
+        if self.session.events && self.last_event.elapsed() >= SAMPLE_INTERVAL {
+            self.last_event = Instant::now();
+            self.session.emit(ProgressEvent::Advanced {
+                name: self.name.clone(),
+                downloaded: self.downloaded,
+                len: self.len,
+            });
+        }
+    }
+
+    /// Stops reporting the progress of the download, clearing the progress bars once every
+    /// download of the session finished.
+    pub fn finish(self, success: bool) {
+        #[cfg(feature = "cli")]
+        if success {
+            self.bar
+                .finish_with_message(format!("{} download complete", self.name));
+        } else {
+            self.bar
+                .abandon_with_message(format!("{} download failed", self.name));
+            // Removed from the total, so it can be retried
+            if let Some(total) = self.session.state.lock().unwrap().total.as_mut() {
+                total.abandon(self.len, self.downloaded);
+            }
+        }
+        self.session.emit(ProgressEvent::Finished {
+            name: self.name.clone(),
+            success,
+        });
+        if self.session.finish_download() && success {
+            info!("All downloads complete");
+        }
+    }
+}
+
+/// Style of the progress bar of each download.
+#[cfg(feature = "cli")]
+fn download_style() -> ProgressStyle {
+    ProgressStyle::with_template(
+        "[{elapsed_precise}] {bar:40.cyan/blue} {bytes:>10}/{total_bytes:10} {bytes_per_sec:>12} {msg}",
+    )
+    .unwrap()
+    .progress_chars("##-")
+}
+
+/// Exponentially weighted moving average of the download throughput.
+#[derive(Debug)]
+pub struct Throughput {
+    /// Smoothed throughput, in bytes per second, once the first sample is taken.
+    rate: Option<f64>,
+    /// Bytes received since the last sample.
+    pending: u64,
+    /// Start of the current sample.
+    sample_start: Instant,
+}
+
+impl Throughput {
+    /// Starts measuring the throughput at `now`.
+    pub fn new(now: Instant) -> Self {
+        Self {
+            rate: None,
+            pending: 0,
+            sample_start: now,
+        }
+    }
+
+    /// Records the bytes received at `now`.
+    pub fn record(&mut self, bytes: u64, now: Instant) {
+        self.pending += bytes;
+        let elapsed = now.saturating_duration_since(self.sample_start);
+        if elapsed < SAMPLE_INTERVAL {
+            return;
+        }
+        let sample = self.pending as f64 / elapsed.as_secs_f64();
+        self.rate = Some(match self.rate {
+            Some(rate) => SMOOTHING * sample + (1.0 - SMOOTHING) * rate,
+            None => sample,
+        });
+        self.pending = 0;
+        self.sample_start = now;
+    }
+
+    /// Returns the smoothed throughput, in bytes per second.
+    pub fn rate(&self) -> Option<f64> {
+        self.rate
+    }
+
+    /// Returns the estimated time to receive the remaining bytes.
+    pub fn eta(&self, remaining: u64) -> Option<Duration> {
+        self.rate
+            .filter(|rate| *rate > 0.0)
+            .map(|rate| Duration::from_secs_f64(remaining as f64 / rate))
+    }
+}
+
+/// Progress bar of the total bytes of the running downloads of a session, with a smoothed
+/// throughput and ETA.
+#[cfg(feature = "cli")]
+struct TotalProgress {
+    bar: ProgressBar,
+    throughput: Throughput,
+}
+
+#[cfg(feature = "cli")]
+impl TotalProgress {
+    /// Adds the total progress bar on top of the ones of the downloads.
+    fn new(bars: &MultiProgress) -> Self {
+        let bar = bars.insert(0, ProgressBar::new(0));
+        bar.set_style(
+            ProgressStyle::with_template(
+                "[{elapsed_precise}] {bar:40.green/white} {bytes:>10}/{total_bytes:10} {msg}",
+            )
+            .unwrap()
+            .progress_chars("##-"),
+        );
+        bar.set_message("total");
+        Self {
+            bar,
+            throughput: Throughput::new(Instant::now()),
+        }
+    }
+
+    /// Advances the total progress bar by the received bytes.
+    fn advance(&mut self, bytes: u64) {
+        self.bar.inc(bytes);
+        self.throughput.record(bytes, Instant::now());
+        if let Some(rate) = self.throughput.rate() {
+            let remaining = self
+                .bar
+                .length()
+                .unwrap_or_default()
+                .saturating_sub(self.bar.position());
+            let eta = self.throughput.eta(remaining).unwrap_or_default();
+            self.bar.set_message(format!(
+                "total, {}/s, ETA {}",
+                HumanBytes(rate as u64),
+                HumanDuration(eta)
+            ));
+        }
+    }
+
+    /// Removes a failed download of `len` bytes, of which `downloaded` were received.
+    fn abandon(&mut self, len: Option<u64>, downloaded: u64) {
+        let length = self.bar.length().unwrap_or_default();
+        self.bar
+            .set_length(length.saturating_sub(len.unwrap_or_default()));
+        self.bar
+            .set_position(self.bar.position().saturating_sub(downloaded));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::toolchain::progress::{PlainProgress, Progress, ProgressEvent, Throughput};
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_progress_sessions() {
+        let first = Progress::hidden();
+        let second = Progress::hidden();
+        let mut rust = first.start_download("rust.tar.xz", Some(1_000));
+        let llvm = first.start_download("llvm.tar.xz", None);
+        let gcc = second.start_download("gcc.tar.xz", Some(500));
+        rust.advance(1_000);
+        assert_eq!(first.state.lock().unwrap().downloads, 2);
+        assert_eq!(second.state.lock().unwrap().downloads, 1);
+
+        // Each session clears its bars once its own downloads finished
+        gcc.finish(false);
+        assert_eq!(second.state.lock().unwrap().downloads, 0);
+        #[cfg(feature = "cli")]
+        assert_eq!(first.state.lock().unwrap().bars.len(), 2);
+        rust.finish(true);
+        llvm.finish(true);
+        assert_eq!(first.state.lock().unwrap().downloads, 0);
+        #[cfg(feature = "cli")]
+        assert!(first.state.lock().unwrap().bars.is_empty());
+    }
+
+    #[test]
+    fn test_progress_event() {
+        let event = ProgressEvent::Advanced {
+            name: "rust.tar.xz".to_string(),
+            downloaded: 250,
+            len: Some(1_000),
+        };
+        let line = serde_json::to_string(&event).unwrap();
+        assert_eq!(
+            line,
+            r#"{"event":"advanced","name":"rust.tar.xz","downloaded":250,"len":1000}"#
+        );
+        assert_eq!(serde_json::from_str::<ProgressEvent>(&line).unwrap(), event);
+    }
+
+    #[test]
+    fn test_throughput() {
+        let start = Instant::now();
+        let mut throughput = Throughput::new(start);
+        // Samples shorter than the interval are accumulated
+        throughput.record(1_000_000, start + Duration::from_millis(100));
+        assert_eq!(throughput.rate(), None);
+        throughput.record(1_000_000, start + Duration::from_secs(1));
+        assert_eq!(throughput.rate(), Some(2_000_000.0));
+        assert_eq!(throughput.eta(4_000_000), Some(Duration::from_secs(2)));
+
+        // Spikes are smoothed
+        throughput.record(12_000_000, start + Duration::from_secs(2));
+        assert!((throughput.rate().unwrap() - 5_000_000.0).abs() < 1.0);
+    }
+
+    #[test]
+    fn test_plain_progress() {
+        let start = Instant::now();
+        let mut progress = PlainProgress::new("rust.tar.xz", Some(1_000), start);
+        assert_eq!(progress.update(100, start + Duration::from_secs(1)), None);
+        assert_eq!(
+            progress.update(250, start + Duration::from_secs(2)),
+            Some("Downloading 'rust.tar.xz': 25% (250 B / 1000 B)".to_string())
+        );
+        assert_eq!(progress.update(300, start + Duration::from_secs(3)), None);
+        // Slow downloads are reported periodically
+        assert!(progress
+            .update(310, start + Duration::from_secs(13))
+            .is_some());
+        assert!(progress
+            .update(1_000, start + Duration::from_secs(14))
+            .is_some());
+
+        // Downloads of unknown size are only reported periodically
+        let mut progress = PlainProgress::new("llvm.tar.xz", None, start);
+        assert_eq!(progress.update(100, start + Duration::from_secs(1)), None);
+        assert_eq!(
+            progress.update(2_000, start + Duration::from_secs(10)),
+            Some("Downloading 'llvm.tar.xz': 1.95 KiB".to_string())
+        );
+    }
+}
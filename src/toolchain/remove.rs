@@ -0,0 +1,242 @@
+//! Removal of installed files.
+//!
+//! On Windows, files opened by other processes (e.g. an editor running `clangd` or a terminal
+//! with the toolchain in its working directory) can't be removed until they are closed.
+
+use crate::error::Error;
+use log::{debug, warn};
+use miette::Result;
+use std::{io, path::Path, time::Duration};
+
+/// Windows error of the files opened by another process without sharing them.
+const ERROR_SHARING_VIOLATION: i32 = 32;
+/// Delays between the attempts to remove files that are in use.
+const RETRY_DELAYS: [Duration; 4] = [
+    Duration::from_millis(250),
+    Duration::from_millis(500),
+    Duration::from_secs(1),
+    Duration::from_secs(2),
+];
+
+/// Removes a directory and all its contents, retrying while its files are in use.
+///
+/// Returns [`Error::DirectoryInUse`] when the files are still in use after every retry.
+pub async fn remove_dir_all(path: &Path) -> Result<(), Error> {
+    let mut delays = RETRY_DELAYS.iter();
+    loop {
+        match tokio::fs::remove_dir_all(path).await {
+            Ok(()) => return Ok(()),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) if is_in_use(&e) => match delays.next() {
+                Some(delay) => {
+                    debug!(
+                        "'{}' is in use, retrying in {}ms",
+                        path.display(),
+                        delay.as_millis()
+                    );
+                    tokio::time::sleep(*delay).await;
+                }
+                None => return Err(in_use_error(path)),
+            },
+            Err(e) => {
+                debug!("Failed to remove '{}': {}", path.display(), e);
+                return Err(Error::RemoveDirectory(path.display().to_string()));
+            }
+        }
+    }
+}
+
+/// Returns whether the error is caused by a file opened by another process.
+///
+/// Only `ERROR_SHARING_VIOLATION` is retried: other errors, e.g. `ERROR_ACCESS_DENIED` for
+/// missing permissions, won't go away by waiting.
+fn is_in_use(error: &io::Error) -> bool {
+    cfg!(windows) && error.raw_os_error() == Some(ERROR_SHARING_VIOLATION)
+}
+
+fn in_use_error(path: &Path) -> Error {
+    #[cfg(windows)]
+    let processes = locking_processes(path);
+    #[cfg(not(windows))]
+    let processes: Vec<String> = Vec::new();
+    warn!("'{}' is still in use", path.display());
+    Error::DirectoryInUse(path.display().to_string(), format_processes(&processes))
+}
+
+/// Formats the processes holding files for the error message.
+fn format_processes(processes: &[String]) -> String {
+    if processes.is_empty() {
+        "an unknown process".to_string()
+    } else {
+        processes.join(", ")
+    }
+}
+
+/// Returns every file and directory below the given path, children first.
+#[cfg(windows)]
+fn entries(path: &Path) -> Vec<std::path::PathBuf> {
+    let mut found = Vec::new();
+    if let Ok(dir) = std::fs::read_dir(path) {
+        for entry in dir.filter_map(|entry| entry.ok()) {
+            let entry_path = entry.path();
+            if entry.file_type().is_ok_and(|file_type| file_type.is_dir()) {
+                found.extend(entries(&entry_path));
+            }
+            found.push(entry_path);
+        }
+    }
+    found
+}
+
+#[cfg(windows)]
+fn to_wide(path: &Path) -> Vec<u16> {
+    use std::os::windows::ffi::OsStrExt;
+
+    path.as_os_str()
+        .encode_wide()
+        .chain(std::iter::once(0))
+        .collect()
+}
+
+/// Returns the processes holding the executables and libraries of a directory open, as reported
+/// by the Restart Manager.
+#[cfg(windows)]
+#[allow(clippy::unnecessary_cast)]
+fn locking_processes(path: &Path) -> Vec<String> {
+    use std::ptr;
+    use winapi::{
+        shared::winerror::{ERROR_MORE_DATA, ERROR_SUCCESS},
+        um::restartmanager::{
+            RmEndSession, RmGetList, RmRegisterResources, RmStartSession, CCH_RM_SESSION_KEY,
+            RM_PROCESS_INFO,
+        },
+    };
+
+    // Only executables and libraries are kept open while running
+    let files: Vec<Vec<u16>> = entries(path)
+        .iter()
+        .filter(|entry| {
+            entry.extension().is_some_and(|extension| {
+                extension.eq_ignore_ascii_case("exe") || extension.eq_ignore_ascii_case("dll")
+            })
+        })
+        .map(|entry| to_wide(entry))
+        .collect();
+    if files.is_empty() {
+        return Vec::new();
+    }
+    let mut file_names: Vec<*const u16> = files.iter().map(|file| file.as_ptr()).collect();
+
+    let mut processes = Vec::new();
+    let mut session = 0;
+    let mut session_key = [0u16; CCH_RM_SESSION_KEY + 1];
+    unsafe {
+        if RmStartSession(&mut session, 0, session_key.as_mut_ptr()) != ERROR_SUCCESS {
+            return processes;
+        }
+        if RmRegisterResources(
+            session,
+            file_names.len() as u32,
+            file_names.as_mut_ptr(),
+            0,
+            ptr::null_mut(),
+            0,
+            ptr::null_mut(),
+        ) == ERROR_SUCCESS
+        {
+            let mut needed = 0;
+            let mut count = 0;
+            let mut reasons = 0;
+            let result = RmGetList(
+                session,
+                &mut needed,
+                &mut count,
+                ptr::null_mut(),
+                &mut reasons,
+            );
+            if result == ERROR_MORE_DATA {
+                let mut infos: Vec<RM_PROCESS_INFO> = vec![std::mem::zeroed(); needed as usize];
+                count = needed;
+                if RmGetList(
+                    session,
+                    &mut needed,
+                    &mut count,
+                    infos.as_mut_ptr(),
+                    &mut reasons,
+                ) == ERROR_SUCCESS
+                {
+                    for info in infos.iter().take(count as usize) {
+                        let name = &info.strAppName;
+                        let len = name.iter().position(|&c| c == 0).unwrap_or(name.len());
+                        processes.push(format!(
+                            "{} (PID {})",
+                            String::from_utf16_lossy(&name[..len]),
+                            info.Process.dwProcessId
+                        ));
+                    }
+                }
+            }
+        }
+        RmEndSession(session);
+    }
+    processes
+}
+
+/// Schedules the removal of a directory and all its contents on the next reboot.
+///
+/// Requires administrator privileges.
+#[cfg(windows)]
+pub fn schedule_removal_on_reboot(path: &Path) -> Result<(), Error> {
+    use std::ptr;
+    use winapi::um::winbase::{MoveFileExW, MOVEFILE_DELAY_UNTIL_REBOOT};
+
+    // Pending removals run in order, directories have to be empty by the time they are removed
+    let mut paths = entries(path);
+    paths.push(path.to_path_buf());
+    for entry in paths {
+        debug!("Scheduling the removal of '{}' on reboot", entry.display());
+        let wide = to_wide(&entry);
+        if unsafe { MoveFileExW(wide.as_ptr(), ptr::null(), MOVEFILE_DELAY_UNTIL_REBOOT) } == 0 {
+            return Err(Error::ScheduleRemoval(path.display().to_string()));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::toolchain::remove::{format_processes, is_in_use, remove_dir_all};
+    use std::{
+        fs::{create_dir_all, write},
+        io,
+    };
+    use tempfile::TempDir;
+
+    #[tokio::test]
+    async fn test_remove_dir_all() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("esp").join("bin");
+        create_dir_all(&path).unwrap();
+        write(path.join("rustc"), "").unwrap();
+        remove_dir_all(&dir.path().join("esp")).await.unwrap();
+        assert!(!dir.path().join("esp").exists());
+
+        // Missing directories are already removed
+        remove_dir_all(&dir.path().join("esp")).await.unwrap();
+
+        // Permission errors are never retried
+        assert!(!is_in_use(&io::Error::from(
+            io::ErrorKind::PermissionDenied
+        )));
+        assert_eq!(is_in_use(&io::Error::from_raw_os_error(32)), cfg!(windows));
+
+        assert_eq!(format_processes(&[]), "an unknown process");
+        assert_eq!(
+            format_processes(&[
+                "clangd.exe (PID 42)".to_string(),
+                "cmd.exe (PID 7)".to_string()
+            ]),
+            "clangd.exe (PID 42), cmd.exe (PID 7)"
+        );
+    }
+}
@@ -1,54 +1,126 @@
 //! Xtensa Rust Toolchain source and installation tools.
 
+#[cfg(unix)]
+use crate::toolchain::io::extraction_permit;
 use crate::{
+    env::{Export, ExportKind},
     error::Error,
     host_triple::HostTriple,
+    manifest::Manifest,
+    releases::{fetch_releases, latest_release, releases, Release, RUST_BUILD_REPOSITORY},
+    sandbox::{sandboxed, write_checked},
     toolchain::{
-        download_file,
+        disk_usage, download_file,
+        fetch::{artifact_missing, GithubQueryOpts},
         gcc::{RISCV_GCC, XTENSA_GCC},
-        github_query,
         llvm::CLANG_NAME,
-        Installable,
+        offline::cached_artifact,
+        overrides::is_overridden,
+        progress::Progress,
+        remove::remove_dir_all,
+        run_command,
+        rustup::lock_rustup,
+        rustup_cache::{record_rustup, record_toolchain, rustup_verified, toolchain_verified},
+        tmp::{configured_tmp_dir, create_tmp_dir},
+        Component, PREFIX_MARKER,
     },
 };
 use async_trait::async_trait;
 use directories::BaseDirs;
-use log::{debug, info, warn};
+use log::{debug, info, log_enabled, warn, Level};
 use miette::Result;
 use regex::Regex;
-#[cfg(unix)]
-use std::fs::create_dir_all;
+use serde::{Deserialize, Serialize};
+#[cfg(windows)]
+use std::fs::rename;
 use std::{
     env,
-    fmt::Debug,
-    fs::read_dir,
+    ffi::OsStr,
+    fmt::{self, Debug},
+    fs::{read_dir, read_to_string, write},
     io,
     path::{Path, PathBuf},
     process::{Command, Stdio},
+    sync::OnceLock,
 };
-#[cfg(unix)]
-use tempfile::tempdir_in;
-use tokio::fs::{remove_dir_all, remove_file};
+use strum::{EnumIter, IntoEnumIterator};
+use tokio::fs::remove_file;
 
 /// Xtensa Rust Toolchain repository
 const DEFAULT_XTENSA_RUST_REPOSITORY: &str =
     "https://github.com/esp-rs/rust-build/releases/download";
 
-/// Xtensa Rust Toolchain API URL
-const XTENSA_RUST_LATEST_API_URL: &str =
-    "https://api.github.com/repos/esp-rs/rust-build/releases/latest";
-const XTENSA_RUST_API_URL: &str =
-    "https://api.github.com/repos/esp-rs/rust-build/releases?page=1&per_page=100";
-
 /// Xtensa Rust Toolchain version regex.
 pub const RE_EXTENDED_SEMANTIC_VERSION: &str = r"^(?P<major>0|[1-9]\d*)\.(?P<minor>0|[1-9]\d*)\.(?P<patch>0|[1-9]\d*)\.(?P<subpatch>0|[1-9]\d*)?$";
+/// RISC-V Rust targets installed through rustup.
+pub(crate) const RISCV_TARGETS: [&str; 3] = [
+    "riscv32imc-unknown-none-elf",
+    "riscv32imac-unknown-none-elf",
+    "riscv32imafc-unknown-none-elf",
+];
+/// Components that can be installed in the RISC-V toolchain, see `rustup component list`.
+const RUSTUP_COMPONENTS: [&str; 12] = [
+    "cargo",
+    "clippy",
+    "llvm-tools",
+    "llvm-tools-preview",
+    "miri",
+    "rust-analyzer",
+    "rust-docs",
+    "rust-src",
+    "rust-std",
+    "rustc",
+    "rustc-dev",
+    "rustfmt",
+];
+/// File of the toolchain directory where the installed Xtensa Rust version is recorded.
+const VERSION_FILE: &str = "lib/rustlib/espup-version";
+/// Directory of the toolchain with the sources of the `rust-src` component.
+const SRC_DIR: &str = "lib/rustlib/src";
+/// File of the toolchain directory listing the installed dist components.
+const COMPONENTS_FILE: &str = "lib/rustlib/components";
+/// Documentation components of the Xtensa Rust dist, which are never installed.
+#[cfg(unix)]
+const XTENSA_DOC_COMPONENTS: [&str; 2] = ["rust-docs-json-preview", "rust-docs"];
 const RE_SEMANTIC_VERSION: &str =
     r"^(?P<major>0|[1-9]\d*)\.(?P<minor>0|[1-9]\d*)\.(?P<patch>0|[1-9]\d*)?$";
 
+/// Optional component of the Xtensa Rust dist.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, EnumIter)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[serde(rename_all = "lowercase")]
+pub enum XtensaComponent {
+    /// `clippy-preview`.
+    Clippy,
+    /// `rustfmt-preview`.
+    Rustfmt,
+}
+
+impl XtensaComponent {
+    /// Returns the name of the component in the dist.
+    pub fn dist_name(&self) -> &'static str {
+        match self {
+            XtensaComponent::Clippy => "clippy-preview",
+            XtensaComponent::Rustfmt => "rustfmt-preview",
+        }
+    }
+}
+
+impl fmt::Display for XtensaComponent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(match self {
+            XtensaComponent::Clippy => "clippy",
+            XtensaComponent::Rustfmt => "rustfmt",
+        })
+    }
+}
+
 #[derive(Debug, Clone, Default)]
 pub struct XtensaRust {
     /// Path to the cargo home directory.
     pub cargo_home: PathBuf,
+    /// Optional components to install, all the ones shipped in the dist if `None`.
+    pub components: Option<Vec<XtensaComponent>>,
     /// Xtensa Rust toolchain file.
     pub dist_file: String,
     /// Xtensa Rust toolchain URL.
@@ -73,21 +145,58 @@ pub struct XtensaRust {
 
 impl XtensaRust {
     /// Get the latest version of Xtensa Rust toolchain.
-    pub async fn get_latest_version() -> Result<String> {
-        let json = tokio::task::spawn_blocking(|| github_query(XTENSA_RUST_LATEST_API_URL))
-            .await
-            .unwrap()?;
-        let mut version = json["tag_name"].to_string();
-
-        version.retain(|c| c != 'v' && c != '"');
+    pub async fn get_latest_version(query_opts: GithubQueryOpts) -> Result<String, Error> {
+        let version = latest_release(RUST_BUILD_REPOSITORY, query_opts)
+            .await?
+            .version()
+            .to_string();
         let borrowed = version.clone();
-        tokio::task::spawn_blocking(move || Self::parse_version(&borrowed))
+        tokio::task::spawn_blocking(move || Self::parse_version(&borrowed, &query_opts))
             .await
             .expect("Join blocking task error")?;
         debug!("Latest Xtensa Rust version: {}", version);
         Ok(version)
     }
 
+    /// Returns `version` if its artifacts for the host are published, the nearest older version
+    /// publishing them otherwise, unless `fallback` is unset.
+    ///
+    /// Releases occasionally lack the artifacts of a host. Without a fallback version, the missing
+    /// artifacts are reported by the preflight checks.
+    pub async fn published_version(
+        version: &str,
+        host_triple: &HostTriple,
+        query_opts: &GithubQueryOpts,
+        fallback: bool,
+    ) -> Result<String, Error> {
+        let mut missing = false;
+        for url in Self::new(version, host_triple, Path::new("")).artifact_urls() {
+            if cached_artifact(&url).is_none() && artifact_missing(&url).await? {
+                missing = true;
+                break;
+            }
+        }
+        if !missing {
+            return Ok(version.to_string());
+        }
+        let releases = releases(RUST_BUILD_REPOSITORY, *query_opts).await?;
+        let Some(fallback_version) = fallback_version(&releases, version, host_triple) else {
+            return Ok(version.to_string());
+        };
+        if !fallback {
+            warn!(
+                "Xtensa Rust {} is not published for '{}', {} is the nearest older version that is. Use '--toolchain-version {}' to install it",
+                version, host_triple, fallback_version, fallback_version
+            );
+            return Ok(version.to_string());
+        }
+        warn!(
+            "Xtensa Rust {} is not published for '{}', installing {} instead, the nearest older version that is. Use '--no-fallback' to fail instead",
+            version, host_triple, fallback_version
+        );
+        Ok(fallback_version)
+    }
+
     /// Create a new instance.
     pub fn new(toolchain_version: &str, host_triple: &HostTriple, toolchain_path: &Path) -> Self {
         let artifact_extension = get_artifact_extension(host_triple);
@@ -107,6 +216,7 @@ impl XtensaRust {
 
         Self {
             cargo_home,
+            components: None,
             dist_file,
             dist_url,
             host_triple: host_triple.to_string(),
@@ -122,47 +232,176 @@ impl XtensaRust {
     }
 
     /// Parses the version of the Xtensa toolchain.
-    pub fn parse_version(arg: &str) -> Result<String, Error> {
+    pub fn parse_version(arg: &str, query_opts: &GithubQueryOpts) -> Result<String, Error> {
         debug!("Parsing Xtensa Rust version: {}", arg);
         let re_extended = Regex::new(RE_EXTENDED_SEMANTIC_VERSION).unwrap();
         let re_semver = Regex::new(RE_SEMANTIC_VERSION).unwrap();
-        let json = github_query(XTENSA_RUST_API_URL)?;
+        let tags: Vec<String> = fetch_releases(RUST_BUILD_REPOSITORY, query_opts)?
+            .iter()
+            .map(|release| release.version().to_string())
+            .collect();
         if re_semver.is_match(arg) {
-            let mut extended_versions: Vec<String> = Vec::new();
-            for release in json.as_array().unwrap() {
-                let tag_name = release["tag_name"].to_string().replace(['\"', 'v'], "");
-                if tag_name.starts_with(arg) {
-                    extended_versions.push(tag_name);
+            return find_latest_version(arg, &tags)
+                .ok_or_else(|| Error::InvalidVersion(arg.to_string()));
+        } else if re_extended.is_match(arg) && tags.iter().any(|tag| tag.starts_with(arg)) {
+            return Ok(arg.to_string());
+        }
+        Err(Error::InvalidVersion(arg.to_string()))
+    }
+
+    /// Runs the installer of the `rust-src` component extracted in the given directory.
+    #[cfg(unix)]
+    fn run_src_installer(&self, tmp_dir_path: &str) -> Result<(), Error> {
+        info!("Installing 'rust-src' component for Xtensa Rust toolchain");
+        run_command(
+            "rust-src",
+            Command::new("/usr/bin/env")
+                .arg("bash")
+                .arg(format!("{}/rust-src-nightly/install.sh", tmp_dir_path))
+                .arg(format!(
+                    "--destdir={}",
+                    self.toolchain_destination.display()
+                ))
+                .arg("--prefix=''")
+                .arg("--disable-ldconfig")
+                .args(install_script_verbosity()),
+            Error::XtensaRustSrc,
+        )
+    }
+
+    /// Returns the optional components of the dist, listed in `dist_components`, to leave out of
+    /// the installation.
+    fn excluded_components(&self, dist_components: &[String]) -> Result<Vec<String>, Error> {
+        let Some(requested) = &self.components else {
+            return Ok(Vec::new());
+        };
+        let shipped = |component: &XtensaComponent| {
+            dist_components
+                .iter()
+                .any(|name| name == component.dist_name())
+        };
+        if let Some(missing) = requested.iter().find(|component| !shipped(component)) {
+            return Err(Error::MissingXtensaComponent(
+                missing.dist_name().to_string(),
+                self.version.clone(),
+            ));
+        }
+        Ok(XtensaComponent::iter()
+            .filter(|component| shipped(component) && !requested.contains(component))
+            .map(|component| component.dist_name().to_string())
+            .collect())
+    }
+
+    /// Returns whether the optional components of the installed toolchain are the requested ones.
+    fn has_requested_components(&self) -> bool {
+        let Some(requested) = &self.components else {
+            return true;
+        };
+        let Some(installed) = installed_components(&self.toolchain_destination) else {
+            return true;
+        };
+        XtensaComponent::iter().all(|component| {
+            installed.iter().any(|name| name == component.dist_name())
+                == requested.contains(&component)
+        })
+    }
+
+    /// Removes the optional components that were not requested from the extracted bundle.
+    #[cfg(windows)]
+    fn remove_excluded_components(&self) -> Result<(), Error> {
+        if self.components.is_none() {
+            return Ok(());
+        }
+        let Some(installed) = installed_components(&self.toolchain_destination) else {
+            warn!("Failed to list the components of the Xtensa Rust bundle, keeping all of them");
+            return Ok(());
+        };
+        for component in self.excluded_components(&installed)? {
+            let manifest = self
+                .toolchain_destination
+                .join("lib/rustlib")
+                .join(format!("manifest-{component}"));
+            if let Ok(files) = read_to_string(&manifest) {
+                for file in files.lines().filter_map(|line| line.strip_prefix("file:")) {
+                    let path = self.toolchain_destination.join(file.trim());
+                    if path.exists() {
+                        std::fs::remove_file(&path)?;
+                    }
                 }
             }
-            if extended_versions.is_empty() {
-                return Err(Error::InvalidVersion(arg.to_string()));
+            forget_component(&self.toolchain_destination, &component)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the directory where the temporary installation directories are created: the
+    /// configured one, the rustup one, or the toolchain directory itself when it is installed
+    /// without rustup or in sandbox mode, where only the prefix is writable.
+    fn tmp_parent(&self) -> PathBuf {
+        if let Some(tmp_dir) = configured_tmp_dir() {
+            tmp_dir
+        } else if self.toolchain_destination.starts_with(&self.rustup_home) && !sandboxed() {
+            self.rustup_home.join("tmp")
+        } else {
+            self.toolchain_destination.clone()
+        }
+    }
+
+    /// Reinstalls only the `rust-src` component of an existing installation, e.g. to repair it.
+    pub async fn reinstall_src(&self, progress: &Progress) -> Result<(), Error> {
+        info!(
+            "Reinstalling 'rust-src' component of Xtensa Rust {} toolchain",
+            self.version
+        );
+        let tmp_dir = create_tmp_dir(&self.tmp_parent())?;
+        let tmp_dir_path = &tmp_dir.path().display().to_string();
+        let src_dir = self.toolchain_destination.join(SRC_DIR);
+
+        #[cfg(unix)]
+        {
+            download_file(
+                self.src_dist_url.clone(),
+                "rust-src.tar.xz",
+                tmp_dir_path,
+                true,
+                false,
+                progress,
+            )
+            .await?;
+            // Start from scratch, the installer can't uninstall a corrupted component
+            if src_dir.exists() {
+                remove_dir_all(&src_dir).await?;
             }
-            let mut max_version = extended_versions.pop().unwrap();
-            let mut max_subpatch = 0;
-            for version in extended_versions {
-                let subpatch: i8 = re_extended
-                    .captures(&version)
-                    .and_then(|cap| {
-                        cap.name("subpatch")
-                            .map(|subpatch| subpatch.as_str().parse().unwrap())
-                    })
-                    .unwrap();
-                if subpatch > max_subpatch {
-                    max_subpatch = subpatch;
-                    max_version = version;
-                }
+            forget_component(&self.toolchain_destination, "rust-src")?;
+
+            let _permit = extraction_permit().await;
+            self.run_src_installer(tmp_dir_path)?;
+        }
+        // The Windows bundle includes the sources, only its source tree is moved to the toolchain
+        #[cfg(windows)]
+        {
+            download_file(
+                self.dist_url.clone(),
+                "rust.zip",
+                tmp_dir_path,
+                true,
+                true,
+                progress,
+            )
+            .await?;
+            let extracted_src_dir = tmp_dir.path().join(SRC_DIR);
+            if !extracted_src_dir.exists() {
+                return Err(Error::XtensaRustSrc(format!(
+                    "'{SRC_DIR}' is missing from the bundle"
+                )));
             }
-            return Ok(max_version);
-        } else if re_extended.is_match(arg) {
-            for release in json.as_array().unwrap() {
-                let tag_name = release["tag_name"].to_string().replace(['\"', 'v'], "");
-                if tag_name.starts_with(arg) {
-                    return Ok(arg.to_string());
-                }
+            if src_dir.exists() {
+                remove_dir_all(&src_dir).await?;
             }
+            rename(&extracted_src_dir, &src_dir)?;
         }
-        Err(Error::InvalidVersion(arg.to_string()))
+
+        Ok(())
     }
 
     /// Removes the Xtensa Rust toolchain.
@@ -171,18 +410,17 @@ impl XtensaRust {
         let dir = read_dir(toolchain_path)?;
         for entry in dir {
             let entry_path = entry.unwrap().path();
-            let entry_name = entry_path.display().to_string();
-            if !entry_name.contains(RISCV_GCC)
-                && !entry_name.contains(XTENSA_GCC)
-                && !entry_name.contains(CLANG_NAME)
-            {
-                if entry_path.is_dir() {
-                    remove_dir_all(Path::new(&entry_name))
-                        .await
-                        .map_err(|_| Error::RemoveDirectory(entry_name))?;
-                } else {
-                    remove_file(&entry_name).await?;
-                }
+            // The other components and the prefix marker are kept
+            if entry_path.file_name().is_some_and(|name| {
+                [PREFIX_MARKER, RISCV_GCC, XTENSA_GCC, CLANG_NAME]
+                    .contains(&&*name.to_string_lossy())
+            }) {
+                continue;
+            }
+            if entry_path.is_dir() {
+                remove_dir_all(&entry_path).await?;
+            } else {
+                remove_file(&entry_path).await?;
             }
         }
         Ok(())
@@ -190,48 +428,33 @@ impl XtensaRust {
 }
 
 #[async_trait]
-impl Installable for XtensaRust {
-    async fn install(&self) -> Result<Vec<String>, Error> {
+impl Component for XtensaRust {
+    async fn install(&self, progress: &Progress) -> Result<Vec<Export>, Error> {
         if self.toolchain_destination.exists() {
-            let toolchain_name = format!(
-                "+{}",
-                self.toolchain_destination
-                    .file_name()
-                    .unwrap()
-                    .to_str()
-                    .unwrap(),
-            );
-            let rustc_version = Command::new("rustc")
-                .args([&toolchain_name, "--version"])
-                .stdout(Stdio::piped())
-                .output()?;
-            let output = String::from_utf8_lossy(&rustc_version.stdout);
-            if rustc_version.status.success() && output.contains(&self.version) {
-                warn!(
+            match self.installed_version() {
+                Some(version)
+                    if version == self.version
+                        && !is_overridden(&["xtensa-rust", "rust-src"])
+                        && self.has_requested_components() =>
+                {
+                    warn!(
                 "Previous installation of Xtensa Rust {} exists in: '{}'. Reusing this installation",
                 &self.version,
                 &self.toolchain_destination.display()
             );
-                return Ok(vec![]);
-            } else {
-                if !rustc_version.status.success() {
-                    warn!("Failed to detect version of Xtensa Rust, reinstalling it");
+                    return Ok(vec![]);
                 }
-                Self::uninstall(&self.toolchain_destination).await?;
+                Some(_) => {}
+                None => warn!("Failed to detect version of Xtensa Rust, reinstalling it"),
             }
+            Self::uninstall(&self.toolchain_destination).await?;
         }
 
         info!("Installing Xtensa Rust {} toolchain", self.version);
 
         #[cfg(unix)]
         if cfg!(unix) {
-            let path = get_rustup_home().join("tmp");
-            if !path.exists() {
-                info!("Creating directory: '{}'", path.display());
-                create_dir_all(&path)
-                    .map_err(|_| Error::CreateDirectory(path.display().to_string()))?;
-            }
-            let tmp_dir = tempdir_in(path)?;
+            let tmp_dir = create_tmp_dir(&self.tmp_parent())?;
             let tmp_dir_path = &tmp_dir.path().display().to_string();
 
             download_file(
@@ -240,6 +463,7 @@ impl Installable for XtensaRust {
                 tmp_dir_path,
                 true,
                 false,
+                progress,
             )
             .await?;
 
@@ -249,50 +473,45 @@ impl Installable for XtensaRust {
                 tmp_dir_path,
                 true,
                 false,
+                progress,
             )
             .await?;
 
-            info!("Installing 'rust' component for Xtensa Rust toolchain");
+            let dist_dir = format!("{}/rust-nightly-{}", tmp_dir_path, &self.host_triple);
+            let dist_components: Vec<String> = read_to_string(format!("{dist_dir}/components"))
+                .unwrap_or_default()
+                .lines()
+                .map(|line| line.trim().to_string())
+                .collect();
+            let without: Vec<String> = XTENSA_DOC_COMPONENTS
+                .iter()
+                .map(|component| component.to_string())
+                .chain(self.excluded_components(&dist_components)?)
+                .collect();
 
-            if !Command::new("/usr/bin/env")
-                .arg("bash")
-                .arg(format!(
-                    "{}/rust-nightly-{}/install.sh",
-                    tmp_dir_path, &self.host_triple,
-                ))
-                .arg(format!(
-                    "--destdir={}",
-                    self.toolchain_destination.display()
-                ))
-                .arg("--prefix=''")
-                .arg("--without=rust-docs-json-preview,rust-docs")
-                .arg("--disable-ldconfig")
-                .stdout(Stdio::null())
-                .output()?
-                .status
-                .success()
-            {
-                Self::uninstall(&self.toolchain_destination).await?;
-                return Err(Error::XtensaRust);
-            }
+            // The install scripts copy the whole toolchain, schedule them like extractions
+            let _permit = extraction_permit().await;
+            info!("Installing 'rust' component for Xtensa Rust toolchain");
 
-            info!("Installing 'rust-src' component for Xtensa Rust toolchain");
-            if !Command::new("/usr/bin/env")
-                .arg("bash")
-                .arg(format!("{}/rust-src-nightly/install.sh", tmp_dir_path))
-                .arg(format!(
-                    "--destdir={}",
-                    self.toolchain_destination.display()
-                ))
-                .arg("--prefix=''")
-                .arg("--disable-ldconfig")
-                .stdout(Stdio::null())
-                .output()?
-                .status
-                .success()
-            {
+            let installed = run_command(
+                "rust",
+                Command::new("/usr/bin/env")
+                    .arg("bash")
+                    .arg(format!("{dist_dir}/install.sh"))
+                    .arg(format!(
+                        "--destdir={}",
+                        self.toolchain_destination.display()
+                    ))
+                    .arg("--prefix=''")
+                    .arg(format!("--without={}", without.join(",")))
+                    .arg("--disable-ldconfig")
+                    .args(install_script_verbosity()),
+                Error::XtensaRust,
+            )
+            .and_then(|()| self.run_src_installer(tmp_dir_path));
+            if let Err(e) = installed {
                 Self::uninstall(&self.toolchain_destination).await?;
-                return Err(Error::XtensaRustSrc);
+                return Err(e);
             }
         }
         // Some platfroms like Windows are available in single bundle rust + src, because install
@@ -305,91 +524,473 @@ impl Installable for XtensaRust {
                 &self.toolchain_destination.display().to_string(),
                 true,
                 true,
+                progress,
             )
             .await?;
+            if let Err(e) = self.remove_excluded_components() {
+                Self::uninstall(&self.toolchain_destination).await?;
+                return Err(e);
+            }
+        }
+
+        let version_file = self.toolchain_destination.join(VERSION_FILE);
+        if let Err(e) = write_checked(&version_file, &self.version) {
+            warn!(
+                "Failed to record the Xtensa Rust version in '{}': {}",
+                version_file.display(),
+                e
+            );
         }
 
         Ok(vec![]) // No exports
     }
 
+    async fn uninstall(&self) -> Result<(), Error> {
+        Self::uninstall(&self.toolchain_destination).await
+    }
+
     fn name(&self) -> String {
         "Xtensa Rust".to_string()
     }
+
+    fn id(&self) -> String {
+        "xtensa-rust".to_string()
+    }
+
+    fn path(&self) -> PathBuf {
+        self.toolchain_destination.clone()
+    }
+
+    fn installed_version(&self) -> Option<String> {
+        installed_version(&self.toolchain_destination)
+    }
+
+    fn latest_version(&self) -> String {
+        self.version.clone()
+    }
+
+    fn artifact_urls(&self) -> Vec<String> {
+        #[cfg(unix)]
+        let urls = vec![self.dist_url.clone(), self.src_dist_url.clone()];
+        #[cfg(windows)]
+        let urls = vec![self.dist_url.clone()];
+        urls
+    }
+
+    fn size_on_disk(&self) -> u64 {
+        let Ok(entries) = read_dir(&self.toolchain_destination) else {
+            return 0;
+        };
+        entries
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| {
+                let name = entry.file_name();
+                name != RISCV_GCC && name != XTENSA_GCC && name != CLANG_NAME
+            })
+            .map(|entry| disk_usage(&entry.path()))
+            .sum()
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct RiscVTarget {
+    /// Additional rustup components.
+    pub components: Vec<String>,
+    /// Rustup and Cargo homes.
+    pub homes: Homes,
     /// Nightly version.
     pub nightly_version: String,
+    /// Rustup profile.
+    pub profile: String,
 }
 
 impl RiscVTarget {
     /// Create a crate instance.
-    pub fn new(nightly_version: &str) -> Self {
+    pub fn new(nightly_version: &str, profile: &str, components: &[String], homes: &Homes) -> Self {
         RiscVTarget {
+            components: components.to_vec(),
+            homes: homes.clone(),
             nightly_version: nightly_version.to_string(),
+            profile: profile.to_string(),
         }
     }
 
+    /// Returns the RISC-V targets and components missing from the nightly toolchain, `None` if
+    /// the toolchain is not installed.
+    fn missing(&self) -> Option<Vec<String>> {
+        let targets = rustup_installed("target", &self.nightly_version, &self.homes)?;
+        let components = rustup_installed("component", &self.nightly_version, &self.homes)?;
+        Some(missing_riscv_parts(&targets, &components, &self.components))
+    }
+
+    /// Returns the name of the cached check of the toolchain, which depends on the required
+    /// components.
+    fn check_name(&self) -> String {
+        format!(
+            "riscv:{}:{}:{}",
+            self.nightly_version,
+            self.profile,
+            self.components.join(",")
+        )
+    }
+
     /// Uninstalls the RISC-V target.
-    pub fn uninstall(nightly_version: &str) -> Result<(), Error> {
+    pub async fn uninstall(nightly_version: &str, homes: &Homes) -> Result<(), Error> {
         info!("Uninstalling RISC-V target");
 
-        if !Command::new("rustup")
-            .args([
-                "target",
-                "remove",
-                "--toolchain",
-                nightly_version,
-                "riscv32imc-unknown-none-elf",
-                "riscv32imac-unknown-none-elf",
-                "riscv32imafc-unknown-none-elf",
-            ])
-            .stdout(Stdio::null())
-            .status()?
-            .success()
-        {
-            return Err(Error::UninstallRiscvTarget);
-        }
-        Ok(())
+        let _rustup = lock_rustup(homes).await?;
+        run_command(
+            "rustup",
+            rustup_command(homes)
+                .args(["target", "remove", "--toolchain", nightly_version])
+                .args(RISCV_TARGETS),
+            Error::UninstallRiscvTarget,
+        )
     }
 }
 
 #[async_trait]
-impl Installable for RiscVTarget {
-    async fn install(&self) -> Result<Vec<String>, Error> {
+impl Component for RiscVTarget {
+    async fn install(&self, _progress: &Progress) -> Result<Vec<Export>, Error> {
+        let check = self.check_name();
+        if toolchain_verified(&check, &self.path()) {
+            info!(
+                "RISC-V Rust targets are already installed for '{}' toolchain",
+                &self.nightly_version
+            );
+            return Ok(vec![]);
+        }
+        let _rustup = lock_rustup(&self.homes).await?;
+        // Running rustup anyway would check for updates of the channel over the network
+        match self.missing() {
+            Some(missing) if missing.is_empty() => {
+                info!(
+                    "RISC-V Rust targets are already installed for '{}' toolchain",
+                    &self.nightly_version
+                );
+                record_toolchain(&check, &self.path());
+                return Ok(vec![]);
+            }
+            Some(missing) => debug!("Missing from '{}': {:?}", &self.nightly_version, missing),
+            None => debug!("Toolchain '{}' is not installed", &self.nightly_version),
+        }
+
         info!(
             "Installing RISC-V Rust targets ('riscv32imc-unknown-none-elf', 'riscv32imac-unknown-none-elf' and 'riscv32imafc-unknown-none-elf') for '{}' toolchain",            &self.nightly_version
         );
 
-        if !Command::new("rustup")
-            .args([
-                "toolchain",
-                "install",
-                &self.nightly_version,
-                "--profile",
-                "minimal",
-                "--component",
-                "rust-src",
-                "--target",
-                "riscv32imc-unknown-none-elf",
-                "riscv32imac-unknown-none-elf",
-                "riscv32imafc-unknown-none-elf",
-            ])
-            .stdout(Stdio::null())
-            .stderr(Stdio::null())
-            .status()?
-            .success()
-        {
-            return Err(Error::InstallRiscvTarget(self.nightly_version.clone()));
-        }
+        run_command(
+            "rustup",
+            rustup_command(&self.homes)
+                .args([
+                    "toolchain",
+                    "install",
+                    &self.nightly_version,
+                    "--profile",
+                    &self.profile,
+                    "--component",
+                    "rust-src",
+                ])
+                .args(
+                    self.components
+                        .iter()
+                        .flat_map(|component| ["--component", component.as_str()]),
+                )
+                .arg("--target")
+                .args(RISCV_TARGETS),
+            |stderr| Error::InstallRiscvTarget(self.nightly_version.clone(), stderr),
+        )?;
+        record_toolchain(&check, &self.path());
 
         Ok(vec![]) // No exports
     }
 
+    async fn uninstall(&self) -> Result<(), Error> {
+        Self::uninstall(&self.nightly_version, &self.homes).await
+    }
+
     fn name(&self) -> String {
         "RISC-V Rust target".to_string()
     }
+
+    fn id(&self) -> String {
+        "riscv-rust-targets".to_string()
+    }
+
+    fn path(&self) -> PathBuf {
+        // rustup names the toolchain directories `<channel>-<host triple>`
+        let toolchains_dir = self.homes.rustup_home().join("toolchains");
+        read_dir(&toolchains_dir)
+            .ok()
+            .and_then(|entries| {
+                entries.filter_map(|entry| entry.ok()).find(|entry| {
+                    entry
+                        .file_name()
+                        .to_string_lossy()
+                        .starts_with(&format!("{}-", self.nightly_version))
+                })
+            })
+            .map(|entry| entry.path())
+            .unwrap_or_else(|| toolchains_dir.join(&self.nightly_version))
+    }
+
+    fn installed_version(&self) -> Option<String> {
+        let installed = rustup_installed("target", &self.nightly_version, &self.homes)?;
+        RISCV_TARGETS
+            .iter()
+            .all(|target| installed.lines().any(|line| line.trim() == *target))
+            .then(|| self.nightly_version.clone())
+    }
+
+    fn latest_version(&self) -> String {
+        self.nightly_version.clone()
+    }
+
+    fn size_on_disk(&self) -> u64 {
+        // rustup names the toolchain directories `<channel>-<host triple>`
+        let Ok(entries) = read_dir(self.homes.rustup_home().join("toolchains")) else {
+            return 0;
+        };
+        entries
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| {
+                entry
+                    .file_name()
+                    .to_string_lossy()
+                    .starts_with(&format!("{}-", self.nightly_version))
+            })
+            .flat_map(|entry| {
+                RISCV_TARGETS
+                    .iter()
+                    .map(move |target| disk_usage(&entry.path().join("lib/rustlib").join(target)))
+            })
+            .sum()
+    }
+}
+
+/// Returns the newest release older than `version` that publishes the Xtensa Rust artifacts of
+/// the host, `releases` being sorted newest first.
+fn fallback_version(
+    releases: &[Release],
+    version: &str,
+    host_triple: &HostTriple,
+) -> Option<String> {
+    releases
+        .iter()
+        .skip_while(|release| release.version() != version)
+        .skip(1)
+        .filter(|release| !release.draft && !release.prerelease)
+        .find(|release| {
+            XtensaRust::new(release.version(), host_triple, Path::new(""))
+                .artifact_urls()
+                .iter()
+                .all(|url| release.asset(url.rsplit('/').next().unwrap()).is_some())
+        })
+        .map(|release| release.version().to_string())
+}
+
+/// Returns the output of `rustup <list> list --installed` for a toolchain, `None` if it is not
+/// installed.
+pub fn rustup_installed(list: &str, toolchain: &str, homes: &Homes) -> Option<String> {
+    let output = Command::new("rustup")
+        .envs(homes.envs())
+        .args([list, "list", "--installed", "--toolchain", toolchain])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+    output
+        .status
+        .success()
+        .then(|| String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Returns the RISC-V targets, `rust-src` and the additional components missing from the output
+/// of `rustup target list --installed` and `rustup component list --installed`.
+///
+/// Host specific components are listed with the host triple, e.g. `clippy-x86_64-pc-windows-msvc`.
+fn missing_riscv_parts(
+    installed_targets: &str,
+    installed_components: &str,
+    components: &[String],
+) -> Vec<String> {
+    let is_installed = |installed: &str, name: &str| {
+        installed.lines().map(str::trim).any(|line| {
+            line == name
+                || line
+                    .strip_prefix(name)
+                    .is_some_and(|host| host.starts_with('-'))
+        })
+    };
+    let targets = RISCV_TARGETS
+        .iter()
+        .filter(|target| {
+            !installed_targets
+                .lines()
+                .any(|line| line.trim() == **target)
+        })
+        .map(|target| target.to_string());
+    let components = std::iter::once("rust-src")
+        .chain(components.iter().map(String::as_str))
+        .filter(|component| !is_installed(installed_components, component))
+        .map(String::from);
+    targets.chain(components).collect()
+}
+
+/// Parses the name of a rustup component.
+pub fn parse_component(component: &str) -> Result<String, Error> {
+    let component = component.trim().to_lowercase();
+    if RUSTUP_COMPONENTS.contains(&component.as_str()) {
+        Ok(component)
+    } else {
+        Err(Error::UnsupportedComponent(component))
+    }
+}
+
+/// Returns the Xtensa Rust version installed in the toolchain directory.
+///
+/// The toolchain metadata is read directly, rather than asking `rustc +<toolchain>`, which
+/// rustup overrides or `PATH` shims may redirect to another toolchain.
+pub(crate) fn installed_version(toolchain_dir: &Path) -> Option<String> {
+    if let Some(components) = installed_components(toolchain_dir) {
+        if !components.iter().any(|component| component == "rustc") {
+            debug!(
+                "Xtensa Rust toolchain in '{}' lacks the 'rustc' component",
+                toolchain_dir.display()
+            );
+            return None;
+        }
+    }
+    if let Ok(version) = read_to_string(toolchain_dir.join(VERSION_FILE)) {
+        let version = version.trim();
+        if parse_extended_version(version).is_some() {
+            return Some(version.to_string());
+        }
+        debug!("Ignoring malformed Xtensa Rust version file: '{}'", version);
+    }
+    // Toolchains installed by older versions don't record their version, ask their own `rustc`
+    let rustc = toolchain_dir
+        .join("bin")
+        .join(format!("rustc{}", env::consts::EXE_SUFFIX));
+    let output = Command::new(rustc)
+        .arg("--version")
+        .stdout(Stdio::piped())
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    parse_rustc_version(&String::from_utf8_lossy(&output.stdout))
+}
+
+/// Removes a dist component from the installer metadata of the toolchain directory, so its
+/// installer runs as if it was never installed.
+fn forget_component(toolchain_dir: &Path, component: &str) -> Result<(), Error> {
+    let manifest = toolchain_dir
+        .join("lib/rustlib")
+        .join(format!("manifest-{component}"));
+    if manifest.exists() {
+        std::fs::remove_file(&manifest)?;
+    }
+    if let Some(components) = installed_components(toolchain_dir) {
+        let remaining: String = components
+            .iter()
+            .filter(|installed| *installed != component)
+            .map(|installed| format!("{installed}\n"))
+            .collect();
+        write(toolchain_dir.join(COMPONENTS_FILE), remaining)?;
+    }
+    Ok(())
+}
+
+/// Returns the dist components listed in the toolchain directory, if any.
+fn installed_components(toolchain_dir: &Path) -> Option<Vec<String>> {
+    let components = read_to_string(toolchain_dir.join(COMPONENTS_FILE)).ok()?;
+    Some(
+        components
+            .lines()
+            .map(|line| line.trim().to_string())
+            .filter(|line| !line.is_empty())
+            .collect(),
+    )
+}
+
+/// Parses the Xtensa Rust version out of the `rustc --version` output.
+fn parse_rustc_version(output: &str) -> Option<String> {
+    // e.g. `rustc 1.82.0-nightly (ff68ea3d7 2024-10-17) (1.82.0.3)`
+    let re_version = Regex::new(r"\((\d+\.\d+\.\d+\.\d+)\)").unwrap();
+    re_version
+        .captures(output)
+        .map(|captures| captures[1].to_string())
+}
+
+/// Returns the newest extended version (`major.minor.patch.subpatch`) of the given semantic
+/// version among the release tags.
+///
+/// Tags that are not in extended format (e.g. release candidates) are skipped.
+fn find_latest_version(version: &str, tags: &[String]) -> Option<String> {
+    let prefix = format!("{version}.");
+    tags.iter()
+        .filter(|tag| tag.starts_with(&prefix))
+        .filter_map(|tag| match parse_extended_version(tag) {
+            Some(components) => Some((components, tag)),
+            None => {
+                debug!("Skipping malformed Xtensa Rust release tag: '{}'", tag);
+                None
+            }
+        })
+        .max_by_key(|(components, _)| *components)
+        .map(|(_, tag)| tag.clone())
+}
+
+/// Parses the components of an extended version (`major.minor.patch.subpatch`).
+pub(crate) fn parse_extended_version(version: &str) -> Option<(u64, u64, u64, u64)> {
+    let re_extended = Regex::new(RE_EXTENDED_SEMANTIC_VERSION).unwrap();
+    let captures = re_extended.captures(version)?;
+    let component = |name: &str| captures.name(name)?.as_str().parse().ok();
+    Some((
+        component("major")?,
+        component("minor")?,
+        component("patch")?,
+        component("subpatch")?,
+    ))
+}
+
+/// Makes the toolchain the rustup default one, or the override of the current directory.
+pub async fn select_toolchain(
+    name: &str,
+    directory_override: bool,
+    homes: &Homes,
+) -> Result<(), Error> {
+    let mut command = rustup_command(homes);
+    match directory_override {
+        true => command.args(["override", "set", name]),
+        false => command.args(["default", name]),
+    };
+    let _rustup = lock_rustup(homes).await?;
+    run_command("rustup", &mut command, |stderr| {
+        Error::SelectToolchain(name.to_string(), stderr)
+    })
+}
+
+/// Returns a `rustup` command, verbose when debug logging is enabled.
+fn rustup_command(homes: &Homes) -> Command {
+    let mut cmd = Command::new("rustup");
+    cmd.envs(homes.envs());
+    if log_enabled!(Level::Debug) {
+        cmd.arg("--verbose");
+    }
+    cmd
+}
+
+/// Returns the verbosity arguments for the rust-installer `install.sh` scripts.
+#[cfg(unix)]
+fn install_script_verbosity() -> &'static [&'static str] {
+    if log_enabled!(Level::Debug) {
+        &["--verbose"]
+    } else {
+        &[]
+    }
 }
 
 /// Gets the artifact extension based on the host architecture.
@@ -400,8 +1001,84 @@ fn get_artifact_extension(host_triple: &HostTriple) -> &str {
     }
 }
 
-/// Gets the default cargo home path.
-fn get_cargo_home() -> PathBuf {
+/// Rustup and Cargo homes of an invocation, overridden with `--rustup-home` and `--cargo-home`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Homes {
+    rustup_home: Option<PathBuf>,
+    cargo_home: Option<PathBuf>,
+}
+
+impl Homes {
+    /// Creates the homes, taking precedence over `RUSTUP_HOME` and `CARGO_HOME` when set.
+    ///
+    /// Relative paths are resolved against the current directory, as the rustup and Cargo
+    /// processes may run elsewhere.
+    pub fn new(rustup_home: Option<PathBuf>, cargo_home: Option<PathBuf>) -> Self {
+        let absolute = |path: PathBuf| match env::current_dir() {
+            Ok(current_dir) if path.is_relative() => current_dir.join(path),
+            _ => path,
+        };
+        Self {
+            rustup_home: rustup_home.map(absolute),
+            cargo_home: cargo_home.map(absolute),
+        }
+    }
+
+    /// Returns the homes, using the ones of `fallback` where they aren't overridden.
+    pub fn or(self, fallback: Homes) -> Self {
+        Self {
+            rustup_home: self.rustup_home.or(fallback.rustup_home),
+            cargo_home: self.cargo_home.or(fallback.cargo_home),
+        }
+    }
+
+    /// Returns the rustup home.
+    pub fn rustup_home(&self) -> PathBuf {
+        self.rustup_home.clone().unwrap_or_else(get_rustup_home)
+    }
+
+    /// Returns the Cargo home.
+    pub fn cargo_home(&self) -> PathBuf {
+        self.cargo_home.clone().unwrap_or_else(get_cargo_home)
+    }
+
+    /// Returns the environment variables passing the overridden homes to the rustup and Cargo
+    /// processes.
+    pub(crate) fn envs(&self) -> Vec<(&'static str, PathBuf)> {
+        self.rustup_home
+            .clone()
+            .map(|home| ("RUSTUP_HOME", home))
+            .into_iter()
+            .chain(self.cargo_home.clone().map(|home| ("CARGO_HOME", home)))
+            .collect()
+    }
+
+    /// Returns the exports of the overridden homes, so the toolchain is found by the shells
+    /// sourcing the export file.
+    pub fn exports(&self) -> Vec<Export> {
+        self.envs()
+            .into_iter()
+            .map(|(variable, home)| Export::set("homes", variable, &home.display().to_string()))
+            .collect()
+    }
+
+    /// Returns the homes overridden by the exports of an installation.
+    pub fn from_exports(exports: &[Export]) -> Self {
+        let home = |variable: &str| {
+            exports
+                .iter()
+                .find(|export| export.kind == ExportKind::Set && export.variable == variable)
+                .map(|export| PathBuf::from(&export.value))
+        };
+        Self {
+            rustup_home: home("RUSTUP_HOME"),
+            cargo_home: home("CARGO_HOME"),
+        }
+    }
+}
+
+/// Gets the cargo home path, from `CARGO_HOME` or the home directory.
+pub(crate) fn get_cargo_home() -> PathBuf {
     PathBuf::from(env::var("CARGO_HOME").unwrap_or_else(|_e| {
         format!(
             "{}",
@@ -410,8 +1087,8 @@ fn get_cargo_home() -> PathBuf {
     }))
 }
 
-/// Gets the default rustup home path.
-pub fn get_rustup_home() -> PathBuf {
+/// Gets the default rustup home path, from `RUSTUP_HOME` or the home directory.
+fn default_rustup_home() -> PathBuf {
     PathBuf::from(env::var("RUSTUP_HOME").unwrap_or_else(|_e| {
         format!(
             "{}",
@@ -424,50 +1101,487 @@ pub fn get_rustup_home() -> PathBuf {
     }))
 }
 
+/// Rustup home reported by `rustup show home`, if rustup is available.
+fn reported_rustup_home() -> Option<PathBuf> {
+    let output = Command::new("rustup")
+        .args(["show", "home"])
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let home = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    (!home.is_empty()).then(|| PathBuf::from(home))
+}
+
+/// Returns the rustup home, preferring the one reported by rustup, and warns when it differs from
+/// the default one.
+fn select_rustup_home(default: PathBuf, reported: Option<PathBuf>) -> PathBuf {
+    match reported {
+        Some(reported) if reported != default => {
+            warn!(
+                "rustup reports '{}' as its home directory instead of '{}', using it",
+                reported.display(),
+                default.display()
+            );
+            reported
+        }
+        _ => default,
+    }
+}
+
+/// Gets the rustup home path, unless overridden by [`Homes`].
+///
+/// Distro packaged rustup, or rustup running under `sudo`, may store the toolchains somewhere
+/// else than `RUSTUP_HOME` or `~/.rustup`, so rustup is asked first. The answer is cached for the
+/// rest of the process.
+pub fn get_rustup_home() -> PathBuf {
+    static RUSTUP_HOME: OnceLock<PathBuf> = OnceLock::new();
+    RUSTUP_HOME
+        .get_or_init(|| select_rustup_home(default_rustup_home(), reported_rustup_home()))
+        .clone()
+}
+
+/// Rustup channel names that must not be used as the Xtensa Rust toolchain name.
+const RESERVED_TOOLCHAIN_NAMES: [&str; 3] = ["stable", "beta", "nightly"];
+
+/// Validates the Xtensa Rust toolchain name.
+///
+/// Names that match official rustup channels (`stable`, `nightly-2024-01-01`, `1.82.0`...) or an
+/// existing toolchain not installed by espup are rejected, unless `force` is set, as installing
+/// or uninstalling would overwrite the rustup toolchain.
+pub fn validate_toolchain_name(name: &str, force: bool, homes: &Homes) -> Result<(), Error> {
+    if name.is_empty()
+        || name == "."
+        || name == ".."
+        || name.contains(['/', '\\'])
+        || name.trim() != name
+    {
+        return Err(Error::InvalidToolchainName(
+            name.to_string(),
+            "it is not a valid directory name".to_string(),
+        ));
+    }
+    if force {
+        return Ok(());
+    }
+
+    let channel = name.split('-').next().unwrap_or_default();
+    let re_version = Regex::new(r"^\d+\.\d+(\.\d+)?$").unwrap();
+    if RESERVED_TOOLCHAIN_NAMES.contains(&channel) || re_version.is_match(channel) {
+        return Err(Error::InvalidToolchainName(
+            name.to_string(),
+            "it collides with an official rustup toolchain. Please, use a different name or pass '--force'".to_string(),
+        ));
+    }
+
+    let toolchain_dir = homes.rustup_home().join("toolchains").join(name);
+    if toolchain_dir.exists() && !is_espup_toolchain(name, &toolchain_dir) {
+        return Err(Error::InvalidToolchainName(
+            name.to_string(),
+            format!(
+                "'{}' already exists and was not installed by espup. Please, use a different name or pass '--force'",
+                toolchain_dir.display()
+            ),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Returns true if the toolchain directory was created by espup.
+fn is_espup_toolchain(name: &str, toolchain_dir: &Path) -> bool {
+    Manifest::path(name).exists()
+        || [CLANG_NAME, XTENSA_GCC, RISCV_GCC]
+            .iter()
+            .any(|component| toolchain_dir.join(component).exists())
+        || toolchain_dir
+            .join("lib")
+            .join("rustlib")
+            .join("xtensa-esp32-none-elf")
+            .exists()
+}
+
 /// Checks if rustup is installed.
-pub async fn check_rust_installation() -> Result<(), Error> {
+pub async fn check_rust_installation(homes: &Homes) -> Result<(), Error> {
     info!("Checking Rust installation");
 
-    if let Err(e) = Command::new("rustup")
-        .arg("--version")
-        .stdout(Stdio::piped())
-        .output()
-    {
-        if let io::ErrorKind::NotFound = e.kind() {
-            return Err(Error::MissingRust);
-        } else {
-            return Err(Error::RustupDetection(e.to_string()));
+    if rustup_verified() {
+        debug!("rustup installation unchanged since the last check");
+    } else {
+        if let Err(e) = Command::new("rustup")
+            .arg("--version")
+            .stdout(Stdio::piped())
+            .output()
+        {
+            if let io::ErrorKind::NotFound = e.kind() {
+                return Err(Error::MissingRust);
+            } else {
+                return Err(Error::RustupDetection(e.to_string()));
+            }
         }
+        record_rustup();
+    }
+
+    let cargo_bin = homes.cargo_home().join("bin");
+    // The installation still works, only the `+toolchain` arguments don't
+    if let Some(binary) = env::var_os("PATH").and_then(|path| shadowing_binary(&path, &cargo_bin)) {
+        warn!(
+            "'{}' shadows the rustup proxy in '{}', so commands like 'cargo +esp' won't work. Uninstall it (e.g. 'brew uninstall rust' if it comes from Homebrew), or move '{}' before its directory in PATH",
+            binary.display(),
+            cargo_bin.display(),
+            cargo_bin.display()
+        );
     }
 
     Ok(())
 }
 
+/// Returns the `cargo` or `rustc` binary found first in `path` that isn't a rustup proxy of
+/// `cargo_bin`, e.g. the ones of Homebrew's `rust`, which don't support `+toolchain` arguments.
+///
+/// Nothing is reported when rustup didn't install its proxies into `cargo_bin`, e.g. when it's
+/// installed by a package manager.
+fn shadowing_binary(path: &OsStr, cargo_bin: &Path) -> Option<PathBuf> {
+    let rustup = cargo_bin.join(format!("rustup{}", env::consts::EXE_SUFFIX));
+    ["cargo", "rustc"].into_iter().find_map(|name| {
+        let name = format!("{name}{}", env::consts::EXE_SUFFIX);
+        let proxy = cargo_bin.join(&name);
+        if !proxy.is_file() {
+            return None;
+        }
+        let found = env::split_paths(path)
+            .map(|dir| dir.join(&name))
+            .find(|binary| binary.is_file())?;
+        // Proxies may also be linked from other directories
+        let resolved = found.canonicalize().ok()?;
+        let is_proxy = [&proxy, &rustup]
+            .iter()
+            .any(|proxy| proxy.canonicalize().is_ok_and(|proxy| proxy == resolved));
+        (!is_proxy).then_some(found)
+    })
+}
+
 #[cfg(test)]
 mod tests {
+    #[cfg(feature = "cli")]
+    use crate::logging::initialize_logger;
+    #[cfg(unix)]
+    use crate::toolchain::rust::forget_component;
     use crate::{
-        logging::initialize_logger,
-        toolchain::rust::{get_cargo_home, get_rustup_home, XtensaRust},
+        error::Error,
+        host_triple::HostTriple,
+        releases::{Asset, Release},
+        toolchain::{
+            fetch::GithubQueryOpts,
+            rust::{
+                default_rustup_home, fallback_version, find_latest_version, get_cargo_home,
+                installed_version, missing_riscv_parts, parse_component, parse_extended_version,
+                parse_rustc_version, select_rustup_home, shadowing_binary, validate_toolchain_name,
+                Homes, XtensaComponent, XtensaRust, COMPONENTS_FILE, VERSION_FILE,
+            },
+        },
     };
     use directories::BaseDirs;
-    use std::env;
+    #[cfg(unix)]
+    use std::fs::read_to_string;
+    use std::{
+        env,
+        fs::{create_dir_all, write},
+        path::PathBuf,
+    };
     use tempfile::TempDir;
 
+    #[test]
+    fn test_installed_version() {
+        let temp_dir = TempDir::new().unwrap();
+        let toolchain_dir = temp_dir.path();
+        assert_eq!(installed_version(toolchain_dir), None);
+
+        create_dir_all(toolchain_dir.join("lib/rustlib")).unwrap();
+        write(toolchain_dir.join(VERSION_FILE), "1.82.0.3\n").unwrap();
+        assert_eq!(
+            installed_version(toolchain_dir),
+            Some("1.82.0.3".to_string())
+        );
+
+        // Partial installations are not reused
+        write(toolchain_dir.join(COMPONENTS_FILE), "rust-src\n").unwrap();
+        assert_eq!(installed_version(toolchain_dir), None);
+        write(
+            toolchain_dir.join(COMPONENTS_FILE),
+            "rustc\ncargo\nrust-std-xtensa-esp32-none-elf\nrust-src\n",
+        )
+        .unwrap();
+        assert_eq!(
+            installed_version(toolchain_dir),
+            Some("1.82.0.3".to_string())
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_forget_component() {
+        let temp_dir = TempDir::new().unwrap();
+        let toolchain_dir = temp_dir.path();
+        create_dir_all(toolchain_dir.join("lib/rustlib")).unwrap();
+        write(
+            toolchain_dir.join(COMPONENTS_FILE),
+            "rustc\nrust-src\ncargo\n",
+        )
+        .unwrap();
+        write(toolchain_dir.join("lib/rustlib/manifest-rust-src"), "").unwrap();
+
+        forget_component(toolchain_dir, "rust-src").unwrap();
+        assert_eq!(
+            read_to_string(toolchain_dir.join(COMPONENTS_FILE)).unwrap(),
+            "rustc\ncargo\n"
+        );
+        assert!(!toolchain_dir.join("lib/rustlib/manifest-rust-src").exists());
+    }
+
+    #[test]
+    fn test_xtensa_components() {
+        let temp_dir = TempDir::new().unwrap();
+        let dist_components: Vec<String> = ["rustc", "cargo", "clippy-preview", "rustfmt-preview"]
+            .map(String::from)
+            .to_vec();
+        let mut xtensa_rust = XtensaRust {
+            toolchain_destination: temp_dir.path().to_path_buf(),
+            version: "1.82.0.3".to_string(),
+            ..Default::default()
+        };
+        // All the optional components are kept by default
+        assert!(xtensa_rust
+            .excluded_components(&dist_components)
+            .unwrap()
+            .is_empty());
+        assert!(xtensa_rust.has_requested_components());
+
+        xtensa_rust.components = Some(vec![XtensaComponent::Clippy]);
+        assert_eq!(
+            xtensa_rust.excluded_components(&dist_components).unwrap(),
+            vec!["rustfmt-preview"]
+        );
+        xtensa_rust.components = Some(Vec::new());
+        assert_eq!(
+            xtensa_rust.excluded_components(&dist_components).unwrap(),
+            vec!["clippy-preview", "rustfmt-preview"]
+        );
+        xtensa_rust.components = Some(vec![XtensaComponent::Rustfmt]);
+        assert!(matches!(
+            xtensa_rust.excluded_components(&dist_components[..3]),
+            Err(Error::MissingXtensaComponent(component, version))
+                if component == "rustfmt-preview" && version == "1.82.0.3"
+        ));
+
+        create_dir_all(temp_dir.path().join("lib/rustlib")).unwrap();
+        write(
+            temp_dir.path().join(COMPONENTS_FILE),
+            "rustc
+cargo
+rustfmt-preview
+",
+        )
+        .unwrap();
+        assert!(xtensa_rust.has_requested_components());
+        xtensa_rust.components = Some(vec![XtensaComponent::Clippy]);
+        assert!(!xtensa_rust.has_requested_components());
+    }
+
+    #[test]
+    fn test_parse_rustc_version() {
+        assert_eq!(
+            parse_rustc_version("rustc 1.82.0-nightly (ff68ea3d7 2024-10-17) (1.82.0.3)\n"),
+            Some("1.82.0.3".to_string())
+        );
+        assert_eq!(
+            parse_rustc_version("rustc 1.82.0-nightly (ff68ea3d7 2024-10-17)\n"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_find_latest_version() {
+        let tags: Vec<String> = [
+            "1.85.0.1",
+            "1.85.0.10",
+            "1.85.0.2",
+            "1.85.0.11-rc1",
+            "1.85.0",
+            "1.85.01.0",
+            "1.85.0.",
+            "1.84.0.3",
+            "1.850.0.0",
+        ]
+        .iter()
+        .map(|tag| tag.to_string())
+        .collect();
+        assert_eq!(
+            find_latest_version("1.85.0", &tags),
+            Some("1.85.0.10".to_string())
+        );
+        assert_eq!(
+            find_latest_version("1.84.0", &tags),
+            Some("1.84.0.3".to_string())
+        );
+        assert_eq!(find_latest_version("1.83.0", &tags), None);
+        assert_eq!(
+            find_latest_version("1.85.0", &["1.85.0.0-rc1".to_string()]),
+            None
+        );
+    }
+
+    #[test]
+    fn test_parse_extended_version() {
+        assert_eq!(parse_extended_version("1.85.0.10"), Some((1, 85, 0, 10)));
+        assert_eq!(parse_extended_version("1.85.0.0-rc1"), None);
+        assert_eq!(parse_extended_version("1.85.0."), None);
+        assert_eq!(parse_extended_version("1.85.0"), None);
+    }
+
+    #[test]
+    fn test_parse_component() {
+        assert_eq!(parse_component("clippy").unwrap(), "clippy");
+        assert_eq!(parse_component(" RustFmt ").unwrap(), "rustfmt");
+        assert!(parse_component("llvm-tools").is_ok());
+        assert!(parse_component("clipy").is_err());
+        assert!(parse_component("").is_err());
+    }
+
+    #[test]
+    fn test_fallback_version() {
+        let release = |version: &str, hosts: &[&str]| Release {
+            tag_name: format!("v{version}"),
+            name: None,
+            draft: false,
+            prerelease: false,
+            published_at: None,
+            assets: hosts
+                .iter()
+                .flat_map(|host| {
+                    [
+                        format!("rust-{version}-{host}.tar.xz"),
+                        format!("rust-src-{version}.tar.xz"),
+                    ]
+                })
+                .map(|name| Asset {
+                    browser_download_url: format!("https://example.com/{name}"),
+                    name,
+                    size: 0,
+                    digest: None,
+                })
+                .collect(),
+        };
+        let releases = [
+            release("1.83.0.0", &["x86_64-unknown-linux-gnu"]),
+            release("1.82.0.3", &["x86_64-unknown-linux-gnu"]),
+            release("1.82.0.2", &["x86_64-unknown-linux-gnu"]),
+            release(
+                "1.82.0.1",
+                &["x86_64-unknown-linux-gnu", "aarch64-unknown-linux-gnu"],
+            ),
+            release("1.82.0.0", &["aarch64-unknown-linux-gnu"]),
+        ];
+        let aarch64 = HostTriple::Aarch64UnknownLinuxGnu;
+        assert_eq!(
+            fallback_version(&releases, "1.83.0.0", &aarch64),
+            Some("1.82.0.1".to_string())
+        );
+        assert_eq!(
+            fallback_version(&releases, "1.82.0.1", &aarch64),
+            Some("1.82.0.0".to_string())
+        );
+        // Only older releases are considered
+        let x86_64 = HostTriple::X86_64UnknownLinuxGnu;
+        assert_eq!(fallback_version(&releases, "1.82.0.1", &x86_64), None);
+        assert_eq!(fallback_version(&releases, "1.84.0.0", &aarch64), None);
+    }
+
+    #[test]
+    fn test_missing_riscv_parts() {
+        let targets = "riscv32imac-unknown-none-elf\n\
+                       riscv32imafc-unknown-none-elf\n\
+                       riscv32imc-unknown-none-elf\n\
+                       x86_64-unknown-linux-gnu\n";
+        let components = "cargo-x86_64-unknown-linux-gnu\n\
+                          clippy-x86_64-unknown-linux-gnu\n\
+                          rust-src\n\
+                          rust-std-x86_64-unknown-linux-gnu\n\
+                          rustc-x86_64-unknown-linux-gnu\n";
+        assert!(missing_riscv_parts(targets, components, &[]).is_empty());
+        assert!(missing_riscv_parts(targets, components, &["clippy".to_string()]).is_empty());
+        assert_eq!(
+            missing_riscv_parts(targets, components, &["rustfmt".to_string()]),
+            ["rustfmt"]
+        );
+        assert_eq!(
+            missing_riscv_parts(
+                "x86_64-unknown-linux-gnu\nriscv32imc-unknown-none-elf\n",
+                "rustc-x86_64-unknown-linux-gnu\n",
+                &[]
+            ),
+            [
+                "riscv32imac-unknown-none-elf",
+                "riscv32imafc-unknown-none-elf",
+                "rust-src"
+            ]
+        );
+    }
+
     #[test]
     fn test_xtensa_rust_parse_version() {
+        #[cfg(feature = "cli")]
         initialize_logger("debug");
-        assert_eq!(XtensaRust::parse_version("1.65.0.0").unwrap(), "1.65.0.0");
-        assert_eq!(XtensaRust::parse_version("1.65.0.1").unwrap(), "1.65.0.1");
-        assert_eq!(XtensaRust::parse_version("1.64.0.0").unwrap(), "1.64.0.0");
-        assert_eq!(XtensaRust::parse_version("1.82.0").unwrap(), "1.82.0.3");
-        assert_eq!(XtensaRust::parse_version("1.65.0").unwrap(), "1.65.0.1");
-        assert_eq!(XtensaRust::parse_version("1.64.0").unwrap(), "1.64.0.0");
-        assert!(XtensaRust::parse_version("422.0.0").is_err());
-        assert!(XtensaRust::parse_version("422.0.0.0").is_err());
-        assert!(XtensaRust::parse_version("a.1.1.1").is_err());
-        assert!(XtensaRust::parse_version("1.1.1.1.1").is_err());
-        assert!(XtensaRust::parse_version("1..1.1").is_err());
-        assert!(XtensaRust::parse_version("1._.*.1").is_err());
+        let query_opts = GithubQueryOpts::default();
+        assert_eq!(
+            XtensaRust::parse_version("1.65.0.0", &query_opts).unwrap(),
+            "1.65.0.0"
+        );
+        assert_eq!(
+            XtensaRust::parse_version("1.65.0.1", &query_opts).unwrap(),
+            "1.65.0.1"
+        );
+        assert_eq!(
+            XtensaRust::parse_version("1.64.0.0", &query_opts).unwrap(),
+            "1.64.0.0"
+        );
+        assert_eq!(
+            XtensaRust::parse_version("1.82.0", &query_opts).unwrap(),
+            "1.82.0.3"
+        );
+        assert_eq!(
+            XtensaRust::parse_version("1.65.0", &query_opts).unwrap(),
+            "1.65.0.1"
+        );
+        assert_eq!(
+            XtensaRust::parse_version("1.64.0", &query_opts).unwrap(),
+            "1.64.0.0"
+        );
+        assert!(XtensaRust::parse_version("422.0.0", &query_opts).is_err());
+        assert!(XtensaRust::parse_version("422.0.0.0", &query_opts).is_err());
+        assert!(XtensaRust::parse_version("a.1.1.1", &query_opts).is_err());
+        assert!(XtensaRust::parse_version("1.1.1.1.1", &query_opts).is_err());
+        assert!(XtensaRust::parse_version("1..1.1", &query_opts).is_err());
+        assert!(XtensaRust::parse_version("1._.*.1", &query_opts).is_err());
+    }
+
+    #[test]
+    fn test_validate_toolchain_name() {
+        assert!(validate_toolchain_name("esp", false, &Homes::default()).is_ok());
+        assert!(validate_toolchain_name("esp-1.82", false, &Homes::default()).is_ok());
+        assert!(validate_toolchain_name("stable", false, &Homes::default()).is_err());
+        assert!(validate_toolchain_name("nightly-2024-01-01", false, &Homes::default()).is_err());
+        assert!(
+            validate_toolchain_name("beta-x86_64-unknown-linux-gnu", false, &Homes::default())
+                .is_err()
+        );
+        assert!(validate_toolchain_name("1.82.0", false, &Homes::default()).is_err());
+        assert!(validate_toolchain_name("1.82", false, &Homes::default()).is_err());
+        assert!(validate_toolchain_name("stable", true, &Homes::default()).is_ok());
+        assert!(validate_toolchain_name("../stable", true, &Homes::default()).is_err());
+        assert!(validate_toolchain_name("", true, &Homes::default()).is_err());
     }
 
     #[test]
@@ -490,13 +1604,92 @@ mod tests {
         // No RUSTUP_HOME set
         env::remove_var("RUSTUP_HOME");
         assert_eq!(
-            get_rustup_home(),
+            default_rustup_home(),
             BaseDirs::new().unwrap().home_dir().join(".rustup")
         );
         // RUSTUP_HOME set
         let temp_dir = TempDir::new().unwrap();
         let rustup_home = temp_dir.path().to_path_buf();
         env::set_var("RUSTUP_HOME", rustup_home.to_str().unwrap());
-        assert_eq!(get_rustup_home(), rustup_home);
+        assert_eq!(default_rustup_home(), rustup_home);
+        // rustup reports another home, e.g. when packaged by the distro
+        let reported = PathBuf::from("/var/lib/rustup");
+        assert_eq!(
+            select_rustup_home(rustup_home.clone(), Some(reported.clone())),
+            reported
+        );
+        assert_eq!(
+            select_rustup_home(rustup_home.clone(), Some(rustup_home.clone())),
+            rustup_home
+        );
+        assert_eq!(select_rustup_home(rustup_home.clone(), None), rustup_home);
+    }
+
+    #[test]
+    fn test_homes() {
+        let temp_dir = TempDir::new().unwrap();
+        let rustup_home = temp_dir.path().join("rustup");
+        let homes = Homes::new(Some(rustup_home.clone()), None);
+        assert_eq!(homes.rustup_home(), rustup_home);
+        assert_eq!(homes.envs(), vec![("RUSTUP_HOME", rustup_home.clone())]);
+        // Only the overridden homes are exported, and found again in the exports
+        let exports = homes.exports();
+        assert_eq!(exports.len(), 1);
+        assert_eq!(Homes::from_exports(&exports), homes);
+        assert_eq!(Homes::from_exports(&[]), Homes::default());
+        // Relative homes are resolved against the current directory
+        assert_eq!(
+            Homes::new(None, Some(PathBuf::from("cargo"))).cargo_home(),
+            env::current_dir().unwrap().join("cargo")
+        );
+        // Overrides take precedence over the fallback ones
+        let cargo_home = temp_dir.path().join("cargo");
+        let fallback = Homes::new(
+            Some(temp_dir.path().join("other")),
+            Some(cargo_home.clone()),
+        );
+        assert_eq!(
+            homes.or(fallback),
+            Homes::new(Some(rustup_home), Some(cargo_home))
+        );
+    }
+
+    #[test]
+    fn test_shadowing_binary() {
+        let temp_dir = TempDir::new().unwrap();
+        let cargo_bin = temp_dir.path().join(".cargo/bin");
+        let brew_bin = temp_dir.path().join("homebrew/bin");
+        let exe = |name: &str| format!("{name}{}", env::consts::EXE_SUFFIX);
+        create_dir_all(&cargo_bin).unwrap();
+        create_dir_all(&brew_bin).unwrap();
+        write(brew_bin.join(exe("cargo")), "cargo").unwrap();
+        let path = |dirs: &[&PathBuf]| env::join_paths(dirs).unwrap();
+
+        // Without rustup proxies there is nothing to shadow
+        let brew_first = path(&[&brew_bin, &cargo_bin]);
+        assert_eq!(shadowing_binary(&brew_first, &cargo_bin), None);
+
+        write(cargo_bin.join(exe("rustup")), "rustup").unwrap();
+        write(cargo_bin.join(exe("cargo")), "rustup").unwrap();
+        assert_eq!(
+            shadowing_binary(&brew_first, &cargo_bin),
+            Some(brew_bin.join(exe("cargo")))
+        );
+        assert_eq!(
+            shadowing_binary(&path(&[&cargo_bin, &brew_bin]), &cargo_bin),
+            None
+        );
+
+        #[cfg(unix)]
+        {
+            // Links to the proxies are proxies as well
+            let local_bin = temp_dir.path().join("local/bin");
+            create_dir_all(&local_bin).unwrap();
+            std::os::unix::fs::symlink(cargo_bin.join("rustup"), local_bin.join("cargo")).unwrap();
+            assert_eq!(
+                shadowing_binary(&path(&[&local_bin, &brew_bin]), &cargo_bin),
+                None
+            );
+        }
     }
 }
@@ -0,0 +1,216 @@
+//! Serialization of the rustup invocations.
+//!
+//! Concurrent rustup processes share the download cache of the rustup home and may corrupt it.
+//! The invocations of espup that install, remove or select toolchains are serialized, and wait for
+//! the rustup processes started by the user, detected from the process list and the downloads in
+//! progress, to finish. Read-only queries (`rustup --version`, `rustup show home` and the
+//! `list --installed` ones) neither download nor write to the rustup home, so they don't take the
+//! lock, and can be run while it's held.
+
+use crate::{error::Error, toolchain::rust::Homes};
+use log::{info, warn};
+use std::{
+    fs::read_dir,
+    path::Path,
+    process::{Command, Stdio},
+    sync::Mutex,
+    time::{Duration, Instant, SystemTime},
+};
+use tokio::sync::{Mutex as AsyncMutex, MutexGuard};
+
+/// Downloads of rustup modified more recently than this are considered in progress.
+const ACTIVE_DOWNLOAD_AGE: Duration = Duration::from_secs(30);
+/// Delay between the checks for running rustup processes.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+/// Delay between the reports of the wait.
+const REPORT_INTERVAL: Duration = Duration::from_secs(15);
+/// Maximum wait for the other rustup processes, rustup is run anyway afterwards.
+const MAX_WAIT: Duration = Duration::from_secs(10 * 60);
+
+/// Behavior when other rustup processes are running.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum ConcurrentRustup {
+    /// Waits for them to finish, for up to 10 minutes.
+    #[default]
+    Wait,
+    /// Fails right away.
+    Fail,
+    /// Runs rustup anyway.
+    Ignore,
+}
+
+/// Behavior when other rustup processes are running, set with `--concurrent-rustup`.
+static CONCURRENT_RUSTUP: Mutex<ConcurrentRustup> = Mutex::new(ConcurrentRustup::Wait);
+/// Held during the rustup invocations of espup, across await points.
+static RUSTUP: AsyncMutex<()> = AsyncMutex::const_new(());
+
+/// Sets the behavior when other rustup processes are running.
+pub fn set_concurrent_rustup(concurrent_rustup: ConcurrentRustup) {
+    *CONCURRENT_RUSTUP.lock().unwrap() = concurrent_rustup;
+}
+
+/// Waits for the other rustup processes to finish and returns a guard serializing the rustup
+/// invocations of espup until dropped.
+pub async fn lock_rustup(homes: &Homes) -> Result<MutexGuard<'static, ()>, Error> {
+    let guard = RUSTUP.lock().await;
+    let concurrent_rustup = *CONCURRENT_RUSTUP.lock().unwrap();
+    if concurrent_rustup == ConcurrentRustup::Ignore {
+        return Ok(guard);
+    }
+    let start = Instant::now();
+    let mut last_report: Option<Instant> = None;
+    let rustup_home = homes.rustup_home();
+    while let Some(activity) = tokio::task::spawn_blocking({
+        let rustup_home = rustup_home.clone();
+        move || rustup_activity(&rustup_home)
+    })
+    .await
+    .map_err(|e| Error::RustupDetection(e.to_string()))?
+    {
+        if concurrent_rustup == ConcurrentRustup::Fail {
+            return Err(Error::ConcurrentRustup(activity));
+        }
+        if start.elapsed() >= MAX_WAIT {
+            warn!(
+                "Running rustup while {}, after waiting for {} minutes",
+                activity,
+                MAX_WAIT.as_secs() / 60
+            );
+            break;
+        }
+        if last_report.map_or(true, |report| report.elapsed() >= REPORT_INTERVAL) {
+            info!(
+                "Waiting while {} ({}s elapsed)",
+                activity,
+                start.elapsed().as_secs()
+            );
+            last_report = Some(Instant::now());
+        }
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+    Ok(guard)
+}
+
+/// Describes the activity of other rustup processes, if any.
+fn rustup_activity(rustup_home: &Path) -> Option<String> {
+    let pids = rustup_processes();
+    if !pids.is_empty() {
+        let pids: Vec<String> = pids.iter().map(u32::to_string).collect();
+        return Some(format!("rustup is running (PID {})", pids.join(", ")));
+    }
+    let downloads = active_downloads(&rustup_home.join("downloads"), SystemTime::now());
+    (downloads > 0).then(|| format!("rustup is downloading {downloads} files"))
+}
+
+/// Returns the number of partial downloads of the rustup download cache still being written.
+fn active_downloads(downloads_dir: &Path, now: SystemTime) -> usize {
+    let Ok(entries) = read_dir(downloads_dir) else {
+        return 0;
+    };
+    entries
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| {
+            entry
+                .path()
+                .extension()
+                .is_some_and(|extension| extension == "partial")
+        })
+        .filter(|entry| {
+            entry
+                .metadata()
+                .and_then(|metadata| metadata.modified())
+                .is_ok_and(|modified| {
+                    now.duration_since(modified).unwrap_or_default() < ACTIVE_DOWNLOAD_AGE
+                })
+        })
+        .count()
+}
+
+/// Returns the IDs of the running rustup processes.
+#[cfg(unix)]
+fn rustup_processes() -> Vec<u32> {
+    let Ok(output) = Command::new("pgrep")
+        .args(["-x", "rustup"])
+        .stderr(Stdio::null())
+        .output()
+    else {
+        return Vec::new();
+    };
+    parse_pgrep(&String::from_utf8_lossy(&output.stdout))
+}
+
+/// Returns the IDs of the running rustup processes.
+#[cfg(windows)]
+fn rustup_processes() -> Vec<u32> {
+    let Ok(output) = Command::new("tasklist")
+        .args(["/FI", "IMAGENAME eq rustup.exe", "/FO", "CSV", "/NH"])
+        .stderr(Stdio::null())
+        .output()
+    else {
+        return Vec::new();
+    };
+    parse_tasklist(&String::from_utf8_lossy(&output.stdout))
+}
+
+/// Parses the process IDs listed by `pgrep`, one per line.
+#[cfg(any(unix, test))]
+fn parse_pgrep(output: &str) -> Vec<u32> {
+    output
+        .lines()
+        .filter_map(|line| line.trim().parse().ok())
+        .collect()
+}
+
+/// Parses the process IDs of the `tasklist /FO CSV /NH` output, e.g.
+/// `"rustup.exe","1234","Console","1","10,240 K"`.
+#[cfg(any(windows, test))]
+fn parse_tasklist(output: &str) -> Vec<u32> {
+    output
+        .lines()
+        .filter_map(|line| line.split("\",\"").nth(1)?.parse().ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::toolchain::rustup::{active_downloads, parse_pgrep, parse_tasklist};
+    use std::{
+        fs::{create_dir_all, write},
+        time::{Duration, SystemTime},
+    };
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_rustup_processes() {
+        assert_eq!(parse_pgrep("1234\n5678\n"), vec![1234, 5678]);
+        assert!(parse_pgrep("").is_empty());
+        assert_eq!(
+            parse_tasklist(
+                "\"rustup.exe\",\"1234\",\"Console\",\"1\",\"10,240 K\"\r\n\
+                 \"rustup.exe\",\"5678\",\"Console\",\"1\",\"8,192 K\"\r\n"
+            ),
+            vec![1234, 5678]
+        );
+        assert!(
+            parse_tasklist("INFO: No tasks are running which match the specified criteria.")
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn test_active_downloads() {
+        let temp_dir = TempDir::new().unwrap();
+        let downloads_dir = temp_dir.path().join("downloads");
+        assert_eq!(active_downloads(&downloads_dir, SystemTime::now()), 0);
+
+        create_dir_all(&downloads_dir).unwrap();
+        write(downloads_dir.join("0123abcd.partial"), "").unwrap();
+        write(downloads_dir.join("4567ef01"), "").unwrap();
+        let now = SystemTime::now();
+        assert_eq!(active_downloads(&downloads_dir, now), 1);
+        // Leftovers of interrupted downloads are ignored
+        let later = now + Duration::from_secs(60);
+        assert_eq!(active_downloads(&downloads_dir, later), 0);
+    }
+}
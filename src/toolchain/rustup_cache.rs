@@ -0,0 +1,221 @@
+//! Cache of the checks of the rustup installation.
+//!
+//! Every installation checks that rustup is installed and that the RISC-V toolchain has the
+//! required targets and components, which takes seconds of rustup invocations even when nothing
+//! changed. The verified state is persisted in the cache directory, keyed by the rustup binary,
+//! which changes when rustup is updated, and by the modification times of the toolchain
+//! directories, which change when toolchains, targets or components are installed or removed.
+//! In sandbox mode, the checks are only recorded when the cache directory is writable.
+
+use crate::{env::get_cache_dir, sandbox::write_checked};
+use log::debug;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    env,
+    fs::{metadata, read_to_string},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// File of the cache directory with the verified state.
+const CACHE_FILE: &str = "rustup-checks.json";
+/// Maximum age of a verified state, changes the keys miss are checked again afterwards.
+const MAX_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Size and modification time of a file or directory.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct Stamp {
+    path: PathBuf,
+    len: u64,
+    modified_ns: u64,
+}
+
+/// State verified by a check.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct Verified {
+    /// Stamps of the files the check depends on.
+    stamps: Vec<Stamp>,
+    /// Time of the check, in seconds since the Unix epoch.
+    verified_at: u64,
+}
+
+/// Verified states, by check.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct RustupCache {
+    #[serde(flatten)]
+    checks: BTreeMap<String, Verified>,
+}
+
+/// Returns the stamps of the paths, `None` if any of them is missing.
+fn stamps(paths: &[PathBuf]) -> Option<Vec<Stamp>> {
+    paths
+        .iter()
+        .map(|path| {
+            let metadata = metadata(path).ok()?;
+            let modified = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+            Some(Stamp {
+                path: path.clone(),
+                len: metadata.len(),
+                modified_ns: modified.as_nanos() as u64,
+            })
+        })
+        .collect()
+}
+
+/// Returns the seconds since the Unix epoch.
+fn epoch_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Returns whether the check passed since the paths last changed, at most [`MAX_AGE`] ago.
+fn is_verified(file: &Path, check: &str, paths: &[PathBuf], now: SystemTime) -> bool {
+    let Some(cache) = read_to_string(file)
+        .ok()
+        .and_then(|content| serde_json::from_str::<RustupCache>(&content).ok())
+    else {
+        return false;
+    };
+    let Some(verified) = cache.checks.get(check) else {
+        return false;
+    };
+    epoch_secs(now).saturating_sub(verified.verified_at) < MAX_AGE.as_secs()
+        && stamps(paths).is_some_and(|stamps| stamps == verified.stamps)
+}
+
+/// Records that the check passed with the current state of the paths.
+fn record_verified(file: &Path, check: &str, paths: &[PathBuf], now: SystemTime) {
+    let Some(stamps) = stamps(paths) else {
+        return;
+    };
+    let mut cache: RustupCache = read_to_string(file)
+        .ok()
+        .and_then(|content| serde_json::from_str(&content).ok())
+        .unwrap_or_default();
+    cache.checks.insert(
+        check.to_string(),
+        Verified {
+            stamps,
+            verified_at: epoch_secs(now),
+        },
+    );
+    if let Err(e) = write_checked(file, serde_json::to_string_pretty(&cache).unwrap()) {
+        debug!("Failed to record the '{}' check: {}", check, e);
+    }
+}
+
+/// Returns the rustup binary found first in `PATH`.
+fn rustup_binary() -> Option<PathBuf> {
+    let name = format!("rustup{}", env::consts::EXE_SUFFIX);
+    env::split_paths(&env::var_os("PATH")?)
+        .map(|dir| dir.join(&name))
+        .find(|binary| binary.is_file())
+}
+
+/// Returns whether rustup was found installed since it was last updated.
+pub fn rustup_verified() -> bool {
+    rustup_binary().is_some_and(|binary| {
+        is_verified(
+            &get_cache_dir().join(CACHE_FILE),
+            "rustup",
+            &[binary],
+            SystemTime::now(),
+        )
+    })
+}
+
+/// Records that rustup is installed.
+pub fn record_rustup() {
+    if let Some(binary) = rustup_binary() {
+        record_verified(
+            &get_cache_dir().join(CACHE_FILE),
+            "rustup",
+            &[binary],
+            SystemTime::now(),
+        );
+    }
+}
+
+/// Returns the paths whose modification times change when targets or components are installed
+/// into or removed from the toolchain.
+fn toolchain_paths(toolchain_dir: &Path) -> Vec<PathBuf> {
+    let rustlib_dir = toolchain_dir.join("lib").join("rustlib");
+    vec![
+        toolchain_dir.to_path_buf(),
+        rustlib_dir.join("components"),
+        rustlib_dir,
+    ]
+}
+
+/// Returns whether the check of the toolchain passed since it last changed.
+pub fn toolchain_verified(check: &str, toolchain_dir: &Path) -> bool {
+    let paths = toolchain_paths(toolchain_dir);
+    rustup_binary().is_some_and(|binary| {
+        is_verified(
+            &get_cache_dir().join(CACHE_FILE),
+            check,
+            &[paths, vec![binary]].concat(),
+            SystemTime::now(),
+        )
+    })
+}
+
+/// Records that the check of the toolchain passed.
+pub fn record_toolchain(check: &str, toolchain_dir: &Path) {
+    let paths = toolchain_paths(toolchain_dir);
+    if let Some(binary) = rustup_binary() {
+        record_verified(
+            &get_cache_dir().join(CACHE_FILE),
+            check,
+            &[paths, vec![binary]].concat(),
+            SystemTime::now(),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::toolchain::rustup_cache::{is_verified, record_verified, toolchain_paths, MAX_AGE};
+    use std::{
+        fs::{create_dir_all, write},
+        time::SystemTime,
+    };
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_rustup_cache() {
+        let temp_dir = TempDir::new().unwrap();
+        let file = temp_dir.path().join("cache").join("rustup-checks.json");
+        let toolchain_dir = temp_dir.path().join("nightly-x86_64-unknown-linux-gnu");
+        let paths = toolchain_paths(&toolchain_dir);
+        let now = SystemTime::now();
+        assert!(!is_verified(&file, "riscv", &paths, now));
+
+        // Missing toolchains are never verified
+        record_verified(&file, "riscv", &paths, now);
+        assert!(!file.exists());
+
+        create_dir_all(toolchain_dir.join("lib/rustlib")).unwrap();
+        write(toolchain_dir.join("lib/rustlib/components"), "rustc\n").unwrap();
+        record_verified(&file, "riscv", &paths, now);
+        assert!(is_verified(&file, "riscv", &paths, now));
+        assert!(!is_verified(&file, "rustup", &paths, now));
+        assert!(!is_verified(&file, "riscv", &paths, now + MAX_AGE));
+
+        // Installing a component invalidates the check
+        write(
+            toolchain_dir.join("lib/rustlib/components"),
+            "rustc\nrust-src\n",
+        )
+        .unwrap();
+        assert!(!is_verified(&file, "riscv", &paths, now));
+
+        // A corrupted cache is ignored and replaced
+        write(&file, "{").unwrap();
+        assert!(!is_verified(&file, "riscv", &paths, now));
+        record_verified(&file, "riscv", &paths, now);
+        assert!(is_verified(&file, "riscv", &paths, now));
+    }
+}
@@ -0,0 +1,153 @@
+//! Machine readable installation summary, printed with `--summary-only`.
+
+use crate::toolchain::{metrics::ComponentMetrics, ComponentState};
+use serde::Serialize;
+use std::{fmt, path::PathBuf};
+use strum::Display;
+
+/// Outcome of the installation of a component.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Display, Serialize)]
+#[strum(serialize_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum ComponentStatus {
+    /// The component was not installed before.
+    Installed,
+    /// A different version of the component was replaced.
+    Updated,
+    /// The existing installation was reused.
+    Unchanged,
+}
+
+impl From<&ComponentState> for ComponentStatus {
+    fn from(state: &ComponentState) -> Self {
+        match state {
+            ComponentState::NotInstalled => Self::Installed,
+            ComponentState::Outdated(_) => Self::Updated,
+            ComponentState::Installed => Self::Unchanged,
+        }
+    }
+}
+
+/// Summary line of an installed component.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ComponentSummary {
+    /// Identifier of the component.
+    pub component: String,
+    /// Outcome of the installation.
+    pub status: ComponentStatus,
+    /// Installed version, if known.
+    pub version: Option<String>,
+    /// Installation directory.
+    pub path: PathBuf,
+    /// Timing and network metrics of the installation, only included in the JSON summary.
+    pub metrics: ComponentMetrics,
+}
+
+/// Summary of an installation.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct InstallSummary {
+    /// Summaries of the components, sorted by identifier.
+    pub components: Vec<ComponentSummary>,
+    /// Duration of the whole installation, in milliseconds.
+    pub duration_ms: u64,
+}
+
+impl fmt::Display for InstallSummary {
+    /// Formats the summary of each component in its own line.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for component in &self.components {
+            writeln!(f, "{component}")?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for ComponentSummary {
+    /// Formats the summary as `key=value` pairs, quoting values that contain spaces.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "component={} status={} version={} path={}",
+            quote(&self.component),
+            self.status,
+            quote(self.version.as_deref().unwrap_or("unknown")),
+            quote(&self.path.display().to_string())
+        )
+    }
+}
+
+fn quote(value: &str) -> String {
+    if value.is_empty() || value.contains(|c: char| c.is_whitespace() || c == '"') {
+        format!("\"{}\"", value.replace('"', "\\\""))
+    } else {
+        value.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        report::{render, OutputFormat},
+        toolchain::{
+            metrics::{ComponentMetrics, Phase},
+            summary::{ComponentStatus, ComponentSummary, InstallSummary},
+            ComponentState,
+        },
+    };
+    use std::{path::PathBuf, time::Duration};
+
+    #[test]
+    fn test_component_summary() {
+        let mut summary = ComponentSummary {
+            component: "xtensa-rust".to_string(),
+            status: ComponentStatus::from(&ComponentState::NotInstalled),
+            version: Some("1.85.0.0".to_string()),
+            path: PathBuf::from("/home/user/.rustup/toolchains/esp"),
+            metrics: ComponentMetrics::default(),
+        };
+        assert_eq!(
+            summary.to_string(),
+            "component=xtensa-rust status=installed version=1.85.0.0 path=/home/user/.rustup/toolchains/esp"
+        );
+
+        summary.status = ComponentStatus::from(&ComponentState::Installed);
+        summary.version = None;
+        summary.path = PathBuf::from("/home/my user/.rustup/toolchains/esp");
+        assert_eq!(
+            summary.to_string(),
+            "component=xtensa-rust status=unchanged version=unknown path=\"/home/my user/.rustup/toolchains/esp\""
+        );
+    }
+
+    #[test]
+    fn test_install_summary() {
+        let mut metrics = ComponentMetrics {
+            bytes_downloaded: 4096,
+            ..Default::default()
+        };
+        metrics.add_time(Phase::Download, Duration::from_millis(1200));
+        let summary = InstallSummary {
+            components: vec![ComponentSummary {
+                component: "xtensa-rust".to_string(),
+                status: ComponentStatus::Installed,
+                version: Some("1.85.0.0".to_string()),
+                path: PathBuf::from("/home/user/.rustup/toolchains/esp"),
+                metrics,
+            }],
+            duration_ms: 1500,
+        };
+        assert_eq!(
+            render(&summary, OutputFormat::Text),
+            "component=xtensa-rust status=installed version=1.85.0.0 path=/home/user/.rustup/toolchains/esp\n"
+        );
+        let json: serde_json::Value =
+            serde_json::from_str(&render(&summary, OutputFormat::Json)).unwrap();
+        assert_eq!(json["duration_ms"], 1500);
+        assert_eq!(json["components"][0]["status"], "installed");
+        assert_eq!(
+            json["components"][0]["metrics"]["timings_ms"]["download"],
+            1200
+        );
+        assert_eq!(json["components"][0]["metrics"]["bytes_downloaded"], 4096);
+    }
+}
@@ -0,0 +1,167 @@
+//! Temporary directories of the Xtensa Rust installations.
+//!
+//! The archives are downloaded and installed from a temporary directory created in the
+//! `--tmp-dir` directory, `TMPDIR` when it isn't given, or the rustup `tmp` directory otherwise.
+//! `TMPDIR` is ignored in sandbox mode, where it isn't writable. The directory is removed once the
+//! installation completes or fails, even when a panic propagates, and the leftovers of crashed
+//! runs are removed by the following installations.
+
+use crate::{
+    error::Error,
+    sandbox::{check_write, sandboxed},
+};
+use log::{debug, info, warn};
+use std::{
+    env,
+    fs::{create_dir_all, read_dir, remove_dir_all},
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{Duration, SystemTime},
+};
+use tempfile::{Builder, TempDir};
+
+/// Prefix of the temporary directories created by espup.
+pub const TMP_PREFIX: &str = ".tmp-espup-";
+/// Prefix of the temporary directories created in the rustup `tmp` directory by older espup
+/// versions, the default one of `tempfile`. rustup itself uses plain random names.
+pub const LEGACY_TMP_PREFIX: &str = ".tmp";
+/// Temporary directories older than this are considered leftovers from crashed runs.
+pub const STALE_TMP_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Directory where the temporary directories are created, set with `--tmp-dir`.
+static TMP_DIR: Mutex<Option<PathBuf>> = Mutex::new(None);
+
+/// Sets the directory where the temporary directories are created.
+pub fn set_tmp_dir(tmp_dir: Option<PathBuf>) {
+    *TMP_DIR.lock().unwrap() = tmp_dir;
+}
+
+/// Returns the directory where the temporary directories are created, if set with `--tmp-dir`
+/// or `TMPDIR` outside of sandbox mode.
+pub fn configured_tmp_dir() -> Option<PathBuf> {
+    TMP_DIR.lock().unwrap().clone().or_else(|| {
+        env::var_os("TMPDIR")
+            .filter(|dir| !dir.is_empty() && !sandboxed())
+            .map(PathBuf::from)
+    })
+}
+
+/// Creates a temporary directory in `parent`, creating it if needed. The directory is removed
+/// when dropped, and left for the following installations to remove if that fails.
+pub fn create_tmp_dir(parent: &Path) -> Result<TempDir, Error> {
+    check_write(parent)?;
+    if !parent.exists() {
+        info!("Creating directory: '{}'", parent.display());
+        create_dir_all(parent).map_err(|_| Error::CreateDirectory(parent.display().to_string()))?;
+    }
+    Ok(Builder::new().prefix(TMP_PREFIX).tempdir_in(parent)?)
+}
+
+/// Returns the entries of `dir` whose name starts with `prefix` and that were older than
+/// [`STALE_TMP_AGE`] at `now`.
+pub fn stale_tmp_dirs(dir: &Path, prefix: &str, now: SystemTime) -> Result<Vec<PathBuf>, Error> {
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+    let mut stale = Vec::new();
+    for entry in read_dir(dir)? {
+        let entry = entry?;
+        if !entry.file_name().to_string_lossy().starts_with(prefix) {
+            continue;
+        }
+        let age = entry
+            .metadata()?
+            .modified()
+            .ok()
+            .and_then(|modified| now.duration_since(modified).ok())
+            .unwrap_or_default();
+        if age > STALE_TMP_AGE {
+            stale.push(entry.path());
+        }
+    }
+    Ok(stale)
+}
+
+/// Removes the stale temporary directories left by crashed runs in the rustup `tmp` directory
+/// and in the configured one, only logging the failures.
+pub fn remove_stale_tmp_dirs(rustup_tmp_dir: &Path) {
+    let mut dirs = vec![(rustup_tmp_dir.to_path_buf(), LEGACY_TMP_PREFIX)];
+    dirs.extend(configured_tmp_dir().map(|dir| (dir, TMP_PREFIX)));
+    for (dir, prefix) in dirs {
+        let stale = match stale_tmp_dirs(&dir, prefix, SystemTime::now()) {
+            Ok(stale) => stale,
+            Err(e) => {
+                debug!("Failed to list '{}': {}", dir.display(), e);
+                continue;
+            }
+        };
+        for path in stale {
+            debug!("Removing stale temporary directory '{}'", path.display());
+            if let Err(e) = remove_dir_all(&path) {
+                warn!("Failed to remove '{}': {}", path.display(), e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::toolchain::tmp::{create_tmp_dir, stale_tmp_dirs, LEGACY_TMP_PREFIX, TMP_PREFIX};
+    use std::{
+        fs::{create_dir_all, write},
+        time::{Duration, SystemTime},
+    };
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_tmp_dir() {
+        let temp_dir = TempDir::new().unwrap();
+        let parent = temp_dir.path().join("rustup/tmp");
+        let tmp_dir = create_tmp_dir(&parent).unwrap();
+        let path = tmp_dir.path().to_path_buf();
+        assert!(path.starts_with(&parent));
+        assert!(path
+            .file_name()
+            .unwrap()
+            .to_string_lossy()
+            .starts_with(TMP_PREFIX));
+        write(path.join("rust.tar.xz"), "rust").unwrap();
+
+        // Removed on errors and panics as well
+        let result = std::panic::catch_unwind(move || {
+            let _tmp_dir = tmp_dir;
+            panic!("installation failed");
+        });
+        assert!(result.is_err());
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_stale_tmp_dirs() {
+        let temp_dir = TempDir::new().unwrap();
+        let espup = temp_dir.path().join(format!("{TMP_PREFIX}abc"));
+        let legacy = temp_dir.path().join(".tmpdef");
+        let rustup = temp_dir.path().join("ghi");
+        for dir in [&espup, &legacy, &rustup] {
+            create_dir_all(dir).unwrap();
+        }
+        let now = SystemTime::now();
+        assert!(stale_tmp_dirs(temp_dir.path(), TMP_PREFIX, now)
+            .unwrap()
+            .is_empty());
+
+        let later = now + Duration::from_secs(2 * 24 * 60 * 60);
+        assert_eq!(
+            stale_tmp_dirs(temp_dir.path(), TMP_PREFIX, later).unwrap(),
+            vec![espup.clone()]
+        );
+        let mut stale = stale_tmp_dirs(temp_dir.path(), LEGACY_TMP_PREFIX, later).unwrap();
+        stale.sort();
+        assert_eq!(stale, vec![espup, legacy]);
+        assert!(
+            stale_tmp_dirs(&temp_dir.path().join("missing"), TMP_PREFIX, later)
+                .unwrap()
+                .is_empty()
+        );
+    }
+}
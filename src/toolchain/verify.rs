@@ -0,0 +1,457 @@
+//! Post-installation build verification.
+
+use crate::{
+    error::Error,
+    manifest::Manifest,
+    targets::Target,
+    toolchain::{
+        gcc::{Gcc, XTENSA_GCC},
+        llvm::Llvm,
+        rust::Homes,
+    },
+};
+use log::{debug, info, warn};
+use miette::Result;
+use regex::Regex;
+use std::{
+    ffi::{c_char, c_uint, c_void, CStr},
+    fs::{create_dir_all, read_to_string, write},
+    path::{Path, PathBuf},
+    process::{Command, Output},
+};
+use tempfile::TempDir;
+
+const TEST_CRATE_MANIFEST: &str = r#"[package]
+name = "espup-verify"
+version = "0.1.0"
+edition = "2021"
+
+[lib]
+path = "src/lib.rs"
+"#;
+
+const TEST_CRATE_LIB: &str = r#"#![no_std]
+
+pub fn add(a: u32, b: u32) -> u32 {
+    a.wrapping_add(b)
+}
+"#;
+
+const TEST_C_SOURCE: &str = "int main(void) { return 0; }\n";
+
+/// Sources of the standard library installed by the `rust-src` component.
+const RUST_SRC_LIBRARY: &str = "lib/rustlib/src/rust/library";
+/// Crates of the standard library built with `-Zbuild-std`.
+const RUST_SRC_CRATES: [&str; 4] = ["core", "alloc", "std", "panic_abort"];
+
+/// Builds a small test crate for the given target and checks that LLVM and GCC are usable.
+///
+/// `toolchain` is the rustup toolchain used to build the crate (`esp` for Xtensa targets, the
+/// nightly toolchain for RISC-V targets).
+pub fn verify_build(
+    target: &Target,
+    toolchain: &str,
+    llvm: Option<&Llvm>,
+    gcc: Option<&Gcc>,
+    homes: &Homes,
+) -> Result<(), Error> {
+    info!("Verifying the installation by building a test crate for '{target}'");
+    let tmp_dir = TempDir::new()?;
+
+    verify_rustc(tmp_dir.path(), target, toolchain, homes)?;
+    if let Some(llvm) = llvm {
+        verify_llvm(tmp_dir.path(), llvm)?;
+    }
+    if let Some(gcc) = gcc {
+        verify_gcc(tmp_dir.path(), target, gcc)?;
+    }
+
+    info!("Build verification for '{target}' succeeded");
+    Ok(())
+}
+
+/// Checks that the `rust-src` component of the Xtensa Rust toolchain of an installation is
+/// complete, as a broken one makes `-Zbuild-std` fail with `error[E0463]: can't find crate for
+/// 'core'`.
+pub fn check_rust_src(name: &str) -> Result<(), Error> {
+    let manifest = Manifest::load(name)?.ok_or_else(|| Error::MissingManifest(name.to_string()))?;
+    let toolchain_dir = manifest.toolchain_dir();
+    if !toolchain_dir.exists() {
+        // Installations without Xtensa targets have no Xtensa Rust toolchain
+        return Ok(());
+    }
+    info!("Checking the 'rust-src' component of the '{name}' toolchain");
+    match rust_src_problem(&toolchain_dir) {
+        Some(problem) => Err(Error::BrokenRustSrc(name.to_string(), problem)),
+        None => Ok(()),
+    }
+}
+
+/// Describes what is missing from the `rust-src` component of a toolchain directory, if anything.
+///
+/// Besides the sources of the crates built with `-Zbuild-std`, every file recorded in the
+/// installer manifest of the component must exist.
+fn rust_src_problem(toolchain_dir: &Path) -> Option<String> {
+    let library = toolchain_dir.join(RUST_SRC_LIBRARY);
+    if !library.is_dir() {
+        return Some(format!("'{}' is missing", library.display()));
+    }
+    if let Some(krate) = RUST_SRC_CRATES
+        .iter()
+        .find(|krate| !library.join(krate).join("src/lib.rs").is_file())
+    {
+        return Some(format!("the sources of the '{krate}' crate are missing"));
+    }
+    let manifest = read_to_string(toolchain_dir.join("lib/rustlib/manifest-rust-src")).ok()?;
+    let recorded: Vec<&str> = manifest
+        .lines()
+        .filter_map(|line| line.strip_prefix("file:"))
+        .collect();
+    let missing = recorded
+        .iter()
+        .filter(|file| !toolchain_dir.join(file.trim()).exists())
+        .count();
+    (missing > 0).then(|| {
+        format!(
+            "{missing} of the {} files of the component are missing",
+            recorded.len()
+        )
+    })
+}
+
+/// Returns whether the compiler failed to find a crate of the standard library.
+fn missing_std_crate(stderr: &str) -> bool {
+    stderr.contains("error[E0463]")
+}
+
+/// Runs `<binary> --version` to check that an installed binary can be executed in this system.
+///
+/// Fails when the dynamic loader reports missing shared libraries or an old glibc, suggesting the
+/// packages to install; other failures are only reported as warnings.
+pub fn check_executable(component: &str, binary: &Path) -> Result<(), Error> {
+    debug!("Checking that '{}' can be executed", binary.display());
+    let output = match Command::new(binary).arg("--version").output() {
+        Ok(output) => output,
+        Err(e) => {
+            warn!("Failed to execute '{}': {}", binary.display(), e);
+            return Ok(());
+        }
+    };
+    if output.status.success() {
+        return Ok(());
+    }
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    match dependency_hint(&stderr, &distribution_ids()) {
+        Some(hint) => Err(Error::MissingDependency(
+            component.to_string(),
+            stderr.trim().to_string(),
+            hint,
+        )),
+        None => {
+            warn!(
+                "'{} --version' failed, {} may not work in this system: {}",
+                binary.display(),
+                component,
+                stderr.trim()
+            );
+            Ok(())
+        }
+    }
+}
+
+/// Returns the `ID` and `ID_LIKE` values of `/etc/os-release`.
+fn distribution_ids() -> Vec<String> {
+    let Ok(os_release) = read_to_string("/etc/os-release") else {
+        return Vec::new();
+    };
+    os_release
+        .lines()
+        .filter_map(|line| {
+            line.strip_prefix("ID=")
+                .or_else(|| line.strip_prefix("ID_LIKE="))
+        })
+        .flat_map(|value| value.trim_matches('"').split_whitespace())
+        .map(String::from)
+        .collect()
+}
+
+/// Returns how to fix the loader error of a binary, if it was caused by a missing dependency.
+fn dependency_hint(stderr: &str, distribution_ids: &[String]) -> Option<String> {
+    let re_glibc = Regex::new(r"GLIBC_(\d+\.\d+)' not found").unwrap();
+    if let Some(captures) = re_glibc.captures(stderr) {
+        return Some(format!(
+            "glibc {} or newer is required, please upgrade your distribution",
+            &captures[1]
+        ));
+    }
+
+    let re_library = Regex::new(r"error while loading shared libraries: ([^:\s]+)").unwrap();
+    let library = re_library.captures(stderr)?.get(1)?.as_str().to_string();
+    // (library, Debian, Fedora, Arch Linux, openSUSE) packages
+    let packages = [
+        (
+            "libtinfo.so.5",
+            "libtinfo5",
+            "ncurses-compat-libs",
+            "ncurses5-compat-libs",
+            "libncurses5",
+        ),
+        (
+            "libncurses.so.5",
+            "libncurses5",
+            "ncurses-compat-libs",
+            "ncurses5-compat-libs",
+            "libncurses5",
+        ),
+        ("libz.so.1", "zlib1g", "zlib", "zlib", "libz1"),
+        ("libxml2.so.2", "libxml2", "libxml2", "libxml2", "libxml2-2"),
+        (
+            "libstdc++.so.6",
+            "libstdc++6",
+            "libstdc++",
+            "gcc-libs",
+            "libstdc++6",
+        ),
+    ];
+    let fallback = format!("Install the package providing '{library}' with your package manager");
+    let Some((_, debian, fedora, arch, suse)) = packages.iter().find(|(name, ..)| *name == library)
+    else {
+        return Some(fallback);
+    };
+    let is = |id: &str| distribution_ids.iter().any(|i| i == id);
+    let command = if is("debian") || is("ubuntu") {
+        format!("sudo apt install {debian}")
+    } else if is("fedora") || is("rhel") || is("centos") {
+        format!("sudo dnf install {fedora}")
+    } else if is("arch") {
+        format!("sudo pacman -S {arch} (it may only be available in the AUR)")
+    } else if is("suse") || is("opensuse") {
+        format!("sudo zypper install {suse}")
+    } else {
+        return Some(fallback);
+    };
+    Some(format!(
+        "'{library}' is missing, install it with: {command}"
+    ))
+}
+
+/// Builds the test crate with `cargo +<toolchain>`.
+fn verify_rustc(dir: &Path, target: &Target, toolchain: &str, homes: &Homes) -> Result<(), Error> {
+    info!("Verifying 'rustc' stage");
+    let crate_dir = dir.join("espup-verify");
+    create_dir_all(crate_dir.join("src"))?;
+    write(crate_dir.join("Cargo.toml"), TEST_CRATE_MANIFEST)?;
+    write(crate_dir.join("src").join("lib.rs"), TEST_CRATE_LIB)?;
+
+    let mut cmd = Command::new("cargo");
+    cmd.current_dir(&crate_dir)
+        .envs(homes.envs())
+        .arg(format!("+{toolchain}"))
+        .args(["build", "--release", "--target", target.rust_target()]);
+    if !target.is_xtensa() {
+        return run_stage("rustc", &mut cmd);
+    }
+    cmd.arg("-Zbuild-std=core");
+    match run_stage("rustc", &mut cmd) {
+        Err(Error::VerifyBuild(_, stderr)) if missing_std_crate(&stderr) => Err(
+            Error::BrokenRustSrc(toolchain.to_string(), "'core' can't be built".to_string()),
+        ),
+        result => result,
+    }
+}
+
+/// Checks that libclang can be loaded, as bindgen does, and, for extended installations, that
+/// clang can compile.
+fn verify_llvm(dir: &Path, llvm: &Llvm) -> Result<(), Error> {
+    info!("Verifying 'libclang' stage");
+    let libclang = PathBuf::from(llvm.get_lib_path()).join(libclang_file_name());
+    let version =
+        libclang_version(&libclang).map_err(|e| Error::VerifyBuild("libclang".to_string(), e))?;
+    debug!("Loaded '{}': {}", libclang.display(), version);
+
+    if llvm.extended {
+        let source = dir.join("clang.c");
+        write(&source, TEST_C_SOURCE)?;
+        let mut cmd = Command::new(llvm.get_bin_path());
+        cmd.args(["--target=xtensa-esp-elf", "-mcpu=esp32", "-c", "-o"])
+            .arg(dir.join("clang.o"))
+            .arg(&source);
+        run_stage("clang", &mut cmd)?;
+    }
+    Ok(())
+}
+
+/// Compiles and links a trivial C program with GCC.
+fn verify_gcc(dir: &Path, target: &Target, gcc: &Gcc) -> Result<(), Error> {
+    info!("Verifying 'gcc' stage");
+    let source = dir.join("gcc.c");
+    write(&source, TEST_C_SOURCE)?;
+    let compiler = if gcc.arch == XTENSA_GCC {
+        format!("xtensa-{target}-elf-gcc")
+    } else {
+        format!("{}-gcc", gcc.arch)
+    };
+    let mut cmd = Command::new(PathBuf::from(gcc.get_bin_path()).join(compiler));
+    cmd.args(["-nostdlib", "-Wl,--entry=main", "-o"])
+        .arg(dir.join("gcc.elf"))
+        .arg(&source);
+    run_stage("gcc", &mut cmd)
+}
+
+/// Runs the command of a verification stage, mapping failures to [`Error::VerifyBuild`].
+fn run_stage(stage: &str, cmd: &mut Command) -> Result<(), Error> {
+    debug!("Running {:?}", cmd);
+    match cmd.output() {
+        Ok(Output { status, stderr, .. }) if status.success() => {
+            debug!("{}", String::from_utf8_lossy(&stderr));
+            Ok(())
+        }
+        Ok(Output { stderr, .. }) => Err(Error::VerifyBuild(
+            stage.to_string(),
+            String::from_utf8_lossy(&stderr).to_string(),
+        )),
+        Err(e) => Err(Error::VerifyBuild(stage.to_string(), e.to_string())),
+    }
+}
+
+/// String returned by the libclang API.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct CXString {
+    data: *const c_void,
+    private_flags: c_uint,
+}
+
+/// Loads libclang and returns its version, which fails if the library or one of its dependencies
+/// can't be loaded.
+fn libclang_version(libclang: &Path) -> Result<String, String> {
+    let error = |e: libloading::Error| format!("Failed to load '{}': {}", libclang.display(), e);
+    // SAFETY: the symbols are declared with the signatures of the libclang C API, and the string
+    // is copied before being disposed.
+    unsafe {
+        let library = libloading::Library::new(libclang).map_err(error)?;
+        let get_version: libloading::Symbol<unsafe extern "C" fn() -> CXString> =
+            library.get(b"clang_getClangVersion\0").map_err(error)?;
+        let get_c_string: libloading::Symbol<unsafe extern "C" fn(CXString) -> *const c_char> =
+            library.get(b"clang_getCString\0").map_err(error)?;
+        let dispose_string: libloading::Symbol<unsafe extern "C" fn(CXString)> =
+            library.get(b"clang_disposeString\0").map_err(error)?;
+        let version = get_version();
+        let c_string = get_c_string(version);
+        let text = if c_string.is_null() {
+            String::new()
+        } else {
+            CStr::from_ptr(c_string).to_string_lossy().into_owned()
+        };
+        dispose_string(version);
+        Ok(text)
+    }
+}
+
+/// Returns the file name of the libclang shared library in the host.
+fn libclang_file_name() -> &'static str {
+    if cfg!(windows) {
+        "libclang.dll"
+    } else if cfg!(target_os = "macos") {
+        "libclang.dylib"
+    } else {
+        "libclang.so"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::toolchain::verify::{
+        dependency_hint, libclang_version, missing_std_crate, rust_src_problem, RUST_SRC_CRATES,
+        RUST_SRC_LIBRARY,
+    };
+    use std::fs::{create_dir_all, remove_file, write};
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_dependency_hint() {
+        let ubuntu = vec!["ubuntu".to_string(), "debian".to_string()];
+        let stderr = "clang: error while loading shared libraries: libtinfo.so.5: cannot open shared object file: No such file or directory";
+        assert_eq!(
+            dependency_hint(stderr, &ubuntu).as_deref(),
+            Some("'libtinfo.so.5' is missing, install it with: sudo apt install libtinfo5")
+        );
+        assert_eq!(
+            dependency_hint(stderr, &["fedora".to_string()]).as_deref(),
+            Some(
+                "'libtinfo.so.5' is missing, install it with: sudo dnf install ncurses-compat-libs"
+            )
+        );
+        assert_eq!(
+            dependency_hint(stderr, &[]).as_deref(),
+            Some("Install the package providing 'libtinfo.so.5' with your package manager")
+        );
+
+        let stderr = "xtensa-esp-elf-gcc: /lib/x86_64-linux-gnu/libc.so.6: version `GLIBC_2.34' not found (required by xtensa-esp-elf-gcc)";
+        assert_eq!(
+            dependency_hint(stderr, &ubuntu).as_deref(),
+            Some("glibc 2.34 or newer is required, please upgrade your distribution")
+        );
+
+        assert_eq!(dependency_hint("unknown option '--version'", &ubuntu), None);
+    }
+
+    #[test]
+    fn test_rust_src_problem() {
+        let temp_dir = TempDir::new().unwrap();
+        let toolchain_dir = temp_dir.path();
+        assert!(rust_src_problem(toolchain_dir)
+            .unwrap()
+            .ends_with("library' is missing"));
+
+        let library = toolchain_dir.join(RUST_SRC_LIBRARY);
+        for krate in RUST_SRC_CRATES {
+            create_dir_all(library.join(krate).join("src")).unwrap();
+            write(library.join(krate).join("src/lib.rs"), "").unwrap();
+        }
+        assert_eq!(rust_src_problem(toolchain_dir), None);
+
+        write(
+            toolchain_dir.join("lib/rustlib/manifest-rust-src"),
+            format!(
+                "file:{0}/core/src/lib.rs\nfile:{0}/core/src/ptr.rs\ndir:{0}/alloc\n",
+                library.display()
+            ),
+        )
+        .unwrap();
+        assert_eq!(
+            rust_src_problem(toolchain_dir).as_deref(),
+            Some("1 of the 2 files of the component are missing")
+        );
+
+        remove_file(library.join("alloc/src/lib.rs")).unwrap();
+        assert_eq!(
+            rust_src_problem(toolchain_dir).as_deref(),
+            Some("the sources of the 'alloc' crate are missing")
+        );
+    }
+
+    #[test]
+    fn test_libclang_version() {
+        let temp_dir = TempDir::new().unwrap();
+        assert!(libclang_version(&temp_dir.path().join("libclang.so")).is_err());
+
+        // Files that aren't shared libraries fail to load as well
+        let libclang = temp_dir.path().join("libclang.so");
+        write(&libclang, "not a library").unwrap();
+        assert!(libclang_version(&libclang)
+            .unwrap_err()
+            .starts_with("Failed to load"));
+    }
+
+    #[test]
+    fn test_missing_std_crate() {
+        assert!(missing_std_crate(
+            "error[E0463]: can't find crate for `core`\n  |\n  = note: the `xtensa-esp32-none-elf` target may not be installed"
+        ));
+        assert!(!missing_std_crate(
+            "error[E0425]: cannot find value `x` in this scope"
+        ));
+    }
+}
@@ -0,0 +1,158 @@
+//! Version tracking of components extracted without a version directory (Windows).
+//!
+//! Older espup versions created an empty marker file named after the installed version, those
+//! markers are migrated to the `versions.json` file.
+
+use crate::{error::Error, sandbox::write_checked};
+use log::{debug, warn};
+use miette::Result;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeSet,
+    fs::{read_dir, read_to_string, remove_file, rename},
+    path::{Path, PathBuf},
+};
+
+/// Name of the file tracking the installed versions of a component.
+pub const VERSIONS_FILE: &str = "versions.json";
+
+/// Versions installed in a component directory.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Versions {
+    /// Installed versions.
+    pub versions: BTreeSet<String>,
+}
+
+impl Versions {
+    /// Loads the versions installed in the component directory, including legacy markers.
+    pub fn load(dir: &Path) -> Self {
+        let path = dir.join(VERSIONS_FILE);
+        let mut versions = match read_to_string(&path) {
+            Ok(content) => serde_json::from_str(&content).unwrap_or_else(|_| {
+                warn!("Ignoring invalid versions file: '{}'", path.display());
+                Self::default()
+            }),
+            Err(_) => Self::default(),
+        };
+        for marker in legacy_markers(dir) {
+            if let Some(version) = marker.file_name() {
+                versions
+                    .versions
+                    .insert(version.to_string_lossy().to_string());
+            }
+        }
+        versions
+    }
+
+    /// Returns whether the given version is installed.
+    pub fn contains(&self, version: &str) -> bool {
+        self.versions.contains(version)
+    }
+
+    /// Returns the most recent installed version.
+    pub fn latest(&self) -> Option<String> {
+        self.versions.iter().next_back().cloned()
+    }
+
+    /// Records the version installed in the component directory.
+    ///
+    /// Components are extracted on top of any previous installation, so the version replaces any
+    /// other. Recording the same version multiple times is harmless.
+    pub fn record(dir: &Path, version: &str) -> Result<(), Error> {
+        let versions = Self {
+            versions: [version.to_string()].into_iter().collect(),
+        };
+        versions.save(dir)?;
+        remove_legacy_markers(dir)
+    }
+
+    /// Validates the component directory layout, migrating legacy markers and forgetting the
+    /// recorded versions when the component files are missing.
+    pub fn repair(dir: &Path, files_present: bool) -> Result<(), Error> {
+        if !dir.exists() {
+            return Ok(());
+        }
+        let mut versions = Self::load(dir);
+        if !files_present && !versions.versions.is_empty() {
+            warn!(
+                "Component files are missing from '{}', forgetting the recorded versions",
+                dir.display()
+            );
+            versions.versions.clear();
+        }
+        versions.save(dir)?;
+        remove_legacy_markers(dir)
+    }
+
+    /// Writes the versions file, replacing it atomically.
+    fn save(&self, dir: &Path) -> Result<(), Error> {
+        let path = dir.join(VERSIONS_FILE);
+        debug!("Writing versions file: '{}'", path.display());
+        let content = serde_json::to_string_pretty(self).map_err(|_| Error::SerializeJson)?;
+        let tmp_path = dir.join(format!("{VERSIONS_FILE}.tmp"));
+        write_checked(&tmp_path, content)?;
+        rename(&tmp_path, &path)?;
+        Ok(())
+    }
+}
+
+/// Returns the legacy marker files of a component directory: empty files named after a version.
+pub fn legacy_markers(dir: &Path) -> Vec<PathBuf> {
+    let Ok(entries) = read_dir(dir) else {
+        return Vec::new();
+    };
+    entries
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| {
+            let name = entry.file_name().to_string_lossy().to_string();
+            let looks_like_version = name.starts_with(|c: char| c.is_ascii_digit())
+                || (name.starts_with("esp-") && name != "esp-clang");
+            looks_like_version
+                && entry
+                    .metadata()
+                    .is_ok_and(|metadata| metadata.is_file() && metadata.len() == 0)
+        })
+        .map(|entry| entry.path())
+        .collect()
+}
+
+fn remove_legacy_markers(dir: &Path) -> Result<(), Error> {
+    for marker in legacy_markers(dir) {
+        debug!("Removing legacy version marker: '{}'", marker.display());
+        remove_file(marker)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::toolchain::versions::{legacy_markers, Versions};
+    use std::fs::{create_dir_all, File};
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_versions() {
+        let dir = TempDir::new().unwrap();
+        assert_eq!(Versions::load(dir.path()).latest(), None);
+
+        // Legacy markers are migrated, directories named as versions are left alone
+        File::create(dir.path().join("esp-17.0.1_20240419")).unwrap();
+        create_dir_all(dir.path().join("esp-clang")).unwrap();
+        create_dir_all(dir.path().join("esp-18.1.2_20240912")).unwrap();
+        assert!(Versions::load(dir.path()).contains("esp-17.0.1_20240419"));
+        Versions::repair(dir.path(), true).unwrap();
+        assert!(legacy_markers(dir.path()).is_empty());
+        assert!(Versions::load(dir.path()).contains("esp-17.0.1_20240419"));
+
+        // Recording is idempotent and replaces previous versions
+        Versions::record(dir.path(), "esp-18.1.2_20240912").unwrap();
+        Versions::record(dir.path(), "esp-18.1.2_20240912").unwrap();
+        let versions = Versions::load(dir.path());
+        assert_eq!(versions.versions.len(), 1);
+        assert_eq!(versions.latest().as_deref(), Some("esp-18.1.2_20240912"));
+
+        // Versions are forgotten when the files are gone
+        Versions::repair(dir.path(), false).unwrap();
+        assert_eq!(Versions::load(dir.path()).latest(), None);
+    }
+}
@@ -0,0 +1,188 @@
+//! Reminders about new versions of espup and of the installed toolchains.
+//!
+//! The check runs at most once a week: the time of the last one is persisted in the cache
+//! directory. It is skipped with `--no-update-check` or by setting `ESPUP_NO_UPDATE_CHECK`.
+
+use crate::{
+    env::get_cache_dir,
+    manifest::Manifest,
+    releases::{fetch_latest_release, RUST_BUILD_REPOSITORY},
+    toolchain::{fetch::GithubQueryOpts, rust::parse_extended_version},
+};
+use log::{debug, warn};
+use std::{
+    fs::{create_dir_all, read_to_string, write},
+    path::Path,
+    sync::atomic::{AtomicBool, Ordering},
+    thread,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use update_informer::{registry, Check};
+
+/// Minimum time between two checks.
+const CHECK_INTERVAL: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+/// File of the cache directory with the time of the last check, in seconds since the Unix epoch.
+const LAST_CHECK_FILE: &str = "last-update-check";
+
+/// Whether the check is enabled.
+static UPDATE_CHECK: AtomicBool = AtomicBool::new(true);
+
+/// Disables the check for the rest of the process.
+pub fn disable_update_check() {
+    UPDATE_CHECK.store(false, Ordering::Relaxed);
+}
+
+/// Returns whether the last check, recorded in `file`, is older than [`CHECK_INTERVAL`].
+fn check_due(file: &Path, now: SystemTime) -> bool {
+    let Some(last_check) = read_to_string(file)
+        .ok()
+        .and_then(|secs| secs.trim().parse().ok())
+        .map(|secs| UNIX_EPOCH + Duration::from_secs(secs))
+    else {
+        return true;
+    };
+    now.duration_since(last_check)
+        .map_or(true, |elapsed| elapsed >= CHECK_INTERVAL)
+}
+
+/// Records the time of a check in `file`.
+fn record_check(file: &Path, now: SystemTime) {
+    let secs = now.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+    let recorded = file
+        .parent()
+        .map_or(Ok(()), create_dir_all)
+        .and_then(|_| write(file, secs.to_string()));
+    if let Err(e) = recorded {
+        debug!("Failed to record the update check: {}", e);
+    }
+}
+
+/// Returns the installations, with their Xtensa Rust version, older than the latest release.
+pub fn outdated_toolchains(manifests: &[Manifest], latest: &str) -> Vec<(String, String)> {
+    let Some(latest) = parse_extended_version(latest) else {
+        return Vec::new();
+    };
+    manifests
+        .iter()
+        .filter(|manifest| {
+            parse_extended_version(&manifest.toolchain_version)
+                .is_some_and(|installed| installed < latest)
+        })
+        .map(|manifest| (manifest.name.clone(), manifest.toolchain_version.clone()))
+        .collect()
+}
+
+/// Returns the reminder about the new espup version and the outdated installations, if any.
+fn reminder(
+    name: &str,
+    new_version: Option<&str>,
+    outdated: &[(String, String)],
+    latest_toolchain: &str,
+) -> Option<String> {
+    let mut lines = Vec::new();
+    if let Some(version) = new_version {
+        lines.push(format!(
+            "A new version of {name} ('{version}') is available, update it with 'cargo install {name} --locked'"
+        ));
+    }
+    for (toolchain, version) in outdated {
+        lines.push(format!(
+            "Xtensa Rust {latest_toolchain} is available, the '{toolchain}' toolchain uses {version}, update it with 'espup update --name {toolchain}'"
+        ));
+    }
+    (!lines.is_empty()).then(|| lines.join("\n"))
+}
+
+/// Checks crates.io for a new version of the application and GitHub for a new Xtensa Rust
+/// release, at most once a week.
+pub fn check_for_update(name: &str, version: &str) {
+    if !UPDATE_CHECK.load(Ordering::Relaxed) {
+        return;
+    }
+    let file = get_cache_dir().join(LAST_CHECK_FILE);
+    let now = SystemTime::now();
+    if !check_due(&file, now) {
+        return;
+    }
+    record_check(&file, now);
+
+    // Our own interval applies, invalidate the cache of update_informer
+    let informer = update_informer::new(registry::Crates, name, version).interval(Duration::ZERO);
+    let new_version = informer
+        .check_version()
+        .ok()
+        .flatten()
+        .map(|version| version.to_string());
+
+    let manifests = Manifest::list().unwrap_or_default();
+    let latest_toolchain = if manifests.is_empty() {
+        None
+    } else {
+        let opts = GithubQueryOpts {
+            retries: 0,
+            timeout: Duration::from_secs(5),
+        };
+        // The GitHub client is blocking, which is not allowed in the async runtime threads
+        thread::spawn(move || fetch_latest_release(RUST_BUILD_REPOSITORY, &opts))
+            .join()
+            .ok()
+            .and_then(Result::ok)
+            .map(|release| release.version().to_string())
+    };
+    let latest_toolchain = latest_toolchain.unwrap_or_default();
+    let outdated = outdated_toolchains(&manifests, &latest_toolchain);
+    if let Some(reminder) = reminder(name, new_version.as_deref(), &outdated, &latest_toolchain) {
+        warn!("{}", reminder);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        manifest::{manifest_fixture, Manifest},
+        targets::Target,
+        update::{check_due, outdated_toolchains, record_check, reminder, CHECK_INTERVAL},
+    };
+    use std::time::{Duration, SystemTime};
+    use tempfile::TempDir;
+
+    fn manifest(name: &str, toolchain_version: &str) -> Manifest {
+        Manifest {
+            name: name.to_string(),
+            targets: [Target::ESP32S3].into_iter().collect(),
+            toolchain_version: toolchain_version.to_string(),
+            ..manifest_fixture()
+        }
+    }
+
+    #[test]
+    fn test_check_due() {
+        let temp_dir = TempDir::new().unwrap();
+        let file = temp_dir.path().join("cache/last-update-check");
+        let now = SystemTime::now();
+        assert!(check_due(&file, now));
+        record_check(&file, now);
+        assert!(!check_due(&file, now + Duration::from_secs(60)));
+        assert!(check_due(&file, now + CHECK_INTERVAL));
+    }
+
+    #[test]
+    fn test_outdated_toolchains() {
+        let manifests = [
+            manifest("esp", "1.82.0.3"),
+            manifest("esp-old", "1.81.0.0"),
+            manifest("esp-custom", "custom"),
+        ];
+        let outdated = outdated_toolchains(&manifests, "1.82.0.3");
+        assert_eq!(
+            outdated,
+            vec![("esp-old".to_string(), "1.81.0.0".to_string())]
+        );
+        assert!(outdated_toolchains(&manifests, "").is_empty());
+
+        let text = reminder("espup", Some("0.15.0"), &outdated, "1.82.0.3").unwrap();
+        assert_eq!(text.lines().count(), 2);
+        assert!(text.contains("'espup update --name esp-old'"));
+        assert_eq!(reminder("espup", None, &[], "1.82.0.3"), None);
+    }
+}
@@ -0,0 +1,260 @@
+//! Versions of espup and of the installed toolchains, printed by `espup version`.
+
+use crate::{
+    host_triple::HostTriple,
+    manifest::Manifest,
+    releases::{latest_release, RUST_BUILD_REPOSITORY},
+    toolchain::{
+        compatibility::resolve,
+        fetch::GithubQueryOpts,
+        gcc::{Gcc, DEFAULT_GCC_RELEASE, RISCV_GCC, XTENSA_GCC},
+        llvm::Llvm,
+        rust::installed_version,
+        Component, ToolchainComponent,
+    },
+};
+use log::debug;
+use serde::{Serialize, Serializer};
+use std::{fmt, str::FromStr};
+#[cfg(feature = "cli")]
+use update_informer::{registry, Check};
+
+/// Placeholder of the components that are not found on disk.
+const NOT_INSTALLED: &str = "not installed";
+
+/// Versions of the components of an installation, or of the latest release.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ToolchainVersions {
+    /// Toolchain name, `None` for the latest release.
+    pub name: Option<String>,
+    /// Component names and versions, `None` when not installed.
+    #[serde(serialize_with = "serialize_components")]
+    pub components: Vec<(&'static str, Option<String>)>,
+}
+
+/// Serializes the components as an object, keeping their order.
+fn serialize_components<S: Serializer>(
+    components: &[(&'static str, Option<String>)],
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.collect_map(
+        components
+            .iter()
+            .map(|(component, version)| (component, version)),
+    )
+}
+
+impl ToolchainVersions {
+    /// Returns the versions of the components installed for a manifest.
+    pub fn installed(manifest: &Manifest) -> Self {
+        let toolchain_dir = manifest.toolchain_dir();
+        let host_triple = HostTriple::from_str(&manifest.host_triple).ok();
+        let mut components = vec![("Xtensa Rust", installed_version(&toolchain_dir))];
+        let required = |component: ToolchainComponent| {
+            component.required_by(&manifest.targets, manifest.std)
+                && (component != ToolchainComponent::GccRiscv || manifest.esp_riscv_gcc)
+        };
+        if required(ToolchainComponent::Llvm) {
+            let llvm = host_triple.as_ref().and_then(|host_triple| {
+                Llvm::new(
+                    &toolchain_dir,
+                    host_triple,
+                    manifest.extended_llvm,
+                    &manifest.toolchain_version,
+                )
+                .ok()
+            });
+            components.push(("LLVM", llvm.and_then(|llvm| llvm.installed_version())));
+        }
+        for (component, arch, label) in [
+            (ToolchainComponent::GccXtensa, XTENSA_GCC, "Xtensa GCC"),
+            (ToolchainComponent::GccRiscv, RISCV_GCC, "RISC-V GCC"),
+        ] {
+            if !required(component) {
+                continue;
+            }
+            let version = if manifest.from_espidf {
+                Some("from ESP-IDF".to_string())
+            } else {
+                host_triple.as_ref().and_then(|host_triple| {
+                    Gcc::new(arch, host_triple, &toolchain_dir).installed_version()
+                })
+            };
+            components.push((label, version));
+        }
+        Self {
+            name: Some(manifest.name.clone()),
+            components,
+        }
+    }
+
+    /// Returns the versions of the components of an Xtensa Rust release.
+    pub fn release(xtensa_rust_version: &str) -> Self {
+        let mut components = vec![("Xtensa Rust", Some(xtensa_rust_version.to_string()))];
+        match resolve(xtensa_rust_version) {
+            Ok(set) => {
+                components.push(("LLVM", Some(set.llvm.to_string())));
+                components.push(("GCC", Some(set.gcc.to_string())));
+            }
+            Err(_) => components.push(("GCC", Some(DEFAULT_GCC_RELEASE.to_string()))),
+        }
+        Self {
+            name: None,
+            components,
+        }
+    }
+}
+
+/// Versions of espup and of the toolchains.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct VersionReport {
+    /// Running espup version.
+    pub espup: String,
+    /// Latest espup version, if checked and newer. Only checked with the `cli` feature.
+    pub latest_espup: Option<String>,
+    /// Installed toolchains.
+    pub toolchains: Vec<ToolchainVersions>,
+    /// Latest release, if checked.
+    pub latest: Option<ToolchainVersions>,
+}
+
+impl VersionReport {
+    /// Returns the versions of espup and of the installed toolchains and, with `check`, the
+    /// latest available ones.
+    pub async fn new(check: bool) -> Self {
+        let toolchains = Manifest::list()
+            .unwrap_or_default()
+            .iter()
+            .map(ToolchainVersions::installed)
+            .collect();
+        let mut report = Self {
+            espup: env!("CARGO_PKG_VERSION").to_string(),
+            latest_espup: None,
+            toolchains,
+            latest: None,
+        };
+        if !check {
+            return report;
+        }
+        // The crates.io check is part of the update checks of the `cli` feature
+        #[cfg(feature = "cli")]
+        {
+            report.latest_espup = tokio::task::spawn_blocking(|| {
+                update_informer::new(
+                    registry::Crates,
+                    env!("CARGO_PKG_NAME"),
+                    env!("CARGO_PKG_VERSION"),
+                )
+                .interval(std::time::Duration::ZERO)
+                .check_version()
+                .ok()
+                .flatten()
+                .map(|version| version.to_string())
+            })
+            .await
+            .ok()
+            .flatten();
+        }
+        match latest_release(RUST_BUILD_REPOSITORY, GithubQueryOpts::default()).await {
+            Ok(release) => report.latest = Some(ToolchainVersions::release(release.version())),
+            Err(e) => debug!("Failed to query the latest Xtensa Rust release: {}", e),
+        }
+        report
+    }
+}
+
+impl fmt::Display for VersionReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "espup {}", self.espup)?;
+        if let Some(latest) = &self.latest_espup {
+            write!(f, " (latest: {latest})")?;
+        }
+        writeln!(f)?;
+        let sections =
+            self.toolchains
+                .iter()
+                .chain(&self.latest)
+                .map(|versions| match &versions.name {
+                    Some(name) => (format!("Toolchain '{name}':"), versions),
+                    None => ("Latest available:".to_string(), versions),
+                });
+        for (title, versions) in sections {
+            writeln!(f, "\n{title}")?;
+            for (component, version) in &versions.components {
+                writeln!(
+                    f,
+                    "  {:<13}{}",
+                    component,
+                    version.as_deref().unwrap_or(NOT_INSTALLED)
+                )?;
+            }
+        }
+        if self.toolchains.is_empty() {
+            writeln!(f, "\nNo toolchain installed by espup was found")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        report::{render, OutputFormat},
+        toolchain::{gcc::DEFAULT_GCC_RELEASE, llvm::DEFAULT_LLVM_18_VERSION},
+        version::{ToolchainVersions, VersionReport},
+    };
+    use serde_json::json;
+
+    #[test]
+    fn test_version_report() {
+        let report = VersionReport {
+            espup: "0.14.0".to_string(),
+            latest_espup: Some("0.15.0".to_string()),
+            toolchains: vec![ToolchainVersions {
+                name: Some("esp".to_string()),
+                components: vec![
+                    ("Xtensa Rust", Some("1.82.0.3".to_string())),
+                    ("Xtensa GCC", None),
+                ],
+            }],
+            latest: Some(ToolchainVersions::release("1.85.0.0")),
+        };
+        assert_eq!(
+            report.to_string(),
+            format!(
+                "espup 0.14.0 (latest: 0.15.0)\n\
+                 \n\
+                 Toolchain 'esp':\n  \
+                 Xtensa Rust  1.82.0.3\n  \
+                 Xtensa GCC   not installed\n\
+                 \n\
+                 Latest available:\n  \
+                 Xtensa Rust  1.85.0.0\n  \
+                 LLVM         {DEFAULT_LLVM_18_VERSION}\n  \
+                 GCC          {DEFAULT_GCC_RELEASE}\n"
+            )
+        );
+        let json: serde_json::Value =
+            serde_json::from_str(&render(&report, OutputFormat::Json)).unwrap();
+        assert_eq!(json["latest_espup"], "0.15.0");
+        assert_eq!(
+            json["toolchains"][0],
+            json!({
+                "name": "esp",
+                "components": { "Xtensa Rust": "1.82.0.3", "Xtensa GCC": null }
+            })
+        );
+        assert_eq!(json["latest"]["name"], serde_json::Value::Null);
+
+        let report = VersionReport {
+            latest_espup: None,
+            toolchains: Vec::new(),
+            latest: None,
+            ..report
+        };
+        assert_eq!(
+            report.to_string(),
+            "espup 0.14.0\n\nNo toolchain installed by espup was found\n"
+        );
+    }
+}
@@ -0,0 +1,139 @@
+//! Toolchain building the applications of a chip, for `espup which-toolchain`.
+
+use crate::{
+    error::Error, manifest::Manifest, pin::ToolchainPin, targets::Target,
+    toolchain::rust::rustup_installed,
+};
+use log::debug;
+use miette::Result;
+use std::{collections::HashSet, fmt};
+
+/// Toolchain and `cargo build` invocation building the applications of a chip.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ToolchainChoice {
+    /// Rustup toolchain.
+    pub toolchain: String,
+    /// Rust target triple.
+    pub rust_target: &'static str,
+    /// Crates of the standard library built from source, for targets without prebuilt ones.
+    pub build_std: Option<&'static str>,
+}
+
+impl ToolchainChoice {
+    /// Returns the toolchain of an installation building the applications of the given chip.
+    ///
+    /// `stable_targets` is the output of `rustup target list --installed` for the stable
+    /// toolchain, RISC-V `no_std` applications are built with the nightly toolchain espup installs
+    /// the targets to when stable lacks them.
+    pub fn new(manifest: &Manifest, target: Target, stable_targets: Option<&str>) -> Self {
+        let mut toolchain = ToolchainPin::new(manifest, &HashSet::from([target])).channel;
+        if target.is_riscv()
+            && !manifest.std
+            && !stable_targets
+                .unwrap_or_default()
+                .lines()
+                .any(|line| line.trim() == target.rust_target())
+        {
+            toolchain = manifest.nightly_version.clone();
+        }
+        let (rust_target, build_std) = if manifest.std {
+            (target.std_rust_target(), Some("std,panic_abort"))
+        } else if target.is_xtensa() {
+            (target.rust_target(), Some("core"))
+        } else {
+            (target.rust_target(), None)
+        };
+        Self {
+            toolchain,
+            rust_target,
+            build_std,
+        }
+    }
+
+    /// Returns the `cargo build` command line.
+    pub fn cargo_command(&self) -> String {
+        let mut command = format!(
+            "cargo +{} build --target {}",
+            self.toolchain, self.rust_target
+        );
+        if let Some(build_std) = self.build_std {
+            command.push_str(&format!(" -Zbuild-std={build_std}"));
+        }
+        command
+    }
+}
+
+impl fmt::Display for ToolchainChoice {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Toolchain: +{}", self.toolchain)?;
+        writeln!(f, "Target:    {}", self.rust_target)?;
+        writeln!(f, "Command:   {}", self.cargo_command())
+    }
+}
+
+/// Returns the toolchain of the given installation building the applications of a chip.
+pub fn which_toolchain(name: &str, target: Target) -> Result<ToolchainChoice, Error> {
+    let manifest = Manifest::load(name)?.ok_or_else(|| Error::MissingManifest(name.to_string()))?;
+    if !manifest.targets.contains(&target) {
+        return Err(Error::TargetsNotInstalled(
+            name.to_string(),
+            target.to_string(),
+        ));
+    }
+    let stable_targets = target
+        .is_riscv()
+        .then(|| rustup_installed("target", "stable", &manifest.homes()))
+        .flatten();
+    debug!("Stable toolchain targets: {:?}", stable_targets);
+    Ok(ToolchainChoice::new(
+        &manifest,
+        target,
+        stable_targets.as_deref(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        manifest::{manifest_fixture, Manifest},
+        targets::Target,
+        which::ToolchainChoice,
+    };
+    use std::collections::HashSet;
+
+    fn manifest(std: bool) -> Manifest {
+        Manifest {
+            std,
+            targets: HashSet::from([Target::ESP32, Target::ESP32C3]),
+            ..manifest_fixture()
+        }
+    }
+
+    #[test]
+    fn test_toolchain_choice() {
+        let choice = ToolchainChoice::new(&manifest(false), Target::ESP32, None);
+        assert_eq!(choice.toolchain, "esp");
+        assert_eq!(
+            choice.cargo_command(),
+            "cargo +esp build --target xtensa-esp32-none-elf -Zbuild-std=core"
+        );
+
+        let stable = "riscv32imc-unknown-none-elf\nx86_64-unknown-linux-gnu\n";
+        let choice = ToolchainChoice::new(&manifest(false), Target::ESP32C3, Some(stable));
+        assert_eq!(
+            choice.to_string(),
+            "Toolchain: +stable\n\
+             Target:    riscv32imc-unknown-none-elf\n\
+             Command:   cargo +stable build --target riscv32imc-unknown-none-elf\n"
+        );
+        // The RISC-V targets are installed to the nightly toolchain
+        let choice = ToolchainChoice::new(&manifest(false), Target::ESP32C3, None);
+        assert_eq!(choice.toolchain, "nightly");
+
+        let choice = ToolchainChoice::new(&manifest(true), Target::ESP32C3, Some(stable));
+        assert_eq!(
+            choice.cargo_command(),
+            "cargo +nightly build --target riscv32imc-esp-espidf -Zbuild-std=std,panic_abort"
+        );
+    }
+}
@@ -0,0 +1,93 @@
+//! Windows Subsystem for Linux support.
+//!
+//! Windows drives are mounted in WSL through DrvFs (e.g. `/mnt/c`), installing toolchains there
+//! is really slow and symlinks created from WSL are not usable from Windows.
+
+use log::warn;
+use std::{
+    fs::read_to_string,
+    path::{Path, PathBuf},
+};
+
+/// Returns whether espup is running inside WSL.
+pub fn is_wsl() -> bool {
+    if std::env::var_os("WSL_DISTRO_NAME").is_some() {
+        return true;
+    }
+    read_to_string("/proc/sys/kernel/osrelease")
+        .is_ok_and(|release| release.to_lowercase().contains("microsoft"))
+}
+
+/// Returns whether the path is located in a Windows drive mounted with DrvFs.
+pub fn is_drvfs_path(path: &Path) -> bool {
+    let mount_points = read_to_string("/proc/mounts")
+        .map(|mounts| drvfs_mount_points(&mounts))
+        .unwrap_or_default();
+    mount_points
+        .iter()
+        .any(|mount_point| path.starts_with(mount_point))
+}
+
+/// Parses the content of `/proc/mounts` and returns the DrvFs mount points.
+fn drvfs_mount_points(mounts: &str) -> Vec<PathBuf> {
+    mounts
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let _device = fields.next()?;
+            let mount_point = fields.next()?;
+            let fs_type = fields.next()?;
+            let options = fields.next().unwrap_or_default();
+            // WSL 1 uses the `drvfs` type, WSL 2 mounts DrvFs over 9p
+            let is_drvfs = fs_type == "drvfs"
+                || (fs_type == "9p" && options.split([',', ';']).any(|o| o == "aname=drvfs"));
+            // Spaces in mount points are escaped as `\040`
+            is_drvfs.then(|| PathBuf::from(mount_point.replace("\\040", " ")))
+        })
+        .collect()
+}
+
+/// Returns whether symlinks should be avoided in the given directory.
+pub fn avoid_symlinks(path: &Path) -> bool {
+    is_wsl() && is_drvfs_path(path)
+}
+
+/// Warns about every directory located in a Windows drive when running inside WSL.
+pub fn warn_drvfs_paths(paths: &[(&str, &Path)]) {
+    if !is_wsl() {
+        return;
+    }
+    for (name, path) in paths {
+        if is_drvfs_path(path) {
+            warn!(
+                "{} ('{}') is located in a Windows drive. Installing from WSL into Windows drives is really slow and breaks symlinks, move it to the Linux filesystem (e.g. '~/{}')",
+                name,
+                path.display(),
+                path.file_name().unwrap_or_default().to_string_lossy()
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::wsl::drvfs_mount_points;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_drvfs_mount_points() {
+        let mounts = "none /mnt/wsl tmpfs rw,relatime 0 0\n\
+            C:\\ /mnt/c 9p rw,noatime,dirsync,aname=drvfs;path=C:\\;uid=1000;gid=1000;symlinkroot=/mnt/ 0 0\n\
+            D:\\134 /mnt/d 9p rw,noatime,aname=drvfs,path=D:\\ 0 0\n\
+            E: /mnt/my\\040drive drvfs rw,noatime 0 0\n\
+            drvfs /home/user 9p rw,aname=other 0 0\n";
+        assert_eq!(
+            drvfs_mount_points(mounts),
+            vec![
+                PathBuf::from("/mnt/c"),
+                PathBuf::from("/mnt/d"),
+                PathBuf::from("/mnt/my drive")
+            ]
+        );
+    }
+}
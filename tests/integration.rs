@@ -41,3 +41,160 @@ fn verify_uninstall_help() {
         .assert()
         .success();
 }
+
+#[test]
+fn verify_subcommands_help() {
+    // Each help documents an argument specific to its subcommand
+    for (args, expected) in [
+        (&["targets", "add"][..], "<TARGETS>"),
+        (&["env"], "--pure"),
+        (&["fetch"], "--host <HOSTS>"),
+        (&["gc"], "--dry-run"),
+        (&["pin"], "--dir <DIR>"),
+        (&["schema"], "JSON schema"),
+        (&["reinstall", "rust-src"], "--name <NAME>"),
+        (&["completions"], "--all"),
+        (&["generate", "docker"], "--devcontainer"),
+        (&["verify"], "--record-hashes"),
+        (&["version"], "--check"),
+        (&["toolchain", "clone"], "<SOURCE>"),
+        (&["which-toolchain"], "<TARGET>"),
+        (&["auth", "set-mirror"], "<MIRROR>"),
+        (&["llvm", "use"], "<VERSION>"),
+        (&["preflight"], "--host <HOSTS>"),
+    ] {
+        let assert = assert_cmd::Command::cargo_bin("espup")
+            .unwrap()
+            .args(args)
+            .arg("--help")
+            .assert()
+            .success();
+        let stdout = String::from_utf8_lossy(&assert.get_output().stdout);
+        assert!(
+            stdout.contains(expected),
+            "espup {}: {stdout}",
+            args.join(" ")
+        );
+    }
+}
+
+#[test]
+fn verify_uninstall_filters_conflict() {
+    assert_cmd::Command::cargo_bin("espup")
+        .unwrap()
+        .args(["uninstall", "--components", "llvm", "--targets", "esp32s2"])
+        .assert()
+        .failure();
+}
+
+#[test]
+fn verify_daemon_stdio() {
+    let assert = assert_cmd::Command::cargo_bin("espup")
+        .unwrap()
+        .args(["daemon", "--stdio"])
+        .write_stdin(
+            "{\"jsonrpc\": \"2.0\", \"id\": 1, \"method\": \"version\"}\n\
+             {\"jsonrpc\": \"2.0\", \"id\": 2, \"method\": \"shutdown\"}\n",
+        )
+        .assert()
+        .success();
+    let stdout = String::from_utf8_lossy(&assert.get_output().stdout);
+    assert!(stdout.contains("\"protocol\":1"), "{stdout}");
+}
+
+#[test]
+fn verify_matrix() {
+    assert_cmd::Command::cargo_bin("espup")
+        .unwrap()
+        .args(["matrix", "--format", "json"])
+        .assert()
+        .success();
+}
+
+#[test]
+fn verify_version_json() {
+    let assert = assert_cmd::Command::cargo_bin("espup")
+        .unwrap()
+        .args(["version", "--format", "json", "--no-update-check"])
+        .assert()
+        .success();
+    let stdout = String::from_utf8_lossy(&assert.get_output().stdout);
+    assert!(
+        stdout.contains(&format!("\"espup\": \"{}\"", env!("CARGO_PKG_VERSION"))),
+        "{stdout}"
+    );
+}
+
+#[test]
+fn verify_which_toolchain_unknown_target() {
+    assert_cmd::Command::cargo_bin("espup")
+        .unwrap()
+        .args(["which-toolchain", "esp8266"])
+        .assert()
+        .failure();
+}
+
+#[test]
+fn verify_retain_logs() {
+    let data_dir = tempfile::TempDir::new().unwrap();
+    for _ in 0..3 {
+        assert_cmd::Command::cargo_bin("espup")
+            .unwrap()
+            .args(["matrix", "--no-update-check", "--retain-logs", "2"])
+            .env("ESPUP_DATA_DIR", data_dir.path())
+            .assert()
+            .success();
+    }
+    let assert = assert_cmd::Command::cargo_bin("espup")
+        .unwrap()
+        .args(["logs", "--retain-logs", "1"])
+        .env("ESPUP_DATA_DIR", data_dir.path())
+        .assert()
+        .success();
+    let stdout = String::from_utf8_lossy(&assert.get_output().stdout);
+    assert_eq!(stdout.lines().count(), 2, "{stdout}");
+}
+
+#[test]
+fn verify_rustup_home_override() {
+    let rustup_home = tempfile::TempDir::new().unwrap();
+    let assert = assert_cmd::Command::cargo_bin("espup")
+        .unwrap()
+        .args(["version", "--format", "json", "--no-update-check"])
+        .arg("--rustup-home")
+        .arg(rustup_home.path())
+        .assert()
+        .success();
+    let stdout = String::from_utf8_lossy(&assert.get_output().stdout);
+    assert!(stdout.contains("\"toolchains\": []"), "{stdout}");
+}
+
+#[test]
+fn verify_llvm_only_libs_path_conflicts() {
+    assert_cmd::Command::cargo_bin("espup")
+        .unwrap()
+        .args([
+            "install",
+            "--llvm-only-libs-path",
+            "libclang",
+            "--extended-llvm",
+        ])
+        .assert()
+        .failure();
+}
+
+#[test]
+fn verify_ci_install_api_version() {
+    assert_cmd::Command::cargo_bin("espup")
+        .unwrap()
+        .args(["ci-install", "--help"])
+        .assert()
+        .success();
+
+    // Only the published flag sets are accepted
+    assert_cmd::Command::cargo_bin("espup")
+        .unwrap()
+        .args(["ci-install", "--api-version", "2"])
+        .assert()
+        .failure();
+}